@@ -1,57 +1,92 @@
 #![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
 //because i'm coming from python and gdscript. enum variants are SCREAMING_SNAKE_CASE AND YOU CAN'T
 //CONVINCE ME OTHERWISE!!! GRRAHHH
 
-use crate::lexer::TokenType;
+use crate::lexer::{Span, TokenType};
+use crate::random::Dice;
+use crate::value::unescape_string_literal;
 
 ///TOKENS, EXPRESSIONS AND IDENTS 
 
 
 ///IDENTIFIER section
 //this here is an IDENTIFIER. It serves one purpose: to hold the name to variables and function
-//calls (and more in the future). Right now, it is only a type wrapper around a string, which is
-//fine for the purpose it serves.
+//calls (and more in the future). The namespace is whatever came before the last `::`, so
+//`quests::intro::greet` is {namespace: [quests, intro], name: greet}. Plain names have none.
+//The span covers the whole path, so errors and tools can point right at it.
 #[derive(Debug, Clone)]
 pub struct Ident {
-    pub name: String
+    pub name: String,
+    pub namespace: Vec<String>,
+    pub span: Span,
+}
+
+impl Ident {
+    pub fn new(name: &str) -> Self {
+        Ident{ name: name.to_owned(), namespace: Vec::new(), span: Span::default() }
+    }
+    pub fn with_span(name: &str, span: Span) -> Self {
+        Ident{ name: name.to_owned(), namespace: Vec::new(), span }
+    }
+    pub fn is_namespaced(&self) -> bool {
+        !self.namespace.is_empty()
+    }
+    pub fn full_name(&self) -> String {
+        let mut ret = String::new();
+        for segment in &self.namespace {
+            ret += &format!("{segment}::");
+        }
+        ret + &self.name
+    }
 }
 
 ///OPERATOR section
-//this here is a BINARY OPERATOR enum. It represents these operators: +,-,*,/. Support for more
-//operators is intended to be added in the future, if i can get my lazy ass to push further, lmao 
-#[derive(Debug)]
+//this here is a BINARY OPERATOR enum. It represents the arithmetic operators +,-,*,/,%,** and the
+//bitwise operators &,|,^,<<,>> (flag masks, mostly). How each of them behaves on ints vs floats
+//lives in value.rs, how tightly they bind lives in precedence.rs.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinOp {
     ADD,
     SUB,
     MULT,
     DIV,
+    MOD,
+    POW,
+    BIT_AND,
+    BIT_OR,
+    BIT_XOR,
+    SHL,
+    SHR,
 }
 
 impl BinOp {
-    pub fn get_precedence(&self) -> u8 { //decides operator precedence to be utilised in parser.rs
-        match self {
-            BinOp::ADD | BinOp::SUB => 1,
-            BinOp::MULT | BinOp::DIV => 2,
-        }
-    }
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         match self {
             BinOp::ADD => "+".to_string(),
             BinOp::SUB => "-".to_string(),
             BinOp::MULT => "*".to_string(),
             BinOp::DIV => "/".to_string(),
+            BinOp::MOD => "%".to_string(),
+            BinOp::POW => "**".to_string(),
+            BinOp::BIT_AND => "&".to_string(),
+            BinOp::BIT_OR => "|".to_string(),
+            BinOp::BIT_XOR => "^".to_string(),
+            BinOp::SHL => "<<".to_string(),
+            BinOp::SHR => ">>".to_string(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MonOp {
     POS,
     NEG
 }
 
 impl MonOp {
-    pub fn get_precedence(&self) -> u8 {3}
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         match self {
             MonOp::POS => "+".to_string(),
@@ -67,17 +102,20 @@ impl MonOp {
 pub enum Atom {
     LITERAL_FLOAT(f64),
     LITERAL_INT(i64),
+    LITERAL_DICE(Dice), //rolled every time it's evaluated, see random.rs
     LITERAL_STRING(String),
     IDENTIFIER(Ident),
 }
 
 impl Atom {
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         match self {
             Atom::LITERAL_INT(val) => val.to_string(),
             Atom::LITERAL_FLOAT(val) => val.to_string(),
-            Atom::LITERAL_STRING(val) => format!(r"{}", val),
-            Atom::IDENTIFIER(ident) => ident.name.clone(),
+            Atom::LITERAL_DICE(dice) => dice.to_string(),
+            Atom::LITERAL_STRING(val) => val.clone(),
+            Atom::IDENTIFIER(ident) => ident.full_name(),
         }
     }
 }
@@ -92,7 +130,7 @@ pub struct FnCall {
 
 impl FnCall {
     pub fn to_pretty_string(&self) -> String {
-        let mut ret = String::from(&self.ident.name);
+        let mut ret = self.ident.full_name();
         ret += "(";
         for arg in self.args.as_ref() {
             ret += &format!("[{}]", arg.to_pretty_string());
@@ -130,6 +168,25 @@ impl FieldAccess {
     }
 }
 
+///YIELD section
+//`yield expr` (or `slumber expr`) puts the coroutine running it to sleep, hands expr to the game,
+//and wakes up as whatever value the game resumes it with. a bare `yield` hands over void. it's an
+//expression, `answer = yield "Which door?";`, and a statement on its own.
+#[derive(Debug)]
+pub struct Yield {
+    pub expr: Option<Box<Expr>>,
+    pub span: Span, //of the `yield` keyword
+}
+
+impl Yield {
+    pub fn to_pretty_string(&self) -> String {
+        match &self.expr {
+            Some(expr) => format!("yield {}", expr.to_pretty_string()),
+            None => "yield".to_string(),
+        }
+    }
+}
+
 ///EXPR section
 //this here is an EXPR(expression) enum. It represents either an ATOMIC EXPRESSION (an expression
 //that cannot be divided anymore) or a BINARY OPERATION (like 2+3 or 1-var) or a SCOPE
@@ -150,6 +207,7 @@ pub enum Expr {
     FUNCTION_CALL(FnCall),
     METHOD_CALL(MethodCall),
     FIELD_ACCESS(FieldAccess),
+    YIELD(Yield),
 }
 
 impl Expr {
@@ -168,7 +226,9 @@ impl Expr {
             Expr::UNARY_EXPR{opcode,expr} => format!("({}{})",opcode.to_string(),expr.to_pretty_string()),
             Expr::SCOPE(scope) => scope.to_pretty_string(),
             Expr::FUNCTION_CALL(fncall) => fncall.to_pretty_string(),
-            _ => format!("")
+            Expr::METHOD_CALL(call) => call.to_pretty_string(),
+            Expr::FIELD_ACCESS(access) => access.to_pretty_string(),
+            Expr::YIELD(yield_expr) => format!("({})", yield_expr.to_pretty_string()),
         }
     }
 }
@@ -200,16 +260,33 @@ impl Parameter {
 }
 
 ///FUNCTION DECLARATION section
-//a function declaration DECLARES that the next scope is a reusable block of statements 
+//a function declaration DECLARES that its body scope is a reusable block of statements 
+//
+//an event handler (`on "door_opened"(door: int) {...}`, see events.rs) is one too. it can't be
+//called by name, so the parser names it something no script can write, like `on door_opened #1`.
 #[derive(Debug)]
 pub struct FnDeclaration {
     pub ident: Ident, 
     pub type_t: TokenType, //the return type. unassigned implies TYPE_VOID
-    pub params: Vec<Parameter>
+    pub params: Vec<Parameter>,
+    pub body: Scope,
+    pub event: Option<String>, //the rune literal of the event a handler is for, quotes and all
 }
 impl FnDeclaration {
     pub fn to_pretty_string(&self) -> String {
-        format!("{}({}) -> {:?}", self.ident.name, Parameter::to_pretty_string(&self.params), self.type_t)
+        let name = match &self.event {
+            Some(event) => format!("on {}", event),
+            None => self.ident.name.clone(),
+        };
+        format!(
+            "{}({}) -> {:?} {}",
+            name, Parameter::to_pretty_string(&self.params), self.type_t, self.body.to_pretty_string()
+        )
+    }
+
+    //the event a handler is for, None for an ordinary rite
+    pub fn event_name(&self) -> Option<String> {
+        self.event.as_deref().map(unescape_string_literal)
     }
 }
 
@@ -217,7 +294,122 @@ impl FnDeclaration {
 //do i really need to explain tf this is :sob:
 #[derive(Debug)]
 pub struct ReturnStmt {
-    pub expr: Box<Expr>
+    pub expr: Box<Expr>,
+    pub span: Span, //of the `ret` keyword
+}
+
+///IMPORT section
+//`summon quests::intro;` or `summon quests::intro as intro;`. Without an alias, the module is
+//reachable through the last segment of its path, just like the rest of the world does it.
+#[derive(Debug)]
+pub struct Import {
+    pub path: Vec<Ident>,
+    pub alias: Option<Ident>,
+}
+impl Import {
+    pub fn module_path(&self) -> Vec<String> {
+        self.path.iter().map(|segment| segment.name.clone()).collect()
+    }
+    //the name the importing module uses to reach into this one
+    pub fn local_name(&self) -> &str {
+        match &self.alias {
+            Some(alias) => &alias.name,
+            None => &self.path[self.path.len() - 1].name,
+        }
+    }
+    pub fn to_pretty_string(&self) -> String {
+        let path = self.module_path().join("::");
+        match &self.alias {
+            Some(alias) => format!("summon {} as {}", path, alias.name),
+            None => format!("summon {}", path),
+        }
+    }
+}
+
+///DIALOGUE section
+//a `dialogue { ... }` block: named NODES of lines, each ending in choices, a jump to another node,
+//or nothing (which ends the dialogue). the first node is where it starts. what it all means and
+//how it runs is written down in dialogue.rs.
+#[derive(Debug)]
+pub struct Dialogue {
+    pub nodes: Vec<DialogueNode>,
+    pub span: Span, //from `dialogue` to the `}`, both included
+}
+
+#[derive(Debug)]
+pub struct DialogueNode {
+    pub ident: Ident,
+    pub lines: Vec<DialogueLine>,
+    pub exit: NodeExit,
+    pub span: Span, //from `node` to the `}`, both included
+}
+
+//`Guard: "Halt!";`, or just `"The door creaks.";` with nobody saying it
+#[derive(Debug)]
+pub struct DialogueLine {
+    pub speaker: Option<Ident>,
+    pub text: Text,
+}
+
+impl DialogueLine {
+    pub fn span(&self) -> Span {
+        match &self.speaker {
+            Some(speaker) => speaker.span,
+            None => self.text.span,
+        }
+    }
+}
+
+//`choice "Bribe him." if gold -> bribe;`. without a condition it's always on offer.
+#[derive(Debug)]
+pub struct Choice {
+    pub text: Text,
+    pub condition: Option<Expr>,
+    pub target: Ident,
+    pub span: Span, //of the `choice` keyword
+}
+
+//how a node is left once its lines are said
+#[derive(Debug)]
+pub enum NodeExit {
+    END,
+    JUMP(Ident), //`-> node;`
+    CHOICES(Vec<Choice>),
+}
+
+//a rune with `{expr}`s in it. the literal is kept exactly as written, quotes and all, for the
+//formatter and for anyone pulling the text out of a script.
+#[derive(Debug)]
+pub struct Text {
+    pub literal: String,
+    pub parts: Vec<TextPart>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum TextPart {
+    TEXT(String), //already unescaped
+    EXPR(Expr),
+}
+
+impl Dialogue {
+    pub fn to_pretty_string(&self) -> String {
+        let nodes: Vec<String> = self.nodes.iter().map(|node| {
+            let lines: Vec<String> = node.lines.iter().map(|line| match &line.speaker {
+                Some(speaker) => format!("{}: {}", speaker.name, line.text.literal),
+                None => line.text.literal.clone(),
+            }).collect();
+            let exit = match &node.exit {
+                NodeExit::END => String::new(),
+                NodeExit::JUMP(target) => format!(" -> {}", target.name),
+                NodeExit::CHOICES(choices) => choices.iter()
+                    .map(|choice| format!(" [{} -> {}]", choice.text.literal, choice.target.name))
+                    .collect(),
+            };
+            format!("{}[{}]{}", node.ident.name, lines.join(", "), exit)
+        }).collect();
+        format!("dialogue{{{}}}", nodes.join(" "))
+    }
 }
 
 ///STATEMENT section
@@ -227,23 +419,67 @@ pub struct ReturnStmt {
 pub enum Stmt {
     STATEMENT_ASSIGNMENT(Assignment),
     STATEMENT_FUNCTION_DECLARATION(FnDeclaration),
-    STATEMENT_ZERO_EFFECT,
+    STATEMENT_ZERO_EFFECT(Option<Ident>), //`foo;` keeps the foo, a stray `;` has nothing
     STATEMENT_RETURN(ReturnStmt),
     STATEMENT_FUNCTION_CALL(FnCall),
+    STATEMENT_IMPORT(Import),
+    STATEMENT_EXPORT(Box<Stmt>), //only ever wraps a FUNCTION_DECLARATION or an ASSIGNMENT
+    STATEMENT_YIELD(Yield),
+    STATEMENT_DIALOGUE(Dialogue),
     SCOPE(Scope)
 }
 
 impl Stmt {
     pub fn to_pretty_string(&self) -> String {
         match self {
-            Stmt::STATEMENT_ZERO_EFFECT => "ZERO-EFFECT".to_string(),
+            Stmt::STATEMENT_ZERO_EFFECT(_) => "ZERO-EFFECT".to_string(),
             Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => decl.to_pretty_string(),
             Stmt::STATEMENT_ASSIGNMENT(Assignment{ident,type_t, expr}) => {
-                format!("{}:{:?} = {}",ident.name, type_t, expr.to_pretty_string())
+                format!("{}:{:?} = {}",ident.full_name(), type_t, expr.to_pretty_string())
             },
             Stmt::STATEMENT_RETURN(ret) => ret.expr.to_pretty_string(),
             Stmt::SCOPE(scope) => scope.to_pretty_string(),
             Stmt::STATEMENT_FUNCTION_CALL(fncall) => fncall.to_pretty_string(),
+            Stmt::STATEMENT_IMPORT(import) => import.to_pretty_string(),
+            Stmt::STATEMENT_EXPORT(stmt) => format!("export {}", stmt.to_pretty_string()),
+            Stmt::STATEMENT_YIELD(yield_stmt) => yield_stmt.to_pretty_string(),
+            Stmt::STATEMENT_DIALOGUE(dialogue) => dialogue.to_pretty_string(),
+        }
+    }
+
+    //where a statement starts, as far as the ast knows. a `;` on its own doesn't have one.
+    pub fn span(&self) -> Option<Span> {
+        let span = match self {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) => assignment.ident.span,
+            Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => decl.ident.span,
+            Stmt::STATEMENT_RETURN(ret) => ret.span,
+            Stmt::STATEMENT_FUNCTION_CALL(fncall) => fncall.ident.span,
+            Stmt::STATEMENT_IMPORT(import) => import.path[0].span,
+            Stmt::STATEMENT_EXPORT(inner) => return inner.span(),
+            Stmt::STATEMENT_YIELD(yield_stmt) => yield_stmt.span,
+            Stmt::STATEMENT_DIALOGUE(dialogue) => dialogue.span,
+            Stmt::SCOPE(scope) => scope.span,
+            Stmt::STATEMENT_ZERO_EFFECT(Some(ident)) => ident.span,
+            Stmt::STATEMENT_ZERO_EFFECT(None) => return None,
+        };
+        //spans made by hand (Ident::new) have no line
+        match span.line {
+            0 => None,
+            _ => Some(span),
+        }
+    }
+    pub fn line(&self) -> Option<u32> {
+        self.span().map(|span| span.line)
+    }
+
+    //whether running this statement always ends in a `ret`. a `ret` in a nested scope statement
+    //returns from the whole rite, so a scope that always returns counts too.
+    pub fn always_returns(&self) -> bool {
+        match self {
+            Stmt::STATEMENT_RETURN(_) => true,
+            Stmt::SCOPE(scope) => scope.stmts.iter().any(Stmt::always_returns),
+            Stmt::STATEMENT_EXPORT(inner) => inner.always_returns(),
+            _ => false,
         }
     }
 }
@@ -252,7 +488,8 @@ impl Stmt {
 //A SCOPE defines a collective lifetime for all variables defined within itself
 #[derive(Debug)]
 pub struct Scope {
-    pub stmts: Vec<Stmt>
+    pub stmts: Vec<Stmt>,
+    pub span: Span, //from the `{` to the `}`, both included
 }
 
 impl Scope {
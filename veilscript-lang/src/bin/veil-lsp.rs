@@ -0,0 +1,25 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::process::ExitCode;
+
+use veilscript_lang::lsp::{serve, LanguageServer};
+
+///VEIL-LSP section
+//the language server, over stdin and stdout. editors start it themselves, nobody types this.
+//`print` is always there since `veil run` gives it to every script. games hand over the rest of
+//their natives through the editor's initializationOptions: `{ "natives": ["give_gold", ...] }`.
+
+fn main() -> ExitCode {
+    let mut server = LanguageServer::new(vec!["print".to_string()]);
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    match serve(&mut server, &mut stdin.lock(), &mut stdout.lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        //the spec says to exit with 1 when there was no shutdown first
+        Ok(false) => ExitCode::from(1),
+        Err(err) => {
+            eprintln!("veil-lsp: {}", err);
+            ExitCode::from(1)
+        },
+    }
+}
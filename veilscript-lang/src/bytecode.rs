@@ -0,0 +1,220 @@
+#![allow(non_camel_case_types)]
+
+use crate::ast::{BinOp, MonOp};
+use crate::lexer::TokenType;
+use crate::value::Value;
+
+///OPCODE section
+//every instruction is one opcode byte followed by its operands, packed little endian. the comment
+//next to each opcode is its operands and what it does to the stack.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    CONSTANT,          //u16 constant             -> push constants[constant]
+    VOID,              //                         -> push void
+    POP,               //                         -> drop the top value
+    POP_N,             //u16 count                -> drop `count` values (locals going out of scope)
+    SLIDE,             //u16 count                -> keep the top value, drop `count` values under it
+    GET_LOCAL,         //u16 slot                 -> push the local in `slot` of the current frame
+    SET_LOCAL,         //u16 slot                 -> pop into the local in `slot`
+    GET_GLOBAL,        //u16 slot                 -> push a global of the current module
+    SET_GLOBAL,        //u16 slot                 -> pop into a global of the current module
+    GET_MODULE_GLOBAL, //u16 module, u16 slot     -> push a global of another module
+    CONFORM,           //u8 type, u16 name        -> check (and maybe convert) the top value for a typed assignment
+    UNARY,             //u8 monop                 -> apply a unary operator to the top value
+    BINARY,            //u8 binop                 -> pop two values, push the result
+    CALL,              //u16 function, u8 argc    -> call a rite with the top `argc` values as its arguments
+    CALL_NATIVE,       //u16 name, u8 argc        -> call a native by name
+    FIELD,             //u16 name                 -> read a field off the top value
+    JUMP,              //u16 target               -> continue at `target` in the same chunk
+    RETURN,            //                         -> leave the current rite with the top value
+    YIELD,             //                         -> suspend the coroutine with the top value, push what it's resumed with
+    ROLL,              //u16 dice                 -> roll the dice written out in constants[dice], push the total
+    JUMP_IF_NOT,       //u16 target               -> pop a condition, continue at `target` unless it holds
+    TEXT,              //u16 node, u16 line       -> pop the values of the `{expr}`s of a dialogue line, push the line (translated) as a rune
+    BRANCH,            //u16 index, u16 target    -> if the top value is the int `index`, pop it and continue at `target`
+    PICK,              //u16 count                -> pop the index of the choice picked and the conditions of the `count` choices
+                       //                            under it, fail unless that choice was on offer, push the index back
+}
+
+impl OpCode {
+    pub const ALL: [OpCode; 24] = [
+        OpCode::CONSTANT, OpCode::VOID, OpCode::POP, OpCode::POP_N, OpCode::SLIDE,
+        OpCode::GET_LOCAL, OpCode::SET_LOCAL, OpCode::GET_GLOBAL, OpCode::SET_GLOBAL,
+        OpCode::GET_MODULE_GLOBAL, OpCode::CONFORM, OpCode::UNARY, OpCode::BINARY, OpCode::CALL,
+        OpCode::CALL_NATIVE, OpCode::FIELD, OpCode::JUMP, OpCode::RETURN, OpCode::YIELD,
+        OpCode::ROLL, OpCode::JUMP_IF_NOT, OpCode::TEXT, OpCode::BRANCH, OpCode::PICK,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+
+    //how many bytes of operands follow the opcode
+    pub fn operand_size(&self) -> usize {
+        match self {
+            OpCode::VOID | OpCode::POP | OpCode::RETURN | OpCode::YIELD => 0,
+            OpCode::UNARY | OpCode::BINARY => 1,
+            OpCode::CALL | OpCode::CALL_NATIVE => 3,
+            OpCode::CONFORM => 3,
+            OpCode::GET_MODULE_GLOBAL | OpCode::TEXT | OpCode::BRANCH => 4,
+            _ => 2,
+        }
+    }
+}
+
+//the operands of one instruction pulled apart. every layout is some mix of one u8 and up to two
+//u16s, so this is all of them; whatever an opcode doesn't have stays 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Operands {
+    pub byte: u8,
+    pub first: u16,
+    pub second: u16,
+}
+
+//decodes the instruction at `offset`. hands back the opcode, its operands and where the next
+//instruction starts, or an error if the code there is garbage.
+pub fn decode_at(code: &[u8], offset: usize) -> Result<(OpCode, Operands, usize), String> {
+    let byte = *code.get(offset).ok_or_else(|| format!("Corrupt bytecode! Nothing at offset {}.", offset))?;
+    let opcode = OpCode::from_byte(byte).ok_or_else(|| format!("Corrupt bytecode! {} is not an opcode.", byte))?;
+    let next = offset + 1 + opcode.operand_size();
+    let bytes = code.get(offset + 1..next)
+        .ok_or_else(|| format!("Corrupt bytecode! {:?} at offset {} is cut off.", opcode, offset))?;
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let operands = match bytes.len() {
+        0 => Operands::default(),
+        1 => Operands { byte: bytes[0], ..Operands::default() },
+        2 => Operands { first: u16_at(0), ..Operands::default() },
+        3 if opcode == OpCode::CONFORM => Operands { byte: bytes[0], first: u16_at(1), second: 0 },
+        3 => Operands { byte: bytes[2], first: u16_at(0), second: 0 },
+        _ => Operands { byte: 0, first: u16_at(0), second: u16_at(2) },
+    };
+    Ok((opcode, operands, next))
+}
+
+///OPERAND ENCODING section
+//operators and types go into the bytecode as single bytes.
+pub const BINOPS: [BinOp; 11] = [
+    BinOp::ADD, BinOp::SUB, BinOp::MULT, BinOp::DIV, BinOp::MOD, BinOp::POW,
+    BinOp::BIT_AND, BinOp::BIT_OR, BinOp::BIT_XOR, BinOp::SHL, BinOp::SHR,
+];
+pub const MONOPS: [MonOp; 2] = [MonOp::POS, MonOp::NEG];
+pub const TYPES: [TokenType; 4] = [
+    TokenType::EXPERIMENTAL_TYPE_INT, TokenType::TYPE_FLOAT, TokenType::TYPE_STRING, TokenType::TYPE_VOID,
+];
+
+pub fn binop_code(opcode: BinOp) -> u8 {
+    BINOPS.iter().position(|op| *op == opcode).unwrap_or(0) as u8
+}
+
+pub fn monop_code(opcode: MonOp) -> u8 {
+    MONOPS.iter().position(|op| *op == opcode).unwrap_or(0) as u8
+}
+
+//None for anything that isn't one of the four types
+pub fn type_code(type_t: &TokenType) -> Option<u8> {
+    TYPES.iter().position(|ty| ty == type_t).map(|code| code as u8)
+}
+
+///CHUNK section
+//a CHUNK is the bytecode of one function together with the constants it refers to. `lines` is
+//the debug line table: (offset, line) pairs sorted by offset, each one saying that the code from
+//that offset on came from that source line.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub lines: Vec<(u32, u32)>,
+}
+
+impl Chunk {
+    pub fn emit(&mut self, opcode: OpCode) {
+        self.code.push(opcode as u8);
+    }
+    pub fn emit_u8(&mut self, byte: u8) {
+        self.code.push(byte);
+    }
+    pub fn emit_u16(&mut self, value: u16) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn patch_u16(&mut self, at: usize, value: u16) {
+        self.code[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    pub fn read_u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes([self.code[at], self.code[at + 1]])
+    }
+
+    //everything emitted from here on came from `line`
+    pub fn mark_line(&mut self, line: u32) {
+        let offset = self.code.len() as u32;
+        match self.lines.last_mut() {
+            Some((_, last)) if *last == line => {},
+            Some((at, last)) if *at == offset => *last = line,
+            _ => self.lines.push((offset, line)),
+        }
+    }
+    //the source line of the instruction at `offset`, if the compiler knew one
+    pub fn line_at(&self, offset: usize) -> Option<u32> {
+        self.lines.iter().rev().find(|(at, _)| *at as usize <= offset).map(|(_, line)| *line)
+    }
+
+    //adds a constant (or finds the one that's already there) and hands back its index
+    pub fn add_constant(&mut self, value: Value) -> Result<u16, String> {
+        let same = |constant: &Value| match (constant, &value) {
+            //0.0 == -0.0, but they're not the same constant
+            (Value::FLOAT(a), Value::FLOAT(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        if let Some(index) = self.constants.iter().position(same) {
+            return Ok(index as u16);
+        }
+        if self.constants.len() >= u16::MAX as usize {
+            return Err("Too many constants in one rite!".to_string());
+        }
+        self.constants.push(value);
+        Ok(self.constants.len() as u16 - 1)
+    }
+}
+
+///FUNCTION section
+//one compiled rite. every module also gets one of these for its top level code, with is_init set.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub module: usize,
+    pub params: Vec<(String, TokenType)>,
+    pub return_type: TokenType,
+    pub is_init: bool,
+    pub event: Option<String>, //what it's the handler for, if it's one (see events.rs)
+    pub chunk: Chunk,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledModule {
+    pub id: String,
+    pub version: u32, //see save::module_version
+    pub globals: Vec<String>, //global slot -> name
+    pub rites: Vec<usize>,    //indices into Program::functions
+    pub init: usize,
+}
+
+///PROGRAM section
+//a PROGRAM is everything the vm needs: every module, every function. modules are in load order,
+//so running the inits front to back runs dependencies first.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub modules: Vec<CompiledModule>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    pub fn find_module(&self, id: &str) -> Option<usize> {
+        self.modules.iter().position(|module| module.id == id)
+    }
+    pub fn find_rite(&self, module: usize, name: &str) -> Option<usize> {
+        self.modules[module].rites.iter().copied().find(|index| self.functions[*index].name == name)
+    }
+    pub fn find_global(&self, module: usize, name: &str) -> Option<usize> {
+        self.modules[module].globals.iter().position(|global| global == name)
+    }
+}
@@ -0,0 +1,386 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::bytecode::*;
+use crate::lexer::TokenType;
+use crate::locale::placeholder_count;
+use crate::random::Dice;
+use crate::value::Value;
+
+///BYTECODE FILE section
+//this here is how a compiled PROGRAM gets written to disk and read back, so a game can ship
+//bytecode instead of sources. the layout, everything little endian:
+//
+//  magic      4 bytes   "VLBC"
+//  version    u16       FORMAT_VERSION, anything else is refused
+//  length     u32       size of the payload in bytes
+//  checksum   u32       crc32 of the payload
+//  payload    modules, then functions (see write_program)
+//
+//reading never trusts the file. past the header checks, every index an instruction uses is checked
+//against what it points into, so a bad file is an error instead of a vm that falls over later.
+
+pub const MAGIC: [u8; 4] = *b"VLBC";
+//bump this whenever the payload layout or the meaning of an opcode changes
+pub const FORMAT_VERSION: u16 = 4;
+const HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+
+//tags for the constants in a chunk
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_VOID: u8 = 3;
+
+//plain crc32 (the zip/png one). done bit by bit because files are small and it's loaded once.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+///WRITING
+//the writer and reader are shared with save files (see save.rs), which are built the same way
+
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn count(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.count(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+    pub(crate) fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+    fn type_t(&mut self, type_t: &TokenType) -> Result<(), String> {
+        let code = type_code(type_t).ok_or_else(|| format!("{:?} is not a type, it can't be written out!", type_t))?;
+        self.u8(code);
+        Ok(())
+    }
+    pub(crate) fn value(&mut self, value: &Value) {
+        match value {
+            Value::INT(int) => {
+                self.u8(TAG_INT);
+                self.u64(*int as u64);
+            },
+            Value::FLOAT(float) => {
+                self.u8(TAG_FLOAT);
+                self.u64(float.to_bits());
+            },
+            Value::STRING(string) => {
+                self.u8(TAG_STRING);
+                self.string(string);
+            },
+            Value::VOID => self.u8(TAG_VOID),
+        }
+    }
+}
+
+//turns a program into the bytes of a bytecode file
+pub fn write_program(program: &Program) -> Result<Vec<u8>, String> {
+    let mut payload = Writer { bytes: Vec::new() };
+
+    payload.count(program.modules.len());
+    for module in &program.modules {
+        payload.string(&module.id);
+        payload.u32(module.version);
+        payload.count(module.globals.len());
+        for global in &module.globals {
+            payload.string(global);
+        }
+        payload.count(module.rites.len());
+        for rite in &module.rites {
+            payload.count(*rite);
+        }
+        payload.count(module.init);
+    }
+
+    payload.count(program.functions.len());
+    for function in &program.functions {
+        payload.string(&function.name);
+        payload.count(function.module);
+        payload.count(function.params.len());
+        for (name, type_t) in &function.params {
+            payload.string(name);
+            payload.type_t(type_t)?;
+        }
+        payload.type_t(&function.return_type)?;
+        payload.u8(function.is_init as u8);
+        payload.string(function.event.as_deref().unwrap_or("")); //no event has an empty name
+        payload.bytes(&function.chunk.code);
+        payload.count(function.chunk.constants.len());
+        for constant in &function.chunk.constants {
+            payload.value(constant);
+        }
+        payload.count(function.chunk.lines.len());
+        for (offset, line) in &function.chunk.lines {
+            payload.u32(*offset);
+            payload.u32(*line);
+        }
+    }
+
+    let mut file = Vec::with_capacity(HEADER_SIZE + payload.bytes.len());
+    file.extend_from_slice(&MAGIC);
+    file.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    file.extend_from_slice(&(payload.bytes.len() as u32).to_le_bytes());
+    file.extend_from_slice(&crc32(&payload.bytes).to_le_bytes());
+    file.extend_from_slice(&payload.bytes);
+    Ok(file)
+}
+
+///READING
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+    pub(crate) what: &'static str, //what kind of file it is, for the errors
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(count).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("{} ends too early!", self.what))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+    //a count or an index. a count can't be bigger than what's left of the file, which keeps a
+    //corrupt length from asking for a gigantic allocation.
+    pub(crate) fn count(&mut self) -> Result<usize, String> {
+        let count = self.u32()? as usize;
+        if count > self.bytes.len() - self.pos {
+            return Err(format!("{} claims {} entries, but it isn't that big!", self.what, count));
+        }
+        Ok(count)
+    }
+    pub(crate) fn index(&mut self) -> Result<usize, String> {
+        Ok(self.u32()? as usize)
+    }
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let count = self.count()?;
+        self.take(count)
+    }
+    pub(crate) fn string(&mut self) -> Result<String, String> {
+        let what = self.what;
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| format!("{} has text that isn't utf-8!", what))
+    }
+    fn type_t(&mut self) -> Result<TokenType, String> {
+        let code = self.u8()?;
+        TYPES.get(code as usize).cloned().ok_or_else(|| format!("{} has {} where a type should be!", self.what, code))
+    }
+    pub(crate) fn value(&mut self) -> Result<Value, String> {
+        match self.u8()? {
+            TAG_INT => Ok(Value::INT(self.u64()? as i64)),
+            TAG_FLOAT => Ok(Value::FLOAT(f64::from_bits(self.u64()?))),
+            TAG_STRING => Ok(Value::STRING(self.string()?)),
+            TAG_VOID => Ok(Value::VOID),
+            tag => Err(format!("{} has a value tagged {}, which isn't a thing!", self.what, tag)),
+        }
+    }
+}
+
+//reads a bytecode file back into a program the vm can load, checking everything on the way
+pub fn read_program(bytes: &[u8]) -> Result<Program, String> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+        return Err("Not a Veilscript bytecode file!".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "Bytecode file is format version {}, but this build only reads version {}! Recompile the scripts.",
+            version, FORMAT_VERSION
+        ));
+    }
+    let length = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+    let checksum = u32::from_le_bytes([bytes[10], bytes[11], bytes[12], bytes[13]]);
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != length {
+        return Err(format!("Bytecode file should have {} bytes of code, but has {}!", length, payload.len()));
+    }
+    if crc32(payload) != checksum {
+        return Err("Bytecode file is corrupt! The checksum doesn't match.".to_string());
+    }
+
+    let mut reader = Reader { bytes: payload, pos: 0, what: "Bytecode file" };
+    let mut program = Program::default();
+
+    for _ in 0..reader.count()? {
+        let id = reader.string()?;
+        let version = reader.u32()?;
+        let globals = (0..reader.count()?).map(|_| reader.string()).collect::<Result<Vec<_>, _>>()?;
+        let rites = (0..reader.count()?).map(|_| reader.index()).collect::<Result<Vec<_>, _>>()?;
+        let init = reader.index()?;
+        program.modules.push(CompiledModule { id, version, globals, rites, init });
+    }
+
+    for _ in 0..reader.count()? {
+        let name = reader.string()?;
+        let module = reader.index()?;
+        let mut params = Vec::new();
+        for _ in 0..reader.count()? {
+            let param = reader.string()?;
+            params.push((param, reader.type_t()?));
+        }
+        let return_type = reader.type_t()?;
+        let is_init = reader.u8()? != 0;
+        let event = Some(reader.string()?).filter(|event| !event.is_empty());
+        let code = reader.bytes()?.to_vec();
+        let constants = (0..reader.count()?).map(|_| reader.value()).collect::<Result<Vec<_>, _>>()?;
+        let mut lines = Vec::new();
+        for _ in 0..reader.count()? {
+            lines.push((reader.u32()?, reader.u32()?));
+        }
+        program.functions.push(Function {
+            name, module, params, return_type, is_init, event,
+            chunk: Chunk { code, constants, lines },
+        });
+    }
+
+    if reader.pos != payload.len() {
+        return Err("Bytecode file has junk after the last function!".to_string());
+    }
+    validate_program(&program)?;
+    Ok(program)
+}
+
+///VALIDATION
+
+//every cross reference in the program has to land somewhere real, and every chunk has to decode
+//into instructions whose operands make sense
+pub fn validate_program(program: &Program) -> Result<(), String> {
+    for (index, module) in program.modules.iter().enumerate() {
+        let init = program.functions.get(module.init)
+            .ok_or_else(|| format!("Module {} starts at function {}, which doesn't exist!", module.id, module.init))?;
+        if !init.is_init || init.module != index {
+            return Err(format!("Module {} starts at function {}, which isn't its top level code!", module.id, module.init));
+        }
+        for rite in &module.rites {
+            match program.functions.get(*rite) {
+                Some(function) if !function.is_init && function.module == index => {},
+                _ => return Err(format!("Module {} lists function {} as one of its rites, but it isn't!", module.id, rite)),
+            }
+        }
+    }
+    for function in &program.functions {
+        if function.module >= program.modules.len() {
+            return Err(format!("Function {} belongs to module {}, which doesn't exist!", function.name, function.module));
+        }
+        validate_chunk(program, function).map_err(|err| format!("In function {}: {}", function.name, err))?;
+    }
+    Ok(())
+}
+
+fn validate_chunk(program: &Program, function: &Function) -> Result<(), String> {
+    let chunk = &function.chunk;
+    let constant = |index: u16| -> Result<&Value, String> {
+        chunk.constants.get(index as usize).ok_or_else(|| format!("Constant {} doesn't exist!", index))
+    };
+    let name_constant = |index: u16| -> Result<(), String> {
+        match constant(index)? {
+            Value::STRING(_) => Ok(()),
+            _ => Err(format!("Constant {} should be a name!", index)),
+        }
+    };
+    let global = |module: usize, slot: u16| -> Result<(), String> {
+        match program.modules.get(module) {
+            Some(module) if (slot as usize) < module.globals.len() => Ok(()),
+            _ => Err(format!("Global slot {} of module {} doesn't exist!", slot, module)),
+        }
+    };
+
+    //first find where every instruction starts, so jumps can be checked against them
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    let mut last = None;
+    while offset < chunk.code.len() {
+        starts.push(offset);
+        let (opcode, operands, next) = decode_at(&chunk.code, offset)?;
+        last = Some(opcode);
+
+        match opcode {
+            OpCode::CONSTANT => { constant(operands.first)?; },
+            OpCode::GET_GLOBAL | OpCode::SET_GLOBAL => global(function.module, operands.first)?,
+            OpCode::GET_MODULE_GLOBAL => global(operands.first as usize, operands.second)?,
+            OpCode::CONFORM => {
+                if operands.byte as usize >= TYPES.len() {
+                    return Err(format!("{} is not a type!", operands.byte));
+                }
+                name_constant(operands.first)?;
+            },
+            OpCode::UNARY if operands.byte as usize >= MONOPS.len() => {
+                return Err(format!("{} is not a unary operator!", operands.byte));
+            },
+            OpCode::BINARY if operands.byte as usize >= BINOPS.len() => {
+                return Err(format!("{} is not a binary operator!", operands.byte));
+            },
+            OpCode::CALL => match program.functions.get(operands.first as usize) {
+                Some(callee) if !callee.is_init => {},
+                _ => return Err(format!("Calls function {}, which isn't a rite!", operands.first)),
+            },
+            OpCode::CALL_NATIVE | OpCode::FIELD => name_constant(operands.first)?,
+            OpCode::ROLL => match constant(operands.first)? {
+                Value::STRING(dice) => { Dice::parse(dice)?; },
+                _ => return Err(format!("Constant {} should be dice!", operands.first)),
+            },
+            OpCode::TEXT => {
+                name_constant(operands.first)?;
+                match constant(operands.second)? {
+                    Value::STRING(line) => { placeholder_count(line)?; },
+                    _ => return Err(format!("Constant {} should be a line of dialogue!", operands.second)),
+                }
+            },
+            _ => {},
+        }
+        offset = next;
+    }
+    //running off the end of a chunk would be bad, so the last thing in it has to leave
+    if last != Some(OpCode::RETURN) {
+        return Err("Doesn't end with a RETURN!".to_string());
+    }
+
+    for start in &starts {
+        let (opcode, operands, _) = decode_at(&chunk.code, *start)?;
+        let target = match opcode {
+            OpCode::JUMP | OpCode::JUMP_IF_NOT => operands.first,
+            OpCode::BRANCH => operands.second,
+            _ => continue,
+        };
+        if !starts.contains(&(target as usize)) {
+            return Err(format!("Jumps to {}, which isn't the start of an instruction!", target));
+        }
+    }
+    if chunk.lines.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+        return Err("Line table is out of order!".to_string());
+    }
+    Ok(())
+}
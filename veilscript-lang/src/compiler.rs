@@ -0,0 +1,587 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::*;
+use crate::bytecode::*;
+use crate::dialogue::{find_node, CHOOSE, SAY};
+use crate::module::Module;
+use crate::random::unrolled_dice;
+use crate::runtime::{global_names, rite_declarations, validate_module};
+use crate::save::module_version;
+use crate::value::{unescape_string_literal, Value};
+
+///COMPILER section
+//turns the AST of whole modules into bytecode for vm.rs. Name resolution happens here, once: a
+//name is a local if some enclosing scope of the rite declared it, a global of the module if the
+//module assigns it at the top level, and otherwise it turns into a brand new local. Same rules the
+//interpreter follows at runtime, just decided ahead of time.
+
+//compiles `modules` (in load order) and adds them to `program`. on error the program is untouched.
+pub fn compile(program: &mut Program, modules: &[Module]) -> Result<(), String> {
+    let mut staged = program.clone();
+    let first = staged.modules.len();
+
+    //first pass: every module gets its global slots and every rite gets a function index, so
+    //calls can point at rites that haven't been compiled yet
+    for (offset, module) in modules.iter().enumerate() {
+        validate_module(&module.stmts).map_err(|err| format!("In module {}: {}", module.id, err))?;
+        let index = first + offset;
+        let mut rites = Vec::new();
+        for decl in rite_declarations(&module.stmts) {
+            rites.push(staged.functions.len());
+            staged.functions.push(Function {
+                name: decl.ident.name.clone(),
+                module: index,
+                params: decl.params.iter().map(|param| (param.ident.name.clone(), param.type_t.clone())).collect(),
+                return_type: decl.type_t.clone(),
+                is_init: false,
+                event: decl.event_name(),
+                chunk: Chunk::default(),
+            });
+        }
+        let init = staged.functions.len();
+        staged.functions.push(Function {
+            name: format!("<{}>", module.id),
+            module: index,
+            params: Vec::new(),
+            return_type: crate::lexer::TokenType::TYPE_VOID,
+            is_init: true,
+            event: None,
+            chunk: Chunk::default(),
+        });
+        staged.modules.push(CompiledModule {
+            id: module.id.clone(),
+            version: module_version(&module.stmts),
+            globals: global_names(&module.stmts),
+            rites,
+            init,
+        });
+    }
+
+    //second pass: the actual code
+    for (offset, module) in modules.iter().enumerate() {
+        compile_code(&mut staged, module, first + offset)?;
+    }
+
+    *program = staged;
+    Ok(())
+}
+
+//compiles a module that's in `program` already again, for a reload (see reload.rs). everything
+//that points into the module from outside keeps working: rites that are still there keep their
+//function index and global slots are only ever added. rites that are gone stay in the program,
+//they're just not the module's rites any more. on error the program is untouched.
+pub fn recompile(program: &mut Program, index: usize, module: &Module) -> Result<(), String> {
+    validate_module(&module.stmts).map_err(|err| format!("In module {}: {}", module.id, err))?;
+    let mut staged = program.clone();
+
+    let mut rites = Vec::new();
+    for decl in rite_declarations(&module.stmts) {
+        let params = decl.params.iter().map(|param| (param.ident.name.clone(), param.type_t.clone())).collect();
+        match staged.find_rite(index, &decl.ident.name) {
+            Some(function) => {
+                let existing = &mut staged.functions[function];
+                existing.params = params;
+                existing.return_type = decl.type_t.clone();
+                existing.event = decl.event_name();
+                rites.push(function);
+            },
+            None => {
+                rites.push(staged.functions.len());
+                staged.functions.push(Function {
+                    name: decl.ident.name.clone(),
+                    module: index,
+                    params,
+                    return_type: decl.type_t.clone(),
+                    is_init: false,
+                    event: decl.event_name(),
+                    chunk: Chunk::default(),
+                });
+            },
+        }
+    }
+    let compiled = &mut staged.modules[index];
+    compiled.rites = rites;
+    compiled.version = module_version(&module.stmts);
+    for name in global_names(&module.stmts) {
+        if !compiled.globals.contains(&name) {
+            compiled.globals.push(name);
+        }
+    }
+
+    compile_code(&mut staged, module, index)?;
+    *program = staged;
+    Ok(())
+}
+
+//the code of every rite of a module and of its top level, into functions that are there already
+fn compile_code(staged: &mut Program, module: &Module, index: usize) -> Result<(), String> {
+    let wrap = |err: String| format!("In module {}: {}", module.id, err);
+
+    for decl in rite_declarations(&module.stmts) {
+        let function = staged.find_rite(index, &decl.ident.name).ok_or_else(|| wrap("lost a rite".to_string()))?;
+        let mut compiler = FnCompiler::new(staged, module, index);
+        if decl.ident.span.line > 0 {
+            compiler.chunk.mark_line(decl.ident.span.line);
+        }
+        compiler.begin_scope();
+        for param in &decl.params {
+            compiler.declare_param(&param.ident.name);
+        }
+        compiler.compile_stmts(&decl.body.stmts).map_err(wrap)?;
+        compiler.chunk.emit(OpCode::VOID); //falling off the end gives back void
+        compiler.chunk.emit(OpCode::RETURN);
+        let chunk = compiler.chunk;
+        staged.functions[function].chunk = chunk;
+    }
+
+    let init = staged.modules[index].init;
+    let mut compiler = FnCompiler::new(staged, module, index);
+    compiler.compile_stmts(&module.stmts).map_err(wrap)?;
+    compiler.chunk.emit(OpCode::VOID);
+    compiler.chunk.emit(OpCode::RETURN);
+    let chunk = compiler.chunk;
+    staged.functions[init].chunk = chunk;
+    Ok(())
+}
+
+struct Local {
+    name: String,
+    slot: u16,
+}
+
+//a scope expression being compiled. a `ret` in there jumps to its end instead of leaving the rite.
+struct BlockExpr {
+    height: usize,
+    exits: Vec<usize>, //JUMP operands to patch once we know where the block ends
+}
+
+struct FnCompiler<'p> {
+    program: &'p mut Program,
+    module: &'p Module,
+    module_index: usize,
+    module_globals: Vec<String>, //the names the module assigns at its top level
+    chunk: Chunk,
+    locals: Vec<Local>,
+    scopes: Vec<(usize, usize)>, //(locals, height) when each open scope started
+    blocks: Vec<BlockExpr>,
+    height: usize, //how many values this function has on the stack at this point
+}
+
+impl<'p> FnCompiler<'p> {
+    fn new(program: &'p mut Program, module: &'p Module, module_index: usize) -> Self {
+        FnCompiler {
+            program, module, module_index,
+            module_globals: global_names(&module.stmts),
+            chunk: Chunk::default(),
+            locals: Vec::new(),
+            scopes: Vec::new(),
+            blocks: Vec::new(),
+            height: 0,
+        }
+    }
+
+    ///EMITTING
+    //every emit keeps track of the stack height, which is what local slots are made of
+
+    fn emit(&mut self, opcode: OpCode, pushes: usize, pops: usize) {
+        self.chunk.emit(opcode);
+        self.height = self.height + pushes - pops;
+    }
+
+    fn emit_u16_op(&mut self, opcode: OpCode, operand: usize, pushes: usize, pops: usize) -> Result<(), String> {
+        let operand = u16::try_from(operand).map_err(|_| format!("Too many things for one {:?}!", opcode))?;
+        self.emit(opcode, pushes, pops);
+        self.chunk.emit_u16(operand);
+        Ok(())
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<(), String> {
+        let constant = self.chunk.add_constant(value)?;
+        self.emit_u16_op(OpCode::CONSTANT, constant as usize, 1, 0)
+    }
+
+    fn name_constant(&mut self, name: &str) -> Result<u16, String> {
+        self.chunk.add_constant(Value::STRING(name.to_owned()))
+    }
+
+    fn emit_jump(&mut self) -> usize {
+        self.chunk.emit(OpCode::JUMP);
+        let at = self.chunk.code.len();
+        self.chunk.emit_u16(0);
+        at
+    }
+
+    //where the next instruction will go, as a jump target
+    fn here(&self) -> Result<u16, String> {
+        u16::try_from(self.chunk.code.len()).map_err(|_| "Rite is too long!".to_string())
+    }
+
+    ///SCOPES AND NAMES
+
+    fn begin_scope(&mut self) {
+        self.scopes.push((self.locals.len(), self.height));
+    }
+
+    //drops every local of the innermost scope off the stack
+    fn end_scope(&mut self) -> Result<(), String> {
+        let (locals, height) = self.scopes.pop().unwrap_or((0, 0));
+        self.locals.truncate(locals);
+        let count = self.height - height;
+        if count > 0 {
+            self.emit_u16_op(OpCode::POP_N, count, 0, count)?;
+        }
+        Ok(())
+    }
+
+    //the value on top of the stack becomes a local
+    fn declare_local(&mut self, name: &str) {
+        self.locals.push(Local { name: name.to_owned(), slot: (self.height - 1) as u16 });
+    }
+
+    //params are already on the stack when the rite starts, the caller put them there
+    fn declare_param(&mut self, name: &str) {
+        self.height += 1;
+        self.declare_local(name);
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u16> {
+        self.locals.iter().rev().find(|local| local.name == name).map(|local| local.slot)
+    }
+
+    //global slots are made on demand, so reading a name nobody defines still has a slot to fail on
+    fn global_slot(&mut self, module: usize, name: &str) -> usize {
+        match self.program.find_global(module, name) {
+            Some(slot) => slot,
+            None => {
+                self.program.modules[module].globals.push(name.to_owned());
+                self.program.modules[module].globals.len() - 1
+            },
+        }
+    }
+
+    fn target_module(&self, ident: &Ident) -> Result<usize, String> {
+        self.module.resolve_namespace(&ident.namespace)
+            .and_then(|id| self.program.find_module(id))
+            .ok_or_else(|| crate::runtime::not_defined(&ident.full_name()))
+    }
+
+    ///STATEMENTS
+
+    fn compile_stmts(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        for stmt in stmts {
+            self.compile_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        if let Some(line) = stmt.line() {
+            self.chunk.mark_line(line);
+        }
+        match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) => self.compile_assignment(assignment),
+            Stmt::STATEMENT_RETURN(ret) => {
+                let height = self.height;
+                self.compile_expr(&ret.expr)?;
+                match self.blocks.last() {
+                    //inside a scope expression: becomes the value of the scope
+                    Some(block) => {
+                        let count = self.height - 1 - block.height;
+                        self.emit_u16_op(OpCode::SLIDE, count, 0, count)?;
+                        let exit = self.emit_jump();
+                        if let Some(block) = self.blocks.last_mut() {
+                            block.exits.push(exit);
+                        }
+                    },
+                    None => self.emit(OpCode::RETURN, 0, 1),
+                }
+                //anything after a `ret` is unreachable, but it still gets compiled as if the
+                //`ret` wasn't there so the bookkeeping of the enclosing scopes stays right
+                self.height = height;
+                Ok(())
+            },
+            Stmt::STATEMENT_FUNCTION_CALL(fncall) => {
+                self.compile_call(&fncall.ident, None, &fncall.args)?;
+                self.emit(OpCode::POP, 0, 1);
+                Ok(())
+            },
+            Stmt::SCOPE(scope) => {
+                self.begin_scope();
+                self.compile_stmts(&scope.stmts)?;
+                self.end_scope()
+            },
+            Stmt::STATEMENT_EXPORT(inner) => self.compile_stmt(inner),
+            Stmt::STATEMENT_YIELD(yield_stmt) => {
+                self.compile_yield(yield_stmt)?;
+                self.emit(OpCode::POP, 0, 1);
+                Ok(())
+            },
+            Stmt::STATEMENT_DIALOGUE(dialogue) => self.compile_dialogue(dialogue),
+            //rites get their own functions, imports were handled by the loader
+            Stmt::STATEMENT_FUNCTION_DECLARATION(_) | Stmt::STATEMENT_IMPORT(_)
+                | Stmt::STATEMENT_ZERO_EFFECT(_) => Ok(()),
+        }
+    }
+
+    fn compile_assignment(&mut self, assignment: &Assignment) -> Result<(), String> {
+        let name = &assignment.ident.name;
+        self.compile_expr(&assignment.expr)?;
+
+        if let Some(type_t) = &assignment.type_t {
+            let code = type_code(type_t).ok_or_else(|| format!("{:?} is not a type!", type_t))?;
+            let constant = self.name_constant(name)?;
+            self.emit(OpCode::CONFORM, 0, 0);
+            self.chunk.emit_u8(code);
+            self.chunk.emit_u16(constant);
+            if self.scopes.is_empty() {
+                let slot = self.global_slot(self.module_index, name);
+                return self.emit_u16_op(OpCode::SET_GLOBAL, slot, 0, 1);
+            }
+            self.declare_local(name);
+            return Ok(());
+        }
+
+        if let Some(slot) = self.resolve_local(name) {
+            return self.emit_u16_op(OpCode::SET_LOCAL, slot as usize, 0, 1);
+        }
+        if self.module_globals.contains(name) || self.scopes.is_empty() {
+            let slot = self.global_slot(self.module_index, name);
+            return self.emit_u16_op(OpCode::SET_GLOBAL, slot, 0, 1);
+        }
+        self.declare_local(name);
+        Ok(())
+    }
+
+    ///EXPRESSIONS
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::ATOM(atom) => self.compile_atom(atom),
+            Expr::GROUPED_EXPR(inner) => self.compile_expr(inner),
+            Expr::BINARY_EXPR { left, opcode, right } => {
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                self.emit(OpCode::BINARY, 0, 1);
+                self.chunk.emit_u8(binop_code(*opcode));
+                Ok(())
+            },
+            Expr::UNARY_EXPR { opcode, expr } => {
+                self.compile_expr(expr)?;
+                self.emit(OpCode::UNARY, 0, 0);
+                self.chunk.emit_u8(monop_code(*opcode));
+                Ok(())
+            },
+            Expr::SCOPE(scope) => self.compile_scope_expr(scope),
+            Expr::FUNCTION_CALL(fncall) => self.compile_call(&fncall.ident, None, &fncall.args),
+            Expr::METHOD_CALL(call) => self.compile_call(&call.call.ident, Some(&call.base), &call.call.args),
+            Expr::FIELD_ACCESS(access) => {
+                self.compile_expr(&access.base)?;
+                let constant = self.name_constant(&access.access.name)?;
+                self.emit_u16_op(OpCode::FIELD, constant as usize, 0, 0)
+            },
+            Expr::YIELD(yield_expr) => self.compile_yield(yield_expr),
+        }
+    }
+
+    fn compile_atom(&mut self, atom: &Atom) -> Result<(), String> {
+        match atom {
+            Atom::LITERAL_INT(val) => self.emit_constant(Value::INT(*val)),
+            Atom::LITERAL_FLOAT(val) => self.emit_constant(Value::FLOAT(*val)),
+            Atom::LITERAL_DICE(dice) => {
+                let constant = self.name_constant(&dice.to_string())?;
+                self.emit_u16_op(OpCode::ROLL, constant as usize, 1, 0)
+            },
+            Atom::LITERAL_STRING(val) => self.emit_constant(Value::STRING(unescape_string_literal(val))),
+            Atom::IDENTIFIER(ident) if ident.is_namespaced() => {
+                let target = self.target_module(ident)?;
+                let slot = self.global_slot(target, &ident.name);
+                let target = u16::try_from(target).map_err(|_| "Too many modules!".to_string())?;
+                self.emit_u16_op(OpCode::GET_MODULE_GLOBAL, target as usize, 1, 0)?;
+                self.chunk.emit_u16(slot as u16);
+                Ok(())
+            },
+            Atom::IDENTIFIER(ident) => match self.resolve_local(&ident.name) {
+                Some(slot) => self.emit_u16_op(OpCode::GET_LOCAL, slot as usize, 1, 0),
+                None => {
+                    let slot = self.global_slot(self.module_index, &ident.name);
+                    self.emit_u16_op(OpCode::GET_GLOBAL, slot, 1, 0)
+                },
+            },
+        }
+    }
+
+    //a bare yield hands over void. either way exactly one value goes out and one comes back.
+    fn compile_yield(&mut self, yield_expr: &Yield) -> Result<(), String> {
+        match &yield_expr.expr {
+            Some(expr) => self.compile_expr(expr)?,
+            None => self.emit(OpCode::VOID, 1, 0),
+        }
+        self.emit(OpCode::YIELD, 0, 0);
+        Ok(())
+    }
+
+    //thing.rite(args) compiles exactly like rite(thing, args)
+    fn compile_call(&mut self, ident: &Ident, base: Option<&Expr>, args: &[Expr]) -> Result<(), String> {
+        for arg in base.into_iter().chain(args) {
+            match unrolled_dice(ident, arg) {
+                Some(dice) if self.program.find_rite(self.module_index, &ident.name).is_none() => {
+                    self.emit_constant(Value::STRING(dice.to_string()))?;
+                },
+                _ => self.compile_expr(arg)?,
+            }
+        }
+        self.emit_call(ident, args.len() + usize::from(base.is_some()))
+    }
+
+    //calls whatever `ident` names with the top `argc` values as its arguments
+    fn emit_call(&mut self, ident: &Ident, argc: usize) -> Result<(), String> {
+        let argc_byte = u8::try_from(argc).map_err(|_| format!("Too many arguments to {}!", ident.full_name()))?;
+
+        let target = match ident.is_namespaced() {
+            true => self.target_module(ident)?,
+            false => self.module_index,
+        };
+        match self.program.find_rite(target, &ident.name) {
+            Some(function) => self.emit_u16_op(OpCode::CALL, function, 1, argc)?,
+            //not a rite of the module, so it had better be a native by the time it runs
+            None => {
+                let constant = self.name_constant(&ident.full_name())?;
+                self.emit_u16_op(OpCode::CALL_NATIVE, constant as usize, 1, argc)?;
+            },
+        }
+        self.chunk.emit_u8(argc_byte);
+        Ok(())
+    }
+
+    fn compile_scope_expr(&mut self, scope: &Scope) -> Result<(), String> {
+        self.blocks.push(BlockExpr { height: self.height, exits: Vec::new() });
+        self.begin_scope();
+        self.compile_stmts(&scope.stmts)?;
+
+        //no `ret` reached: the scope is worth void
+        self.emit(OpCode::VOID, 1, 0);
+        let (locals, height) = self.scopes.pop().unwrap_or((0, 0));
+        self.locals.truncate(locals);
+        let count = self.height - 1 - height;
+        self.emit_u16_op(OpCode::SLIDE, count, 0, count)?;
+
+        let block = self.blocks.pop().unwrap_or(BlockExpr { height, exits: Vec::new() });
+        let end = self.here()?;
+        for exit in block.exits {
+            self.chunk.patch_u16(exit, end);
+        }
+        Ok(())
+    }
+
+    ///DIALOGUE
+    //each line of a node is a call to say and a yield, then the node is left through its choices,
+    //its jump or the end of the dialogue. nodes are laid out one after the other and only find out
+    //where the others start once they're all there. see dialogue.rs for what the game sees.
+
+    fn compile_dialogue(&mut self, dialogue: &Dialogue) -> Result<(), String> {
+        let height = self.height;
+        let mut starts = Vec::new();
+        let mut to_nodes: Vec<(usize, &str)> = Vec::new(); //operands to patch with where a node starts
+        let mut to_end = Vec::new();
+
+        for node in &dialogue.nodes {
+            starts.push(self.here()?);
+            for line in &node.lines {
+                if line.span().line > 0 {
+                    self.chunk.mark_line(line.span().line);
+                }
+                match &line.speaker {
+                    Some(speaker) => self.emit_constant(Value::STRING(speaker.name.clone()))?,
+                    None => self.emit(OpCode::VOID, 1, 0),
+                }
+                self.compile_text(&node.ident.name, &line.text)?;
+                self.emit_call(&Ident::new(SAY), 2)?;
+                self.emit(OpCode::YIELD, 0, 0);
+                self.emit(OpCode::POP, 0, 1);
+            }
+            match &node.exit {
+                NodeExit::END => to_end.push(self.emit_jump()),
+                NodeExit::JUMP(target) => to_nodes.push((self.emit_jump(), &target.name)),
+                //every choice gets a slot for its condition under the call to choose, so PICK can
+                //tell whether the choice the coroutine wakes up with was on offer. the slots are
+                //filled in as each choice comes up, condition then text, like the interpreter does
+                NodeExit::CHOICES(choices) => {
+                    let conditions = self.height;
+                    for choice in choices {
+                        match &choice.condition {
+                            Some(_) => self.emit(OpCode::VOID, 1, 0),
+                            None => self.emit_constant(Value::INT(1))?,
+                        }
+                    }
+                    for (index, choice) in choices.iter().enumerate() {
+                        if choice.span.line > 0 {
+                            self.chunk.mark_line(choice.span.line);
+                        }
+                        self.compile_choice(&node.ident.name, choice, conditions + index)?;
+                    }
+                    self.emit_call(&Ident::new(CHOOSE), choices.len())?;
+                    self.emit(OpCode::YIELD, 0, 0);
+                    self.emit_u16_op(OpCode::PICK, choices.len(), 1, choices.len() + 1)?;
+                    for (index, choice) in choices.iter().enumerate() {
+                        self.emit_u16_op(OpCode::BRANCH, index, 0, 0)?;
+                        to_nodes.push((self.chunk.code.len(), &choice.target.name));
+                        self.chunk.emit_u16(0);
+                    }
+                },
+            }
+            self.height = height;
+        }
+
+        let end = self.here()?;
+        for at in to_end {
+            self.chunk.patch_u16(at, end);
+        }
+        for (at, target) in to_nodes {
+            let node = find_node(dialogue, target).ok_or_else(|| crate::runtime::not_defined(target))?;
+            self.chunk.patch_u16(at, starts[node]);
+        }
+        Ok(())
+    }
+
+    //a choice that isn't on offer is void instead, so the indices of the others stay put.
+    //`slot` is the local its condition is worked out into, if it has one.
+    fn compile_choice(&mut self, node: &str, choice: &Choice, slot: usize) -> Result<(), String> {
+        let Some(condition) = &choice.condition else {
+            return self.compile_text(node, &choice.text);
+        };
+        self.compile_expr(condition)?;
+        self.emit_u16_op(OpCode::SET_LOCAL, slot, 0, 1)?;
+        self.emit_u16_op(OpCode::GET_LOCAL, slot, 1, 0)?;
+        self.emit(OpCode::JUMP_IF_NOT, 0, 1);
+        let skip = self.chunk.code.len();
+        self.chunk.emit_u16(0);
+        self.compile_text(node, &choice.text)?;
+        let over = self.emit_jump();
+
+        let here = self.here()?;
+        self.chunk.patch_u16(skip, here);
+        self.height -= 1; //only one of the two ever runs
+        self.emit(OpCode::VOID, 1, 0);
+        let here = self.here()?;
+        self.chunk.patch_u16(over, here);
+        Ok(())
+    }
+
+    //text without any `{expr}` in it is a plain constant, anything else gets glued together
+    //the line is put together when it's said, so it can come out translated (see locale.rs)
+    fn compile_text(&mut self, node: &str, text: &Text) -> Result<(), String> {
+        let mut count = 0;
+        for part in &text.parts {
+            if let TextPart::EXPR(expr) = part {
+                self.compile_expr(expr)?;
+                count += 1;
+            }
+        }
+        let node = self.name_constant(node)?;
+        let line = self.chunk.add_constant(Value::STRING(text.literal.clone()))?;
+        self.emit(OpCode::TEXT, 1, count);
+        self.chunk.emit_u16(node);
+        self.chunk.emit_u16(line);
+        Ok(())
+    }
+}
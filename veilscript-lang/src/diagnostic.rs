@@ -0,0 +1,55 @@
+#![allow(non_camel_case_types)]
+
+use crate::lexer::Span;
+
+///DIAGNOSTIC section
+//a DIAGNOSTIC is something a static pass (the type checker, and whatever else reads scripts without
+//running them) has to say about a script, pinned to the place it's about. errors mean the script
+//is broken, warnings mean it probably isn't what was meant.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    ERROR,
+    WARNING,
+}
+
+impl Severity {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            Severity::ERROR => "error".to_string(),
+            Severity::WARNING => "warning".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(file: &str, span: Span, message: String) -> Self {
+        Diagnostic { severity: Severity::ERROR, file: file.to_owned(), span, message }
+    }
+    pub fn warning(file: &str, span: Span, message: String) -> Self {
+        Diagnostic { severity: Severity::WARNING, file: file.to_owned(), span, message }
+    }
+
+    //`quests/intro.veil:12: error: Can't put rune into gold: int!`, the way compilers do it so
+    //editors and CI logs can jump to it
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self.span.line {
+            0 => format!("{}: {}: {}", self.file, self.severity.to_string(), self.message),
+            line => format!("{}:{}: {}: {}", self.file, line, self.severity.to_string(), self.message),
+        }
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::ERROR)
+}
@@ -0,0 +1,174 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::ast::*;
+use crate::resolve::did_you_mean;
+use crate::value::unescape_string_literal;
+
+///DIALOGUE section
+//this here is DIALOGUE. writers want to write conversations, not rites full of say() calls and
+//yields, so a rite can hold a dialogue block:
+//
+//  rite meet_guard(name: rune, gold: int) {
+//      dialogue {
+//          node start {
+//              Guard: "Halt! Who goes there?";
+//              "The guard squints at {name}.";
+//              choice "A friend." -> friend;
+//              choice "Here, {gold} gold." if gold -> bribe;
+//          }
+//          node friend {
+//              Guard: "Never heard of you.";
+//              -> start;
+//          }
+//          node bribe {
+//              Guard: "Move along then.";
+//          }
+//      }
+//  }
+//
+//a NODE is some LINES, each either a speaker (any name) and a rune or just a rune for narration,
+//and then one of: CHOICES, a jump to another node (`-> start;`), or nothing, which ends the
+//dialogue so the rite carries on after the block. it starts at the first node. `{expr}` in a line
+//or a choice is replaced by whatever expr turns out to be, and `{{` and `}}` are braces of their
+//own. runes can't go inside the braces, their quotes would end the line.
+//
+//dialogue runs on coroutines (see runtime.rs) and the game does the talking, through two rites
+//it registers as natives (or the script declares itself):
+//  say(speaker, text)       for every line. speaker is a rune, or void for narration. whatever say
+//                           gives back is yielded, and whatever the coroutine is resumed with after
+//                           that is thrown away.
+//  choose(text, text, ...)  once per node with choices, all of them in order. a choice whose
+//                           condition doesn't hold (see Value::is_truthy) is void instead of its
+//                           text, so the indices stay put. whatever choose gives back is yielded,
+//                           and the coroutine has to be resumed with the index (an int, from 0) of
+//                           the choice that was picked. anything else is an error, and so is a
+//                           choice that was void.
+//
+//every line and choice can be translated, see locale.rs.
+
+pub const SAY: &str = "say";
+pub const CHOOSE: &str = "choose";
+
+//one piece of a line as written: text, or where the source of an `{expr}` sits in the literal
+pub enum Piece {
+    TEXT(String), //unescaped, with `{{` and `}}` already turned into braces
+    EXPR(Range<usize>),
+}
+
+//splits a rune literal (quotes and all) into its text and its `{expr}`s
+pub fn split_text(literal: &str) -> Result<Vec<Piece>, String> {
+    let inner = literal.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).unwrap_or(literal);
+    let offset = usize::from(inner.len() < literal.len()); //the opening quote
+    let mut pieces = Vec::new();
+    let mut raw = String::new();
+    let mut chars = inner.char_indices().peekable();
+
+    while let Some((at, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        match (c, next) {
+            //escapes are left for unescape_string_literal, `\{` is no brace of its own
+            ('\\', Some(escaped)) => {
+                raw.push(c);
+                raw.push(escaped);
+                chars.next();
+            },
+            ('{', Some('{')) | ('}', Some('}')) => {
+                raw.push(c);
+                chars.next();
+            },
+            ('{', _) => {
+                let close = closing_brace(&inner[at + 1..])
+                    .ok_or_else(|| "A { in this line is never closed! Write {{ for a brace of its own.".to_string())?;
+                if !raw.is_empty() {
+                    pieces.push(Piece::TEXT(unescape_string_literal(&format!("\"{}\"", raw))));
+                    raw.clear();
+                }
+                let start = offset + at + 1;
+                pieces.push(Piece::EXPR(start..start + close));
+                while chars.peek().is_some_and(|(next_at, _)| *next_at <= at + close + 1) {
+                    chars.next();
+                }
+            },
+            ('}', _) => return Err("A } in this line was never opened! Write }} for a brace of its own.".to_string()),
+            _ => raw.push(c),
+        }
+    }
+    if !raw.is_empty() {
+        pieces.push(Piece::TEXT(unescape_string_literal(&format!("\"{}\"", raw))));
+    }
+    Ok(pieces)
+}
+
+//how far into `rest` the `}` closing an `{` just before it is. scope expressions have braces of
+//their own, so they're counted.
+fn closing_brace(rest: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (at, c) in rest.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(at),
+            '}' => depth -= 1,
+            _ => {},
+        }
+    }
+    None
+}
+
+//the nodes a node can go to next
+pub fn targets(node: &DialogueNode) -> Vec<&Ident> {
+    match &node.exit {
+        NodeExit::END => Vec::new(),
+        NodeExit::JUMP(target) => vec![target],
+        NodeExit::CHOICES(choices) => choices.iter().map(|choice| &choice.target).collect(),
+    }
+}
+
+pub fn find_node(dialogue: &Dialogue, name: &str) -> Option<usize> {
+    dialogue.nodes.iter().position(|node| node.ident.name == name)
+}
+
+//the rules a dialogue follows on top of its expressions being fine: node names are unique, every
+//jump and choice goes to a node that exists, and no nodes just jump around in a circle without
+//ever saying anything (that would hang the game without a single yield).
+pub fn check_dialogue(dialogue: &Dialogue) -> Result<(), String> {
+    if dialogue.nodes.is_empty() {
+        return Err("A dialogue needs at least one node!".to_string());
+    }
+    let mut names: HashSet<&str> = HashSet::new();
+    for node in &dialogue.nodes {
+        if !names.insert(&node.ident.name) {
+            return Err(format!("Node {} is declared twice in one dialogue!", node.ident.name));
+        }
+    }
+    for node in &dialogue.nodes {
+        for target in targets(node) {
+            if names.contains(target.name.as_str()) {
+                continue;
+            }
+            let message = format!("Node {} leads to {}, but there's no node named that!", node.ident.name, target.name);
+            return Err(match did_you_mean(&target.name, names.iter().copied()) {
+                Some(suggestion) => format!("{} Did you mean {}?", message, suggestion),
+                None => message,
+            });
+        }
+    }
+
+    for start in 0..dialogue.nodes.len() {
+        let mut path = vec![start];
+        let mut at = &dialogue.nodes[start];
+        while let (true, NodeExit::JUMP(target)) = (at.lines.is_empty(), &at.exit) {
+            let next = find_node(dialogue, &target.name).unwrap_or(start);
+            let looped = path.iter().position(|node| *node == next);
+            path.push(next);
+            if let Some(from) = looped {
+                let names: Vec<&str> = path[from..].iter().map(|node| dialogue.nodes[*node].ident.name.as_str()).collect();
+                return Err(format!("Nodes {} only jump in a circle and never say anything!", names.join(" -> ")));
+            }
+            at = &dialogue.nodes[next];
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::bytecode::*;
+use crate::value::{type_name_of, Value};
+
+///DISASSEMBLER section
+//this here turns bytecode back into something a human can read. one line per instruction: the
+//offset, the source line (or `|` when it's the same as the line above), the opcode, its raw
+//operands, and after a `;` what those operands actually point at.
+//
+//  == rite twice(x: int) -> int [module lib] ==
+//  constants:
+//      0  int 2
+//  0000     2  GET_LOCAL          0
+//  0003     |  CONSTANT           0        ; 2
+
+//a constant the way it would look in a script
+fn show_constant(value: &Value) -> String {
+    match value {
+        Value::STRING(string) => format!("{:?}", string),
+        other => other.to_string(),
+    }
+}
+
+fn name_of(chunk: &Chunk, index: u16) -> String {
+    match chunk.constants.get(index as usize) {
+        Some(Value::STRING(name)) => name.clone(),
+        Some(other) => show_constant(other),
+        None => format!("<constant {}?>", index),
+    }
+}
+
+fn global_name(program: &Program, module: usize, slot: u16) -> String {
+    program.modules.get(module)
+        .and_then(|module| module.globals.get(slot as usize))
+        .cloned()
+        .unwrap_or_else(|| format!("<global {}?>", slot))
+}
+
+//what an instruction's operands mean, for the comment after the `;`
+fn explain(program: &Program, function: &Function, opcode: OpCode, operands: Operands) -> String {
+    let chunk = &function.chunk;
+    match opcode {
+        OpCode::CONSTANT => chunk.constants.get(operands.first as usize).map(show_constant).unwrap_or_default(),
+        OpCode::GET_GLOBAL | OpCode::SET_GLOBAL => global_name(program, function.module, operands.first),
+        OpCode::GET_MODULE_GLOBAL => {
+            let module = program.modules.get(operands.first as usize).map(|module| module.id.as_str()).unwrap_or("?");
+            format!("{}::{}", module, global_name(program, operands.first as usize, operands.second))
+        },
+        OpCode::CONFORM => {
+            let type_name = TYPES.get(operands.byte as usize).and_then(type_name_of).unwrap_or("?");
+            format!("{}: {}", name_of(chunk, operands.first), type_name)
+        },
+        OpCode::UNARY => MONOPS.get(operands.byte as usize).map(|op| op.to_string()).unwrap_or_default(),
+        OpCode::BINARY => BINOPS.get(operands.byte as usize).map(|op| op.to_string()).unwrap_or_default(),
+        OpCode::CALL => {
+            let callee = program.functions.get(operands.first as usize).map(|callee| callee.name.as_str()).unwrap_or("?");
+            format!("{} with {} argument(s)", callee, operands.byte)
+        },
+        OpCode::CALL_NATIVE => format!("{} with {} argument(s)", name_of(chunk, operands.first), operands.byte),
+        OpCode::FIELD => format!(".{}", name_of(chunk, operands.first)),
+        OpCode::ROLL => name_of(chunk, operands.first),
+        OpCode::JUMP | OpCode::JUMP_IF_NOT => format!("to {:04}", operands.first),
+        OpCode::BRANCH => format!("choice {} to {:04}", operands.first, operands.second),
+        OpCode::PICK => format!("one of {} choice(s)", operands.first),
+        OpCode::TEXT => format!("{} in node {}", name_of(chunk, operands.second), name_of(chunk, operands.first)),
+        _ => String::new(),
+    }
+}
+
+fn raw_operands(opcode: OpCode, operands: Operands) -> String {
+    match opcode.operand_size() {
+        0 => String::new(),
+        1 => operands.byte.to_string(),
+        2 => operands.first.to_string(),
+        3 => format!("{} {}", operands.first, operands.byte),
+        _ => format!("{} {}", operands.first, operands.second),
+    }
+}
+
+fn header(program: &Program, function: &Function) -> String {
+    let module = program.modules.get(function.module).map(|module| module.id.as_str()).unwrap_or("?");
+    if function.is_init {
+        return format!("== {} [top level of module {}] ==\n", function.name, module);
+    }
+    let params: Vec<String> = function.params.iter()
+        .map(|(name, type_t)| format!("{}: {}", name, type_name_of(type_t).unwrap_or("?")))
+        .collect();
+    format!(
+        "== rite {}({}) -> {} [module {}] ==\n",
+        function.name, params.join(", "), type_name_of(&function.return_type).unwrap_or("?"), module
+    )
+}
+
+pub fn disassemble_function(program: &Program, function: &Function) -> String {
+    let chunk = &function.chunk;
+    let mut ret = header(program, function);
+
+    if !chunk.constants.is_empty() {
+        ret += "constants:\n";
+        for (index, constant) in chunk.constants.iter().enumerate() {
+            let kind = match constant {
+                Value::STRING(_) => "rune",
+                other => other.type_name(),
+            };
+            ret += &format!("    {:>3}  {} {}\n", index, kind, show_constant(constant));
+        }
+    }
+
+    let mut offset = 0;
+    let mut last_line = None;
+    while offset < chunk.code.len() {
+        let (opcode, operands, next) = match decode_at(&chunk.code, offset) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                ret += &format!("{:04}  {}\n", offset, err);
+                break;
+            },
+        };
+        let line = chunk.line_at(offset);
+        let line_column = match line {
+            Some(line) if last_line == Some(line) => "    |".to_string(),
+            Some(line) => format!("{:>5}", line),
+            None => "    ?".to_string(),
+        };
+        last_line = line;
+
+        let mut text = format!("{:04} {}  {:<18} {:<8}", offset, line_column, format!("{:?}", opcode), raw_operands(opcode, operands));
+        let explanation = explain(program, function, opcode, operands);
+        if !explanation.is_empty() {
+            text += &format!(" ; {}", explanation);
+        }
+        ret += text.trim_end();
+        ret += "\n";
+        offset = next;
+    }
+    ret
+}
+
+//every function of every module, module by module, top level code first
+pub fn disassemble_program(program: &Program) -> String {
+    let mut ret = String::new();
+    for module in &program.modules {
+        let mut functions = vec![module.init];
+        functions.extend(&module.rites);
+        for index in functions {
+            if let Some(function) = program.functions.get(index) {
+                ret += &disassemble_function(program, function);
+                ret += "\n";
+            }
+        }
+    }
+    ret
+}
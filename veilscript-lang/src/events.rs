@@ -0,0 +1,93 @@
+use crate::ast::Stmt;
+use crate::runtime::rite_declarations;
+
+///EVENTS section
+//this here is how scripts hear about what happens in the game. a script says what it wants to
+//hear about with a handler:
+//
+//  on "door_opened"(door: int, by: rune) {
+//      print(by + " opened a door");
+//  }
+//
+//and the game emits events with whatever goes with them (ScriptRuntime::emit), which calls every
+//handler subscribed to that event, in the order they subscribed. loading subscribes the handlers of
+//every module, modules in load order (so dependencies hear it first) and each one top to bottom.
+//the game can subscribe any rite it likes on top of that (ScriptRuntime::subscribe), and it hears
+//the event after everything that was already there.
+//
+//every handler is a call of its own from the host: it gets its own fuel, and one that fails doesn't
+//keep the ones after it from running. emit hands back what went wrong with each one that failed. a
+//handler whose parameters don't fit what the event came with fails like any rite called with the
+//wrong arguments would. events have no types of their own, so payloads are plain values.
+//
+//every subscription has an id, and unsubscribing takes that id. which handlers a script has is up
+//to the script, so subscriptions don't go into save files: loading the scripts subscribes them.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub id: usize,
+    pub event: String,
+    pub module: String,
+    pub rite: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Events {
+    subscriptions: Vec<Subscription>, //in the order they subscribed
+    next_id: usize,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Events::default()
+    }
+
+    //doesn't check the rite exists, ScriptRuntime::subscribe does that
+    pub fn subscribe(&mut self, event: &str, module: &str, rite: &str) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscriptions.push(Subscription {
+            id,
+            event: event.to_string(),
+            module: module.to_string(),
+            rite: rite.to_string(),
+        });
+        id
+    }
+
+    //false if nothing was subscribed with that id
+    pub fn unsubscribe(&mut self, id: usize) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|subscription| subscription.id != id);
+        self.subscriptions.len() < before
+    }
+
+    //everything subscribed to `event`, in the order it runs
+    pub fn subscriptions(&self, event: &str) -> Vec<Subscription> {
+        self.subscriptions.iter().filter(|subscription| subscription.event == event).cloned().collect()
+    }
+
+    //every handler in `stmts`, top to bottom. one that's subscribed already (the repl can declare
+    //a handler of the same name again, which replaces it) isn't subscribed twice.
+    pub fn subscribe_handlers(&mut self, module: &str, stmts: &[Stmt]) {
+        for decl in rite_declarations(stmts) {
+            let Some(event) = decl.event_name() else { continue };
+            let subscribed = self.subscriptions.iter()
+                .any(|subscription| subscription.module == module && subscription.rite == decl.ident.name);
+            if !subscribed {
+                self.subscribe(&event, module, &decl.ident.name);
+            }
+        }
+    }
+
+    //after `module` was reloaded with `stmts` (see reload.rs): unsubscribes whatever of it isn't a
+    //rite any more and subscribes its new handlers. hands back what it unsubscribed.
+    pub fn resubscribe_handlers(&mut self, module: &str, stmts: &[Stmt]) -> Vec<Subscription> {
+        let rites: Vec<&str> = rite_declarations(stmts).iter().map(|decl| decl.ident.name.as_str()).collect();
+        let (gone, kept) = std::mem::take(&mut self.subscriptions).into_iter()
+            .partition(|subscription| subscription.module == module && !rites.contains(&subscription.rite.as_str()));
+        self.subscriptions = kept;
+        self.subscribe_handlers(module, stmts);
+        gone
+    }
+}
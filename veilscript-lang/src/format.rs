@@ -0,0 +1,444 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::*;
+use crate::lexer::{tokenise_lossless, LosslessToken, Span, TokenType, TriviaKind};
+use crate::parser::Parser;
+use crate::precedence::{Assoc, INFIX_TABLE, PREFIX_TABLE};
+use crate::value::type_name_of;
+
+///FORMATTER section
+//this here is `veil fmt`. it parses a file and prints it back out the one canonical way: four space
+//indents, one statement per line, spaces around binary operators, `rite`/`ret`/`summon`/`pub` and
+//the int/float/rune/void type names. whatever blank lines separate statements in the source are
+//kept (at most one in a row), and so is every comment.
+//
+//the ast has no comments in it, so they come from the lossless token stream (see lexer.rs) and get
+//woven back in by position: a comment on a line of its own stays on a line of its own before
+//whatever followed it, and a comment that came after code on the same line gets stuck to the end
+//of the line that code ended up on. formatting already formatted code never changes it.
+//
+//stray `;`s are empty statements and do nothing, so they're dropped. a name on its own (`foo;`)
+//does nothing either, but it's code someone wrote, so it stays and lint points it out.
+
+const INDENT: &str = "    ";
+
+struct Comment<'src> {
+    text: &'src str,
+    start: usize,
+    line: u32,
+    trailing: bool, //was there code before it on the same line?
+}
+
+//every comment in the file, in source order
+fn collect_comments<'src>(tokens: &[LosslessToken<'src>]) -> Vec<Comment<'src>> {
+    let mut comments = Vec::new();
+    for token in tokens {
+        let leading = token.leading.iter().map(|trivia| (trivia, false));
+        let trailing = token.trailing.iter().map(|trivia| (trivia, true));
+        for (trivia, trailing) in leading.chain(trailing).filter(|(trivia, _)| trivia.is_comment()) {
+            let text = match trivia.kind {
+                TriviaKind::BLOCK_COMMENT => trivia.text,
+                _ => trivia.text.trim_end(),
+            };
+            comments.push(Comment { text, start: trivia.span.start, line: trivia.span.line, trailing });
+        }
+    }
+    comments
+}
+
+struct Formatter<'src> {
+    source_lines: Vec<&'src str>,
+    comments: Vec<Comment<'src>>,
+    next_comment: usize,
+    lines: Vec<String>, //the last one is the line being written
+    indent: usize,
+    block_has_content: bool, //has anything been printed since the current block opened?
+}
+
+impl<'src> Formatter<'src> {
+    ///OUTPUT
+
+    fn write(&mut self, text: &str) {
+        let indent = INDENT.repeat(self.indent);
+        if let Some(line) = self.lines.last_mut() {
+            if line.is_empty() {
+                line.push_str(&indent);
+            }
+            line.push_str(text);
+        }
+    }
+
+    //makes sure whatever comes next starts on a fresh line
+    fn start_line(&mut self) {
+        if self.lines.last().is_some_and(|line| !line.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    //something that sat on source line `line` is about to be printed. if there was a blank line
+    //right before it, there's one in the output too (unless it's the first thing in a block).
+    fn blank_line_before(&mut self, line: u32) {
+        self.start_line();
+        let blank_above = line >= 2
+            && self.source_lines.get(line as usize - 2).is_some_and(|above| above.trim().is_empty());
+        let last_done = self.lines.len().checked_sub(2).map(|index| self.lines[index].is_empty());
+        if blank_above && self.block_has_content && last_done == Some(false) {
+            self.lines.push(String::new());
+        }
+    }
+
+    ///COMMENTS
+
+    //prints every comment that starts before `offset`
+    fn flush_comments(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.start >= offset {
+                break;
+            }
+            let (text, line, trailing) = (comment.text, comment.line, comment.trailing);
+            self.next_comment += 1;
+
+            //stuck to the end of whatever line the code before it ended up on
+            let current = self.lines.len() - 1;
+            let target = match self.lines[current].is_empty() {
+                true => current.checked_sub(1),
+                false => Some(current),
+            };
+            if trailing && let Some(target) = target && !self.lines[target].is_empty() {
+                self.lines[target] += " ";
+                self.lines[target] += text;
+                continue;
+            }
+            self.blank_line_before(line);
+            self.write(text);
+            self.start_line();
+            self.block_has_content = true;
+        }
+    }
+
+    fn has_comments_before(&self, offset: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|comment| comment.start < offset)
+    }
+
+    ///STATEMENTS
+
+    //the statements of a block, then any comments left in it before `end`
+    fn block(&mut self, stmts: &[Stmt], end: usize) {
+        self.block_has_content = false;
+        for stmt in stmts.iter().filter(|stmt| !is_stray_semicolon(stmt)) {
+            //statements put together by hand have no place to line comments up with
+            if let Some(span) = stmt.span() {
+                self.flush_comments(span.start);
+                self.blank_line_before(span.line);
+            }
+            self.stmt(stmt);
+            self.start_line();
+            self.block_has_content = true;
+        }
+        self.flush_comments(end);
+    }
+
+    //`{ ... }`, starting wherever the writer currently is
+    fn scope(&mut self, scope: &Scope) {
+        let end = scope.span.end.saturating_sub(1); //the `}`
+        let printable = scope.stmts.iter().any(|stmt| !is_stray_semicolon(stmt));
+        if !printable && !self.has_comments_before(end) {
+            self.write("{}");
+            return;
+        }
+        self.write("{");
+        self.lines.push(String::new());
+        self.indent += 1;
+        self.block(&scope.stmts, end);
+        self.indent -= 1;
+        self.start_line();
+        self.write("}");
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) => {
+                self.write(&assignment.ident.full_name());
+                if let Some(type_t) = &assignment.type_t {
+                    self.write(&format!(": {}", type_name(type_t)));
+                }
+                self.write(" = ");
+                self.expr(&assignment.expr);
+                self.write(";");
+            },
+            Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => {
+                let params: Vec<String> = decl.params.iter()
+                    .map(|param| format!("{}: {}", param.ident.name, type_name(&param.type_t)))
+                    .collect();
+                match &decl.event {
+                    Some(event) => self.write(&format!("on {}({}) ", event, params.join(", "))),
+                    None => self.write(&format!("rite {}({}) ", decl.ident.name, params.join(", "))),
+                }
+                if decl.type_t != TokenType::TYPE_VOID {
+                    self.write(&format!("-> {} ", type_name(&decl.type_t)));
+                }
+                self.scope(&decl.body);
+            },
+            Stmt::STATEMENT_RETURN(ret) => {
+                self.write("ret ");
+                self.expr(&ret.expr);
+                self.write(";");
+            },
+            Stmt::STATEMENT_FUNCTION_CALL(fncall) => {
+                self.call(fncall);
+                self.write(";");
+            },
+            Stmt::STATEMENT_IMPORT(import) => self.write(&format!("{};", import.to_pretty_string())),
+            Stmt::STATEMENT_EXPORT(inner) => {
+                self.write("pub ");
+                self.stmt(inner);
+            },
+            Stmt::SCOPE(scope) => self.scope(scope),
+            Stmt::STATEMENT_ZERO_EFFECT(Some(ident)) => {
+                self.write(&ident.full_name());
+                self.write(";");
+            },
+            Stmt::STATEMENT_ZERO_EFFECT(None) => {},
+            Stmt::STATEMENT_YIELD(yield_stmt) => {
+                self.yield_expr(yield_stmt);
+                self.write(";");
+            },
+            Stmt::STATEMENT_DIALOGUE(dialogue) => self.dialogue(dialogue),
+        }
+    }
+
+    ///DIALOGUE
+    //one node, line, choice or jump per line. text is printed exactly as it was written, `{expr}`s
+    //and all.
+
+    fn dialogue(&mut self, dialogue: &Dialogue) {
+        self.write("dialogue {");
+        self.lines.push(String::new());
+        self.indent += 1;
+        self.block_has_content = false;
+        for node in &dialogue.nodes {
+            self.start_item(node.span);
+            let end = node.span.end.saturating_sub(1); //the `}`
+            if node.lines.is_empty() && matches!(node.exit, NodeExit::END) && !self.has_comments_before(end) {
+                self.write(&format!("node {} {{}}", node.ident.name));
+                self.end_item();
+                continue;
+            }
+            self.write(&format!("node {} {{", node.ident.name));
+            self.lines.push(String::new());
+            self.indent += 1;
+            self.block_has_content = false;
+            for line in &node.lines {
+                self.start_item(line.span());
+                match &line.speaker {
+                    Some(speaker) => self.write(&format!("{}: {};", speaker.name, line.text.literal)),
+                    None => self.write(&format!("{};", line.text.literal)),
+                }
+                self.end_item();
+            }
+            match &node.exit {
+                NodeExit::END => {},
+                NodeExit::JUMP(target) => {
+                    self.start_item(target.span);
+                    self.write(&format!("-> {};", target.name));
+                    self.end_item();
+                },
+                NodeExit::CHOICES(choices) => for choice in choices {
+                    self.start_item(choice.span);
+                    self.write(&format!("choice {}", choice.text.literal));
+                    if let Some(condition) = &choice.condition {
+                        self.write(" if ");
+                        self.expr(condition);
+                    }
+                    self.write(&format!(" -> {};", choice.target.name));
+                    self.end_item();
+                },
+            }
+            self.flush_comments(end);
+            self.indent -= 1;
+            self.start_line();
+            self.write("}");
+            self.end_item();
+        }
+        self.flush_comments(dialogue.span.end.saturating_sub(1));
+        self.indent -= 1;
+        self.start_line();
+        self.write("}");
+    }
+
+    fn start_item(&mut self, span: Span) {
+        self.flush_comments(span.start);
+        self.blank_line_before(span.line);
+    }
+
+    fn end_item(&mut self) {
+        self.start_line();
+        self.block_has_content = true;
+    }
+
+    ///EXPRESSIONS
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::ATOM(atom) => self.write(&atom_text(atom)),
+            Expr::GROUPED_EXPR(inner) => {
+                self.write("(");
+                self.expr(inner);
+                self.write(")");
+            },
+            Expr::BINARY_EXPR { left, opcode, right } => {
+                let (precedence, assoc) = binop_precedence(*opcode);
+                //a tree straight from the parser never needs these, but one a pass built might
+                let left_parens = match left.as_ref() {
+                    Expr::BINARY_EXPR { opcode: inner, .. } => {
+                        let (inner, _) = binop_precedence(*inner);
+                        inner < precedence || (inner == precedence && assoc == Assoc::RIGHT)
+                    },
+                    Expr::UNARY_EXPR { .. } => precedence > prefix_precedence(),
+                    Expr::YIELD(_) => true,
+                    _ => false,
+                };
+                let right_parens = match right.as_ref() {
+                    Expr::BINARY_EXPR { opcode: inner, .. } => {
+                        let (inner, _) = binop_precedence(*inner);
+                        inner < precedence || (inner == precedence && assoc == Assoc::LEFT)
+                    },
+                    Expr::YIELD(_) => true,
+                    _ => false,
+                };
+                self.operand(left, left_parens);
+                self.write(&format!(" {} ", opcode.to_string()));
+                self.operand(right, right_parens);
+            },
+            Expr::UNARY_EXPR { opcode, expr } => {
+                self.write(&opcode.to_string());
+                let parens = match expr.as_ref() {
+                    Expr::BINARY_EXPR { opcode, .. } => binop_precedence(*opcode).0 <= prefix_precedence(),
+                    Expr::YIELD(_) => true,
+                    _ => false,
+                };
+                self.operand(expr, parens);
+            },
+            Expr::SCOPE(scope) => self.scope(scope),
+            Expr::FUNCTION_CALL(fncall) => self.call(fncall),
+            Expr::METHOD_CALL(call) => {
+                self.operand(&call.base, needs_parens_as_base(&call.base));
+                self.write(".");
+                self.call(&call.call);
+            },
+            Expr::FIELD_ACCESS(access) => {
+                self.operand(&access.base, needs_parens_as_base(&access.base));
+                self.write(&format!(".{}", access.access.name));
+            },
+            Expr::YIELD(yield_expr) => self.yield_expr(yield_expr),
+        }
+    }
+
+    //a yield swallows everything after it, so as anyone's operand it gets parentheses (see above)
+    fn yield_expr(&mut self, yield_expr: &Yield) {
+        self.write("yield");
+        if let Some(expr) = &yield_expr.expr {
+            self.write(" ");
+            self.expr(expr);
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parens: bool) {
+        if parens {
+            self.write("(");
+        }
+        self.expr(expr);
+        if parens {
+            self.write(")");
+        }
+    }
+
+    fn call(&mut self, fncall: &FnCall) {
+        self.write(&format!("{}(", fncall.ident.full_name()));
+        for (index, arg) in fncall.args.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.expr(arg);
+        }
+        self.write(")");
+    }
+}
+
+fn type_name(type_t: &TokenType) -> String {
+    type_name_of(type_t).map(str::to_owned).unwrap_or_else(|| format!("{:?}", type_t))
+}
+
+fn atom_text(atom: &Atom) -> String {
+    match atom {
+        //2.0 prints as "2", which would come back as an int
+        Atom::LITERAL_FLOAT(val) => {
+            let text = val.to_string();
+            match text.contains('.') {
+                true => text,
+                false => text + ".0",
+            }
+        },
+        other => other.to_string(),
+    }
+}
+
+fn binop_precedence(opcode: BinOp) -> (u8, Assoc) {
+    INFIX_TABLE.iter()
+        .find(|op| op.opcode == opcode)
+        .map(|op| (op.precedence, op.assoc))
+        .unwrap_or((0, Assoc::LEFT))
+}
+
+fn prefix_precedence() -> u8 {
+    PREFIX_TABLE.first().map(|op| op.precedence).unwrap_or(0)
+}
+
+//`.` binds tighter than any operator, so an operator on its left needs brackets
+fn needs_parens_as_base(base: &Expr) -> bool {
+    matches!(base, Expr::BINARY_EXPR { .. } | Expr::UNARY_EXPR { .. } | Expr::YIELD(_))
+}
+
+fn is_stray_semicolon(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::STATEMENT_ZERO_EFFECT(None))
+}
+
+//prints a tree the canonical way, without the comments and blank lines only a source has
+pub fn format_stmts(stmts: &[Stmt]) -> String {
+    let mut formatter = Formatter {
+        source_lines: Vec::new(),
+        comments: Vec::new(),
+        next_comment: 0,
+        lines: vec![String::new()],
+        indent: 0,
+        block_has_content: false,
+    };
+    formatter.block(stmts, 0);
+    formatter.lines.join("\n")
+}
+
+//formats a whole file. fails only if the file doesn't parse.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let tokens = tokenise_lossless(source);
+    let comments = collect_comments(&tokens);
+    let mut parser = Parser::new(tokens.into_iter().map(|token| token.token).collect());
+    let stmts = parser.parse_program()?;
+
+    let mut formatter = Formatter {
+        source_lines: source.lines().collect(),
+        comments,
+        next_comment: 0,
+        lines: vec![String::new()],
+        indent: 0,
+        block_has_content: false,
+    };
+    formatter.block(&stmts, source.len());
+
+    while formatter.lines.last().is_some_and(|line| line.is_empty()) {
+        formatter.lines.pop();
+    }
+    if formatter.lines.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(formatter.lines.join("\n") + "\n")
+}
@@ -0,0 +1,320 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashMap;
+
+use crate::ast::Stmt;
+use crate::json::Json;
+use crate::lexer::*;
+use crate::parser::Parser;
+use crate::resolve::{resolve, Binding, SymbolKind};
+
+///HIGHLIGHT section
+//this here is what every editor integration colours scripts with, so none of them has to keep its
+//own list of keywords that drifts away from the lexer (and forgets `numeric` is a float, again).
+//
+//highlight() sorts every bit of a source into a HIGHLIGHT. keywords, types, literals, operators
+//and comments come straight from the lexer. identifiers are resolved when the file parses, so a
+//rite, a native, a global, a parameter and a local all look different; when it doesn't parse
+//they're guessed from what follows them.
+//
+//textmate_grammar() writes the same rules out as a TextMate grammar for editors that only do
+//regex highlighting, built from lexer::SPELLINGS and the lexer's own patterns.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    KEYWORD,
+    TYPE,
+    NUMBER,
+    STRING,
+    OPERATOR,
+    PUNCTUATION,
+    COMMENT,
+    DOC_COMMENT,
+    RITE,
+    NATIVE,
+    GLOBAL,
+    PARAMETER,
+    LOCAL,
+    NAMESPACE,
+    IDENTIFIER, //a name nothing defines, or one in a file that doesn't parse
+    ERROR,
+}
+
+impl Highlight {
+    //what a token is on its own, before anyone looks at what a name refers to. None for the
+    //things that never show up in a token stream.
+    pub fn of(kind: &TokenType) -> Option<Highlight> {
+        use TokenType::*;
+        let highlight = match kind {
+            FN | RETURN | IMPORT | EXPORT | AS | YIELD | DIALOGUE | NODE | CHOICE | IF | ON => Highlight::KEYWORD,
+            TYPE_FLOAT | EXPERIMENTAL_TYPE_INT | TYPE_STRING | TYPE_VOID => Highlight::TYPE,
+            EQUALS | ARROW | PLUS | MINUS | SLASH | ASTERISK | DOUBLE_ASTERISK | PERCENT | AMPERSAND
+                | PIPE | CARET | SHIFT_LEFT | SHIFT_RIGHT => Highlight::OPERATOR,
+            COLON | DOUBLE_COLON | DOT | COMMA | SEMICOLON | LPAREN | RPAREN | LBRACE | RBRACE => Highlight::PUNCTUATION,
+            LITERAL_STRING => Highlight::STRING,
+            LITERAL_FLOAT | LITERAL_DICE | LITERAL_INT => Highlight::NUMBER,
+            IDENTIFIER => Highlight::IDENTIFIER,
+            COMMENT | BLOCK_COMMENT => Highlight::COMMENT,
+            ERROR => Highlight::ERROR,
+            WHITESPACE | EOF => return None,
+        };
+        Some(highlight)
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    //the LSP semantic token type and modifiers for this, see LSP_TOKEN_TYPES. None for things
+    //editors colour well enough on their own.
+    pub fn lsp_token(&self) -> Option<(&'static str, &'static [&'static str])> {
+        let token = match self {
+            Highlight::KEYWORD => ("keyword", &[][..]),
+            Highlight::TYPE => ("type", &[][..]),
+            Highlight::NUMBER => ("number", &[][..]),
+            Highlight::STRING => ("string", &[][..]),
+            Highlight::OPERATOR => ("operator", &[][..]),
+            Highlight::COMMENT => ("comment", &[][..]),
+            Highlight::DOC_COMMENT => ("comment", &["documentation"][..]),
+            Highlight::RITE => ("function", &[][..]),
+            Highlight::NATIVE => ("function", &["defaultLibrary"][..]),
+            Highlight::GLOBAL => ("variable", &["static"][..]),
+            Highlight::PARAMETER => ("parameter", &[][..]),
+            Highlight::LOCAL | Highlight::IDENTIFIER => ("variable", &[][..]),
+            Highlight::NAMESPACE => ("namespace", &[][..]),
+            Highlight::PUNCTUATION | Highlight::ERROR => return None,
+        };
+        Some(token)
+    }
+}
+
+//the legend a language server announces. lsp_token only ever hands out names from these.
+pub const LSP_TOKEN_TYPES: [&str; 10] = [
+    "keyword", "type", "number", "string", "operator", "comment", "function", "variable", "parameter", "namespace",
+];
+pub const LSP_TOKEN_MODIFIERS: [&str; 3] = ["documentation", "defaultLibrary", "static"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlighted {
+    pub span: Span,
+    pub highlight: Highlight,
+}
+
+//every token and comment of a source, in order. whitespace is left out.
+pub fn highlight(source: &str, natives: &[String]) -> Vec<Highlighted> {
+    let tokens = tokenise_lossless(source);
+    let resolved = resolve_names(source, natives);
+
+    let mut ret = Vec::new();
+    for (at, token) in tokens.iter().enumerate() {
+        for trivia in &token.leading {
+            push_trivia(&mut ret, trivia);
+        }
+        let next = tokens.get(at + 1).map(|next| &next.token.kind);
+        let highlight = match token.token.kind {
+            TokenType::IDENTIFIER => resolved.get(&token.token.span.start).copied()
+                .unwrap_or_else(|| guess_name(&token.token, next, natives)),
+            ref kind => Highlight::of(kind).unwrap_or(Highlight::ERROR),
+        };
+        if token.token.kind != TokenType::EOF {
+            ret.push(Highlighted { span: token.token.span, highlight });
+        }
+        for trivia in &token.trailing {
+            push_trivia(&mut ret, trivia);
+        }
+    }
+    ret
+}
+
+fn push_trivia(ret: &mut Vec<Highlighted>, trivia: &Trivia) {
+    let highlight = match trivia.kind {
+        TriviaKind::DOC_COMMENT => Highlight::DOC_COMMENT,
+        TriviaKind::LINE_COMMENT | TriviaKind::BLOCK_COMMENT => Highlight::COMMENT,
+        TriviaKind::WHITESPACE | TriviaKind::NEWLINE => return,
+    };
+    ret.push(Highlighted { span: trivia.span, highlight });
+}
+
+//a name in a file that doesn't parse: `a::` is a module, `a(` gets called
+fn guess_name(token: &Token, next: Option<&TokenType>, natives: &[String]) -> Highlight {
+    match next {
+        Some(TokenType::DOUBLE_COLON) => Highlight::NAMESPACE,
+        Some(TokenType::LPAREN) if natives.iter().any(|native| native == token.lexeme) => Highlight::NATIVE,
+        Some(TokenType::LPAREN) => Highlight::RITE,
+        _ => Highlight::IDENTIFIER,
+    }
+}
+
+//what each name in the source turned out to be, by where its last segment starts. `intro::gold`
+//only gets `gold` in here, `intro` is taken care of by guess_name.
+fn resolve_names(source: &str, natives: &[String]) -> HashMap<usize, Highlight> {
+    let mut ret = HashMap::new();
+    let stmts = match Parser::new(tokenise(source)).parse_program() {
+        Ok(stmts) => stmts,
+        Err(_) => return ret,
+    };
+    let resolution = resolve("", &stmts, Some(natives));
+    for reference in resolution.references {
+        let ident = &reference.ident;
+        let highlight = match reference.binding {
+            Binding::SYMBOL(symbol) => match resolution.symbols[symbol].kind {
+                SymbolKind::RITE => Highlight::RITE,
+                SymbolKind::GLOBAL => Highlight::GLOBAL,
+                SymbolKind::PARAM => Highlight::PARAMETER,
+                SymbolKind::LOCAL => Highlight::LOCAL,
+            },
+            Binding::NATIVE => Highlight::NATIVE,
+            Binding::MODULE if reference.call => Highlight::RITE,
+            Binding::MODULE => Highlight::GLOBAL,
+            Binding::UNDEFINED => Highlight::IDENTIFIER,
+        };
+        ret.insert(ident.span.end - ident.name.len(), highlight);
+    }
+    for stmt in &stmts {
+        if let Stmt::STATEMENT_IMPORT(import) = stmt {
+            for ident in import.path.iter().chain(&import.alias) {
+                ret.insert(ident.span.start, Highlight::NAMESPACE);
+            }
+        }
+    }
+    ret
+}
+
+///TEXTMATE
+
+//every spelling in lexer::SPELLINGS has to lex to exactly the token it claims to be
+pub fn verify_spellings() -> Result<(), String> {
+    for (spelling, kind) in SPELLINGS {
+        let kinds: Vec<TokenType> = tokenise(spelling).into_iter().map(|token| token.kind).collect();
+        if kinds != [kind.clone(), TokenType::EOF] {
+            return Err(format!("{:?} is listed as {:?}, but lexes as {:?}!", spelling, kind, kinds));
+        }
+    }
+    Ok(())
+}
+
+//the TextMate scope of a fixed spelling
+fn textmate_scope(kind: &TokenType) -> &'static str {
+    use TokenType::*;
+    match kind {
+        FN | ON => "storage.type.function.veil",
+        RETURN => "keyword.control.return.veil",
+        YIELD => "keyword.control.yield.veil",
+        DIALOGUE | NODE | CHOICE => "keyword.other.dialogue.veil",
+        IF => "keyword.control.conditional.veil",
+        IMPORT | AS => "keyword.control.import.veil",
+        EXPORT => "storage.modifier.veil",
+        TYPE_FLOAT | EXPERIMENTAL_TYPE_INT | TYPE_STRING | TYPE_VOID => "storage.type.veil",
+        EQUALS => "keyword.operator.assignment.veil",
+        ARROW => "keyword.operator.arrow.veil",
+        COLON => "punctuation.separator.colon.veil",
+        DOUBLE_COLON | DOT => "punctuation.accessor.veil",
+        COMMA => "punctuation.separator.comma.veil",
+        SEMICOLON => "punctuation.terminator.veil",
+        LPAREN | RPAREN => "punctuation.section.parens.veil",
+        LBRACE | RBRACE => "punctuation.section.block.veil",
+        _ => "keyword.operator.veil",
+    }
+}
+
+fn regex_escape(text: &str) -> String {
+    let mut ret = String::new();
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}/".contains(c) {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+fn pattern(scope: &str, regex: &str) -> Json {
+    Json::object([("name", Json::from(scope)), ("match", Json::from(regex))])
+}
+
+fn include(name: &str) -> Json {
+    Json::object([("include", Json::from(format!("#{}", name)))])
+}
+
+pub fn textmate_grammar() -> Result<Json, String> {
+    verify_spellings()?;
+    let word = |regex: &str| format!(r"\b{}\b", regex);
+
+    //words become one alternation per scope, in the order the lexer declares them
+    let mut words: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut symbols: Vec<(&str, &str)> = Vec::new();
+    for (spelling, kind) in SPELLINGS {
+        let scope = textmate_scope(kind);
+        if !spelling.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            symbols.push((spelling, scope));
+            continue;
+        }
+        match words.iter_mut().find(|(existing, _)| *existing == scope) {
+            Some((_, spellings)) => spellings.push(spelling),
+            None => words.push((scope, vec![spelling])),
+        }
+    }
+    //longest first, so `**` wins over `*` and `::` over `:`
+    symbols.sort_by_key(|(spelling, _)| std::cmp::Reverse(spelling.len()));
+
+    let keywords: Vec<Json> = words.iter()
+        .map(|(scope, spellings)| pattern(scope, &word(&format!("(?:{})", spellings.join("|")))))
+        .collect();
+    let operators: Vec<Json> = symbols.iter()
+        .map(|(spelling, scope)| pattern(scope, &regex_escape(spelling)))
+        .collect();
+    let rite_keywords: Vec<&str> = SPELLINGS.iter()
+        .filter(|(_, kind)| *kind == TokenType::FN)
+        .map(|(spelling, _)| *spelling)
+        .collect();
+
+    let repository = Json::object([
+        ("comments", Json::object([("patterns", Json::from(vec![
+            pattern("comment.line.documentation.veil", "///(?!/).*$"),
+            pattern("comment.line.double-slash.veil", "//.*$"),
+            include("block-comment"),
+        ]))])),
+        //block comments nest, so they include themselves
+        ("block-comment", Json::object([
+            ("name", Json::from("comment.block.veil")),
+            ("begin", Json::from(r"/\*")),
+            ("end", Json::from(r"\*/")),
+            ("patterns", Json::from(vec![include("block-comment")])),
+        ])),
+        ("strings", Json::object([
+            ("name", Json::from("string.quoted.double.veil")),
+            ("begin", Json::from("\"")),
+            ("end", Json::from("\"")),
+            ("patterns", Json::from(vec![pattern("constant.character.escape.veil", r"\\.")])),
+        ])),
+        ("numbers", Json::object([("patterns", Json::from(vec![
+            pattern("constant.numeric.float.veil", &word(FLOAT_PATTERN)),
+            pattern("constant.numeric.dice.veil", &word(DICE_PATTERN)),
+            pattern("constant.numeric.integer.veil", &word(INT_PATTERN)),
+        ]))])),
+        ("rite-declarations", Json::object([
+            ("match", Json::from(format!(r"\b({})\s+({})", rite_keywords.join("|"), IDENTIFIER_PATTERN))),
+            ("captures", Json::object([
+                ("1", Json::object([("name", Json::from(textmate_scope(&TokenType::FN)))])),
+                ("2", Json::object([("name", Json::from("entity.name.function.veil"))])),
+            ])),
+        ])),
+        ("keywords", Json::object([("patterns", Json::from(keywords))])),
+        ("calls", pattern("entity.name.function.call.veil", &format!(r"\b{}(?=\s*\()", IDENTIFIER_PATTERN))),
+        ("namespaces", pattern("entity.name.namespace.veil", &format!(r"\b{}(?=\s*::)", IDENTIFIER_PATTERN))),
+        ("operators", Json::object([("patterns", Json::from(operators))])),
+        ("identifiers", pattern("variable.other.veil", &word(IDENTIFIER_PATTERN))),
+    ]);
+
+    let order = ["comments", "strings", "rite-declarations", "keywords", "numbers", "calls", "namespaces", "operators", "identifiers"];
+    Ok(Json::object([
+        ("$schema", Json::from("https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json")),
+        ("name", Json::from("Veilscript")),
+        ("scopeName", Json::from("source.veil")),
+        ("fileTypes", Json::from(vec![Json::from("veil")])),
+        ("patterns", Json::from(order.iter().map(|name| include(name)).collect::<Vec<Json>>())),
+        ("repository", repository),
+    ]))
+}
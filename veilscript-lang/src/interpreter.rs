@@ -0,0 +1,889 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::dialogue::{find_node, CHOOSE, SAY};
+use crate::events::Events;
+use crate::lexer::TokenType;
+use crate::locale::{line_text, Catalogue};
+use crate::module::{Module, ModuleLoader};
+use crate::optimise::optimise;
+use crate::random::{unrolled_dice, Rng};
+use crate::reload::{carry_over, prepare_reload, resubscribe, top_level_failed, Reload};
+use crate::runtime::*;
+use crate::save::{module_version, SaveState, SavedCoroutine, SavedFrame, SavedModule, SavedReplay};
+use crate::value::{unescape_string_literal, Value};
+
+///INTERPRETER section
+//this here is the REFERENCE INTERPRETER. It walks the AST directly, which makes it slow but
+//really easy to read, so it's the thing that defines what a script means. The vm in vm.rs has to
+//do exactly what this does, just faster. coroutines work differently here, see COROUTINES.
+
+//what running a statement did: either nothing special, or it hit a `ret`
+#[allow(clippy::upper_case_acronyms)]
+enum Flow {
+    NORMAL,
+    RETURN(Value),
+}
+
+//the locals of one running rite (or of a module's top level code), innermost scope last
+struct Frame {
+    module: usize,
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+//a coroutine between resumes. this interpreter can't put one to sleep, so it plays it back
+//instead, see COROUTINES below.
+struct Coroutine {
+    module: usize,
+    rite: String,
+    args: Vec<Value>, //bound already
+    log: Vec<Value>, //what every effect so far came out as, in order
+    steps: u64, //fuel burnt so far
+    asleep: bool, //sleeping in a yield, rather than not started yet or out of fuel
+    eras: Vec<Era>, //the code it ran on before reloads, oldest first
+    //how far the play back has got, only while it's being resumed
+    replayed: usize,
+    paid: u64,
+    era: usize,
+    falling_asleep: Option<Resumed>,
+}
+
+//the code a coroutine ran on before a reload, up to the effects and steps it had got to when it
+//last fell asleep on it
+struct Era {
+    until: (usize, u64),
+    modules: Vec<Rc<Module>>,
+    rites: Vec<HashMap<String, usize>>,
+    global_names: Vec<HashSet<String>>,
+}
+
+impl Era {
+    //puts this era's code in place of the interpreter's, or the other way round
+    fn swap(&mut self, interpreter: &mut Interpreter) {
+        std::mem::swap(&mut self.modules, &mut interpreter.modules);
+        std::mem::swap(&mut self.rites, &mut interpreter.rites);
+        std::mem::swap(&mut self.global_names, &mut interpreter.global_names);
+    }
+}
+
+#[derive(Default)]
+pub struct Interpreter {
+    pub natives: Natives,
+    modules: Vec<Rc<Module>>,
+    module_index: HashMap<String, usize>,
+    rites: Vec<HashMap<String, usize>>, //per module: rite name -> index of its top level statement
+    globals: Vec<HashMap<String, Value>>,
+    global_names: Vec<HashSet<String>>,
+    call_depth: usize,
+    limits: Limits,
+    fuel: Fuel,
+    unoptimised: bool, //runs scripts exactly as written, for debugging the optimiser
+    locale: Option<Catalogue>,
+    events: Events,
+    coroutines: HashMap<usize, Coroutine>, //the ones still alive, by id
+    next_coroutine: usize,
+    running: Option<Coroutine>, //the one being resumed right now
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::default()
+    }
+
+    fn find_module(&self, id: &str) -> Result<usize, String> {
+        self.module_index.get(id).copied().ok_or_else(|| format!("No module named {}!", id))
+    }
+
+    fn target_module(&self, frame: &Frame, ident: &Ident) -> Result<usize, String> {
+        let module = &self.modules[frame.module];
+        let target = match module.resolve_namespace(&ident.namespace) {
+            Some(id) => self.find_module(id)?,
+            None => return Err(not_defined(&ident.full_name())),
+        };
+        //the loader checked the modules' own code already, but a session can type anything
+        let reached = &self.modules[target];
+        if reached.definitions.contains(&ident.name) && !reached.exports.contains(&ident.name) {
+            return Err(not_exported(&ident.name, &reached.id));
+        }
+        Ok(target)
+    }
+
+    ///STATEMENTS
+
+    fn exec_block(&mut self, frame: &mut Frame, stmts: &[Stmt]) -> Result<Flow, String> {
+        for stmt in stmts {
+            if let Flow::RETURN(value) = self.exec_stmt(frame, stmt)? {
+                return Ok(Flow::RETURN(value));
+            }
+        }
+        Ok(Flow::NORMAL)
+    }
+
+    fn exec_scope(&mut self, frame: &mut Frame, scope: &Scope) -> Result<Flow, String> {
+        frame.scopes.push(HashMap::new());
+        let flow = self.exec_block(frame, &scope.stmts);
+        frame.scopes.pop();
+        flow
+    }
+
+    fn exec_stmt(&mut self, frame: &mut Frame, stmt: &Stmt) -> Result<Flow, String> {
+        match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) => {
+                self.exec_assignment(frame, assignment)?;
+                Ok(Flow::NORMAL)
+            },
+            Stmt::STATEMENT_RETURN(ret) => Ok(Flow::RETURN(self.eval(frame, &ret.expr)?)),
+            Stmt::STATEMENT_FUNCTION_CALL(fncall) => {
+                let args = self.eval_args(frame, &fncall.ident, &fncall.args)?;
+                self.call_named(frame, &fncall.ident, args)?;
+                Ok(Flow::NORMAL)
+            },
+            Stmt::SCOPE(scope) => self.exec_scope(frame, scope),
+            Stmt::STATEMENT_EXPORT(inner) => self.exec_stmt(frame, inner),
+            Stmt::STATEMENT_YIELD(yield_stmt) => {
+                let value = self.eval_yield(frame, yield_stmt)?;
+                self.suspend(value)?;
+                Ok(Flow::NORMAL)
+            },
+            Stmt::STATEMENT_DIALOGUE(dialogue) => self.exec_dialogue(frame, dialogue),
+            //rites are hoisted when the module loads, and imports were dealt with by the loader
+            Stmt::STATEMENT_FUNCTION_DECLARATION(_) | Stmt::STATEMENT_IMPORT(_)
+                | Stmt::STATEMENT_ZERO_EFFECT(_) => Ok(Flow::NORMAL),
+        }
+    }
+
+    fn exec_assignment(&mut self, frame: &mut Frame, assignment: &Assignment) -> Result<(), String> {
+        let name = &assignment.ident.name;
+        let value = self.eval(frame, &assignment.expr)?;
+
+        //a typed assignment always declares something new in the innermost scope
+        if let Some(type_t) = &assignment.type_t {
+            let value = conform_assignment(name, type_t, value)?;
+            match frame.scopes.last_mut() {
+                Some(scope) => { scope.insert(name.clone(), value); },
+                None => self.set_global(frame.module, name, value)?,
+            }
+            return Ok(());
+        }
+
+        //otherwise it's the closest local, then a global of this module, then a brand new local
+        if let Some(scope) = frame.scopes.iter_mut().rev().find(|scope| scope.contains_key(name)) {
+            scope.insert(name.clone(), value);
+        } else if self.global_names[frame.module].contains(name) || frame.scopes.is_empty() {
+            self.set_global(frame.module, name, value)?;
+        } else if let Some(scope) = frame.scopes.last_mut() {
+            scope.insert(name.clone(), value);
+        }
+        Ok(())
+    }
+
+    //every line is said and then yielded with whatever say gave back, the value it's resumed with
+    //is thrown away. choices are yielded the same way, and the coroutine has to be resumed with
+    //the index of one that was on offer (see dialogue.rs).
+    fn exec_dialogue(&mut self, frame: &mut Frame, dialogue: &Dialogue) -> Result<Flow, String> {
+        let mut node = &dialogue.nodes[0];
+        loop {
+            for line in &node.lines {
+                let speaker = match &line.speaker {
+                    Some(speaker) => Value::STRING(speaker.name.clone()),
+                    None => Value::VOID,
+                };
+                let text = self.eval_text(frame, &node.ident.name, &line.text)?;
+                let said = self.call_named(frame, &Ident::new(SAY), vec![speaker, text])?;
+                self.suspend(said)?;
+            }
+            let target = match &node.exit {
+                NodeExit::END => return Ok(Flow::NORMAL),
+                NodeExit::JUMP(target) => target,
+                //a choice that isn't on offer is void instead, so the others keep their indices
+                NodeExit::CHOICES(choices) => {
+                    let mut offered = Vec::new();
+                    let mut texts = Vec::new();
+                    for choice in choices {
+                        offered.push(match &choice.condition {
+                            Some(condition) => self.eval(frame, condition)?.is_truthy(),
+                            None => true,
+                        });
+                        texts.push(match offered.last() {
+                            Some(true) => self.eval_text(frame, &node.ident.name, &choice.text)?,
+                            _ => Value::VOID,
+                        });
+                    }
+                    let chosen = self.call_named(frame, &Ident::new(CHOOSE), texts)?;
+                    let picked = self.suspend(chosen)?;
+                    let index = match picked {
+                        Value::INT(index) if index >= 0 && (index as usize) < choices.len() => {
+                            index as usize
+                        },
+                        _ => return Err(no_such_choice(&picked, choices.len())),
+                    };
+                    if !offered[index] {
+                        return Err(choice_not_offered(&picked));
+                    }
+                    &choices[index].target
+                },
+            };
+            let next = find_node(dialogue, &target.name).ok_or_else(|| not_defined(&target.name))?;
+            node = &dialogue.nodes[next];
+        }
+    }
+
+    //text without any `{expr}` in it is just a rune literal (or its translation), so only glued
+    //together text has to fit the rune limit
+    fn eval_text(&mut self, frame: &mut Frame, node: &str, text: &Text) -> Result<Value, String> {
+        let mut values = Vec::new();
+        for part in &text.parts {
+            if let TextPart::EXPR(expr) = part {
+                values.push(self.eval(frame, expr)?);
+            }
+        }
+        let ret = Value::STRING(line_text(self.locale.as_ref(), node, &text.literal, &values)?);
+        match values.is_empty() {
+            true => Ok(ret),
+            false => check_rune(ret, &self.limits),
+        }
+    }
+
+    ///EXPRESSIONS
+
+    fn eval(&mut self, frame: &mut Frame, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::ATOM(atom) => self.eval_atom(frame, atom),
+            Expr::GROUPED_EXPR(inner) => self.eval(frame, inner),
+            Expr::BINARY_EXPR { left, opcode, right } => {
+                let left = self.eval(frame, left)?;
+                let right = self.eval(frame, right)?;
+                self.burn()?;
+                check_rune(opcode.apply(&left, &right)?, &self.limits)
+            },
+            Expr::UNARY_EXPR { opcode, expr } => {
+                let value = self.eval(frame, expr)?;
+                self.burn()?;
+                opcode.apply(&value)
+            },
+            //a `ret` inside a scope expression gives the scope its value
+            Expr::SCOPE(scope) => match self.exec_scope(frame, scope)? {
+                Flow::RETURN(value) => Ok(value),
+                Flow::NORMAL => Ok(Value::VOID),
+            },
+            Expr::FUNCTION_CALL(fncall) => {
+                let args = self.eval_args(frame, &fncall.ident, &fncall.args)?;
+                self.call_named(frame, &fncall.ident, args)
+            },
+            //thing.rite(args) is rite(thing, args)
+            Expr::METHOD_CALL(call) => {
+                let mut args = vec![self.eval_arg(frame, &call.call.ident, &call.base)?];
+                args.extend(self.eval_args(frame, &call.call.ident, &call.call.args)?);
+                self.call_named(frame, &call.call.ident, args)
+            },
+            Expr::FIELD_ACCESS(access) => {
+                let value = self.eval(frame, &access.base)?;
+                Err(no_such_field(&access.access.name, &value))
+            },
+            Expr::YIELD(yield_expr) => {
+                let value = self.eval_yield(frame, yield_expr)?;
+                self.suspend(value)
+            },
+        }
+    }
+
+    fn eval_yield(&mut self, frame: &mut Frame, yield_expr: &Yield) -> Result<Value, String> {
+        match &yield_expr.expr {
+            Some(expr) => self.eval(frame, expr),
+            None => Ok(Value::VOID),
+        }
+    }
+
+    fn eval_atom(&mut self, frame: &mut Frame, atom: &Atom) -> Result<Value, String> {
+        match atom {
+            Atom::LITERAL_INT(val) => Ok(Value::INT(*val)),
+            Atom::LITERAL_FLOAT(val) => Ok(Value::FLOAT(*val)),
+            //a roll takes a step, like calling roll() would
+            Atom::LITERAL_DICE(dice) => {
+                self.burn()?;
+                self.effect(|this| Ok(Value::INT(dice.roll(&mut this.natives.rng))))
+            },
+            Atom::LITERAL_STRING(val) => Ok(Value::STRING(unescape_string_literal(val))),
+            Atom::IDENTIFIER(ident) if ident.is_namespaced() => {
+                let target = self.target_module(frame, ident)?;
+                self.effect(|this| {
+                    let full_name = || format!("{}::{}", this.modules[target].id, ident.name);
+                    this.globals[target].get(&ident.name).cloned().ok_or_else(|| not_defined(&full_name()))
+                })
+            },
+            Atom::IDENTIFIER(ident) => {
+                if let Some(scope) = frame.scopes.iter().rev().find(|scope| scope.contains_key(&ident.name)) {
+                    return Ok(scope[&ident.name].clone());
+                }
+                self.effect(|this| {
+                    this.globals[frame.module].get(&ident.name).cloned()
+                        .ok_or_else(|| not_defined(&ident.name))
+                })
+            },
+        }
+    }
+
+    fn eval_args(&mut self, frame: &mut Frame, ident: &Ident, args: &[Expr]) -> Result<Vec<Value>, String> {
+        args.iter().map(|arg| self.eval_arg(frame, ident, arg)).collect()
+    }
+
+    //an argument of a call to whatever `ident` names
+    fn eval_arg(&mut self, frame: &mut Frame, ident: &Ident, arg: &Expr) -> Result<Value, String> {
+        match unrolled_dice(ident, arg) {
+            Some(dice) if !self.rites[frame.module].contains_key(&ident.name) => {
+                Ok(Value::STRING(dice.to_string()))
+            },
+            _ => self.eval(frame, arg),
+        }
+    }
+
+    ///CALLS
+
+    //rites of the module come first, natives second
+    fn call_named(&mut self, frame: &Frame, ident: &Ident, args: Vec<Value>) -> Result<Value, String> {
+        if ident.is_namespaced() {
+            let target = self.target_module(frame, ident)?;
+            if !self.rites[target].contains_key(&ident.name) {
+                return Err(no_such_rite(&ident.full_name()));
+            }
+            return self.call_rite(target, &ident.name, args);
+        }
+        if self.rites[frame.module].contains_key(&ident.name) {
+            return self.call_rite(frame.module, &ident.name, args);
+        }
+        self.burn()?;
+        let value = self.effect(|this| this.natives.call(&ident.name, &args))?;
+        check_rune(value, &self.limits)
+    }
+
+    //the rite is looked up before its step is paid for, so a coroutine spawned before a reload
+    //starts on the code it was spawned with, like a vm frame pushed at spawn would
+    fn call_rite(&mut self, module_index: usize, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let module = Rc::clone(&self.modules[module_index]);
+        let decl = declaration(&module, self.rites[module_index][name], name)?;
+        self.burn()?;
+        if self.call_depth >= self.limits.max_call_depth {
+            return Err(stack_overflow(&self.limits));
+        }
+
+        let params = parameters(decl);
+        let args = bind_arguments(name, &params, args)?;
+        let locals: HashMap<String, Value> = params.into_iter().map(|(name, _)| name).zip(args).collect();
+        let mut frame = Frame { module: module_index, scopes: vec![locals] };
+
+        self.call_depth += 1;
+        let flow = self.exec_block(&mut frame, &decl.body.stmts);
+        self.call_depth -= 1;
+
+        let value = match flow? {
+            Flow::RETURN(value) => value,
+            Flow::NORMAL => Value::VOID,
+        };
+        conform_return(name, &decl.type_t, value)
+    }
+}
+
+///COROUTINES
+//the interpreter keeps a rite's locals on rust's stack, which can't be put aside and woken up
+//later. so instead of putting a coroutine to sleep, it PLAYS IT BACK: every resume runs the
+//coroutine's rite again from the start. what the rite can't work out again by itself is every
+//EFFECT: reading or writing a global, calling a native, rolling dice and yielding. the first time
+//one happens, what it came out as goes into the coroutine's log, and every time after that it's
+//taken from there instead of happening again. fuel burnt before is counted off the same way,
+//without burning any. once the log and the steps run out, the coroutine is exactly where it fell
+//asleep and carries on for real. every resume plays back everything so far, so it's slow, but it's
+//simple enough to be the reference the vm's coroutines have to match.
+//
+//a coroutine finishes on the code it started on (see reload.rs): a reload hands every coroutine
+//an ERA, the code it has run on so far, and the play back moves on to the next era's code at the
+//point the coroutine had got to when that one was swapped out.
+impl Interpreter {
+    //takes a step's worth of fuel. a coroutine that runs dry falls asleep instead of failing.
+    fn burn(&mut self) -> Result<(), String> {
+        let Some(running) = &mut self.running else {
+            return self.fuel.burn(&self.limits);
+        };
+        if running.paid < running.steps {
+            running.paid += 1;
+            self.catch_up();
+            return Ok(());
+        }
+        if self.fuel.burn(&self.limits).is_err() {
+            return Err(self.fall_asleep(Resumed::OUT_OF_FUEL));
+        }
+        running.steps += 1;
+        running.paid += 1;
+        Ok(())
+    }
+
+    //while playing back, what the effect came out as the first time. otherwise it happens now.
+    fn effect(&mut self, live: impl FnOnce(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if let Some(value) = self.replay() {
+            return Ok(value);
+        }
+        let value = live(self)?;
+        if let Some(running) = &mut self.running {
+            running.log.push(value.clone());
+            running.replayed += 1;
+        }
+        Ok(value)
+    }
+
+    fn set_global(&mut self, module: usize, name: &str, value: Value) -> Result<(), String> {
+        self.effect(|this| {
+            this.globals[module].insert(name.to_string(), value);
+            Ok(Value::VOID)
+        })?;
+        Ok(())
+    }
+
+    //a yield that was played back turns into what the coroutine was resumed with back then. a new
+    //one puts the coroutine to sleep.
+    fn suspend(&mut self, value: Value) -> Result<Value, String> {
+        if self.running.is_none() {
+            return Err(yield_outside_coroutine());
+        }
+        match self.replay() {
+            Some(resumed_with) => Ok(resumed_with),
+            None => Err(self.fall_asleep(Resumed::YIELDED(value))),
+        }
+    }
+
+    fn replay(&mut self) -> Option<Value> {
+        let running = self.running.as_mut()?;
+        let value = running.log.get(running.replayed)?.clone();
+        running.replayed += 1;
+        self.catch_up();
+        Some(value)
+    }
+
+    //falling asleep unwinds the coroutine like an error would, resume tells the two apart
+    fn fall_asleep(&mut self, why: Resumed) -> String {
+        if let Some(running) = &mut self.running {
+            running.falling_asleep = Some(why);
+        }
+        "Asleep!".to_string()
+    }
+
+    //moves the play back on to newer code wherever it has got as far as an era went
+    fn catch_up(&mut self) {
+        let Some(mut running) = self.running.take() else { return };
+        while let Some(era) = running.eras.get_mut(running.era)
+            && era.until == (running.replayed, running.paid) {
+            era.swap(self);
+            running.era += 1;
+            if let Some(next) = running.eras.get_mut(running.era) {
+                next.swap(self);
+            }
+        }
+        self.running = Some(running);
+    }
+
+    //the code as it is right now, for end_era
+    fn era(&self) -> Era {
+        Era {
+            until: (0, 0),
+            modules: self.modules.clone(),
+            rites: self.rites.clone(),
+            global_names: self.global_names.clone(),
+        }
+    }
+
+    //a reload that changes what the module's rites or globals are leaves every coroutine an era of
+    //the code from before it. a coroutine that hasn't moved since the last one keeps just that,
+    //nothing has run on the code in between.
+    fn end_era(&mut self, era: Era) {
+        for coroutine in self.coroutines.values_mut() {
+            let until = (coroutine.log.len() + coroutine.asleep as usize, coroutine.steps);
+            if coroutine.eras.last().is_none_or(|last| last.until != until) {
+                coroutine.eras.push(Era {
+                    until,
+                    modules: era.modules.clone(),
+                    rites: era.rites.clone(),
+                    global_names: era.global_names.clone(),
+                });
+            }
+        }
+    }
+
+    //a coroutine out of a save, checked against the code that's loaded
+    fn restore_coroutine(&self, saved: &SavedCoroutine) -> Result<Coroutine, String> {
+        let Some(replay) = &saved.replay else {
+            return Err(saved_by_the_other_backend(saved.id, Backend::VM));
+        };
+        let broken = |err: String| format!("Coroutine {} in the save is broken! {}", saved.id, err);
+        let [frame] = saved.frames.as_slice() else {
+            let count = saved.frames.len();
+            return Err(broken(format!("It has {} rites on its stack instead of one.", count)));
+        };
+        let module = self.find_module(&frame.module).map_err(broken)?;
+        let rite = &frame.rite;
+        let stmt = *self.rites[module].get(rite).ok_or_else(|| broken(no_such_rite(rite)))?;
+        let decl = declaration(&self.modules[module], stmt, rite).map_err(broken)?;
+        let args = bind_arguments(rite, &parameters(decl), saved.stack.clone()).map_err(broken)?;
+        Ok(Coroutine {
+            module,
+            rite: frame.rite.clone(),
+            args,
+            log: replay.log.clone(),
+            steps: replay.steps,
+            asleep: saved.asleep,
+            eras: Vec::new(),
+            replayed: 0,
+            paid: 0,
+            era: 0,
+            falling_asleep: None,
+        })
+    }
+}
+
+///SESSIONS
+//a session (the repl, a debug console in the game) keeps feeding an already loaded module more
+//top level code, one bit at a time. it runs exactly like the module's own top level did, except
+//that a rite declared again replaces the old one instead of being an error.
+impl Interpreter {
+    pub fn extend(&mut self, module: &str, mut stmts: Vec<Stmt>) -> Result<(), String> {
+        let index = self.find_module(module)?;
+        validate_module(&stmts)?;
+        if stmts.iter().any(|stmt| matches!(stmt, Stmt::STATEMENT_IMPORT(_))) {
+            return Err("Can't summon anything from here, summon it in a script instead!".to_string());
+        }
+        if !self.unoptimised {
+            optimise(&mut stmts);
+        }
+
+        let module = Rc::get_mut(&mut self.modules[index])
+            .ok_or_else(|| format!("Module {} is still running!", module))?;
+        let first = module.stmts.len();
+        module.stmts.extend(stmts);
+        for (stmt_index, stmt) in module.stmts.iter().enumerate().skip(first) {
+            if let Some(decl) = rite_declarations(std::slice::from_ref(stmt)).first() {
+                self.rites[index].insert(decl.ident.name.clone(), stmt_index);
+                module.definitions.insert(decl.ident.name.clone());
+            }
+        }
+        for stmt in &module.stmts[first..] {
+            if let Stmt::STATEMENT_EXPORT(inner) = stmt {
+                let name = match inner.as_ref() {
+                    Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => &decl.ident.name,
+                    Stmt::STATEMENT_ASSIGNMENT(assignment) => &assignment.ident.name,
+                    _ => continue,
+                };
+                module.exports.insert(name.clone());
+            }
+        }
+        let globals = global_names(&module.stmts[first..]);
+        module.definitions.extend(globals.iter().cloned());
+        self.global_names[index].extend(globals);
+
+        let module = Rc::clone(&self.modules[index]);
+        let mut frame = Frame { module: index, scopes: Vec::new() };
+        self.fuel.fill(&self.limits);
+        self.exec_block(&mut frame, &module.stmts[first..])?;
+        self.events.subscribe_handlers(&module.id, &module.stmts[first..]);
+        Ok(())
+    }
+
+    //evaluates an expression as if it sat at the top level of a module
+    pub fn eval_in(&mut self, module: &str, expr: &Expr) -> Result<Value, String> {
+        let index = self.find_module(module)?;
+        let mut frame = Frame { module: index, scopes: Vec::new() };
+        self.fuel.fill(&self.limits);
+        self.eval(&mut frame, expr)
+    }
+
+    pub fn module(&self, id: &str) -> Option<Rc<Module>> {
+        let index = self.find_module(id).ok()?;
+        Some(Rc::clone(&self.modules[index]))
+    }
+
+    pub fn globals(&self, module: &str) -> Option<&HashMap<String, Value>> {
+        let index = self.find_module(module).ok()?;
+        self.globals.get(index)
+    }
+}
+
+impl ScriptRuntime for Interpreter {
+    fn register_native(&mut self, name: &str, native: NativeFn) {
+        self.natives.register(name, native);
+    }
+
+    fn load(&mut self, loader: ModuleLoader) -> Result<(), String> {
+        let mut modules = loader.into_modules();
+        for module in &modules {
+            validate_module(&module.stmts).map_err(|err| format!("In module {}: {}", module.id, err))?;
+        }
+        if !self.unoptimised {
+            for module in &mut modules {
+                optimise(&mut module.stmts);
+            }
+        }
+
+        let first = self.modules.len();
+        for module in modules {
+            let index = self.modules.len();
+            self.module_index.insert(module.id.clone(), index);
+            self.rites.push(rite_index(&module.stmts));
+            self.global_names.push(global_names(&module.stmts).into_iter().collect());
+            self.globals.push(HashMap::new());
+            self.modules.push(Rc::new(module));
+        }
+
+        //top level code runs once, dependencies first. a `ret` up there just ends it early.
+        for index in first..self.modules.len() {
+            let module = Rc::clone(&self.modules[index]);
+            let mut frame = Frame { module: index, scopes: Vec::new() };
+            self.fuel.fill(&self.limits);
+            self.exec_block(&mut frame, &module.stmts)?;
+        }
+        for module in &self.modules[first..] {
+            self.events.subscribe_handlers(&module.id, &module.stmts);
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<Value, String> {
+        let index = self.find_module(module)?;
+        if !self.rites[index].contains_key(rite) {
+            return Err(no_such_rite(rite));
+        }
+        self.fuel.fill(&self.limits);
+        self.call_rite(index, rite, args.to_vec())
+    }
+
+    fn global(&self, module: &str, name: &str) -> Option<Value> {
+        let index = self.find_module(module).ok()?;
+        self.globals[index].get(name).cloned()
+    }
+
+    fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    fn fuel_left(&self) -> Option<u64> {
+        self.fuel.left()
+    }
+
+    fn set_optimising(&mut self, optimising: bool) {
+        self.unoptimised = !optimising;
+    }
+
+    //the arguments are bound (and checked) right away and the call's step is paid for, the same as
+    //the vm does it
+    fn spawn(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<usize, String> {
+        let index = self.find_module(module)?;
+        let stmt = *self.rites[index].get(rite).ok_or_else(|| no_such_rite(rite))?;
+        let params = parameters(declaration(&self.modules[index], stmt, rite)?);
+        self.fuel.fill(&self.limits);
+        self.fuel.burn(&self.limits)?;
+        if self.limits.max_call_depth == 0 {
+            return Err(stack_overflow(&self.limits));
+        }
+        let args = bind_arguments(rite, &params, args.to_vec())?;
+
+        let id = self.next_coroutine;
+        self.next_coroutine += 1;
+        self.coroutines.insert(id, Coroutine {
+            module: index,
+            rite: rite.to_string(),
+            args,
+            log: Vec::new(),
+            steps: 1,
+            asleep: false,
+            eras: Vec::new(),
+            replayed: 0,
+            paid: 0,
+            era: 0,
+            falling_asleep: None,
+        });
+        Ok(id)
+    }
+
+    fn resume(&mut self, coroutine: usize, value: Value) -> Result<Resumed, String> {
+        let mut running = self.coroutines.remove(&coroutine)
+            .ok_or_else(|| no_such_coroutine(coroutine))?;
+        if running.asleep {
+            running.log.push(value);
+        }
+        (running.replayed, running.paid, running.era) = (0, 0, 0);
+        if let Some(era) = running.eras.first_mut() {
+            era.swap(self);
+        }
+        let (module, rite, args) = (running.module, running.rite.clone(), running.args.clone());
+        self.running = Some(running);
+        self.fuel.fill(&self.limits);
+        let result = self.call_rite(module, &rite, args);
+
+        let Some(mut running) = self.running.take() else {
+            return Err(no_such_coroutine(coroutine));
+        };
+        if let Some(era) = running.eras.get_mut(running.era) {
+            era.swap(self);
+        }
+        //on an error it just never gets put back
+        match running.falling_asleep.take() {
+            Some(resumed) => {
+                running.asleep = matches!(resumed, Resumed::YIELDED(_));
+                self.coroutines.insert(coroutine, running);
+                Ok(resumed)
+            },
+            None => result.map(Resumed::FINISHED),
+        }
+    }
+
+    fn cancel(&mut self, coroutine: usize) -> bool {
+        self.coroutines.remove(&coroutine).is_some()
+    }
+
+    fn save_state(&self) -> SaveState {
+        let modules = self.modules.iter().zip(&self.globals).map(|(module, globals)| {
+            let mut globals: Vec<(String, Value)> = globals.iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            globals.sort_by(|a, b| a.0.cmp(&b.0));
+            SavedModule { id: module.id.clone(), version: module_version(&module.stmts), globals }
+        }).collect();
+
+        //one that has run on code from before a reload can't be played back on today's, so it's
+        //saved under a rite that doesn't exist and a restore refuses it
+        let mut ids: Vec<usize> = self.coroutines.keys().copied().collect();
+        ids.sort();
+        let coroutines = ids.into_iter().map(|id| {
+            let coroutine = &self.coroutines[&id];
+            let rite = match coroutine.eras.is_empty() {
+                true => coroutine.rite.clone(),
+                false => format!("{} (before the reload)", coroutine.rite),
+            };
+            let module = self.modules[coroutine.module].id.clone();
+            SavedCoroutine {
+                id,
+                asleep: coroutine.asleep,
+                stack: coroutine.args.clone(),
+                frames: vec![SavedFrame { module, rite, ip: 0, base: 0 }],
+                replay: Some(SavedReplay { log: coroutine.log.clone(), steps: coroutine.steps }),
+            }
+        }).collect();
+        SaveState { rng: self.natives.rng, modules, coroutines }
+    }
+
+    fn restore_state(&mut self, state: &SaveState) -> Result<(), String> {
+        state.check_versions(|id| {
+            self.module_index.get(id).map(|index| module_version(&self.modules[*index].stmts))
+        })?;
+        for module in &state.modules {
+            let index = self.module_index[&module.id];
+            if let Some((name, _)) = module.globals.iter().find(|(name, _)| !self.global_names[index].contains(name)) {
+                return Err(not_a_global(&module.id, name));
+            }
+        }
+        let mut coroutines = HashMap::new();
+        for saved in &state.coroutines {
+            if coroutines.insert(saved.id, self.restore_coroutine(saved)?).is_some() {
+                return Err(format!("The save has coroutine {} twice!", saved.id));
+            }
+        }
+
+        for module in &state.modules {
+            let index = self.module_index[&module.id];
+            self.globals[index] = module.globals.iter().cloned().collect();
+        }
+        let next = coroutines.keys().max().map_or(0, |id| id + 1);
+        self.next_coroutine = self.next_coroutine.max(next);
+        self.coroutines = coroutines;
+        self.natives.rng = state.rng;
+        Ok(())
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        &mut self.natives.rng
+    }
+
+    fn set_locale(&mut self, locale: Option<Catalogue>) {
+        self.locale = locale;
+    }
+
+    //the top level code runs on the new module with the globals emptied, so what it sets can be
+    //told apart from what was there before
+    fn reload(&mut self, module: &str, source: &str) -> Result<Reload, String> {
+        let index = self.find_module(module)?;
+        let others: Vec<&Module> = self.modules.iter()
+            .filter(|other| other.id != module)
+            .map(|other| other.as_ref())
+            .collect();
+        let (new, mut reload) = prepare_reload(&self.modules[index], source, &others, !self.unoptimised)?;
+
+        let declared = global_names(&new.stmts);
+        let before = self.era();
+        let old_rites = std::mem::replace(&mut self.rites[index], rite_index(&new.stmts));
+        let old_names = std::mem::replace(&mut self.global_names[index], declared.iter().cloned().collect());
+        let old_globals = std::mem::take(&mut self.globals[index]);
+        let old = std::mem::replace(&mut self.modules[index], Rc::new(new));
+
+        let new = Rc::clone(&self.modules[index]);
+        let mut frame = Frame { module: index, scopes: Vec::new() };
+        self.fuel.fill(&self.limits);
+        if let Err(err) = self.exec_block(&mut frame, &new.stmts) {
+            self.modules[index] = old;
+            self.rites[index] = old_rites;
+            self.global_names[index] = old_names;
+            self.globals[index] = old_globals;
+            return Err(top_level_failed(module, err));
+        }
+
+        let mut fresh = std::mem::take(&mut self.globals[index]);
+        for name in declared {
+            let value = match old_globals.get(&name) {
+                Some(old) => Some(carry_over(&name, old.clone(), fresh.remove(&name), &mut reload)),
+                None => fresh.remove(&name),
+            };
+            if let Some(value) = value {
+                self.globals[index].insert(name, value);
+            }
+        }
+        resubscribe(&mut self.events, &old, &new, &mut reload);
+        let renamed = old_names != self.global_names[index];
+        if !reload.changed.is_empty() || !reload.removed.is_empty() || renamed {
+            self.end_era(before);
+        }
+        Ok(reload)
+    }
+
+    fn events(&mut self) -> &mut Events {
+        &mut self.events
+    }
+
+    fn has_rite(&self, module: &str, rite: &str) -> bool {
+        self.find_module(module).is_ok_and(|index| self.rites[index].contains_key(rite))
+    }
+}
+
+//rite name -> index of its top level statement
+fn rite_index(stmts: &[Stmt]) -> HashMap<String, usize> {
+    let mut rites = HashMap::new();
+    for (stmt_index, stmt) in stmts.iter().enumerate() {
+        if let Some(decl) = rite_declarations(std::slice::from_ref(stmt)).first() {
+            rites.insert(decl.ident.name.clone(), stmt_index);
+        }
+    }
+    rites
+}
+
+//the declaration behind a rite, from the index of its top level statement
+fn declaration<'a>(module: &'a Module, stmt: usize, name: &str) -> Result<&'a FnDeclaration, String> {
+    match &module.stmts[stmt] {
+        Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => Ok(decl),
+        Stmt::STATEMENT_EXPORT(inner) => match inner.as_ref() {
+            Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => Ok(decl),
+            _ => Err(no_such_rite(name)),
+        },
+        _ => Err(no_such_rite(name)),
+    }
+}
+
+fn parameters(decl: &FnDeclaration) -> Vec<(String, TokenType)> {
+    decl.params.iter().map(|param| (param.ident.name.clone(), param.type_t.clone())).collect()
+}
@@ -0,0 +1,349 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+///JSON section
+//just enough JSON for the tooling (the language server speaks it, and so do editors' grammar
+//files). Objects keep their keys in the order they were written, which is what people expect
+//when they read the output. Numbers are f64, like in javascript.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    NULL,
+    BOOL(bool),
+    NUMBER(f64),
+    STRING(String),
+    ARRAY(Vec<Json>),
+    OBJECT(Vec<(String, Json)>),
+}
+
+impl Json {
+    //builds an object out of key/value pairs, `Json::object([("line", Json::from(3))])`
+    pub fn object<const N: usize>(pairs: [(&str, Json); N]) -> Json {
+        Json::OBJECT(pairs.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::OBJECT(pairs) => pairs.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    //digs through nested objects, `message.path(&["params", "textDocument", "uri"])`
+    pub fn path(&self, keys: &[&str]) -> Option<&Json> {
+        keys.iter().try_fold(self, |json, key| json.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::STRING(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::NUMBER(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::ARRAY(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    //sets a key on an object, replacing whatever was there
+    pub fn set(&mut self, key: &str, value: Json) {
+        if let Json::OBJECT(pairs) = self {
+            match pairs.iter_mut().find(|(name, _)| name == key) {
+                Some(pair) => pair.1 = value,
+                None => pairs.push((key.to_owned(), value)),
+            }
+        }
+    }
+
+    //compact, all on one line
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        let mut ret = String::new();
+        self.write(&mut ret, None, 0);
+        ret
+    }
+
+    //one thing per line, indented by `indent` spaces per level
+    pub fn to_pretty_string(&self, indent: usize) -> String {
+        let mut ret = String::new();
+        self.write(&mut ret, Some(indent), 0);
+        ret
+    }
+
+    fn write(&self, ret: &mut String, indent: Option<usize>, level: usize) {
+        let newline = |ret: &mut String, level: usize| {
+            if let Some(indent) = indent {
+                ret.push('\n');
+                ret.push_str(&" ".repeat(indent * level));
+            }
+        };
+        match self {
+            Json::NULL => ret.push_str("null"),
+            Json::BOOL(value) => ret.push_str(if *value { "true" } else { "false" }),
+            //json has no NaN or infinities
+            Json::NUMBER(number) if !number.is_finite() => ret.push_str("null"),
+            Json::NUMBER(number) => ret.push_str(&number.to_string()),
+            Json::STRING(text) => write_string(ret, text),
+            Json::ARRAY(items) if items.is_empty() => ret.push_str("[]"),
+            Json::ARRAY(items) => {
+                ret.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        ret.push(',');
+                    }
+                    newline(ret, level + 1);
+                    item.write(ret, indent, level + 1);
+                }
+                newline(ret, level);
+                ret.push(']');
+            },
+            Json::OBJECT(pairs) if pairs.is_empty() => ret.push_str("{}"),
+            Json::OBJECT(pairs) => {
+                ret.push('{');
+                for (index, (key, value)) in pairs.iter().enumerate() {
+                    if index > 0 {
+                        ret.push(',');
+                    }
+                    newline(ret, level + 1);
+                    write_string(ret, key);
+                    ret.push(':');
+                    if indent.is_some() {
+                        ret.push(' ');
+                    }
+                    value.write(ret, indent, level + 1);
+                }
+                newline(ret, level);
+                ret.push('}');
+            },
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Json {
+        Json::STRING(text.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Json {
+        Json::STRING(text)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::BOOL(value)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(number: f64) -> Json {
+        Json::NUMBER(number)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Json {
+        Json::NUMBER(number as f64)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(number: u32) -> Json {
+        Json::NUMBER(number as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        Json::ARRAY(items)
+    }
+}
+
+fn write_string(ret: &mut String, text: &str) {
+    ret.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+}
+
+///PARSING
+
+pub fn parse_json(text: &str) -> Result<Json, String> {
+    let mut reader = Reader { bytes: text.as_bytes(), at: 0 };
+    let json = reader.value()?;
+    reader.skip_whitespace();
+    match reader.at == reader.bytes.len() {
+        true => Ok(json),
+        false => Err(format!("Junk after the JSON at byte {}!", reader.at)),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.at).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.at).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.peek() {
+            Some(found) if found == byte => {
+                self.at += 1;
+                Ok(())
+            },
+            _ => Err(format!("Expected '{}' at byte {} of the JSON!", byte as char, self.at)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, json: Json) -> Result<Json, String> {
+        match self.bytes[self.at..].starts_with(word.as_bytes()) {
+            true => {
+                self.at += word.len();
+                Ok(json)
+            },
+            false => Err(format!("Can't make sense of the JSON at byte {}!", self.at)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::NULL),
+            Some(b't') => self.keyword("true", Json::BOOL(true)),
+            Some(b'f') => self.keyword("false", Json::BOOL(false)),
+            Some(b'"') => Ok(Json::STRING(self.string()?)),
+            Some(b'[') => {
+                self.at += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.at += 1;
+                    return Ok(Json::ARRAY(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b']')?;
+                Ok(Json::ARRAY(items))
+            },
+            Some(b'{') => {
+                self.at += 1;
+                let mut pairs = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.at += 1;
+                    return Ok(Json::OBJECT(pairs));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return Err(format!("Expected a key at byte {} of the JSON!", self.at));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    pairs.push((key, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b'}')?;
+                Ok(Json::OBJECT(pairs))
+            },
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(format!("Can't make sense of the JSON at byte {}!", self.at)),
+            None => Err("The JSON ends too early!".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        while self.bytes.get(self.at).is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.at += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.at]).unwrap_or("");
+        text.parse::<f64>()
+            .map(Json::NUMBER)
+            .map_err(|_| format!("{} is not a number!", text))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.at..self.at + 4).ok_or("The JSON ends in the middle of an escape!")?;
+        let digits = std::str::from_utf8(digits).map_err(|err| err.to_string())?;
+        self.at += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| format!("\\u{} is not an escape!", digits))
+    }
+
+    //the opening quote is next
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut ret: Vec<u8> = Vec::new();
+        loop {
+            let byte = *self.bytes.get(self.at).ok_or("The JSON ends in the middle of a string!")?;
+            self.at += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.bytes.get(self.at).ok_or("The JSON ends in the middle of an escape!")?;
+                    self.at += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            //characters outside the basic plane come as two escapes
+                            if (0xD800..0xDC00).contains(&code) && self.bytes[self.at..].starts_with(b"\\u") {
+                                self.at += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            char::from_u32(code).unwrap_or('\u{FFFD}')
+                        },
+                        other => return Err(format!("\\{} is not an escape!", other as char)),
+                    };
+                    let mut buffer = [0u8; 4];
+                    ret.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                other => ret.push(other),
+            }
+        }
+        String::from_utf8(ret).map_err(|err| err.to_string())
+    }
+}
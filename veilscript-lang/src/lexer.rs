@@ -1,124 +1,191 @@
-use logos::Logos;
-
-#[allow(non_camel_case_types)]
-#[derive(Logos, Debug, PartialEq, Clone)]
-pub enum TokenType {
-    //keywords
-    #[token("fn")]
-    #[token("rite")]
-    FN,
-
-    #[token("return")]
-    #[token("ret")]
-    RETURN,
-
-    //types
-    #[token("float")]
-    #[token("num")]
-    #[token("numeric")]
-    TYPE_FLOAT,
-
-    #[token("int")]
-    EXPERIMENTAL_TYPE_INT,
-
-    #[token("rune")]
-    #[token("string")]
-    TYPE_STRING,
-
-    #[token("void")]
-    #[token("nothing")]
-    #[token("null")]
-    TYPE_VOID,
-    
-    //punctuation
-    #[token("=")]
-    EQUALS,
-
-    #[token(":")]
-    COLON,
-
-    #[token("::")]
-    DOUBLE_COLON,
-
-    #[token(".")]
-    DOT,
-
-    #[token("->")]
-    ARROW,
-
-    #[token(",")]
-    COMMA,
-
-    #[token(";")]
-    SEMICOLON,
+#![allow(clippy::empty_line_after_doc_comments)]
 
-    #[token("(")]
-    LPAREN,
-
-    #[token(")")]
-    RPAREN,
-
-    #[token("{")]
-    LBRACE,
-
-    #[token("}")]
-    RBRACE,
-
-    #[token("+")]
-    PLUS,
+use logos::Logos;
 
-    #[token("-")]
-    MINUS,
+//TokenType, SPELLINGS and the *_PATTERN constants all come out of this one list, so tooling
+//can't drift from what the lexer really takes:
+//  - `KIND = ["spelling", ...]` is a fixed spelling (aliases included). each one is a `#[token]`
+//    and goes into SPELLINGS.
+//  - `KIND = r"regex" as NAME` is a token the lexer matches with a regex. it's a `#[regex]` and
+//    `NAME` is a constant holding the regex.
+//  - anything else is passed through as it is.
+macro_rules! token_types {
+    (
+        spelled { $( $kind:ident = [ $( $spelling:literal ),+ ], )* }
+        patterned { $( $pattern_kind:ident = $pattern:literal as $pattern_name:ident, )* }
+        other { $( $( #[ $( $attribute:tt )* ] )* $other_kind:ident, )* }
+    ) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Logos, Debug, PartialEq, Clone)]
+        pub enum TokenType {
+            $( $( #[token($spelling)] )+ $kind, )*
+            $( #[regex($pattern)] $pattern_kind, )*
+            $( $( #[ $( $attribute )* ] )* $other_kind, )*
+        }
+
+        //every fixed spelling the lexer knows, aliases included, in the order they're declared.
+        //tooling (highlighting, editor grammars, completion) reads this instead of hardcoding
+        //keywords, and highlight::verify_spellings makes sure each one lexes to what it says.
+        pub const SPELLINGS: &[(&str, TokenType)] = &[ $( $( ($spelling, TokenType::$kind), )+ )* ];
+
+        //the patterns behind the tokens that aren't fixed spellings
+        $( pub const $pattern_name: &str = $pattern; )*
+    };
+}
 
-    #[token("/")]
-    SLASH,
+token_types! {
+    spelled {
+        //keywords
+        FN = ["fn", "rite"],
+        RETURN = ["return", "ret"],
+        IMPORT = ["import", "summon"],
+        EXPORT = ["export", "pub"],
+        AS = ["as"],
+        YIELD = ["yield", "slumber"],
+        DIALOGUE = ["dialogue"],
+        NODE = ["node"],
+        CHOICE = ["choice"],
+        IF = ["if"],
+        ON = ["on"],
+
+        //types
+        TYPE_FLOAT = ["float", "num", "numeric"],
+        EXPERIMENTAL_TYPE_INT = ["int"],
+        TYPE_STRING = ["rune", "string"],
+        TYPE_VOID = ["void", "nothing", "null"],
+
+        //punctuation
+        EQUALS = ["="],
+        COLON = [":"],
+        DOUBLE_COLON = ["::"],
+        DOT = ["."],
+        ARROW = ["->"],
+        COMMA = [","],
+        SEMICOLON = [";"],
+        LPAREN = ["("],
+        RPAREN = [")"],
+        LBRACE = ["{"],
+        RBRACE = ["}"],
+        PLUS = ["+"],
+        MINUS = ["-"],
+        SLASH = ["/"],
+        ASTERISK = ["*"],
+        DOUBLE_ASTERISK = ["**"],
+        PERCENT = ["%"],
+        AMPERSAND = ["&"],
+        PIPE = ["|"],
+        CARET = ["^"],
+        SHIFT_LEFT = ["<<"],
+        SHIFT_RIGHT = [">>"],
+    }
 
-    #[token("*")]
-    ASTERISK,
+    patterned {
+        //literals
+        LITERAL_STRING = r#""([^"\\]|\\.)*""# as STRING_PATTERN,
+        LITERAL_FLOAT = r"[0-9]+\.[0-9]+" as FLOAT_PATTERN,
+        //`3d6`, `1d20`. a bonus is just an addition (`1d20 + 5`), so it binds like any other one.
+        LITERAL_DICE = r"[0-9]+d[0-9]+" as DICE_PATTERN,
+        LITERAL_INT = r"[0-9]+" as INT_PATTERN,
+        IDENTIFIER = r"[a-zA-Z_][a-zA-Z0-9_]*" as IDENTIFIER_PATTERN,
+    }
 
+    other {
+        //covers `///` doc comments too. none of these ever reach the parser, see tokenise_lossless
+        //for the stream that keeps them.
+        #[regex(r"//[^\n]*", logos::skip)]
+        COMMENT,
 
-    //literals
-    #[regex(r#""([^"\\]|\\.)*""#)]
-    LITERAL_STRING,
+        #[token("/*", skip_block_comment)]
+        BLOCK_COMMENT,
 
-    #[regex(r"[0-9]+\.[0-9]+")]
-    LITERAL_FLOAT,
+        #[regex(r"[ \t\n\r\f]+", logos::skip)]
+        WHITESPACE,
 
-    #[regex(r"[0-9]+")]
-    LITERAL_INT,
+        //fallback
+        #[regex(r".", priority=0)]
+        ERROR,
 
-    #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*")]
-    IDENTIFIER,
+        //last reached
+        EOF,
+    }
+}
 
-    #[regex(r"//[^\n]*", logos::skip)]
-    COMMENT,
+//block comments nest, so `/* a /* b */ c */` is one comment. `rest` is everything after the
+//opening `/*`; hands back how much of it the comment takes up, or None if it never ends.
+pub fn block_comment_length(rest: &str) -> Option<usize> {
+    let bytes = rest.as_bytes();
+    let mut depth = 1;
+    let mut at = 0;
+    while at + 1 < bytes.len() {
+        match (bytes[at], bytes[at + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                at += 2;
+            },
+            (b'*', b'/') => {
+                depth -= 1;
+                at += 2;
+                if depth == 0 {
+                    return Some(at);
+                }
+            },
+            _ => at += 1,
+        }
+    }
+    None
+}
 
-    #[regex(r"[ \t\n\r\f]+", logos::skip)]
-    WHITESPACE,
+fn skip_block_comment(lexer: &mut logos::Lexer<TokenType>) -> logos::FilterResult<(), ()> {
+    let rest = lexer.remainder();
+    match block_comment_length(rest) {
+        Some(length) => {
+            lexer.bump(length);
+            logos::FilterResult::Skip
+        },
+        //an unclosed comment eats the rest of the file and becomes an error
+        None => {
+            lexer.bump(rest.len());
+            logos::FilterResult::Error(())
+        },
+    }
+}
 
-    //fallback
-    #[regex(r".", priority=0)]
-    ERROR,
-    
-    //last reached
-    EOF
+//where a token sits in its source: byte offsets, plus the (1 based) line it starts on
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token<'src> {
     pub lexeme: &'src str,
     pub kind: TokenType,
+    pub span: Span,
 }
 
-pub fn tokenise(source_string: &str) -> Vec<Token> {
+pub fn tokenise(source_string: &str) -> Vec<Token<'_>> {
     let mut result_vector= Vec::<Token>::new();
     let mut lexer = TokenType::lexer(source_string);
 
-    while let Some(Ok(kind)) = lexer.next() {
+    //lines are counted as we go, so nobody has to rescan the source to report one
+    let mut line: u32 = 1;
+    let mut counted_up_to = 0;
+
+    while let Some(result) = lexer.next() {
+        //whatever logos refuses becomes an ERROR token, so the parser complains about it instead
+        //of the rest of the file quietly vanishing
+        let kind = result.unwrap_or(TokenType::ERROR);
         let lexeme = lexer.slice();
-        result_vector.push( Token{lexeme, kind} );
+        let range = lexer.span();
+        line += source_string[counted_up_to..range.start].matches('\n').count() as u32;
+        counted_up_to = range.start;
+        result_vector.push( Token{lexeme, kind, span: Span{start: range.start, end: range.end, line}} );
     }
-    result_vector.push( Token{lexeme:"STOP", kind:TokenType::EOF});
+    let end = source_string.len();
+    line += source_string[counted_up_to..].matches('\n').count() as u32;
+    result_vector.push( Token{lexeme:"STOP", kind:TokenType::EOF, span: Span{start: end, end, line}});
     result_vector
 }
 
@@ -128,3 +195,148 @@ pub fn print_tokens_from_string(source_string: &str){
         println!("{:?} -> {}", token.kind, token.lexeme);
     }
 }
+
+///LOSSLESS section
+//tokenise throws comments and whitespace away, which is exactly what the parser wants and exactly
+//what the formatter and other tooling don't. tokenise_lossless gives back the same tokens, but
+//every bit of text between them is kept as TRIVIA hanging off a token:
+//  trailing trivia is whatever follows a token on its own line (up to, not including, the newline)
+//  leading trivia is everything else before a token, newlines included
+//so gluing every token's leading trivia, lexeme and trailing trivia back together gives back the
+//source byte for byte. whatever is left at the end of the file leads the EOF token.
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    WHITESPACE,    //spaces, tabs and friends, never a newline
+    NEWLINE,
+    LINE_COMMENT,  //two slashes to the end of the line
+    DOC_COMMENT,   //three slashes, documents whatever comes next
+    BLOCK_COMMENT, //slash star to star slash, nests and can span lines
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia<'src> {
+    pub kind: TriviaKind,
+    pub text: &'src str,
+    pub span: Span,
+}
+
+impl Trivia<'_> {
+    pub fn is_comment(&self) -> bool {
+        !matches!(self.kind, TriviaKind::WHITESPACE | TriviaKind::NEWLINE)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessToken<'src> {
+    pub leading: Vec<Trivia<'src>>,
+    pub token: Token<'src>,
+    pub trailing: Vec<Trivia<'src>>,
+}
+
+impl LosslessToken<'_> {
+    //the `///` lines right above this token, with the slashes taken off. a blank line or any
+    //other comment in between means they belong to something else.
+    pub fn doc_comment(&self) -> Option<String> {
+        let mut lines: Vec<&str> = Vec::new();
+        let mut newlines = 0;
+        for trivia in self.leading.iter().rev() {
+            match trivia.kind {
+                TriviaKind::WHITESPACE => {},
+                TriviaKind::NEWLINE => {
+                    newlines += 1;
+                    if newlines > 1 {
+                        break;
+                    }
+                },
+                TriviaKind::DOC_COMMENT => {
+                    newlines = 0;
+                    let text = &trivia.text[3..];
+                    lines.push(text.strip_prefix(' ').unwrap_or(text));
+                },
+                TriviaKind::LINE_COMMENT | TriviaKind::BLOCK_COMMENT => break,
+            }
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        lines.reverse();
+        Some(lines.join("\n"))
+    }
+}
+
+fn is_doc_comment(text: &str) -> bool {
+    text.starts_with("///") && !text.starts_with("////")
+}
+
+//splits the text between two tokens into trivia. `start` is where `gap` begins in the source and
+//`line` the line it begins on.
+fn split_trivia(gap: &str, start: usize, mut line: u32) -> Vec<Trivia<'_>> {
+    let mut pieces = Vec::new();
+    let mut at = 0;
+    while at < gap.len() {
+        let rest = &gap[at..];
+        let (kind, length) = if rest.starts_with('\n') {
+            (TriviaKind::NEWLINE, 1)
+        } else if let Some(inside) = rest.strip_prefix("/*") {
+            //the lexer already made sure it's closed
+            (TriviaKind::BLOCK_COMMENT, 2 + block_comment_length(inside).unwrap_or(inside.len()))
+        } else if rest.starts_with("//") {
+            let length = rest.find('\n').unwrap_or(rest.len());
+            let kind = match is_doc_comment(rest) {
+                true => TriviaKind::DOC_COMMENT,
+                false => TriviaKind::LINE_COMMENT,
+            };
+            (kind, length)
+        } else {
+            let length = rest.find(['\n', '/']).unwrap_or(rest.len());
+            (TriviaKind::WHITESPACE, length.max(1))
+        };
+        let text = &rest[..length];
+        pieces.push(Trivia { kind, text, span: Span { start: start + at, end: start + at + length, line } });
+        line += text.matches('\n').count() as u32;
+        at += length;
+    }
+    pieces
+}
+
+pub fn tokenise_lossless(source_string: &str) -> Vec<LosslessToken<'_>> {
+    let mut result_vector: Vec<LosslessToken> = Vec::new();
+    let mut gap_start = 0;
+    let mut gap_line = 1;
+
+    for mut token in tokenise(source_string) {
+        let gap = &source_string[gap_start..token.span.start];
+        let mut trivia = split_trivia(gap, gap_start, gap_line);
+
+        //up to the first newline belongs to the token before
+        if let Some(previous) = result_vector.last_mut() {
+            let cut = trivia.iter().position(|piece| piece.kind == TriviaKind::NEWLINE).unwrap_or(trivia.len());
+            previous.trailing = trivia.drain(..cut).collect();
+        }
+
+        if token.kind == TokenType::EOF {
+            token.lexeme = "";
+        }
+        gap_line = token.span.line + token.lexeme.matches('\n').count() as u32;
+        gap_start = token.span.end;
+        result_vector.push(LosslessToken { leading: trivia, token, trailing: Vec::new() });
+    }
+    result_vector
+}
+
+//glues a lossless stream back into source text. tokenise_lossless followed by this is a no-op.
+pub fn lossless_to_string(tokens: &[LosslessToken]) -> String {
+    let mut ret = String::new();
+    for token in tokens {
+        for trivia in &token.leading {
+            ret += trivia.text;
+        }
+        ret += token.token.lexeme;
+        for trivia in &token.trailing {
+            ret += trivia.text;
+        }
+    }
+    ret
+}
@@ -0,0 +1,36 @@
+//lints that fight the way this codebase is written (SCREAMING_SNAKE_CASE variants, `///` section
+//headers, to_string on everything) are allowed where they come up, not for the whole crate
+
+
+pub mod lexer;
+pub mod parser;
+pub mod ast;
+pub mod visit;
+pub mod value;
+pub mod precedence;
+pub mod module;
+pub mod source;
+pub mod runtime;
+pub mod interpreter;
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod bytecode_file;
+pub mod disasm;
+pub mod format;
+pub mod diagnostic;
+pub mod typeck;
+pub mod repl;
+pub mod json;
+pub mod lsp;
+pub mod highlight;
+pub mod resolve;
+pub mod lint;
+pub mod optimise;
+pub mod save;
+pub mod random;
+pub mod dialogue;
+pub mod locale;
+pub mod events;
+pub mod reload;
+mod libparse;
@@ -1,8 +1,13 @@
 #![allow(unused_doc_comments)]
+#![allow(clippy::empty_line_after_doc_comments)]
 
 use crate::parser::Parser;
-use crate::lexer::{TokenType};
+use crate::lexer::{tokenise, TokenType};
 use crate::ast::*;
+use crate::dialogue::{split_text, Piece};
+use crate::random::Dice;
+use crate::value::unescape_string_literal;
+use crate::precedence::{lookup_infix, lookup_postfix, lookup_prefix, PostfixKind};
 
 impl<'a> Parser<'a> {
 
@@ -35,6 +40,13 @@ impl<'a> Parser<'a> {
                 Ok(Atom::LITERAL_FLOAT(parsed_value))
             },
 
+            //parse DICE
+            TokenType::LITERAL_DICE => {
+                let parsed_value = Dice::parse(token.lexeme)?;
+                self.advance();
+                Ok(Atom::LITERAL_DICE(parsed_value))
+            },
+
             //parse STRINGS
             TokenType::LITERAL_STRING => {
                 let lexeme = token.lexeme;
@@ -45,10 +57,7 @@ impl<'a> Parser<'a> {
             
             //parse IDENTIFIERS
             TokenType::IDENTIFIER => {
-                let lexeme = token.lexeme;
-                let parsed_value = lexeme.to_owned();
-                self.advance();
-                Ok(Atom::IDENTIFIER(Ident{name:parsed_value}))
+                Ok(Atom::IDENTIFIER(self.parse_path_ident()?))
             },
 
             _ => Err(format!(
@@ -91,19 +100,18 @@ impl<'a> Parser<'a> {
     pub fn parse_unary_expr(&mut self) -> Result<Expr, String> {
 
         let token = self.peek_and_extract()?;
-        match token.kind { 
-            TokenType::PLUS | TokenType::MINUS => {
-                let opcode = if token.kind == TokenType::PLUS {MonOp::POS} else {MonOp::NEG};
+        match lookup_prefix(&token.kind) {
+            Some(prefix) => {
                 self.advance(); //move past the unary
 
                 //grab the next expr 
-                let expr = Box::new(self.parse_expr(opcode.get_precedence()+1)?);
+                let expr = Box::new(self.parse_expr(prefix.right_binding_power())?);
                 Ok(Expr::UNARY_EXPR{
-                    opcode,
+                    opcode: prefix.opcode,
                     expr
                 })
             },
-            _ => Err("Not a valid unary! You insane or what?".to_string())
+            None => Err("Not a valid unary! You insane or what?".to_string())
         }
     }
     
@@ -133,85 +141,109 @@ impl<'a> Parser<'a> {
         }
     }
 
-    ///MATCHES: IDENTIFIER LPAREN Vec<Expr> RPAREN 
-    pub fn parse_fn_or_group(&mut self) -> Result<Expr, String> {
-        let expr = self.parse_group_or_atom()?;
-        match expr {
-            //a function call could POTENTIALLY happen here
-            Expr::ATOM(Atom::IDENTIFIER(ref id)) => {
-                //IDENTIFIER MATCH section
-                match self.peek_and_extract()?.kind {
-
-                    //identifier(args)
-                    TokenType::LPAREN => {
-                        self.advance(); //head past the lparen
-                        let args = Box::new(self.parse_args()?);
-                        let fncall = Expr::FUNCTION_CALL(FnCall{
-                            ident: id.clone(), args
-                        });
-                        return Ok(fncall);
-                    },
-                    _ => {return Ok(expr);}
+    ///MATCHES: Expr (LPAREN Vec<Expr> RPAREN | DOT IDENTIFIER [LPAREN Vec<Expr> RPAREN])
+    //the operator token is still sitting in front of us when we get here
+    pub fn parse_postfix(&mut self, left: Expr, kind: PostfixKind) -> Result<Expr, String> {
+        self.advance(); //move past the '(' or '.'
+        match kind {
+            PostfixKind::CALL => {
+                //only named rites can be called for now, FnCall holds an ident and not an expr
+                let ident = match left {
+                    Expr::ATOM(Atom::IDENTIFIER(ident)) => ident,
+                    other => return Err(format!(
+                        "Only rites can be called, and {} is no rite!", other.to_pretty_string()
+                    )),
+                };
+                let args = Box::new(self.parse_args()?);
+                Ok(Expr::FUNCTION_CALL(FnCall{ ident, args }))
+            },
+            PostfixKind::MEMBER => {
+                let ident = self.parse_next_ident()?;
+                let base = Box::new(left);
+                if self.peek_and_extract()?.kind == TokenType::LPAREN {
+                    self.advance(); //head past the lparen
+                    let args = Box::new(self.parse_args()?);
+                    Ok(Expr::METHOD_CALL(MethodCall{ base, call: FnCall{ ident, args } }))
+                } else {
+                    Ok(Expr::FIELD_ACCESS(FieldAccess{ base, access: ident }))
                 }
-            }, 
-            _ => {return Ok(expr);}
+            },
         }
     }
 
-    pub fn parse_expr(&mut self, current_precedence: u8) -> Result<Expr, String> {
+    ///pratt parser. every operator decision is made by the tables in precedence.rs.
+    ///min_binding_power is how tightly the caller is already holding on to what we parse.
+    pub fn parse_expr(&mut self, min_binding_power: u8) -> Result<Expr, String> {
         
         //handle the possible 9000 clusterfucks a small group can extend into
         let token = self.peek_and_extract()?;
         let mut left = match token.kind {
-            TokenType::PLUS | TokenType::MINUS => self.parse_unary_expr()?,
             TokenType::LBRACE => self.parse_scoped_expr()?,
-            _ => self.parse_fn_or_group()?,
+            TokenType::YIELD => Expr::YIELD(self.parse_yield()?),
+            ref kind if lookup_prefix(kind).is_some() => self.parse_unary_expr()?,
+            _ => self.parse_group_or_atom()?,
         };
 
         loop {
+            let kind = self.peek_and_extract()?.kind;
 
-            let operator = match self.peek_and_extract()?.kind {
-                TokenType::PLUS => BinOp::ADD,
-                TokenType::MINUS => BinOp::SUB,
-                TokenType::SLASH => BinOp::DIV,
-                TokenType::ASTERISK => BinOp::MULT,
-                TokenType::EOF | TokenType::RPAREN 
-                            | TokenType::SEMICOLON  => break, //break out if the next is EOF or the
-                                                              //end of a grouping (indicated by RPAREN)
-                                                              //or a semicolon (end of statement) 
-                _ => return Err(format!("Invalid token type detected! Fix your shit, dumbass.")),
-            };
-
-            let precedence = operator.get_precedence();
-            if precedence < current_precedence {
-                break; //stop building
+            if let Some(postfix) = lookup_postfix(&kind) {
+                if postfix.left_binding_power() < min_binding_power {
+                    break;
+                }
+                left = self.parse_postfix(left, postfix.kind)?;
+                continue;
             }
 
-            self.advance(); //look at the token on the right
-            let right = self.parse_expr(precedence+1)?; //recurse lol
+            if let Some(infix) = lookup_infix(&kind) {
+                let (left_binding_power, right_binding_power) = infix.binding_power();
+                if left_binding_power < min_binding_power {
+                    break; //stop building
+                }
+                self.advance(); //look at the token on the right
+                let right = self.parse_expr(right_binding_power)?; //recurse lol
+                left = Expr::BINARY_EXPR {
+                    left: Box::new(left),
+                    opcode: infix.opcode,
+                    right: Box::new(right),
+                };
+                continue;
+            }
 
-            left = Expr::BINARY_EXPR {
-                left: Box::new(left),
-                opcode: operator,
-                right: Box::new(right),
-            };
+            //not an operator -> the expression ends here, and whoever called us gets to decide
+            //whether that token (a ';', ')', ',' and friends) is actually allowed to be there
+            break;
         }
 
         Ok(left)
     }
 
     pub fn parse_full_expr(&mut self) -> Result<Expr, String> {
-        Ok(self.parse_expr(0)?)
+        self.parse_expr(0)
     }
     
     //grabbing an ident with the helpers in parser.rs is tricky because they return the tokentype,
     //not the token itself. i made this to make my life a little easier. this advances forward, so
     //be EXTREMELY sure the next tokentype is for sure syntactically an IDENTIFIER.
     pub fn parse_next_ident(&mut self) -> Result<Ident, String> {
-        let token = self.advance_and_extract()?;
+        let token = self.peek_and_extract()?;
         Parser::check_for(token.clone(), TokenType::IDENTIFIER)?;
-        let name = token.lexeme.to_owned();
-        Ok(Ident{name})
+        self.advance();
+        Ok(Ident::with_span(token.lexeme, token.span))
+    }
+
+    ///MATCHES: IDENTIFIER [DOUBLE_COLON IDENTIFIER]*
+    //same deal as parse_next_ident, except it keeps going through `module::thing` paths.
+    pub fn parse_path_ident(&mut self) -> Result<Ident, String> {
+        let mut ident = self.parse_next_ident()?;
+        while self.peek_and_extract()?.kind == TokenType::DOUBLE_COLON {
+            self.advance(); //move past the '::'
+            let next = self.parse_next_ident()?;
+            ident.namespace.push(ident.name);
+            ident.name = next.name;
+            ident.span.end = next.span.end;
+        }
+        Ok(ident)
     }
     
     ///MATCHES: Vec<Parameter> RPAREN
@@ -219,12 +251,9 @@ impl<'a> Parser<'a> {
         let mut params: Vec<Parameter> = Vec::new();
         
         //in case there aren't any parameters
-        match self.check_next_contains(&[TokenType::RPAREN, TokenType::IDENTIFIER])? {
-            TokenType::RPAREN => {
-                self.advance();
-                return Ok(params);
-            }
-            _ => {}
+        if self.check_next_contains(&[TokenType::RPAREN, TokenType::IDENTIFIER])? == TokenType::RPAREN {
+            self.advance();
+            return Ok(params);
         }
         
         //keep getting params
@@ -245,32 +274,249 @@ impl<'a> Parser<'a> {
     }
 
 
-    ///MATCHES: FN IDENTIFIER LPAREN Vec<Parameter> RPAREN ARROW TYPE_T       // {scope}
+    ///MATCHES: FN IDENTIFIER LPAREN Vec<Parameter> RPAREN [ARROW TYPE_T] Scope
     pub fn parse_function_declaration(&mut self) -> Result<Stmt, String> {
         self.check_advance(TokenType::FN)?;
         let ident = self.parse_next_ident()?;
         self.check_advance(TokenType::LPAREN)?;
         let params = self.parse_params()?;
 
-        match self.check_next_contains(&[TokenType::LBRACE, TokenType::ARROW])? {
-            TokenType::LBRACE => Ok(Stmt::STATEMENT_FUNCTION_DECLARATION(FnDeclaration{ident,params,type_t: TokenType::TYPE_VOID})),
+        let type_t = match self.check_next_contains(&[TokenType::LBRACE, TokenType::ARROW])? {
             TokenType::ARROW => {
                 self.advance();
-                let type_t = self.advance_and_extract()?.kind;
-                return Ok(Stmt::STATEMENT_FUNCTION_DECLARATION(FnDeclaration{ident,params,type_t}));
+                self.advance_and_extract()?.kind
             },
-            _ => Err("".to_string())
+            _ => TokenType::TYPE_VOID,
+        };
+        let body = match self.parse_scope()? {
+            Stmt::SCOPE(scope) => scope,
+            other => return Err(format!("Expected the body of {}, found: {:?}", ident.name, other)),
+        };
+        Ok(Stmt::STATEMENT_FUNCTION_DECLARATION(FnDeclaration{ident,params,type_t,body,event: None}))
+    }
+
+    ///MATCHES: ON LITERAL_STRING LPAREN Vec<Parameter> RPAREN Scope
+    //a handler gives nothing back, whoever emitted the event isn't waiting for an answer
+    pub fn parse_event_handler(&mut self) -> Result<Stmt, String> {
+        self.check_advance(TokenType::ON)?;
+        let token = self.peek_and_extract()?;
+        Parser::check_for(token.clone(), TokenType::LITERAL_STRING)?;
+        let event = unescape_string_literal(token.lexeme);
+        if event.is_empty() {
+            return Err("An event needs a name, \"\" isn't one!".to_string());
+        }
+        self.advance();
+        self.handlers += 1;
+        let ident = Ident { name: format!("on {} #{}", event, self.handlers), namespace: Vec::new(), span: token.span };
+
+        self.check_advance(TokenType::LPAREN)?;
+        let params = self.parse_params()?;
+        if self.check_next_contains(&[TokenType::LBRACE, TokenType::ARROW])? == TokenType::ARROW {
+            return Err(format!("The handler for {} can't give anything back, nobody would get it!", token.lexeme));
         }
+        let body = match self.parse_scope()? {
+            Stmt::SCOPE(scope) => scope,
+            other => return Err(format!("Expected the body of the handler for {}, found: {:?}", token.lexeme, other)),
+        };
+        let event = Some(token.lexeme.to_owned());
+        Ok(Stmt::STATEMENT_FUNCTION_DECLARATION(FnDeclaration{ident,params,type_t: TokenType::TYPE_VOID,body,event}))
     }
     
+    ///MATCHES: YIELD [Expr]
+    //whatever is yielded takes everything up to the end of the expression, like `ret` does. a `;`,
+    //`)`, `,` or `}` right after it means there's nothing to yield.
+    pub fn parse_yield(&mut self) -> Result<Yield, String> {
+        let span = self.peek_and_extract()?.span;
+        self.check_advance(TokenType::YIELD)?;
+        let expr = match self.peek_and_extract()?.kind {
+            TokenType::SEMICOLON | TokenType::RPAREN | TokenType::COMMA | TokenType::RBRACE => None,
+            _ => Some(Box::new(self.parse_full_expr()?)),
+        };
+        Ok(Yield{expr, span})
+    }
+
     pub fn parse_return(&mut self) -> Result<Stmt, String> {
+        let span = self.peek_and_extract()?.span;
         self.check_advance(TokenType::RETURN)?;
         let expr = Box::new(self.parse_full_expr()?);
         self.check_advance(TokenType::SEMICOLON)?;
-        Ok(Stmt::STATEMENT_RETURN(ReturnStmt{expr}))
+        Ok(Stmt::STATEMENT_RETURN(ReturnStmt{expr, span}))
     }
     
     
+    ///MATCHES: IMPORT IDENTIFIER [DOUBLE_COLON IDENTIFIER]* [AS IDENTIFIER] SEMICOLON
+    pub fn parse_import(&mut self) -> Result<Stmt, String> {
+        self.check_advance(TokenType::IMPORT)?;
+        let mut path = vec![self.parse_next_ident()?];
+        while self.peek_and_extract()?.kind == TokenType::DOUBLE_COLON {
+            self.advance();
+            path.push(self.parse_next_ident()?);
+        }
+        let alias = match self.check_advance_contains(&[TokenType::AS, TokenType::SEMICOLON])? {
+            TokenType::AS => {
+                let alias = self.parse_next_ident()?;
+                self.check_advance(TokenType::SEMICOLON)?;
+                Some(alias)
+            },
+            _ => None,
+        };
+        Ok(Stmt::STATEMENT_IMPORT(Import{ path, alias }))
+    }
+
+    ///MATCHES: EXPORT (FnDeclaration | Assignment)
+    pub fn parse_export(&mut self) -> Result<Stmt, String> {
+        self.check_advance(TokenType::EXPORT)?;
+        let stmt = match self.check_next_contains(&[TokenType::FN, TokenType::IDENTIFIER])? {
+            TokenType::FN => self.parse_function_declaration()?,
+            _ => match self.parse_assignment_or_fn()? {
+                assignment @ Stmt::STATEMENT_ASSIGNMENT(_) => assignment,
+                other => return Err(format!(
+                    "Only rites and globals can be exported, not {}!", other.to_pretty_string()
+                )),
+            },
+        };
+        Ok(Stmt::STATEMENT_EXPORT(Box::new(stmt)))
+    }
+    
+    
+    ///MATCHES: DIALOGUE LBRACE [DialogueNode]* RBRACE
+    pub fn parse_dialogue(&mut self) -> Result<Stmt, String> {
+        let mut span = self.peek_and_extract()?.span;
+        self.check_advance(TokenType::DIALOGUE)?;
+        self.check_advance(TokenType::LBRACE)?;
+        let mut nodes = Vec::new();
+        loop {
+            let token = self.peek_and_extract()?;
+            match token.kind {
+                TokenType::RBRACE => {
+                    span.end = token.span.end;
+                    self.advance();
+                    return Ok(Stmt::STATEMENT_DIALOGUE(Dialogue{nodes, span}));
+                },
+                _ => nodes.push(self.parse_dialogue_node()?),
+            }
+        }
+    }
+
+    ///MATCHES: NODE IDENTIFIER LBRACE Vec<DialogueLine> [Vec<Choice> | ARROW IDENTIFIER SEMICOLON] RBRACE
+    //lines first, then the choices or the jump. a node with neither ends the dialogue.
+    pub fn parse_dialogue_node(&mut self) -> Result<DialogueNode, String> {
+        let mut span = self.peek_and_extract()?.span;
+        self.check_advance(TokenType::NODE)?;
+        let ident = self.parse_next_ident()?;
+        self.check_advance(TokenType::LBRACE)?;
+        let mut lines = Vec::new();
+        let mut choices = Vec::new();
+        let mut jump = None;
+        loop {
+            let token = self.peek_and_extract()?;
+            match token.kind {
+                TokenType::RBRACE => {
+                    span.end = token.span.end;
+                    self.advance();
+                    break;
+                },
+                _ if jump.is_some() => return Err(format!(
+                    "Node {} has already jumped away, nothing can come after the jump!", ident.name
+                )),
+                TokenType::CHOICE => choices.push(self.parse_choice()?),
+                TokenType::ARROW if choices.is_empty() => {
+                    self.advance(); //move past the '->'
+                    jump = Some(self.parse_next_ident()?);
+                    self.check_advance(TokenType::SEMICOLON)?;
+                },
+                TokenType::ARROW => return Err(format!("Node {} has choices, so it can't jump as well!", ident.name)),
+                TokenType::IDENTIFIER | TokenType::LITERAL_STRING if choices.is_empty() => {
+                    lines.push(self.parse_dialogue_line()?);
+                },
+                TokenType::IDENTIFIER | TokenType::LITERAL_STRING => return Err(format!(
+                    "Node {} has a line after its choices! Lines come first.", ident.name
+                )),
+                other => return Err(format!(
+                    "Expected a line, a choice, a jump or '}}' in node {}, found {:?}!", ident.name, other
+                )),
+            }
+        }
+        let exit = match jump {
+            Some(target) => NodeExit::JUMP(target),
+            None if choices.is_empty() => NodeExit::END,
+            None => NodeExit::CHOICES(choices),
+        };
+        Ok(DialogueNode{ident, lines, exit, span})
+    }
+
+    ///MATCHES: [IDENTIFIER COLON] LITERAL_STRING SEMICOLON
+    pub fn parse_dialogue_line(&mut self) -> Result<DialogueLine, String> {
+        let speaker = match self.peek_and_extract()?.kind {
+            TokenType::IDENTIFIER => {
+                let speaker = self.parse_next_ident()?;
+                self.check_advance(TokenType::COLON)?;
+                Some(speaker)
+            },
+            _ => None,
+        };
+        let text = self.parse_text()?;
+        self.check_advance(TokenType::SEMICOLON)?;
+        Ok(DialogueLine{speaker, text})
+    }
+
+    ///MATCHES: CHOICE LITERAL_STRING [IF Expr] ARROW IDENTIFIER SEMICOLON
+    pub fn parse_choice(&mut self) -> Result<Choice, String> {
+        let span = self.peek_and_extract()?.span;
+        self.check_advance(TokenType::CHOICE)?;
+        let text = self.parse_text()?;
+        let condition = match self.check_next_contains(&[TokenType::IF, TokenType::ARROW])? {
+            TokenType::IF => {
+                self.advance();
+                Some(self.parse_full_expr()?)
+            },
+            _ => None,
+        };
+        self.check_advance(TokenType::ARROW)?;
+        let target = self.parse_next_ident()?;
+        self.check_advance(TokenType::SEMICOLON)?;
+        Ok(Choice{text, condition, target, span})
+    }
+
+    ///MATCHES: LITERAL_STRING
+    //a rune with `{expr}`s in it (see dialogue.rs). every expr gets lexed and parsed on its own,
+    //with its spans moved to where it really sits in the file.
+    pub fn parse_text(&mut self) -> Result<Text, String> {
+        let token = self.peek_and_extract()?;
+        Parser::check_for(token.clone(), TokenType::LITERAL_STRING)?;
+        let mut parts = Vec::new();
+        for piece in split_text(token.lexeme)? {
+            let range = match piece {
+                Piece::TEXT(text) => {
+                    parts.push(TextPart::TEXT(text));
+                    continue;
+                },
+                Piece::EXPR(range) => range,
+            };
+            let start = token.span.start + range.start;
+            let line = token.span.line + token.lexeme[..range.start].matches('\n').count() as u32;
+            let mut tokens = tokenise(&token.lexeme[range]);
+            for inner in tokens.iter_mut() {
+                inner.span.start += start;
+                inner.span.end += start;
+                inner.span.line += line - 1;
+            }
+            let mut parser = Parser::new(tokens);
+            if parser.peek_and_extract()?.kind == TokenType::EOF {
+                return Err("There's nothing between { and } in this line!".to_string());
+            }
+            let expr = parser.parse_full_expr()?;
+            let after = parser.peek_and_extract()?.kind;
+            if after != TokenType::EOF {
+                return Err(format!("Only one expression fits between {{ and }}, but it's followed by {:?}!", after));
+            }
+            parts.push(TextPart::EXPR(expr));
+        }
+        self.advance();
+        Ok(Text{literal: token.lexeme.to_owned(), parts, span: token.span})
+    }
+
+
     ///FULL PARSER METHODS
     ///these allow the parsing of statements
 
@@ -280,13 +526,36 @@ impl<'a> Parser<'a> {
         let statement = match token.kind { //lord save me for this 9000 line match 
             TokenType::IDENTIFIER => self.parse_assignment_or_fn()?,
             TokenType::FN => self.parse_function_declaration()?,
+            TokenType::ON => self.parse_event_handler()?,
             TokenType::RETURN => self.parse_return()?,
             TokenType::LBRACE => self.parse_scope()?,
-            _ => Stmt::STATEMENT_ZERO_EFFECT,
+            TokenType::IMPORT => self.parse_import()?,
+            TokenType::EXPORT => self.parse_export()?,
+            TokenType::YIELD => {
+                let yield_stmt = self.parse_yield()?;
+                self.check_advance(TokenType::SEMICOLON)?;
+                Stmt::STATEMENT_YIELD(yield_stmt)
+            },
+            TokenType::DIALOGUE => self.parse_dialogue()?,
+            TokenType::SEMICOLON => {
+                self.advance(); //a stray ';' is an empty statement
+                Stmt::STATEMENT_ZERO_EFFECT(None)
+            },
+            other => return Err(format!("Can't start a statement with {:?}!", other)),
         };
         Ok(statement)
     }
 
+    ///MATCHES: Vec<Stmt> EOF
+    //a whole file. this is what a module is made of.
+    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut stmts: Vec<Stmt> = Vec::new();
+        while self.peek_and_extract()?.kind != TokenType::EOF {
+            stmts.push(self.parse_statement()?);
+        }
+        Ok(stmts)
+    }
+
     pub fn parse_rhs_expr(&mut self) -> Result<Expr, String> {
         self.check_advance(TokenType::EQUALS)?;
         let rhs = self.parse_full_expr()?;
@@ -295,12 +564,18 @@ impl<'a> Parser<'a> {
     }
 
     pub fn parse_assignment_or_fn(&mut self) -> Result<Stmt, String> {
-        let ident = self.parse_next_ident()?;
+        let ident = self.parse_path_ident()?;
+        let kind = self.advance_and_extract()?.kind;
+
+        //other modules' globals can be read and their rites called, but never written to
+        if ident.is_namespaced() && matches!(kind, TokenType::COLON | TokenType::EQUALS) {
+            return Err(format!("Can't assign to {}, it belongs to another module!", ident.full_name()));
+        }
 
-        match self.advance_and_extract()?.kind {
+        match kind {
             
             TokenType::SEMICOLON => {
-                Ok(Stmt::STATEMENT_ZERO_EFFECT)
+                Ok(Stmt::STATEMENT_ZERO_EFFECT(Some(ident)))
             },
 
             TokenType::COLON => {
@@ -340,19 +615,27 @@ impl<'a> Parser<'a> {
                 }))
             },
 
-            _ => return Err("balls".to_string()),
+            other => {
+                self.pos -= 1; //point at the token that didn't fit
+                Err(format!(
+                    "Expected '=', ':', '(' or ';' after {}, found {:?}!", ident.full_name(), other
+                ))
+            },
         }
     }
     
     pub fn parse_scope(&mut self) -> Result<Stmt, String> {
         let mut stmts: Vec<Stmt> = Vec::new();
+        let mut span = self.peek_and_extract()?.span;
         self.check_advance(TokenType::LBRACE)?;
         loop {
-            match self.peek_and_extract()?.kind {
+            let token = self.peek_and_extract()?;
+            match token.kind {
                 TokenType::EOF => return Err("Unexpected end of input!".to_string()),
                 TokenType::RBRACE => {
+                    span.end = token.span.end;
                     self.advance();
-                    return Ok(Stmt::SCOPE(Scope{stmts}));
+                    return Ok(Stmt::SCOPE(Scope{stmts, span}));
                 },
                 _ => stmts.push(self.parse_statement()?),
             }
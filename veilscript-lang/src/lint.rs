@@ -0,0 +1,347 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::json::Json;
+use crate::lexer::{tokenise, tokenise_lossless, Span, TokenType, Trivia, TriviaKind};
+use crate::parser::Parser;
+use crate::resolve::{did_you_mean, resolve, Binding, SymbolKind};
+use crate::value::type_name_of;
+use crate::visit::{walk_expr, walk_parameter, walk_scope, walk_stmt, walk_stmts, Visitor};
+
+///LINTER section
+//this here is the LINTER. The type checker and the resolver say when a script is broken, the
+//linter says when it's probably not what was meant: variables nothing reads, code after a `ret`,
+//and so on. Every RULE can be turned off, turned into an error, or left as a warning, in a config
+//file or the command line, and turned off (or back on) for part of a file with a comment:
+//  `//lint: allow unused-variable`    on its own line: for the next line. after code: for that line.
+//  `//lint: off division-by-zero`     from here on, until
+//  `//lint: on division-by-zero`      turns it back on (even if the config had it off)
+//several rules can be listed at once, and `all` means every one of them.
+//
+//a file is linted on its own: what it summons gets linted when it's named too.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UNUSED_VARIABLE,
+    UNUSED_PARAMETER,
+    UNREACHABLE_CODE,
+    EMPTY_SCOPE,
+    ZERO_EFFECT,
+    DIVISION_BY_ZERO,
+    MISSING_RETURN,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule::UNUSED_VARIABLE,
+    Rule::UNUSED_PARAMETER,
+    Rule::UNREACHABLE_CODE,
+    Rule::EMPTY_SCOPE,
+    Rule::ZERO_EFFECT,
+    Rule::DIVISION_BY_ZERO,
+    Rule::MISSING_RETURN,
+];
+
+impl Rule {
+    //the name configs and comments use
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            Rule::UNUSED_VARIABLE => "unused-variable".to_string(),
+            Rule::UNUSED_PARAMETER => "unused-parameter".to_string(),
+            Rule::UNREACHABLE_CODE => "unreachable-code".to_string(),
+            Rule::EMPTY_SCOPE => "empty-scope".to_string(),
+            Rule::ZERO_EFFECT => "zero-effect".to_string(),
+            Rule::DIVISION_BY_ZERO => "division-by-zero".to_string(),
+            Rule::MISSING_RETURN => "missing-return".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Rule, String> {
+        match RULES.iter().find(|rule| rule.to_string() == name) {
+            Some(rule) => Ok(*rule),
+            None => {
+                let names: Vec<String> = RULES.iter().map(Rule::to_string).collect();
+                match did_you_mean(name, names.iter().map(String::as_str)) {
+                    Some(suggestion) => Err(format!("No lint rule named {}! Did you mean {}?", name, suggestion)),
+                    None => Err(format!("No lint rule named {}!", name)),
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    OFF,
+    WARNING,
+    ERROR,
+}
+
+impl Level {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            Level::OFF => "off".to_string(),
+            Level::WARNING => "warning".to_string(),
+            Level::ERROR => "error".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Level, String> {
+        match name {
+            "off" => Ok(Level::OFF),
+            "warning" => Ok(Level::WARNING),
+            "error" => Ok(Level::ERROR),
+            other => Err(format!("{} is not a lint level! It's one of off, warning or error.", other)),
+        }
+    }
+}
+
+///CONFIG
+
+//how loud every rule is. anything not mentioned is a warning.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Level>,
+}
+
+impl LintConfig {
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels.get(&rule).copied().unwrap_or(Level::WARNING)
+    }
+
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.insert(rule, level);
+    }
+
+    //`{"rules": {"unused-parameter": "off", "division-by-zero": "error"}}`
+    pub fn from_json(json: &Json) -> Result<LintConfig, String> {
+        let mut config = LintConfig::default();
+        match json.get("rules") {
+            Some(Json::OBJECT(rules)) => for (name, level) in rules {
+                let level = level.as_str().ok_or_else(|| format!("The level of {} has to be a string!", name))?;
+                config.set(Rule::from_name(name)?, Level::from_name(level)?);
+            },
+            Some(_) => return Err("\"rules\" in a lint config has to be an object!".to_string()),
+            None => {},
+        }
+        Ok(config)
+    }
+}
+
+///INLINE COMMENTS
+
+//what the `//lint:` comments in a file asked for
+#[derive(Default)]
+struct Directives {
+    allowed: HashSet<(u32, Rule)>,
+    switches: Vec<(u32, Rule, bool)>, //in source order, true turns a rule back on
+    problems: Vec<Diagnostic>,
+}
+
+impl Directives {
+    fn read(file: &str, source: &str) -> Directives {
+        let mut directives = Directives::default();
+        for token in tokenise_lossless(source) {
+            for trivia in token.leading.iter().chain(&token.trailing) {
+                //the line an `allow` is for is the line of the token it's attached to: the one it
+                //trails, or the next one if it sits on a line of its own
+                directives.comment(file, trivia, token.token.span.line);
+            }
+        }
+        directives
+    }
+
+    fn comment(&mut self, file: &str, trivia: &Trivia, line: u32) {
+        if trivia.kind != TriviaKind::LINE_COMMENT {
+            return;
+        }
+        let Some(directive) = trivia.text[2..].trim().strip_prefix("lint:") else {
+            return;
+        };
+        let mut words = directive.split(|c: char| c == ',' || c.is_whitespace()).filter(|word| !word.is_empty());
+        let action = words.next().unwrap_or("");
+        if !matches!(action, "allow" | "off" | "on") {
+            let message = format!("Don't know what //lint: {} means! It's allow, off or on.", action);
+            self.problems.push(Diagnostic::warning(file, trivia.span, message));
+            return;
+        }
+
+        let mut rules = Vec::new();
+        for word in words {
+            match word {
+                "all" => rules.extend_from_slice(RULES),
+                name => match Rule::from_name(name) {
+                    Ok(rule) => rules.push(rule),
+                    Err(err) => self.problems.push(Diagnostic::warning(file, trivia.span, err)),
+                },
+            }
+        }
+        for rule in rules {
+            match action {
+                "allow" => { self.allowed.insert((line, rule)); },
+                _ => self.switches.push((trivia.span.line, rule, action == "on")),
+            }
+        }
+    }
+
+    fn level(&self, config: &LintConfig, rule: Rule, line: u32) -> Level {
+        if self.allowed.contains(&(line, rule)) {
+            return Level::OFF;
+        }
+        let switch = self.switches.iter().rev().find(|(at, switched, _)| *switched == rule && *at <= line);
+        match (switch, config.level(rule)) {
+            (Some((_, _, false)), _) => Level::OFF,
+            (Some((_, _, true)), Level::OFF) => Level::WARNING,
+            (_, level) => level,
+        }
+    }
+}
+
+///RULES
+
+//0, 0.0, (0), -0 and so on
+fn is_literal_zero(expr: &Expr) -> bool {
+    match expr {
+        Expr::ATOM(Atom::LITERAL_INT(value)) => *value == 0,
+        Expr::ATOM(Atom::LITERAL_FLOAT(value)) => *value == 0.0,
+        Expr::GROUPED_EXPR(inner) | Expr::UNARY_EXPR { expr: inner, .. } => is_literal_zero(inner),
+        _ => false,
+    }
+}
+
+struct Linter {
+    findings: Vec<(Rule, Span, String)>,
+    at: Span,                //the statement being looked at, expressions don't know where they are
+    written: HashSet<usize>, //where the names that get assigned to or declared as parameters start
+}
+
+impl Linter {
+    fn report(&mut self, rule: Rule, span: Span, message: String) {
+        self.findings.push((rule, span, message));
+    }
+}
+
+impl<'ast> Visitor<'ast> for Linter {
+    fn visit_stmts(&mut self, stmts: &'ast [Stmt]) {
+        //only the first thing that can't run is worth pointing at. rites after a top level `ret`
+        //are still declared, so they don't count.
+        if let Some(ret) = stmts.iter().position(Stmt::always_returns) {
+            let unreachable = stmts[ret + 1..].iter()
+                .filter(|stmt| !matches!(stmt, Stmt::STATEMENT_FUNCTION_DECLARATION(_)))
+                .find_map(Stmt::span);
+            if let Some(span) = unreachable {
+                self.report(Rule::UNREACHABLE_CODE, span, "This can never run, it comes after a ret!".to_string());
+            }
+        }
+        walk_stmts(self, stmts);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        if let Some(span) = stmt.span() {
+            self.at = span;
+        }
+        if let Stmt::STATEMENT_ZERO_EFFECT(Some(ident)) = stmt {
+            self.report(Rule::ZERO_EFFECT, ident.span, format!("{}; does nothing!", ident.full_name()));
+        }
+        walk_stmt(self, stmt);
+    }
+
+    //a rite's body is allowed to be empty, that's how stubs look
+    fn visit_scope(&mut self, scope: &'ast Scope) {
+        if scope.stmts.iter().all(|stmt| matches!(stmt, Stmt::STATEMENT_ZERO_EFFECT(None))) {
+            self.report(Rule::EMPTY_SCOPE, scope.span, "This scope is empty!".to_string());
+        }
+        walk_scope(self, scope);
+    }
+
+    fn visit_fn_declaration(&mut self, decl: &'ast FnDeclaration) {
+        if decl.type_t != TokenType::TYPE_VOID && !decl.body.stmts.iter().any(Stmt::always_returns) {
+            let message = format!(
+                "Rite {} should give back {}, but can reach its end without a ret!",
+                decl.ident.name, type_name_of(&decl.type_t).unwrap_or("?")
+            );
+            self.report(Rule::MISSING_RETURN, decl.ident.span, message);
+        }
+        for param in &decl.params {
+            self.visit_parameter(param);
+        }
+        self.visit_stmts(&decl.body.stmts);
+    }
+
+    fn visit_parameter(&mut self, param: &'ast Parameter) {
+        self.written.insert(param.ident.span.start);
+        walk_parameter(self, param);
+    }
+
+    fn visit_assignment(&mut self, assignment: &'ast Assignment) {
+        self.written.insert(assignment.ident.span.start);
+        self.visit_expr(&assignment.expr);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        if let Expr::BINARY_EXPR { opcode: opcode @ (BinOp::DIV | BinOp::MOD), right, .. } = expr
+            && is_literal_zero(right) {
+            let message = match opcode {
+                BinOp::DIV => "This divides by zero!",
+                _ => "This takes a modulo by zero!",
+            };
+            self.report(Rule::DIVISION_BY_ZERO, self.at, message.to_string());
+        }
+        walk_expr(self, expr);
+    }
+}
+
+//lints one file. a file that doesn't parse has nothing to lint, so that's the one error it gets.
+pub fn lint(file: &str, source: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut parser = Parser::new(tokenise(source));
+    let stmts = match parser.parse_program() {
+        Ok(stmts) => stmts,
+        Err(err) => return vec![Diagnostic::error(file, parser.error_span(), err)],
+    };
+
+    let mut linter = Linter { findings: Vec::new(), at: Span::default(), written: HashSet::new() };
+    linter.visit_stmts(&stmts);
+
+    //globals and rites can be used by the game or other modules, so only locals and parameters are
+    //known to be unused. a leading `_` says it's on purpose.
+    let resolution = resolve(file, &stmts, None);
+    let mut read = vec![false; resolution.symbols.len()];
+    for reference in &resolution.references {
+        if let Binding::SYMBOL(symbol) = reference.binding && !linter.written.contains(&reference.ident.span.start) {
+            read[symbol] = true;
+        }
+    }
+    for (symbol, read) in resolution.symbols.iter().zip(read) {
+        if read || symbol.name.starts_with('_') {
+            continue;
+        }
+        match symbol.kind {
+            SymbolKind::LOCAL => linter.report(
+                Rule::UNUSED_VARIABLE, symbol.span, format!("{} is never used!", symbol.name)
+            ),
+            SymbolKind::PARAM => linter.report(
+                Rule::UNUSED_PARAMETER, symbol.span,
+                format!("The parameter {} is never used! Call it _{} if that's on purpose.", symbol.name, symbol.name)
+            ),
+            SymbolKind::RITE | SymbolKind::GLOBAL => {},
+        }
+    }
+
+    let directives = Directives::read(file, source);
+    let mut diagnostics = directives.problems.clone();
+    for (rule, span, message) in linter.findings {
+        let message = format!("{} [{}]", message, rule.to_string());
+        match directives.level(config, rule, span.line) {
+            Level::OFF => {},
+            Level::WARNING => diagnostics.push(Diagnostic::warning(file, span, message)),
+            Level::ERROR => diagnostics.push(Diagnostic::error(file, span, message)),
+        }
+    }
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.start));
+    diagnostics
+}
@@ -0,0 +1,368 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::dialogue::{split_text, Piece};
+use crate::lexer::Span;
+use crate::value::{unescape_string_literal, Value};
+use crate::visit::{walk_fn_declaration, Visitor};
+
+///LOCALISATION section
+//this here is how dialogue gets translated. every line and every choice in a dialogue (see
+//dialogue.rs) is something a player reads, so `veil extract` pulls them all out of the scripts
+//into a table a translator can fill in, and the game hands the filled in table for the language
+//the player picked to the runtime (ScriptRuntime::set_locale). from then on, lines come out
+//translated. a line the table doesn't have comes out as written, so a half done translation
+//is still playable.
+//
+//every line has an ID: a hash of the node it's in and the line exactly as written. so moving
+//lines around, renaming rites or adding new lines elsewhere keeps every ID (and translation) as
+//it was, while changing the words of a line gives it a new one, because the old translation
+//doesn't say the new thing. the same words in nodes of the same name are one line, translated
+//once.
+//
+//a translation keeps the `{expr}`s of its line, written exactly as in the script, but can move
+//them around or leave some out: "Halt, {name}!" could become "{name}, stehen bleiben!". `{{` and
+//`}}` are braces of their own, same as in scripts. a translation with a placeholder the line
+//doesn't have (or a brace that's never closed) is broken, there's nothing to fill it with.
+//Catalogue::check finds those up front, `veil run --locale` warns about them. one that slips
+//through anyway is said as the line is written, a typo in a table isn't worth stopping a cutscene
+//over.
+//
+//the tables are gettext PO files or CSV. in PO files the ID is the msgctxt, in CSV files it's the
+//`id` column, and the translation is the msgstr or the `translation` column. anything else in
+//them is there for the translator and ignored when they're read back. fuzzy PO entries are
+//skipped, like gettext does.
+
+//one line to translate, and everywhere it appears
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: String,
+    pub text: String, //the line as the player would read it, `{expr}`s and all
+    pub literal: String, //the rune literal as written, quotes and all
+    pub rite: Option<String>, //None for a dialogue in top level code
+    pub node: String,
+    pub speaker: Option<String>,
+    pub is_choice: bool,
+    pub places: Vec<(String, Span)>, //file and span of every time it's written
+}
+
+impl Entry {
+    //who says it where, for the translator
+    pub fn context(&self) -> String {
+        let who = match (&self.speaker, self.is_choice) {
+            (_, true) => "a choice".to_string(),
+            (Some(speaker), false) => format!("said by {}", speaker),
+            (None, false) => "narration".to_string(),
+        };
+        match &self.rite {
+            Some(rite) => format!("{}, in node {} of {}", who, self.node, rite),
+            None => format!("{}, in node {}", who, self.node),
+        }
+    }
+
+    pub fn locations(&self) -> Vec<String> {
+        self.places.iter().map(|(file, span)| format!("{}:{}", file, span.line)).collect()
+    }
+}
+
+//the ID of a line, `literal` being the rune literal as written, quotes and all. FNV-1a, so it's
+//the same on every machine and every build.
+pub fn text_id(node: &str, literal: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in node.bytes().chain([0x04]).chain(literal.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+///EXTRACTION
+
+//every line of every dialogue in `stmts`, added to `entries`. a line that's already in there
+//(from another file, say) just gets another place.
+pub fn extract(file: &str, stmts: &[Stmt], entries: &mut Vec<Entry>) {
+    let mut extractor = Extractor { file, rite: None, entries };
+    extractor.visit_stmts(stmts);
+}
+
+struct Extractor<'a, 'ast> {
+    file: &'a str,
+    rite: Option<&'ast str>,
+    entries: &'a mut Vec<Entry>,
+}
+
+impl<'a, 'ast> Extractor<'a, 'ast> {
+    fn add(&mut self, node: &str, speaker: Option<&Ident>, is_choice: bool, text: &Text) {
+        let id = text_id(node, &text.literal);
+        let place = (self.file.to_string(), text.span);
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) {
+            entry.places.push(place);
+            return;
+        }
+        self.entries.push(Entry {
+            id,
+            text: unescape_string_literal(&text.literal),
+            literal: text.literal.clone(),
+            rite: self.rite.map(str::to_string),
+            node: node.to_string(),
+            speaker: speaker.map(|speaker| speaker.name.clone()),
+            is_choice,
+            places: vec![place],
+        });
+    }
+}
+
+impl<'a, 'ast> Visitor<'ast> for Extractor<'a, 'ast> {
+    fn visit_fn_declaration(&mut self, decl: &'ast FnDeclaration) {
+        let outer = self.rite.replace(&decl.ident.name);
+        walk_fn_declaration(self, decl);
+        self.rite = outer;
+    }
+
+    //dialogue can't hold dialogue, so there's nothing further down to extract
+    fn visit_dialogue(&mut self, dialogue: &'ast Dialogue) {
+        for node in &dialogue.nodes {
+            for line in &node.lines {
+                self.add(&node.ident.name, line.speaker.as_ref(), false, &line.text);
+            }
+            if let NodeExit::CHOICES(choices) = &node.exit {
+                for choice in choices {
+                    self.add(&node.ident.name, None, true, &choice.text);
+                }
+            }
+        }
+    }
+}
+
+///PO AND CSV
+
+pub fn to_po(entries: &[Entry]) -> String {
+    let mut ret = String::from("msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset=UTF-8\\n\"\n");
+    for entry in entries {
+        ret += &format!("\n#. {}\n", entry.context());
+        ret += &format!("#: {}\n", entry.locations().join(" "));
+        ret += &format!("msgctxt {}\n", po_string(&entry.id));
+        ret += &format!("msgid {}\n", po_string(&entry.text));
+        ret += "msgstr \"\"\n";
+    }
+    ret
+}
+
+pub fn to_csv(entries: &[Entry]) -> String {
+    let mut ret = String::from("id,context,location,text,translation\n");
+    for entry in entries {
+        let fields = [entry.id.clone(), entry.context(), entry.locations().join(" "), entry.text.clone(), String::new()];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        ret += &fields.join(",");
+        ret += "\n";
+    }
+    ret
+}
+
+fn po_string(text: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\t' => ret += "\\t",
+            '\r' => ret += "\\r",
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+///CATALOGUE section
+//the translations of one locale, by ID. what the game hands to ScriptRuntime::set_locale.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalogue {
+    translations: HashMap<String, String>,
+}
+
+impl Catalogue {
+    pub fn new() -> Self {
+        Catalogue::default()
+    }
+    pub fn insert(&mut self, id: &str, translation: &str) {
+        self.translations.insert(id.to_string(), translation.to_string());
+    }
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.translations.get(id).map(String::as_str)
+    }
+    pub fn len(&self) -> usize {
+        self.translations.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.translations.is_empty()
+    }
+
+    //a warning for every broken translation of `entries`, at the first place its line is written
+    pub fn check(&self, entries: &[Entry]) -> Vec<Diagnostic> {
+        entries.iter().filter_map(|entry| {
+            let translation = self.get(&entry.id)?;
+            let count = placeholder_count(&entry.literal).ok()?;
+            let err = translate(&entry.literal, translation, &vec![Value::VOID; count]).err()?;
+            let (file, span) = entry.places.first()?;
+            Some(Diagnostic::warning(file, *span, format!("{} It's said as written instead.", err)))
+        }).collect()
+    }
+
+    //anything that isn't an entry with an ID and a translation is skipped, it's just not
+    //translated yet
+    pub fn from_po(text: &str) -> Result<Catalogue, String> {
+        let mut catalogue = Catalogue::new();
+        let mut entry: HashMap<&str, String> = HashMap::new();
+        let mut field = None;
+        let mut fuzzy = false;
+        for (number, line) in text.lines().map(str::trim).enumerate().chain([(0, "")]) {
+            let bad_line = || format!("Line {} of the PO file makes no sense: {}", number + 1, line);
+            if line.is_empty() {
+                match (entry.get("msgctxt"), entry.get("msgstr")) {
+                    (Some(id), Some(translation)) if !fuzzy && !translation.is_empty() => catalogue.insert(id, translation),
+                    _ => {},
+                }
+                entry.clear();
+                field = None;
+                fuzzy = false;
+            } else if let Some(flags) = line.strip_prefix("#,") {
+                fuzzy |= flags.split(',').any(|flag| flag.trim() == "fuzzy");
+            } else if line.starts_with('#') {
+                continue;
+            } else if line.starts_with('"') {
+                let field = field.ok_or_else(bad_line)?;
+                let more = po_unquote(line).ok_or_else(bad_line)?;
+                entry.entry(field).or_default().push_str(&more);
+            } else {
+                let (keyword, rest) = line.split_once(char::is_whitespace).ok_or_else(bad_line)?;
+                let keyword = match keyword {
+                    "msgctxt" => "msgctxt",
+                    "msgid" => "msgid",
+                    "msgstr" => "msgstr",
+                    _ => return Err(bad_line()),
+                };
+                entry.insert(keyword, po_unquote(rest.trim()).ok_or_else(bad_line)?);
+                field = Some(keyword);
+            }
+        }
+        Ok(catalogue)
+    }
+
+    //the first row names the columns, `id` and `translation` have to be among them
+    pub fn from_csv(text: &str) -> Result<Catalogue, String> {
+        let mut rows = csv_rows(text)?.into_iter();
+        let header = rows.next().unwrap_or_default();
+        let column = |name: &str| header.iter().position(|column| column.trim() == name)
+            .ok_or_else(|| format!("The CSV file has no {} column!", name));
+        let (id, translation) = (column("id")?, column("translation")?);
+
+        let mut catalogue = Catalogue::new();
+        for row in rows {
+            match (row.get(id), row.get(translation)) {
+                (Some(id), Some(translation)) if !id.is_empty() && !translation.is_empty() => {
+                    catalogue.insert(id, translation);
+                },
+                _ => {},
+            }
+        }
+        Ok(catalogue)
+    }
+}
+
+fn po_unquote(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    Some(unescape_string_literal(&format!("\"{}\"", inner)))
+}
+
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {},
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("The CSV file ends in the middle of a quoted field!".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+///SUBSTITUTION
+
+//what a line turns into when it's said. `literal` is the line as written, `values` what its
+//`{expr}`s turned out to be, in order. both runtimes say every line through here.
+pub fn line_text(locale: Option<&Catalogue>, node: &str, literal: &str, values: &[Value]) -> Result<String, String> {
+    let translated = locale.and_then(|locale| locale.get(&text_id(node, literal)))
+        .and_then(|translation| translate(literal, translation, values).ok());
+    if let Some(translated) = translated {
+        return Ok(translated);
+    }
+    let mut values = values.iter();
+    Ok(split_text(literal)?.iter().map(|piece| match piece {
+        Piece::TEXT(text) => text.clone(),
+        Piece::EXPR(_) => values.next().map(Value::to_string).unwrap_or_default(),
+    }).collect())
+}
+
+//the placeholders of the translation are matched up with the `{expr}`s of the line by how
+//they're written, give or take some spaces
+fn translate(literal: &str, translation: &str, values: &[Value]) -> Result<String, String> {
+    let pieces = split_text(literal)?;
+    let placeholders: Vec<&str> = pieces.iter().filter_map(|piece| match piece {
+        Piece::EXPR(range) => Some(literal[range.clone()].trim()),
+        Piece::TEXT(_) => None,
+    }).collect();
+    let quoted = po_string(translation);
+    let mut ret = String::new();
+    for piece in split_text(&quoted).map_err(|err| format!("The translation of {} is broken! {}", literal, err))? {
+        match piece {
+            Piece::TEXT(text) => ret += &text,
+            Piece::EXPR(range) => {
+                let placeholder = quoted[range].trim();
+                let value = placeholders.iter().position(|known| *known == placeholder).and_then(|at| values.get(at))
+                    .ok_or_else(|| format!(
+                        "The translation of {} has a {{{}}} in it, but the line doesn't!", literal, placeholder
+                    ))?;
+                ret += &value.to_string();
+            },
+        }
+    }
+    Ok(ret)
+}
+
+//how many `{expr}`s a line has, so how many values line_text wants
+pub fn placeholder_count(literal: &str) -> Result<usize, String> {
+    Ok(split_text(literal)?.iter().filter(|piece| matches!(piece, Piece::EXPR(_))).count())
+}
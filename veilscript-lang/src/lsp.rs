@@ -0,0 +1,767 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::highlight::{highlight, LSP_TOKEN_MODIFIERS, LSP_TOKEN_TYPES};
+use crate::json::{parse_json, Json};
+use crate::lexer::{tokenise, tokenise_lossless, Span, TokenType};
+use crate::module::{module_file_name, module_id, Module, ModuleLoader, ModuleResolver};
+use crate::parser::Parser;
+use crate::random::{builtin_help, BUILTINS};
+use crate::resolve::{resolve, Binding, Reference, Symbol, SymbolKind};
+use crate::runtime::{rite_declarations, validate_module};
+use crate::source::SourceFile;
+use crate::typeck::{StaticType, TypeChecker};
+use crate::value::type_name_of;
+
+///LANGUAGE SERVER section
+//this here is the LANGUAGE SERVER behind `veil-lsp`, so editors get squiggles, hovers, go to
+//definition, an outline and completion while designers write scripts. It speaks the Language
+//Server Protocol: JSON messages with a Content-Length header, over stdin and stdout.
+//
+//every open file gets ANALYSED from scratch whenever any open file changes (scripts are small,
+//and it means a file summoning the one being edited sees the edit right away). an analysis is
+//the same pipeline `veil check` runs, tokenise -> Parser -> ModuleLoader -> TypeChecker, plus an
+//INDEX of every name the file defines and every place a name is used.
+//
+//while a file doesn't parse (which is most of the time someone is typing) the last index that did
+//parse sticks around, so completion keeps working. hover and go to definition don't use it, its
+//positions are out of date.
+
+//every keyword worth completing. the aliases work too, these are just the canonical spellings.
+const KEYWORDS: [&str; 14] = [
+    "rite", "ret", "summon", "pub", "yield", "dialogue", "node", "choice", "if", "on", "int", "float", "rune",
+    "void",
+];
+
+///POSITIONS
+//LSP positions are a line and a column counted in utf-16 code units. ours are byte offsets.
+
+fn position(text: &str, offset: usize) -> Json {
+    let offset = offset.min(text.len());
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|at| at + 1).unwrap_or(0);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object([("line", Json::from(line)), ("character", Json::from(character))])
+}
+
+fn range(text: &str, span: Span) -> Json {
+    Json::object([("start", position(text, span.start)), ("end", position(text, span.end))])
+}
+
+fn offset_of(text: &str, position: &Json) -> usize {
+    let line = position.get("line").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+    let character = position.get("character").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+
+    let mut line_start = 0;
+    for _ in 0..line {
+        match text[line_start..].find('\n') {
+            Some(at) => line_start += at + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (at, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + at;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+pub fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let escaped = bytes.get(at + 1..at + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[at], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                at += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                at += 1;
+            },
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).to_string())
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.display().to_string().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            other => uri.push_str(&format!("%{:02X}", other)),
+        }
+    }
+    uri
+}
+
+///ANALYSIS
+
+//summons come from next to the file, and files that are open in the editor win over what's on
+//disk, since that's what the designer is looking at
+struct EditorResolver {
+    root: PathBuf,
+    open: HashMap<PathBuf, String>,
+}
+
+impl ModuleResolver for EditorResolver {
+    fn resolve(&self, path: &[String]) -> Result<SourceFile, String> {
+        let file = self.root.join(module_file_name(path));
+        let text = match self.open.get(&file) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(&file)
+                .map_err(|err| format!("Couldn't summon {}: {}", module_id(path), err))?,
+        };
+        Ok(SourceFile { name: file.display().to_string(), text })
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    stmts: Vec<Stmt>,
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+}
+
+struct Analysis {
+    id: String,
+    name: String,
+    index: Index,
+    stale: bool, //the file didn't parse, so `index` is from an older version of it
+    types: Vec<(Span, StaticType)>,
+    diagnostics: Vec<Diagnostic>,
+    loader: Option<ModuleLoader>,
+}
+
+fn analyse(path: &Path, text: &str, open: &HashMap<PathBuf, String>, natives: Option<&[String]>, previous: Option<Analysis>) -> Analysis {
+    let name = path.display().to_string();
+    let id = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let mut analysis = Analysis {
+        id: id.clone(),
+        name: name.clone(),
+        index: Index::default(),
+        stale: false,
+        types: Vec::new(),
+        diagnostics: Vec::new(),
+        loader: None,
+    };
+
+    let mut parser = Parser::new(tokenise(text));
+    let stmts = match parser.parse_program() {
+        Ok(stmts) => stmts,
+        Err(err) => {
+            analysis.diagnostics.push(Diagnostic::error(&name, parser.error_span(), err));
+            if let Some(previous) = previous {
+                analysis.index = previous.index;
+                analysis.stale = true;
+            }
+            return analysis;
+        },
+    };
+
+    let mut loader = ModuleLoader::new(EditorResolver {
+        root: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        open: open.clone(),
+    });
+    match loader.load_file(&id, SourceFile { name: name.clone(), text: text.to_owned() }) {
+        Ok(()) => analysis.loader = Some(loader),
+        Err(err) if err.file == name => analysis.diagnostics.push(err),
+        //something this file summons is broken, which shows up here as a whole
+        Err(err) => {
+            let err = Diagnostic::error(&name, Span::default(), err.to_string());
+            analysis.diagnostics.push(err);
+        },
+    }
+
+    match validate_module(&stmts) {
+        Err(err) => analysis.diagnostics.push(Diagnostic::error(&name, Span::default(), err)),
+        Ok(()) => {
+            let module = analysis.loader.as_ref().and_then(|loader| Some((loader.get(&id)?, &loader.modules)));
+            let mut checker = match module {
+                Some((module, modules)) => TypeChecker::for_module(&name, module, modules),
+                None => TypeChecker::new(&name, &stmts),
+            };
+            checker.check_stmts(match module {
+                Some((module, _)) => &module.stmts,
+                None => &stmts,
+            });
+            analysis.diagnostics.extend(checker.diagnostics);
+            analysis.types = checker.types;
+        },
+    }
+
+    let resolution = resolve(&name, &stmts, natives);
+    analysis.diagnostics.extend(resolution.diagnostics);
+    analysis.diagnostics.sort_by_key(|diagnostic| diagnostic.span.line);
+    analysis.index = Index { stmts, symbols: resolution.symbols, references: resolution.references };
+    analysis
+}
+
+fn signature(decl: &FnDeclaration) -> String {
+    let params: Vec<String> = decl.params.iter()
+        .map(|param| format!("{}: {}", param.ident.name, type_text(&param.type_t)))
+        .collect();
+    match decl.type_t {
+        TokenType::TYPE_VOID => format!("rite {}({})", decl.ident.name, params.join(", ")),
+        _ => format!("rite {}({}) -> {}", decl.ident.name, params.join(", "), type_text(&decl.type_t)),
+    }
+}
+
+fn type_text(type_t: &TokenType) -> String {
+    type_name_of(type_t).map(str::to_owned).unwrap_or_else(|| format!("{:?}", type_t))
+}
+
+//the `///` comment above whatever starts at `offset`, skipping back over `rite` and `pub`
+fn doc_comment_at(text: &str, offset: usize) -> Option<String> {
+    let tokens = tokenise_lossless(text);
+    let mut at = tokens.iter().position(|token| token.token.span.start == offset)?;
+    while at > 0 && matches!(tokens[at - 1].token.kind, TokenType::FN | TokenType::EXPORT) {
+        at -= 1;
+    }
+    tokens[at].doc_comment()
+}
+
+fn hover_text(code: &str, doc: Option<String>) -> String {
+    let mut text = format!("```veil\n{}\n```", code);
+    if let Some(doc) = doc {
+        text += "\n\n";
+        text += &doc;
+    }
+    text
+}
+
+fn find_rite<'a>(stmts: &'a [Stmt], name: &str) -> Option<&'a FnDeclaration> {
+    rite_declarations(stmts).into_iter().find(|decl| decl.ident.name == name)
+}
+
+//the first top level assignment to a global
+fn find_global<'a>(stmts: &'a [Stmt], name: &str) -> Option<&'a Assignment> {
+    stmts.iter().find_map(|stmt| {
+        let stmt = match stmt {
+            Stmt::STATEMENT_EXPORT(inner) => inner.as_ref(),
+            other => other,
+        };
+        match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) if assignment.ident.name == name => Some(assignment),
+            _ => None,
+        }
+    })
+}
+
+impl Analysis {
+    //the module a `namespace::name` points into, if it was summoned
+    fn target_module(&self, ident: &Ident) -> Option<(&Module, &str)> {
+        let loader = self.loader.as_ref()?;
+        let id = loader.get(&self.id)?.resolve_namespace(&ident.namespace)?;
+        let module = loader.get(id)?;
+        Some((module, loader.sources.text(module.file)))
+    }
+
+    fn reference_at(&self, offset: usize) -> Option<&Reference> {
+        self.index.references.iter()
+            .find(|reference| reference.ident.span.start <= offset && offset <= reference.ident.span.end)
+    }
+
+    fn type_at(&self, span: Span) -> Option<StaticType> {
+        self.types.iter().find(|(at, _)| *at == span).map(|(_, found)| *found)
+    }
+
+    fn hover(&self, text: &str, offset: usize, natives: &[String]) -> Option<String> {
+        let reference = self.reference_at(offset)?;
+        let ident = &reference.ident;
+
+        if ident.is_namespaced() {
+            let (module, module_text) = self.target_module(ident)?;
+            if reference.call {
+                let decl = find_rite(&module.stmts, &ident.name)?;
+                return Some(hover_text(&signature(decl), doc_comment_at(module_text, decl.ident.span.start)));
+            }
+            let assignment = find_global(&module.stmts, &ident.name)?;
+            let type_t = assignment.type_t.as_ref().map(type_text).unwrap_or_else(|| "anything".to_string());
+            let doc = doc_comment_at(module_text, assignment.ident.span.start);
+            return Some(hover_text(&format!("(global) {}: {}", ident.full_name(), type_t), doc));
+        }
+
+        let symbol = match reference.binding {
+            Binding::SYMBOL(symbol) => &self.index.symbols[symbol],
+            Binding::NATIVE if reference.call && natives.contains(&ident.name) => {
+                return Some(hover_text(&format!("(native) {}", ident.name), Some("Provided by the game.".to_string())));
+            },
+            Binding::NATIVE if reference.call && let Some(help) = builtin_help(&ident.name) => {
+                return Some(hover_text(&format!("(builtin) {}", ident.name), Some(help.to_string())));
+            },
+            _ => return None,
+        };
+        if symbol.kind == SymbolKind::RITE {
+            let decl = find_rite(&self.index.stmts, &symbol.name)?;
+            return Some(hover_text(&signature(decl), doc_comment_at(text, decl.ident.span.start)));
+        }
+
+        //inside rites the checker doesn't trust globals, but what they were declared as still holds
+        let found = self.type_at(ident.span)
+            .filter(|found| *found != StaticType::ANY)
+            .or_else(|| symbol.type_t.as_ref().map(StaticType::of))
+            .unwrap_or(StaticType::ANY);
+        let kind = match symbol.kind {
+            SymbolKind::GLOBAL => "global",
+            SymbolKind::PARAM => "parameter",
+            _ => "local",
+        };
+        let doc = match symbol.kind {
+            SymbolKind::GLOBAL => doc_comment_at(text, symbol.span.start),
+            _ => None,
+        };
+        Some(hover_text(&format!("({}) {}: {}", kind, ident.name, found.to_string()), doc))
+    }
+
+    //where the thing at `offset` was defined: a file name and the span of the name there
+    fn definition(&self, offset: usize) -> Option<(String, String, Span)> {
+        let reference = self.reference_at(offset)?;
+        let ident = &reference.ident;
+        if ident.is_namespaced() {
+            let (module, module_text) = self.target_module(ident)?;
+            let span = match reference.call {
+                true => find_rite(&module.stmts, &ident.name)?.ident.span,
+                false => find_global(&module.stmts, &ident.name)?.ident.span,
+            };
+            let name = self.loader.as_ref()?.sources.name(module.file).to_owned();
+            return Some((name, module_text.to_owned(), span));
+        }
+        let symbol = &self.index.symbols[reference.symbol()?];
+        Some((self.name.clone(), String::new(), symbol.span))
+    }
+}
+
+///SERVER
+
+struct Document {
+    text: String,
+    analysis: Option<Analysis>,
+}
+
+#[derive(Default)]
+pub struct LanguageServer {
+    documents: HashMap<String, Document>, //by uri
+    natives: Vec<String>,
+    natives_known: bool, //did the editor hand over every native? if not, unknown calls are fine
+    shut_down: bool,
+    pub exit: Option<bool>, //set by the `exit` notification: did a `shutdown` come first?
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object([("jsonrpc", Json::from("2.0")), ("id", id.clone()), ("result", result)])
+}
+
+fn error_response(id: &Json, code: f64, message: &str) -> Json {
+    let error = Json::object([("code", Json::from(code)), ("message", Json::from(message))]);
+    Json::object([("jsonrpc", Json::from("2.0")), ("id", id.clone()), ("error", error)])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([("jsonrpc", Json::from("2.0")), ("method", Json::from(method)), ("params", params)])
+}
+
+//numbers the LSP spec gives these
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_MODULE: usize = 9;
+const COMPLETION_KEYWORD: usize = 14;
+
+impl LanguageServer {
+    //`natives` are the names the game registers, so they can be completed and hovered
+    pub fn new(natives: Vec<String>) -> Self {
+        LanguageServer { natives, ..LanguageServer::default() }
+    }
+
+    //handles one message from the editor. gives back every message to send back, in order.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::NULL);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            //a notification, nobody waits for an answer
+            None => return self.notify(method, &params),
+        };
+        if method.is_empty() {
+            return Vec::new(); //an answer to something we never ask
+        }
+        if self.shut_down {
+            return vec![error_response(&id, INVALID_REQUEST, "The server is shutting down!")];
+        }
+
+        let result = match method {
+            "initialize" => {
+                if let Some(natives) = params.path(&["initializationOptions", "natives"]).and_then(Json::as_array) {
+                    self.natives.extend(natives.iter().filter_map(Json::as_str).map(str::to_owned));
+                    self.natives_known = true;
+                }
+                self.capabilities()
+            },
+            "shutdown" => {
+                self.shut_down = true;
+                Json::NULL
+            },
+            "textDocument/hover" => self.on_hover(&params),
+            "textDocument/definition" => self.on_definition(&params),
+            "textDocument/documentSymbol" => self.on_document_symbol(&params),
+            "textDocument/completion" => self.on_completion(&params),
+            "textDocument/semanticTokens/full" => self.on_semantic_tokens(&params),
+            other => return vec![error_response(&id, METHOD_NOT_FOUND, &format!("Don't know how to {}!", other))],
+        };
+        vec![response(&id, result)]
+    }
+
+    fn capabilities(&self) -> Json {
+        let capabilities = Json::object([
+            ("textDocumentSync", Json::from(1usize)), //the whole text on every change
+            ("hoverProvider", Json::from(true)),
+            ("definitionProvider", Json::from(true)),
+            ("documentSymbolProvider", Json::from(true)),
+            ("completionProvider", Json::object([("triggerCharacters", Json::from(vec![Json::from(":")]))])),
+            ("semanticTokensProvider", Json::object([
+                ("legend", Json::object([
+                    ("tokenTypes", Json::from(LSP_TOKEN_TYPES.iter().map(|name| Json::from(*name)).collect::<Vec<Json>>())),
+                    ("tokenModifiers", Json::from(LSP_TOKEN_MODIFIERS.iter().map(|name| Json::from(*name)).collect::<Vec<Json>>())),
+                ])),
+                ("full", Json::from(true)),
+            ])),
+        ]);
+        let info = Json::object([("name", Json::from("veil-lsp")), ("version", Json::from(env!("CARGO_PKG_VERSION")))]);
+        Json::object([("capabilities", capabilities), ("serverInfo", info)])
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("").to_owned();
+        match method {
+            "exit" => {
+                self.exit = Some(self.shut_down);
+                Vec::new()
+            },
+            "textDocument/didOpen" => {
+                let text = params.path(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                self.documents.insert(uri, Document { text: text.to_owned(), analysis: None });
+                self.analyse_all()
+            },
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(Json::as_array).unwrap_or(&[]);
+                let text = changes.last().and_then(|change| change.get("text")).and_then(Json::as_str);
+                match (self.documents.get_mut(&uri), text) {
+                    (Some(document), Some(text)) => document.text = text.to_owned(),
+                    _ => return Vec::new(),
+                }
+                self.analyse_all()
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                //whatever was said about it goes away with it
+                let cleared = Json::object([("uri", Json::from(uri)), ("diagnostics", Json::ARRAY(Vec::new()))]);
+                let mut messages = vec![notification("textDocument/publishDiagnostics", cleared)];
+                messages.extend(self.analyse_all());
+                messages
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    //every open file again, since any of them might summon the one that changed
+    fn analyse_all(&mut self) -> Vec<Json> {
+        let open: HashMap<PathBuf, String> = self.documents.iter()
+            .map(|(uri, document)| (uri_to_path(uri), document.text.clone()))
+            .collect();
+        let mut uris: Vec<String> = self.documents.keys().cloned().collect();
+        uris.sort();
+
+        let mut messages = Vec::new();
+        for uri in uris {
+            let document = match self.documents.get_mut(&uri) {
+                Some(document) => document,
+                None => continue,
+            };
+            let natives = self.natives_known.then_some(self.natives.as_slice());
+            let analysis = analyse(&uri_to_path(&uri), &document.text, &open, natives, document.analysis.take());
+            let diagnostics: Vec<Json> = analysis.diagnostics.iter()
+                .filter(|diagnostic| diagnostic.file == analysis.name)
+                .map(|diagnostic| {
+                    let severity = match diagnostic.severity {
+                        Severity::ERROR => 1usize,
+                        Severity::WARNING => 2,
+                    };
+                    Json::object([
+                        ("range", range(&document.text, diagnostic.span)),
+                        ("severity", Json::from(severity)),
+                        ("source", Json::from("veil")),
+                        ("message", Json::from(diagnostic.message.as_str())),
+                    ])
+                })
+                .collect();
+            document.analysis = Some(analysis);
+            let params = Json::object([("uri", Json::from(uri.as_str())), ("diagnostics", Json::ARRAY(diagnostics))]);
+            messages.push(notification("textDocument/publishDiagnostics", params));
+        }
+        messages
+    }
+
+    //the document a request is about and the byte offset it points at
+    fn document_at(&self, params: &Json) -> Option<(&Document, usize)> {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str)?;
+        let document = self.documents.get(uri)?;
+        let offset = offset_of(&document.text, params.get("position")?);
+        Some((document, offset))
+    }
+
+    fn on_hover(&self, params: &Json) -> Json {
+        let hover = self.document_at(params).and_then(|(document, offset)| {
+            let analysis = document.analysis.as_ref().filter(|analysis| !analysis.stale)?;
+            analysis.hover(&document.text, offset, &self.natives)
+        });
+        match hover {
+            Some(text) => Json::object([(
+                "contents",
+                Json::object([("kind", Json::from("markdown")), ("value", Json::from(text))]),
+            )]),
+            None => Json::NULL,
+        }
+    }
+
+    fn on_definition(&self, params: &Json) -> Json {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("");
+        let definition = self.document_at(params).and_then(|(document, offset)| {
+            let analysis = document.analysis.as_ref().filter(|analysis| !analysis.stale)?;
+            let (file, text, span) = analysis.definition(offset)?;
+            match file == analysis.name {
+                true => Some((uri.to_owned(), range(&document.text, span))),
+                false => {
+                    //an open file's text is what the editor shows, so that's what positions count in
+                    let target = path_to_uri(Path::new(&file));
+                    let text = self.documents.get(&target).map(|document| document.text.as_str()).unwrap_or(&text);
+                    Some((target, range(text, span)))
+                },
+            }
+        });
+        match definition {
+            Some((uri, range)) => Json::object([("uri", Json::from(uri)), ("range", range)]),
+            None => Json::NULL,
+        }
+    }
+
+    fn on_document_symbol(&self, params: &Json) -> Json {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("");
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Json::NULL,
+        };
+        let analysis = match document.analysis.as_ref().filter(|analysis| !analysis.stale) {
+            Some(analysis) => analysis,
+            None => return Json::ARRAY(Vec::new()),
+        };
+
+        let mut symbols: Vec<&Symbol> = analysis.index.symbols.iter()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::RITE | SymbolKind::GLOBAL))
+            .collect();
+        symbols.sort_by_key(|symbol| symbol.span.start);
+        let symbols = symbols.into_iter().map(|symbol| {
+            let (kind, detail) = match symbol.kind {
+                SymbolKind::RITE => (
+                    SYMBOL_FUNCTION,
+                    find_rite(&analysis.index.stmts, &symbol.name).map(signature).unwrap_or_default(),
+                ),
+                _ => (SYMBOL_VARIABLE, symbol.type_t.as_ref().map(type_text).unwrap_or_default()),
+            };
+            Json::object([
+                ("name", Json::from(symbol.name.as_str())),
+                ("detail", Json::from(detail)),
+                ("kind", Json::from(kind)),
+                ("range", range(&document.text, symbol.range)),
+                ("selectionRange", range(&document.text, symbol.span)),
+            ])
+        }).collect();
+        Json::ARRAY(symbols)
+    }
+
+    fn on_completion(&self, params: &Json) -> Json {
+        let (document, offset) = match self.document_at(params) {
+            Some(found) => found,
+            None => return Json::ARRAY(Vec::new()),
+        };
+        let mut items: Vec<Json> = Vec::new();
+        let mut seen: Vec<String> = Vec::new();
+        let mut add = |label: &str, kind: usize, detail: String| {
+            if !seen.iter().any(|name| name == label) {
+                seen.push(label.to_owned());
+                items.push(Json::object([
+                    ("label", Json::from(label)),
+                    ("kind", Json::from(kind)),
+                    ("detail", Json::from(detail)),
+                ]));
+            }
+        };
+
+        //`namespace::` is followed by whatever that module exports
+        let before = &document.text[..offset];
+        let word_start = before.rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':')).map(|at| at + 1).unwrap_or(0);
+        let word = &before[word_start..];
+        if let Some((namespace, _)) = word.rsplit_once("::") {
+            let namespace: Vec<String> = namespace.split("::").map(str::to_owned).collect();
+            let ident = Ident { namespace, ..Ident::new("") };
+            if let Some((module, _)) = document.analysis.as_ref().and_then(|analysis| analysis.target_module(&ident)) {
+                let mut exports: Vec<&String> = module.exports.iter().collect();
+                exports.sort();
+                for name in exports {
+                    match find_rite(&module.stmts, name) {
+                        Some(decl) => add(name, COMPLETION_FUNCTION, signature(decl)),
+                        None => add(name, COMPLETION_VARIABLE, "global".to_string()),
+                    }
+                }
+            }
+            return Json::ARRAY(items);
+        }
+
+        if let Some(analysis) = &document.analysis {
+            //innermost first, so a local that shadows something wins
+            let mut symbols: Vec<&Symbol> = analysis.index.symbols.iter()
+                .filter(|symbol| symbol.visible.0 <= offset && offset <= symbol.visible.1)
+                .collect();
+            symbols.sort_by_key(|symbol| std::cmp::Reverse(symbol.visible.0));
+            for symbol in symbols {
+                match symbol.kind {
+                    SymbolKind::RITE => {
+                        let detail = find_rite(&analysis.index.stmts, &symbol.name).map(signature).unwrap_or_default();
+                        add(&symbol.name, COMPLETION_FUNCTION, detail);
+                    },
+                    kind => {
+                        let type_t = symbol.type_t.as_ref().map(type_text).unwrap_or_else(|| "anything".to_string());
+                        let kind = match kind {
+                            SymbolKind::GLOBAL => "global",
+                            SymbolKind::PARAM => "parameter",
+                            _ => "local",
+                        };
+                        add(&symbol.name, COMPLETION_VARIABLE, format!("({}) {}", kind, type_t));
+                    },
+                }
+            }
+            for stmt in &analysis.index.stmts {
+                if let Stmt::STATEMENT_IMPORT(import) = stmt {
+                    add(import.local_name(), COMPLETION_MODULE, module_id(&import.module_path()));
+                }
+            }
+        }
+        for native in &self.natives {
+            add(native, COMPLETION_FUNCTION, "native".to_string());
+        }
+        for builtin in BUILTINS {
+            add(builtin, COMPLETION_FUNCTION, "builtin".to_string());
+        }
+        for keyword in KEYWORDS {
+            add(keyword, COMPLETION_KEYWORD, String::new());
+        }
+        Json::ARRAY(items)
+    }
+}
+
+impl LanguageServer {
+    //five numbers per token: line and column relative to the token before, length, type, and a
+    //bitset of modifiers. tokens can't span lines, so block comments get cut up.
+    fn on_semantic_tokens(&self, params: &Json) -> Json {
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("");
+        let text = match self.documents.get(uri) {
+            Some(document) => document.text.as_str(),
+            None => return Json::NULL,
+        };
+
+        let mut data: Vec<Json> = Vec::new();
+        let (mut last_line, mut last_start) = (0, 0);
+        for highlighted in highlight(text, &self.natives) {
+            let (token_type, modifiers) = match highlighted.highlight.lsp_token() {
+                Some(token) => token,
+                None => continue,
+            };
+            let token_type = LSP_TOKEN_TYPES.iter().position(|name| *name == token_type).unwrap_or(0);
+            let modifiers = LSP_TOKEN_MODIFIERS.iter().enumerate()
+                .filter(|(_, name)| modifiers.contains(name))
+                .fold(0usize, |bits, (index, _)| bits | (1 << index));
+
+            let mut start = highlighted.span.start;
+            for line_text in text[highlighted.span.start..highlighted.span.end].split('\n') {
+                let at = position(text, start);
+                let line = at.get("line").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+                let character = at.get("character").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+                let length: usize = line_text.trim_end_matches('\r').chars().map(char::len_utf16).sum();
+                start += line_text.len() + 1;
+                if length == 0 {
+                    continue;
+                }
+                let delta_start = if line == last_line { character - last_start } else { character };
+                for number in [line - last_line, delta_start, length, token_type, modifiers] {
+                    data.push(Json::from(number));
+                }
+                (last_line, last_start) = (line, character);
+            }
+        }
+        Json::object([("data", Json::ARRAY(data))])
+    }
+}
+
+///TRANSPORT
+//every message is `Content-Length: <bytes>\r\n\r\n` and then that many bytes of JSON
+
+//the next message, or None once the editor hangs up
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| format!("Bad Content-Length {}!", value.trim()))?);
+        }
+    }
+    let length = length.ok_or("A message came without a Content-Length!")?;
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    let body = String::from_utf8(body).map_err(|err| err.to_string())?;
+    parse_json(&body).map(Some)
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body).map_err(|err| err.to_string())?;
+    output.flush().map_err(|err| err.to_string())
+}
+
+//talks to an editor until it says `exit` or hangs up. gives back whether it shut down properly.
+pub fn serve(server: &mut LanguageServer, input: &mut impl BufRead, output: &mut impl Write) -> Result<bool, String> {
+    while let Some(message) = read_message(input)? {
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+        if let Some(clean) = server.exit {
+            return Ok(clean);
+        }
+    }
+    Ok(false)
+}
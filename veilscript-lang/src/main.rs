@@ -1,26 +1,926 @@
-mod lexer;
-mod parser;
-mod ast;
-mod libparse;
-
-use lexer::*;
-use parser::Parser;
-
-fn main() {
-    let source = r#"
-    {
-        balls = 2;
-        cock: int = balls;
-    }
-    "#;
-
-    //trial run
-    let tokens = tokenise(&source);
-    println!("{}", &source);
-    //print_tokens_from_string(&source);
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse_statement().expect("Something exploded");
-    let node = ast.to_pretty_string();
-    println!("--parse-results--");
-    println!("{}",node);
+//`///` section headers aren't docs of whatever follows them, same as in the library
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use veilscript_lang::bytecode::Program;
+use veilscript_lang::bytecode_file::{read_program, write_program, MAGIC};
+use veilscript_lang::compiler::compile;
+use veilscript_lang::dialogue::{CHOOSE, SAY};
+use veilscript_lang::diagnostic::{has_errors, Diagnostic, Severity};
+use veilscript_lang::disasm::disassemble_program;
+use veilscript_lang::format::format_source;
+use veilscript_lang::highlight::textmate_grammar;
+use veilscript_lang::interpreter::Interpreter;
+use veilscript_lang::json::parse_json;
+use veilscript_lang::lexer::*;
+use veilscript_lang::lint::{lint, Level, LintConfig, Rule};
+use veilscript_lang::locale::{extract, to_csv, to_po, Catalogue};
+use veilscript_lang::module::{module_file_name, module_id, ModuleLoader, ModuleResolver};
+use veilscript_lang::optimise::optimise_modules;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::random::Rng;
+use veilscript_lang::repl::Repl;
+use veilscript_lang::runtime::{new_runtime, Backend, Limits, Resumed, ScriptRuntime};
+use veilscript_lang::source::{MemoryLoader, SourceFile};
+use veilscript_lang::typeck::check_loaded;
+use veilscript_lang::value::Value;
+use veilscript_lang::vm::Vm;
+
+///VEIL section
+//this here is the `veil` command. every subcommand exits with
+//  0  everything went fine
+//  1  a script is broken: it doesn't parse, doesn't check, or failed while running
+//  2  veil itself was used wrong: bad arguments, a file that can't be read
+//diagnostics go to stderr as `file:line: error: message`, real output goes to stdout.
+
+const USAGE: &str = "usage: veil <command> [args]
+
+commands:
+    run <file> [--interpreter] [--call <rite>] [--play <rite>] [--emit <event>] [--no-opt]
+        [--fuel <steps>] [--seed <n>] [--locale <table>] [--watch]
+                                        run a script (or a file from compile) on the bytecode vm
+                                        (or the reference interpreter), then maybe call a rite,
+                                        play one as a coroutine or emit an event (with nothing to
+                                        go with it).
+                                        --play prints what it yields, dialogue included, and waits
+                                        for a line to resume it with every time.
+                                        --no-opt runs it exactly as written, without the optimiser,
+                                        --fuel stops anything that takes more steps than that,
+                                        --seed seeds the dice (0 if not given) and --locale says
+                                        dialogue with the translations in a .po or .csv file.
+                                        --watch keeps going, and whenever the script or something
+                                        it summons is saved it's reloaded (keeping the globals) and
+                                        the rite is called or the event emitted again
+    check [--natives a,b] <file>...     parse, resolve and type check scripts and everything they summon.
+                                        without --natives, calls to anything unknown are taken to
+                                        be natives the game provides
+    lint [--config f] [--allow r] [--deny r] <file>...
+                                        point out code that is probably not what was meant
+    tokens <file>                       print the tokens of a file
+    ast <file>                          print the syntax tree of a file
+    fmt [--check] [file]...             format files in place (stdin to stdout if none given)
+    compile [--no-opt] <file> [-o out]  write the bytecode of a script (and everything it
+                                        summons) to a file run and disasm take, <file>.vlbc if no
+                                        -o is given
+    disasm [--no-opt] <file>            print the bytecode of a script or a compiled bytecode file
+    repl [file]                         try code out interactively, inside a script if one is given
+    grammar                             print a TextMate grammar for editors, straight from the lexer
+    extract [--csv] <file>...           print every line of dialogue as a PO (or CSV) file to translate";
+
+const EXIT_BROKEN: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("{}\n\n{}", message, USAGE);
+    ExitCode::from(EXIT_USAGE)
+}
+
+fn read_file(path: &str) -> Result<String, ExitCode> {
+    std::fs::read_to_string(path).map_err(|err| {
+        eprintln!("Couldn't read {}: {}", path, err);
+        ExitCode::from(EXIT_USAGE)
+    })
+}
+
+//summoned modules are looked up next to the script that was named on the command line, and keep
+//their real path as their name so diagnostics point somewhere that exists
+struct DirResolver {
+    root: PathBuf,
+}
+
+impl ModuleResolver for DirResolver {
+    fn resolve(&self, path: &[String]) -> Result<SourceFile, String> {
+        let file = self.root.join(module_file_name(path));
+        let text = std::fs::read_to_string(&file)
+            .map_err(|err| format!("Couldn't summon {}: {}", module_id(path), err))?;
+        Ok(SourceFile { name: file.display().to_string(), text })
+    }
+}
+
+//a loader that summons things from next to a script, and the module id of that script, which is
+//its file name
+fn script_loader(path: &str) -> (String, ModuleLoader) {
+    let file = Path::new(path);
+    let root = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let id = file.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    (id, ModuleLoader::new(DirResolver { root }))
+}
+
+//loads a script and everything it summons
+fn load_script(path: &str) -> Result<(String, ModuleLoader), ExitCode> {
+    let text = read_file(path)?;
+    let (id, mut loader) = script_loader(path);
+    if let Err(err) = loader.load_file(&id, SourceFile { name: path.to_owned(), text }) {
+        eprintln!("{}", err.to_string());
+        return Err(ExitCode::from(EXIT_BROKEN));
+    }
+    Ok((id, loader))
+}
+
+//an error that comes without a line, like one from running a script, still gets its file and
+//severity the way diagnostics do
+fn report(file: &str, err: String) {
+    eprintln!("{}", Diagnostic::error(file, Span::default(), err).to_string());
+}
+
+//a translation table, CSV if the file says so and PO otherwise
+fn read_catalogue(path: &str) -> Result<Catalogue, ExitCode> {
+    let text = read_file(path)?;
+    let catalogue = match path.ends_with(".csv") {
+        true => Catalogue::from_csv(&text),
+        false => Catalogue::from_po(&text),
+    };
+    catalogue.map_err(|err| {
+        eprintln!("{}: error: {}", path, err);
+        ExitCode::from(EXIT_USAGE)
+    })
+}
+
+fn print_native(args: &[Value]) -> Result<Value, String> {
+    let line: Vec<String> = args.iter().map(Value::to_string).collect();
+    println!("{}", line.join(" "));
+    Ok(Value::VOID)
+}
+
+///COMMANDS
+
+fn run(args: &[String]) -> ExitCode {
+    let mut backend = Backend::VM;
+    let mut optimising = true;
+    let mut limits = Limits::default();
+    let mut seed = 0;
+    let mut actions = Actions::default();
+    let mut locale = None;
+    let mut watch = false;
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vm" => backend = Backend::VM, //the default now, still taken so older command lines work
+            "--interpreter" => backend = Backend::INTERPRETER,
+            "--no-opt" => optimising = false,
+            "--watch" => watch = true,
+            "--fuel" => match args.next().map(|steps| steps.parse::<u64>()) {
+                Some(Ok(steps)) => limits.fuel = Some(steps),
+                _ => return usage_error("--fuel needs a number of steps"),
+            },
+            "--seed" => match args.next().map(|number| number.parse::<u64>()) {
+                Some(Ok(number)) => seed = number,
+                _ => return usage_error("--seed needs a number"),
+            },
+            "--call" => match args.next() {
+                Some(rite) => actions.call = Some(rite.clone()),
+                None => return usage_error("--call needs the name of a rite"),
+            },
+            "--play" => match args.next() {
+                Some(rite) => actions.play = Some(rite.clone()),
+                None => return usage_error("--play needs the name of a rite"),
+            },
+            "--emit" => match args.next() {
+                Some(event) => actions.emit = Some(event.clone()),
+                None => return usage_error("--emit needs the name of an event"),
+            },
+            "--locale" => match args.next() {
+                Some(table) => match read_catalogue(table) {
+                    Ok(catalogue) => locale = Some(catalogue),
+                    Err(code) => return code,
+                },
+                None => return usage_error("--locale needs a .po or .csv file"),
+            },
+            _ if file.is_none() => file = Some(arg.clone()),
+            other => return usage_error(&format!("Don't know what to do with {}", other)),
+        }
+    }
+    let file = match file {
+        Some(file) => file,
+        None => return usage_error("run needs a file"),
+    };
+    if is_compiled(&file) {
+        let set_up = |vm: &mut Vm| set_up(vm, optimising, limits, seed, locale);
+        return run_compiled(&file, backend, watch, set_up, &actions);
+    }
+    let (id, loader) = match load_script(&file) {
+        Ok(loaded) => loaded,
+        Err(code) => return code,
+    };
+
+    let files = script_files(&loader, &id);
+    if let Some(locale) = &locale {
+        let mut entries = Vec::new();
+        for (module, path) in &files {
+            if let Some(module) = loader.get(module) {
+                extract(&path.display().to_string(), &module.stmts, &mut entries);
+            }
+        }
+        for warning in locale.check(&entries) {
+            eprintln!("{}", warning.to_string());
+        }
+    }
+
+    let mut runtime = new_runtime(backend);
+    set_up(runtime.as_mut(), optimising, limits, seed, locale);
+    if let Err(err) = runtime.load(loader) {
+        report(&file, err);
+        return ExitCode::from(EXIT_BROKEN);
+    }
+    let ran = run_actions(runtime.as_mut(), &file, &id, &actions);
+    if watch {
+        watch_scripts(runtime.as_mut(), files, |runtime| {
+            run_actions(runtime, &file, &id, &actions);
+        });
+    }
+    match ran {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(EXIT_BROKEN),
+    }
+}
+
+//everything `run` sets before the script is loaded
+fn set_up(
+    runtime: &mut dyn ScriptRuntime, optimising: bool, limits: Limits, seed: u64,
+    locale: Option<Catalogue>,
+) {
+    runtime.set_optimising(optimising);
+    runtime.set_limits(limits);
+    *runtime.rng() = Rng::new(seed);
+    runtime.set_locale(locale);
+    runtime.register_native("print", Box::new(print_native));
+    runtime.register_native(SAY, Box::new(say_native));
+    runtime.register_native(CHOOSE, Box::new(choose_native));
+}
+
+//whether a file is bytecode from `veil compile` rather than a script
+fn is_compiled(path: &str) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    let read = std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+    read.is_ok() && magic == MAGIC
+}
+
+//a compiled file runs on the vm as it is. the script it came from is its last module, everything
+//it summons comes before it. there's no source to reload, so it can't be watched.
+fn run_compiled(
+    file: &str, backend: Backend, watch: bool, set_up: impl FnOnce(&mut Vm), actions: &Actions,
+) -> ExitCode {
+    if backend == Backend::INTERPRETER {
+        return usage_error("--interpreter needs a script, compiled bytecode only runs on the vm");
+    }
+    if watch {
+        return usage_error("--watch needs a script, compiled bytecode can't be reloaded");
+    }
+    let program = match std::fs::read(file) {
+        Ok(bytes) => read_program(&bytes),
+        Err(err) => {
+            eprintln!("Couldn't read {}: {}", file, err);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+    let mut vm = Vm::new();
+    set_up(&mut vm);
+    let loaded = program.and_then(|program| {
+        let id = program.modules.last().map(|module| module.id.clone());
+        vm.load_program(program)?;
+        id.ok_or_else(|| format!("{} has no modules in it!", file))
+    });
+    let id = match loaded {
+        Ok(id) => id,
+        Err(err) => {
+            report(file, err);
+            return ExitCode::from(EXIT_BROKEN);
+        },
+    };
+    match run_actions(&mut vm, file, &id, actions) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(EXIT_BROKEN),
+    }
+}
+
+//what `run` does once the script is loaded, in this order
+#[derive(Default)]
+struct Actions {
+    call: Option<String>,
+    play: Option<String>,
+    emit: Option<String>,
+}
+
+//false if anything failed, which has been reported (against `file`, the script) by then
+fn run_actions(runtime: &mut dyn ScriptRuntime, file: &str, id: &str, actions: &Actions) -> bool {
+    if let Some(rite) = &actions.call {
+        match runtime.call(id, rite, &[]) {
+            Ok(Value::VOID) => {},
+            Ok(value) => println!("{}", value.to_string()),
+            Err(err) => {
+                report(file, err);
+                return false;
+            },
+        }
+    }
+    if let Some(rite) = &actions.play
+        && let Err(err) = play(runtime, id, rite) {
+        report(file, err);
+        return false;
+    }
+    //every handler runs even if some fail, so every failure gets reported
+    match actions.emit.as_ref().map_or(Ok(()), |event| runtime.emit(event, &[])) {
+        Ok(()) => true,
+        Err(errors) => {
+            for err in errors {
+                report(file, err);
+            }
+            false
+        },
+    }
+}
+
+//`run --play`: spawns a rite and keeps resuming it until it's done. whatever it yields is printed
+//(void isn't), and every resume after a yield waits for a line of stdin, read as a number if it is
+//one. so a dialogue is played by pressing enter after each line and typing the number of a choice.
+//running dry stops it for good: a game would carry on next frame, but here nothing would ever
+//fill the tank any fuller, and resuming right away would just go round in circles.
+fn play(runtime: &mut dyn ScriptRuntime, id: &str, rite: &str) -> Result<(), String> {
+    let coroutine = runtime.spawn(id, rite, &[])?;
+    let mut lines = std::io::stdin().lock().lines();
+    let mut value = Value::VOID;
+    loop {
+        let yielded = match runtime.resume(coroutine, value)? {
+            Resumed::FINISHED(returned) => {
+                if returned != Value::VOID {
+                    println!("{}", returned.to_string());
+                }
+                return Ok(());
+            },
+            Resumed::YIELDED(yielded) => yielded,
+            Resumed::OUT_OF_FUEL => {
+                runtime.cancel(coroutine);
+                return Err(format!(
+                    "Out of fuel! {} took more steps than --fuel allows between two yields.", rite
+                ));
+            },
+        };
+        if yielded != Value::VOID {
+            println!("{}", yielded.to_string());
+        }
+        value = match lines.next() {
+            Some(Ok(line)) => input_value(line.trim()),
+            _ => {
+                runtime.cancel(coroutine);
+                return Err(format!("Ran out of input with {} still asleep!", rite));
+            },
+        };
+    }
+}
+
+fn input_value(line: &str) -> Value {
+    if line.is_empty() {
+        return Value::VOID;
+    }
+    match (line.parse::<i64>(), line.parse::<f64>()) {
+        (Ok(int), _) => Value::INT(int),
+        (_, Ok(float)) => Value::FLOAT(float),
+        _ => Value::STRING(line.to_string()),
+    }
+}
+
+//what `run` says dialogue with (see dialogue.rs), unless the script has rites of its own for it
+fn say_native(args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::VOID, text] => println!("{}", text.to_string()),
+        [speaker, text] => println!("{}: {}", speaker.to_string(), text.to_string()),
+        _ => return Err("say takes a speaker and a line!".to_string()),
+    }
+    Ok(Value::VOID)
+}
+
+fn choose_native(args: &[Value]) -> Result<Value, String> {
+    for (index, choice) in args.iter().enumerate() {
+        if *choice != Value::VOID {
+            println!("  {}) {}", index, choice.to_string());
+        }
+    }
+    Ok(Value::VOID)
+}
+
+//the file of every module a script summons (and the script itself), by module id
+fn script_files(loader: &ModuleLoader, id: &str) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    let mut stack = vec![id.to_owned()];
+    while let Some(id) = stack.pop() {
+        let Some(module) = loader.get(&id) else { continue };
+        if files.iter().any(|(seen, _)| *seen == id) {
+            continue;
+        }
+        let mut imports: Vec<String> = module.imports.values().cloned().collect();
+        imports.sort();
+        stack.extend(imports);
+        files.push((id, PathBuf::from(loader.sources.name(module.file))));
+    }
+    files
+}
+
+//`run --watch`: reloads whatever module's file changes, then runs `again`. never gives up, a
+//reload that fails just waits for the file to change again. std has no file events, so it looks
+//at when the files were last written a few times a second.
+fn watch_scripts(
+    runtime: &mut dyn ScriptRuntime, files: Vec<(String, PathBuf)>, again: impl Fn(&mut dyn ScriptRuntime),
+) -> ! {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut files: Vec<(String, PathBuf, _)> = files.into_iter()
+        .map(|(id, path)| {
+            let seen = modified(&path);
+            (id, path, seen)
+        })
+        .collect();
+    eprintln!("Watching {} file(s), ctrl+c to stop.", files.len());
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        for (id, path, seen) in &mut files {
+            let now = modified(path);
+            if now == *seen {
+                continue;
+            }
+            *seen = now;
+            let file = path.display().to_string();
+            let text = match std::fs::read_to_string(&*path) {
+                Ok(text) => text,
+                Err(err) => {
+                    eprintln!("Couldn't read {}: {}", file, err);
+                    continue;
+                },
+            };
+            //a half typed line is what goes wrong most, and this way it's reported against the
+            //file rather than the module, which is all the runtime knows it by
+            let mut parser = Parser::new(tokenise(&text));
+            if let Err(err) = parser.parse_program() {
+                eprintln!("{}", Diagnostic::error(&file, parser.error_span(), err).to_string());
+                continue;
+            }
+            match runtime.reload(id, &text) {
+                Ok(reload) => {
+                    eprintln!("{}", reload.to_string());
+                    again(runtime);
+                },
+                //located already, see reload.rs
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+    }
+}
+
+//`check [--natives a,b,c] <files...>`: with --natives, calling anything that isn't a rite, a
+//builtin, `print` or one of those is an error. without it, veil can't know what the game provides,
+//so it doesn't.
+fn check(args: &[String]) -> ExitCode {
+    let mut natives: Option<Vec<String>> = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--natives" => match args.next() {
+                Some(list) => {
+                    let mut list: Vec<String> = list.split(',').map(|name| name.trim().to_owned()).collect();
+                    list.push("print".to_string());
+                    natives = Some(list);
+                },
+                None => return usage_error("--natives needs a comma separated list of names"),
+            },
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.is_empty() {
+        return usage_error("check needs at least one file");
+    }
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut broken = false;
+    for file in &files {
+        match load_script(file) {
+            //scripts that summon the same module would report its problems twice otherwise
+            Ok((_, loader)) => for diagnostic in check_loaded(&loader, natives.as_deref()) {
+                if !diagnostics.contains(&diagnostic) {
+                    diagnostics.push(diagnostic);
+                }
+            },
+            Err(code) if code == ExitCode::from(EXIT_USAGE) => return code,
+            Err(_) => broken = true,
+        }
+    }
+
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.to_string());
+    }
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::ERROR).count();
+    let warnings = diagnostics.len() - errors;
+    if errors + warnings > 0 {
+        eprintln!("{} error(s), {} warning(s)", errors, warnings);
+    }
+    match broken || has_errors(&diagnostics) {
+        true => ExitCode::from(EXIT_BROKEN),
+        false => ExitCode::SUCCESS,
+    }
+}
+
+//`lint [--config file] [--allow rule] [--deny rule] <files...>`: the config is veil-lint.json in
+//the current directory unless one is given, and --allow (off) and --deny (error) go on top of it
+fn lint_files(args: &[String]) -> ExitCode {
+    let mut config_file = None;
+    let mut overrides: Vec<(Rule, Level)> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(file) => config_file = Some(file.clone()),
+                None => return usage_error("--config needs a file"),
+            },
+            flag @ ("--allow" | "--deny") => {
+                let Some(name) = args.next() else {
+                    return usage_error(&format!("{} needs the name of a rule", flag));
+                };
+                let level = if flag == "--allow" { Level::OFF } else { Level::ERROR };
+                match Rule::from_name(name) {
+                    Ok(rule) => overrides.push((rule, level)),
+                    Err(err) => return usage_error(&err),
+                }
+            },
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.is_empty() {
+        return usage_error("lint needs at least one file");
+    }
+
+    let mut config = LintConfig::default();
+    let config_file = config_file.or_else(|| Path::new("veil-lint.json").exists().then(|| "veil-lint.json".to_string()));
+    if let Some(config_file) = config_file {
+        let text = match read_file(&config_file) {
+            Ok(text) => text,
+            Err(code) => return code,
+        };
+        match parse_json(&text).and_then(|json| LintConfig::from_json(&json)) {
+            Ok(read) => config = read,
+            Err(err) => {
+                eprintln!("{}: error: {}", config_file, err);
+                return ExitCode::from(EXIT_USAGE);
+            },
+        }
+    }
+    for (rule, level) in overrides {
+        config.set(rule, level);
+    }
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for file in &files {
+        match read_file(file) {
+            Ok(source) => diagnostics.extend(lint(file, &source, &config)),
+            Err(code) => return code,
+        }
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.to_string());
+    }
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::ERROR).count();
+    let warnings = diagnostics.len() - errors;
+    if errors + warnings > 0 {
+        eprintln!("{} error(s), {} warning(s)", errors, warnings);
+    }
+    match has_errors(&diagnostics) {
+        true => ExitCode::from(EXIT_BROKEN),
+        false => ExitCode::SUCCESS,
+    }
+}
+
+fn tokens(args: &[String]) -> ExitCode {
+    let [file] = args else {
+        return usage_error("tokens needs exactly one file");
+    };
+    let source = match read_file(file) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let mut ok = true;
+    for token in tokenise(&source) {
+        println!("{:>4}  {:?} -> {}", token.span.line, token.kind, token.lexeme);
+        if token.kind == TokenType::ERROR {
+            eprintln!("{}:{}: error: Can't make sense of {:?}!", file, token.span.line, token.lexeme);
+            ok = false;
+        }
+    }
+    match ok {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(EXIT_BROKEN),
+    }
+}
+
+fn ast(args: &[String]) -> ExitCode {
+    let [file] = args else {
+        return usage_error("ast needs exactly one file");
+    };
+    let source = match read_file(file) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let mut parser = Parser::new(tokenise(&source));
+    match parser.parse_program() {
+        Ok(stmts) => {
+            for stmt in stmts {
+                println!("{}", stmt.to_pretty_string());
+            }
+            ExitCode::SUCCESS
+        },
+        Err(err) => {
+            eprintln!("{}", Diagnostic::error(file, parser.error_span(), err).to_string());
+            ExitCode::from(EXIT_BROKEN)
+        },
+    }
+}
+
+//`fmt [--check] [files...]`: formats files in place, or stdin to stdout when there are none. with
+//--check nothing gets written, it just fails if something isn't formatted yet.
+fn fmt(args: &[String]) -> ExitCode {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("Couldn't read stdin: {}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+        return match format_source(&source) {
+            Ok(formatted) if check && formatted != source => ExitCode::from(EXIT_BROKEN),
+            Ok(_) if check => ExitCode::SUCCESS,
+            Ok(formatted) => {
+                print!("{}", formatted);
+                ExitCode::SUCCESS
+            },
+            Err(err) => {
+                eprintln!("<stdin>: error: {}", err);
+                ExitCode::from(EXIT_BROKEN)
+            },
+        };
+    }
+
+    let mut ok = true;
+    for file in files {
+        let source = match read_file(file) {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        match format_source(&source) {
+            Ok(formatted) if source == formatted => {},
+            Ok(_) if check => {
+                println!("{} is not formatted", file);
+                ok = false;
+            },
+            Ok(formatted) => {
+                if let Err(err) = std::fs::write(file, formatted) {
+                    eprintln!("Couldn't write {}: {}", file, err);
+                    return ExitCode::from(EXIT_USAGE);
+                }
+            },
+            Err(err) => {
+                eprintln!("{}: error: {}", file, err);
+                ok = false;
+            },
+        }
+    }
+    match ok {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(EXIT_BROKEN),
+    }
+}
+
+fn disasm(args: &[String]) -> ExitCode {
+    let optimising = !args.iter().any(|arg| arg == "--no-opt");
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != "--no-opt").collect();
+    let [file] = args[..] else {
+        return usage_error("disasm needs exactly one file");
+    };
+    let bytes = match std::fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Couldn't read {}: {}", file, err);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+
+    //compiled bytecode files are read as they are, anything else is a script to compile first
+    let program = if bytes.starts_with(&MAGIC) {
+        read_program(&bytes)
+    } else {
+        match compile_script(file, optimising) {
+            Ok(program) => program,
+            Err(code) => return code,
+        }
+    };
+    match program {
+        Ok(program) => {
+            print!("{}", disassemble_program(&program));
+            ExitCode::SUCCESS
+        },
+        Err(err) => {
+            report(file, err);
+            ExitCode::from(EXIT_BROKEN)
+        },
+    }
+}
+
+//a script and everything it summons as one program, for the vm
+fn compile_script(file: &str, optimising: bool) -> Result<Result<Program, String>, ExitCode> {
+    let (_, loader) = load_script(file)?;
+    let mut program = Program::default();
+    let mut modules = loader.into_modules();
+    Ok(match optimising {
+        true => optimise_modules(&mut modules),
+        false => Ok(()),
+    }.and_then(|_| compile(&mut program, &modules)).map(|_| program))
+}
+
+//`compile [--no-opt] <file> [-o out]`: writes the bytecode of a script to a file `run` and
+//`disasm` take, next to the script (as .vlbc) if no name is given
+fn compile_file(args: &[String]) -> ExitCode {
+    let mut optimising = true;
+    let mut out = None;
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-opt" => optimising = false,
+            "-o" => match args.next() {
+                Some(path) => out = Some(PathBuf::from(path)),
+                None => return usage_error("-o needs a file to write to"),
+            },
+            _ if file.is_none() => file = Some(arg.clone()),
+            other => return usage_error(&format!("Don't know what to do with {}", other)),
+        }
+    }
+    let Some(file) = file else {
+        return usage_error("compile needs a file");
+    };
+    let out = out.unwrap_or_else(|| Path::new(&file).with_extension("vlbc"));
+    let bytes = match compile_script(&file, optimising) {
+        Ok(program) => program.and_then(|program| write_program(&program)),
+        Err(code) => return code,
+    };
+    let bytes = match bytes {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            report(&file, err);
+            return ExitCode::from(EXIT_BROKEN);
+        },
+    };
+    match std::fs::write(&out, bytes) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Couldn't write {}: {}", out.display(), err);
+            ExitCode::from(EXIT_USAGE)
+        },
+    }
+}
+
+//`repl [file]`: the script (if any) is read again on every :reset, so edits to it get picked up
+fn repl(args: &[String]) -> ExitCode {
+    let file = match args {
+        [] => None,
+        [file] => Some(file.clone()),
+        _ => return usage_error("repl takes at most one file"),
+    };
+    if let Some(file) = &file && let Err(code) = read_file(file) {
+        return code;
+    }
+    let module = match &file {
+        Some(file) => script_loader(file).0,
+        None => "repl".to_string(),
+    };
+
+    let setup = {
+        let module = module.clone();
+        move || -> Result<Interpreter, String> {
+            let loader = match &file {
+                Some(path) => {
+                    let text = std::fs::read_to_string(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+                    let (_, mut loader) = script_loader(path);
+                    //the diagnostic has the file and line, the repl just needs to know it failed
+                    let source = SourceFile { name: path.clone(), text };
+                    if let Err(err) = loader.load_file(&module, source) {
+                        eprintln!("{}", err.to_string());
+                        return Err(format!("Couldn't load {}!", path));
+                    }
+                    loader
+                },
+                None => {
+                    let mut loader = ModuleLoader::from_loader(MemoryLoader::new());
+                    loader.load_source(&module, "").map_err(|err| err.to_string())?;
+                    loader
+                },
+            };
+            let mut interpreter = Interpreter::new();
+            interpreter.register_native("print", Box::new(print_native));
+            interpreter.load(loader)?;
+            Ok(interpreter)
+        }
+    };
+    let mut repl = match Repl::new(&module, Box::new(setup)) {
+        Ok(repl) => repl,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::from(EXIT_BROKEN);
+        },
+    };
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", if repl.is_pending() { "...> " } else { "veil> " });
+        let _ = std::io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("Couldn't read stdin: {}", err);
+                return ExitCode::from(EXIT_USAGE);
+            },
+            None => break,
+        };
+        if !repl.is_pending() && matches!(line.trim(), ":quit" | ":q") {
+            break;
+        }
+        match repl.feed(&line) {
+            Ok(output) if output.is_empty() => {},
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+    println!();
+    ExitCode::SUCCESS
+}
+
+//`extract [--csv] <files...>`: only the files named, not what they summon, so every line turns up
+//once under the file it's written in
+fn extract_lines(args: &[String]) -> ExitCode {
+    let csv = args.iter().any(|arg| arg == "--csv");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--csv").collect();
+    if files.is_empty() {
+        return usage_error("extract needs at least one file");
+    }
+
+    let mut entries = Vec::new();
+    for file in files {
+        let source = match read_file(file) {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        let mut parser = Parser::new(tokenise(&source));
+        match parser.parse_program() {
+            Ok(stmts) => extract(file, &stmts, &mut entries),
+            Err(err) => {
+                eprintln!("{}", Diagnostic::error(file, parser.error_span(), err).to_string());
+                return ExitCode::from(EXIT_BROKEN);
+            },
+        }
+    }
+    print!("{}", if csv { to_csv(&entries) } else { to_po(&entries) });
+    ExitCode::SUCCESS
+}
+
+fn grammar(args: &[String]) -> ExitCode {
+    if !args.is_empty() {
+        return usage_error("grammar takes no arguments");
+    }
+    match textmate_grammar() {
+        Ok(grammar) => {
+            println!("{}", grammar.to_pretty_string(2));
+            ExitCode::SUCCESS
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(EXIT_BROKEN)
+        },
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return usage_error("veil needs a command"),
+    };
+    match command {
+        "run" => run(rest),
+        "check" => check(rest),
+        "lint" => lint_files(rest),
+        "tokens" => tokens(rest),
+        "ast" => ast(rest),
+        "fmt" => fmt(rest),
+        "compile" => compile_file(rest),
+        "disasm" => disasm(rest),
+        "repl" => repl(rest),
+        "grammar" => grammar(rest),
+        "extract" => extract_lines(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        },
+        other => usage_error(&format!("Unknown command {}", other)),
+    }
 }
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{tokenise, Span};
+use crate::parser::Parser;
+use crate::runtime::not_exported;
+use crate::source::{FileId, SourceFile, SourceLoader, SourceMap};
+use crate::visit::Visitor;
+
+///RESOLVER section
+//a RESOLVER is how the module system gets its hands on source code. `path` is whatever came after
+//`summon`, already split on `::`. The game decides where that actually lives: a folder on disk,
+//a hashmap, a packed archive, whatever. The loader only ever asks for a named bit of text.
+pub trait ModuleResolver {
+    fn resolve(&self, path: &[String]) -> Result<SourceFile, String>;
+}
+
+//the canonical name of a module, used as its id everywhere. `quests::intro`, for example.
+pub fn module_id(path: &[String]) -> String {
+    path.join("::")
+}
+
+//the file a module lives in, relative to wherever scripts are kept. `quests/intro.veil`
+pub fn module_file_name(path: &[String]) -> String {
+    format!("{}.veil", path.join("/"))
+}
+
+//the resolver pretty much everyone wants: module paths become file names, and a SourceLoader
+//(std::fs, a hashmap, the game's pak files) fetches them.
+pub struct LoaderResolver<L: SourceLoader> {
+    pub loader: L,
+}
+
+impl<L: SourceLoader> LoaderResolver<L> {
+    pub fn new(loader: L) -> Self {
+        LoaderResolver { loader }
+    }
+}
+
+impl<L: SourceLoader> ModuleResolver for LoaderResolver<L> {
+    fn resolve(&self, path: &[String]) -> Result<SourceFile, String> {
+        let name = module_file_name(path);
+        let text = self.loader.load(&name)
+            .map_err(|err| format!("Couldn't summon {}: {}", module_id(path), err))?;
+        Ok(SourceFile { name, text })
+    }
+}
+
+///MODULE section
+//a MODULE is one parsed file plus everything the module system needs to know about it: which
+//names it defines at the top level, which of those it exports, and what its imports are called.
+#[derive(Debug)]
+pub struct Module {
+    pub id: String,
+    pub file: FileId,
+    pub stmts: Vec<Stmt>,
+    pub imports: HashMap<String, String>, //local name -> module id
+    pub definitions: HashSet<String>,     //every top level rite and global
+    pub exports: HashSet<String>,         //the ones other modules are allowed to touch
+}
+
+impl Module {
+    pub(crate) fn new(id: &str, file: FileId, stmts: Vec<Stmt>) -> Self {
+        let mut definitions = HashSet::new();
+        let mut exports = HashSet::new();
+        for stmt in &stmts {
+            let (inner, exported) = match stmt {
+                Stmt::STATEMENT_EXPORT(inner) => (inner.as_ref(), true),
+                other => (other, false),
+            };
+            let name = match inner {
+                Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => &decl.ident.name,
+                Stmt::STATEMENT_ASSIGNMENT(assignment) => &assignment.ident.name,
+                _ => continue,
+            };
+            definitions.insert(name.clone());
+            if exported {
+                exports.insert(name.clone());
+            }
+        }
+        Module { id: id.to_owned(), file, stmts, imports: HashMap::new(), definitions, exports }
+    }
+
+    //figures out which module a namespace like `intro` or `quests::intro` refers to
+    pub fn resolve_namespace(&self, namespace: &[String]) -> Option<&str> {
+        let written = module_id(namespace);
+        if let Some(id) = self.imports.get(&written) {
+            return Some(id);
+        }
+        //the full path works too, as long as it was summoned
+        self.imports.values().find(|id| **id == written).map(|id| id.as_str())
+    }
+}
+
+///LOADER section
+//the LOADER owns every module that has been summoned so far. It loads imports depth first,
+//refuses import cycles, and checks that every `module::name` actually points at something
+//exported. whatever goes wrong comes back as a diagnostic in the file it went wrong in: a file
+//that doesn't parse, or a summon that can't be found or goes round in a circle.
+pub struct ModuleLoader {
+    resolver: Box<dyn ModuleResolver>,
+    pub sources: SourceMap,
+    pub modules: HashMap<String, Module>,
+    pub order: Vec<String>, //dependencies always come before the modules that summon them
+    loading: Vec<String>,   //the chain of modules currently being loaded, for cycle detection
+}
+
+impl ModuleLoader {
+    pub fn new(resolver: impl ModuleResolver + 'static) -> Self {
+        ModuleLoader {
+            resolver: Box::new(resolver),
+            sources: SourceMap::new(),
+            modules: HashMap::new(),
+            order: Vec::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    pub fn from_loader(loader: impl SourceLoader + 'static) -> Self {
+        ModuleLoader::new(LoaderResolver::new(loader))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Module> {
+        self.modules.get(id)
+    }
+
+    //hands every module over, dependencies first. this is what the runtimes eat.
+    pub fn into_modules(mut self) -> Vec<Module> {
+        self.order.iter().filter_map(|id| self.modules.remove(id)).collect()
+    }
+
+    //summons a module (and everything it summons) through the resolver. returns its id.
+    pub fn load(&mut self, path: &[String]) -> Result<String, Diagnostic> {
+        self.summon(path, &module_id(path), Span::default())
+    }
+
+    //the same, for a `summon` at `span` in `file`, which is where problems finding it get pinned
+    fn summon(&mut self, path: &[String], file: &str, span: Span) -> Result<String, Diagnostic> {
+        let id = module_id(path);
+        let here = |err| Diagnostic::error(file, span, err);
+        if !self.modules.contains_key(&id) && !self.loading.contains(&id) {
+            let source = self.resolver.resolve(path).map_err(here)?;
+            self.load_file(&id, source)?;
+        } else {
+            self.check_cycle(&id).map_err(here)?;
+        }
+        Ok(id)
+    }
+
+    //loads a module whose source the host already has, like the script the game was started with.
+    pub fn load_source(&mut self, id: &str, source: &str) -> Result<(), Diagnostic> {
+        self.load_file(id, SourceFile { name: id.to_owned(), text: source.to_owned() })
+    }
+
+    pub fn load_file(&mut self, id: &str, file: SourceFile) -> Result<(), Diagnostic> {
+        self.check_cycle(id).map_err(|err| Diagnostic::error(&file.name, Span::default(), err))?;
+        if self.modules.contains_key(id) {
+            return Ok(());
+        }
+
+        let file_id = self.sources.add(&file.name, &file.text);
+        let tokens = tokenise(&file.text);
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse_program()
+            .map_err(|err| Diagnostic::error(&file.name, parser.error_span(), err))?;
+        let mut module = Module::new(id, file_id, stmts);
+
+        self.loading.push(id.to_owned());
+        for stmt in &module.stmts {
+            if let Stmt::STATEMENT_IMPORT(import) = stmt {
+                let imported = self.summon(&import.module_path(), &file.name, import.path[0].span);
+                let imported = match imported {
+                    Ok(imported) => imported,
+                    Err(err) => {
+                        self.loading.pop();
+                        return Err(err);
+                    }
+                };
+                module.imports.insert(import.local_name().to_owned(), imported);
+            }
+        }
+        self.loading.pop();
+
+        self.check_visibility(&module)?;
+        self.order.push(id.to_owned());
+        self.modules.insert(id.to_owned(), module);
+        Ok(())
+    }
+
+    fn check_cycle(&self, id: &str) -> Result<(), String> {
+        match self.loading.iter().position(|loading| loading == id) {
+            Some(start) => {
+                let mut chain = self.loading[start..].to_vec();
+                chain.push(id.to_owned());
+                Err(format!("Import cycle! {}", chain.join(" -> ")))
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn check_visibility(&self, module: &Module) -> Result<(), Diagnostic> {
+        check_visibility(module, self.sources.name(module.file), |id| self.modules.get(id))
+    }
+}
+
+//every `module::name` in `module` has to point at something that module exports. `lookup` finds
+//summoned modules by id, one it doesn't know (say, loaded as bytecode) is taken on trust.
+pub(crate) fn check_visibility<'m>(
+    module: &Module, file: &str, lookup: impl Fn(&str) -> Option<&'m Module>,
+) -> Result<(), Diagnostic> {
+    let mut collector = IdentCollector::default();
+    collector.visit_stmts(&module.stmts);
+
+    for ident in collector.idents.into_iter().filter(|ident| ident.is_namespaced()) {
+        let here = |err| Diagnostic::error(file, ident.span, err);
+        let target = module.resolve_namespace(&ident.namespace).ok_or_else(|| here(format!(
+            "{} was never summoned, so {} can't be reached!",
+            module_id(&ident.namespace), ident.full_name()
+        )))?;
+        let Some(target) = lookup(target) else { continue };
+        if !target.definitions.contains(&ident.name) {
+            return Err(here(format!("{} has no rite or global named {}!", target.id, ident.name)));
+        }
+        if !target.exports.contains(&ident.name) {
+            return Err(here(not_exported(&ident.name, &target.id)));
+        }
+    }
+    Ok(())
+}
+
+//every name used anywhere in a module, rite bodies included
+#[derive(Default)]
+struct IdentCollector<'a> {
+    idents: Vec<&'a Ident>,
+}
+
+impl<'a> Visitor<'a> for IdentCollector<'a> {
+    fn visit_ident(&mut self, ident: &'a Ident) {
+        self.idents.push(ident);
+    }
+}
@@ -0,0 +1,114 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::*;
+use crate::module::Module;
+use crate::runtime::validate_module;
+use crate::value::Value;
+use crate::visit::{walk_expr_mut, walk_scope_mut, MutVisitor};
+
+///OPTIMISER section
+//this here is the OPTIMISER. It rewrites a module's AST before either backend runs it, and the
+//one rule it lives by is that no script can tell: same values, same wrapping, same int to float
+//promotion, same errors. the one thing that does change is fuel (see Limits in runtime.rs): an
+//operator folded away is a step nobody pays for, so an optimised script runs out of fuel later,
+//if at all. What it does:
+//  folds arithmetic on literals, `60 * 60 * 24` becomes 86400. the folding is done by BinOp::apply
+//  and MonOp::apply themselves, so it can't drift from the runtime. anything that would be a
+//  runtime error (`1 / 0`) is left alone to fail when it runs, like it always did.
+//  throws away groupings, the tree already says what goes with what
+//  turns `-(-x)` and `+x` into x, but only when x is sure to be a number. for a rune they're an
+//  error, and that error has to stay.
+//  drops whatever comes after a `ret` in a scope or rite body, it can never run. the top of a
+//  module is left alone: assignments up there make globals whether they run or not.
+//
+//it runs after validate_module, so it never hides a broken script by throwing the broken part away.
+
+pub fn optimise(stmts: &mut Vec<Stmt>) {
+    Optimiser.visit_stmts_mut(stmts);
+}
+
+//validates every module, then optimises them. for whoever doesn't validate on their own first.
+pub fn optimise_modules(modules: &mut [Module]) -> Result<(), String> {
+    for module in modules.iter() {
+        validate_module(&module.stmts).map_err(|err| format!("In module {}: {}", module.id, err))?;
+    }
+    for module in modules.iter_mut() {
+        optimise(&mut module.stmts);
+    }
+    Ok(())
+}
+
+struct Optimiser;
+
+impl MutVisitor for Optimiser {
+    fn visit_scope_mut(&mut self, scope: &mut Scope) {
+        if let Some(ret) = scope.stmts.iter().position(Stmt::always_returns) {
+            scope.stmts.truncate(ret + 1);
+        }
+        walk_scope_mut(self, scope);
+    }
+
+    //children first, so `60 * 60 * 24` folds from the inside out
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let placeholder = Expr::ATOM(Atom::LITERAL_INT(0));
+        *expr = fold(std::mem::replace(expr, placeholder));
+    }
+}
+
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::ATOM(Atom::LITERAL_INT(value)) => Some(Value::INT(*value)),
+        Expr::ATOM(Atom::LITERAL_FLOAT(value)) => Some(Value::FLOAT(*value)),
+        _ => None,
+    }
+}
+
+fn literal(value: Value) -> Option<Expr> {
+    match value {
+        Value::INT(value) => Some(Expr::ATOM(Atom::LITERAL_INT(value))),
+        Value::FLOAT(value) => Some(Expr::ATOM(Atom::LITERAL_FLOAT(value))),
+        _ => None,
+    }
+}
+
+//whether an expression gives back a number whenever it doesn't fail. unary operators and every
+//binary one but + only ever give numbers, and + does too as soon as one side is a number (rune
+//plus number is an error).
+fn is_numeric(expr: &Expr) -> bool {
+    match expr {
+        Expr::ATOM(Atom::LITERAL_INT(_) | Atom::LITERAL_FLOAT(_) | Atom::LITERAL_DICE(_)) => true,
+        Expr::UNARY_EXPR { .. } => true,
+        Expr::BINARY_EXPR { left, opcode: BinOp::ADD, right } => is_numeric(left) || is_numeric(right),
+        Expr::BINARY_EXPR { .. } => true,
+        Expr::GROUPED_EXPR(inner) => is_numeric(inner),
+        _ => false,
+    }
+}
+
+fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::GROUPED_EXPR(inner) => *inner,
+        Expr::BINARY_EXPR { left, opcode, right } => {
+            let folded = literal_value(&left)
+                .zip(literal_value(&right))
+                .and_then(|(l, r)| opcode.apply(&l, &r).ok())
+                .and_then(literal);
+            match folded {
+                Some(folded) => folded,
+                None => Expr::BINARY_EXPR { left, opcode, right },
+            }
+        },
+        Expr::UNARY_EXPR { opcode, expr } => {
+            if let Some(folded) = literal_value(&expr).and_then(|value| opcode.apply(&value).ok()).and_then(literal) {
+                return folded;
+            }
+            match (opcode, *expr) {
+                (MonOp::POS, inner) if is_numeric(&inner) => inner,
+                (MonOp::NEG, Expr::UNARY_EXPR { opcode: MonOp::NEG, expr: inner }) if is_numeric(&inner) => *inner,
+                (opcode, inner) => Expr::UNARY_EXPR { opcode, expr: Box::new(inner) },
+            }
+        },
+        other => other,
+    }
+}
@@ -1,25 +1,27 @@
 use crate::lexer::TokenType;
-use crate::lexer::Token;
+use crate::lexer::{Span, Token};
 
 pub struct Parser<'a> {
     pub tokens: Vec<Token<'a>>,
-    pub pos: usize
+    pub pos: usize,
+    pub handlers: usize, //event handlers parsed so far, they're numbered to tell them apart
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: Vec<Token<'a>>) -> Self {
         Parser { 
             tokens, 
-            pos: 0
+            pos: 0,
+            handlers: 0,
         }
     }
 
-    pub fn peek(&self) -> Option<&Token> {
+    pub fn peek(&self) -> Option<&Token<'a>> {
         self.tokens.get(self.pos)
     }
     
     //converts the Option from peek into a Result<&Token,String>
-    pub fn peek_and_extract(&self) -> Result<Token, String> {
+    pub fn peek_and_extract(&self) -> Result<Token<'a>, String> {
         match self.peek() {
             Some(t) => Ok(t.clone()),
             None => Err("Unexpected end of input!".to_string()),
@@ -32,14 +34,14 @@ impl<'a> Parser<'a> {
     //peek -> check if correct token -> move a step ahead -> repeat
     //what you can do is...
     //check if advance() is correct -> repeat
-    pub fn advance(&mut self) -> Option<&Token> {
+    pub fn advance(&mut self) -> Option<&Token<'a>> {
         let token = self.tokens.get(self.pos);
         self.pos += 1;
         token
     }
     
     //converts the option from advance into a result. recommended use this over advance
-    pub fn advance_and_extract(&mut self) -> Result<Token, String> {
+    pub fn advance_and_extract(&mut self) -> Result<Token<'a>, String> {
         match self.advance() {
             Some(t) => Ok(t.clone()),
             None => Err("Unexpected end of input!".to_string()),
@@ -83,16 +85,25 @@ impl<'a> Parser<'a> {
     ///great function, and you will use this all the time.
     ///why is that?:
     //you can relegate the peek -> check -> advance cycle almost COMPLETELY to this method.
+    //only steps forward if the check passes, so a failed parse leaves `pos` on the culprit.
     pub fn check_advance(&mut self, expected: TokenType) -> Result<TokenType, String> {
-        let token = self.advance_and_extract()?;
-        Parser::check_for(token, expected)
+        let kind = Parser::check_for(self.peek_and_extract()?, expected)?;
+        self.advance();
+        Ok(kind)
     }
 
     //grouped version of the method before. 
     ///as great as the previous function, but a bit more limited in usage. used to decide branches.
     pub fn check_advance_contains(&mut self, expected: &[TokenType]) -> Result<TokenType, String> {
-        let token = self.advance_and_extract()?;
-        Parser::check_contains(token, expected)
+        let kind = Parser::check_contains(self.peek_and_extract()?, expected)?;
+        self.advance();
+        Ok(kind)
+    }
+
+    //where the parser is looking right now. after an error that's the token it choked on.
+    pub fn error_span(&self) -> Span {
+        let at = self.pos.min(self.tokens.len().saturating_sub(1));
+        self.tokens.get(at).map(|token| token.span).unwrap_or_default()
     }
 
 }
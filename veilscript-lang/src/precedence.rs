@@ -0,0 +1,110 @@
+#![allow(non_camel_case_types)]
+
+use crate::ast::{BinOp, MonOp};
+use crate::lexer::TokenType;
+
+///PRECEDENCE TABLE section
+//this is the ONE place that decides how tightly operators bind. parse_expr in libparse.rs is a
+//pratt parser that only ever asks these tables questions, so adding an operator means adding a
+//row here (and an AST node if it's a new kind of thing), never touching the loop itself.
+//
+//precedences are "levels": higher binds tighter. the tables turn a level into a pair of binding
+//powers (left, right) the way matklad's pratt parsing writeup does it:
+//  left associative  -> (2p, 2p+1)   a-b-c == (a-b)-c
+//  right associative -> (2p+1, 2p)   a**b**c == a**(b**c)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    LEFT,
+    RIGHT,
+}
+
+//what a postfix operator does to the expression on its left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostfixKind {
+    CALL,   //rite(args)
+    MEMBER, //thing.field or thing.method(args)
+}
+
+pub struct PrefixOp {
+    pub token: TokenType,
+    pub opcode: MonOp,
+    pub precedence: u8,
+}
+
+pub struct InfixOp {
+    pub token: TokenType,
+    pub opcode: BinOp,
+    pub precedence: u8,
+    pub assoc: Assoc,
+}
+
+pub struct PostfixOp {
+    pub token: TokenType,
+    pub kind: PostfixKind,
+    pub precedence: u8,
+}
+
+pub const PREFIX_TABLE: &[PrefixOp] = &[
+    PrefixOp { token: TokenType::PLUS,  opcode: MonOp::POS, precedence: 7 },
+    PrefixOp { token: TokenType::MINUS, opcode: MonOp::NEG, precedence: 7 },
+];
+
+pub const INFIX_TABLE: &[InfixOp] = &[
+    InfixOp { token: TokenType::PIPE,            opcode: BinOp::BIT_OR,  precedence: 1, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::CARET,           opcode: BinOp::BIT_XOR, precedence: 2, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::AMPERSAND,       opcode: BinOp::BIT_AND, precedence: 3, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::SHIFT_LEFT,      opcode: BinOp::SHL,     precedence: 4, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::SHIFT_RIGHT,     opcode: BinOp::SHR,     precedence: 4, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::PLUS,            opcode: BinOp::ADD,     precedence: 5, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::MINUS,           opcode: BinOp::SUB,     precedence: 5, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::ASTERISK,        opcode: BinOp::MULT,    precedence: 6, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::SLASH,           opcode: BinOp::DIV,     precedence: 6, assoc: Assoc::LEFT },
+    InfixOp { token: TokenType::PERCENT,         opcode: BinOp::MOD,     precedence: 6, assoc: Assoc::LEFT },
+    //prefix ops sit at 7, so ** binding at 8 makes -2**2 == -(2**2)
+    InfixOp { token: TokenType::DOUBLE_ASTERISK, opcode: BinOp::POW,     precedence: 8, assoc: Assoc::RIGHT },
+];
+
+pub const POSTFIX_TABLE: &[PostfixOp] = &[
+    PostfixOp { token: TokenType::LPAREN, kind: PostfixKind::CALL,   precedence: 9 },
+    PostfixOp { token: TokenType::DOT,    kind: PostfixKind::MEMBER, precedence: 9 },
+];
+
+impl PrefixOp {
+    //the operand of a prefix op gets parsed with this as its minimum binding power
+    pub fn right_binding_power(&self) -> u8 {
+        self.precedence * 2 + 1
+    }
+}
+
+impl InfixOp {
+    pub fn binding_power(&self) -> (u8, u8) {
+        let level = self.precedence * 2;
+        match self.assoc {
+            Assoc::LEFT => (level, level + 1),
+            Assoc::RIGHT => (level + 1, level),
+        }
+    }
+}
+
+impl PostfixOp {
+    pub fn left_binding_power(&self) -> u8 {
+        self.precedence * 2
+    }
+}
+
+pub fn lookup_prefix(kind: &TokenType) -> Option<&'static PrefixOp> {
+    PREFIX_TABLE.iter().find(|op| &op.token == kind)
+}
+
+pub fn lookup_infix(kind: &TokenType) -> Option<&'static InfixOp> {
+    INFIX_TABLE.iter().find(|op| &op.token == kind)
+}
+
+pub fn lookup_postfix(kind: &TokenType) -> Option<&'static PostfixOp> {
+    POSTFIX_TABLE.iter().find(|op| &op.token == kind)
+}
+
+pub fn lookup_binop(opcode: &BinOp) -> Option<&'static InfixOp> {
+    INFIX_TABLE.iter().find(|op| &op.opcode == opcode)
+}
@@ -0,0 +1,234 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::{Atom, Expr, Ident};
+use crate::value::Value;
+
+///RANDOM section
+//this here is the RNG scripts roll their dice with. lockstep multiplayer and replays need a script
+//to do exactly the same thing every time it runs with the same inputs, so nothing in here looks at
+//a clock or asks the OS: a runtime starts out seeded with 0 and only the game ever changes that
+//(ScriptRuntime::rng). the whole state is one u64 and it goes into save files, so a loaded game
+//rolls exactly what it would have rolled had it never been saved.
+//
+//the generator is SplitMix64. it's tiny, quick, good enough for anything a game does with dice,
+//and any u64 is a fine state.
+//
+//the BUILTINS are rites every script has without the game registering anything:
+//  roll(low, high)   an int from low to high, both ends included
+//  roll("2d6+3")     rolls dice, see Dice
+//  chance(p)         1 with probability p (from 0.0 to 1.0), otherwise 0
+//  pick(a, b, ...)   one of its arguments
+//  dice_min("3d6")   the lowest the dice can roll, without rolling them. dice_max is the highest,
+//  dice_avg("3d6")   and dice_avg what they roll on average (a float, 10.5 here)
+//a dice literal like `3d6` rolls the moment it's evaluated, so the inspecting ones take dice
+//written out as a rune. one handed straight to them is the exception: dice_min(3d6) gets the dice
+//as written, unrolled, exactly like dice_min("3d6") (see unrolled_dice). they're all called like
+//natives, and a native the game registers under the same name wins, so no game that already had
+//its own roll breaks.
+
+pub const BUILTINS: [&str; 6] = ["roll", "chance", "pick", "dice_min", "dice_max", "dice_avg"];
+
+//more dice than this in one roll is a typo, not a game mechanic
+pub const MAX_DICE: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    //the whole state, for saving. Rng::new of it carries on exactly where this one is.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    //a new generator seeded off this one, for handing a separate stream to something else (another
+    //runtime, a replay) without the two ever rolling the same numbers
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+
+    //from 0 up to (not including) `bound`, which can't be 0
+    fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+
+    //from low to high, both included. low can't be above high.
+    pub fn int_between(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+        match span.checked_add(1) {
+            Some(bound) => low.wrapping_add(self.below(bound) as i64),
+            None => self.next_u64() as i64, //every i64 there is
+        }
+    }
+
+    //from 0.0 up to (not including) 1.0
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+///DICE section
+//`2d6+3` is two six sided dice, added up, plus 3. the count can be left out (`d20` is one die) and
+//so can the bonus, which can also be taken off (`1d8-1`). that's what dice written out as a rune
+//look like. dice literals in scripts (see lexer.rs) are only ever `2d6`: they always have a count
+//(`d20` on its own is just a name there), and a bonus is added like anything else, `2d6 + 3`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dice {
+    pub count: u32,
+    pub sides: u32,
+    pub bonus: i64,
+}
+
+impl Dice {
+    pub fn parse(text: &str) -> Result<Dice, String> {
+        let not_dice = || format!("{:?} isn't dice! Dice look like 2d6, d20 or 1d8+3.", text);
+        let (count, rest) = text.split_once('d').ok_or_else(not_dice)?;
+        let (sides, bonus) = match rest.find(['+', '-']) {
+            Some(at) => (&rest[..at], Some(&rest[at..])),
+            None => (rest, None),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+
+        let count = match count {
+            "" => 1,
+            count if digits(count) => count.parse::<u32>().map_err(|_| not_dice())?,
+            _ => return Err(not_dice()),
+        };
+        if !digits(sides) {
+            return Err(not_dice());
+        }
+        let sides = sides.parse::<u32>().map_err(|_| not_dice())?;
+        let bonus = match bonus {
+            None => 0,
+            Some(bonus) if digits(&bonus[1..]) => bonus.parse::<i64>().map_err(|_| not_dice())?,
+            Some(_) => return Err(not_dice()),
+        };
+
+        if count == 0 || count > MAX_DICE {
+            return Err(format!("{} can't be rolled! Roll from 1 to {} dice at once.", text, MAX_DICE));
+        }
+        if sides == 0 {
+            return Err(format!("{} can't be rolled! A die needs at least one side.", text));
+        }
+        Ok(Dice { count, sides, bonus })
+    }
+
+    //bonuses wrap around like any other int arithmetic
+    pub fn roll(&self, rng: &mut Rng) -> i64 {
+        let total: i64 = (0..self.count).map(|_| rng.int_between(1, self.sides as i64)).sum();
+        total.wrapping_add(self.bonus)
+    }
+
+    pub fn min(&self) -> i64 {
+        (self.count as i64).wrapping_add(self.bonus)
+    }
+
+    pub fn max(&self) -> i64 {
+        (self.count as i64 * self.sides as i64).wrapping_add(self.bonus)
+    }
+
+    pub fn average(&self) -> f64 {
+        self.count as f64 * (self.sides as f64 + 1.0) / 2.0 + self.bonus as f64
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self.bonus {
+            0 => format!("{}d{}", self.count, self.sides),
+            bonus if bonus > 0 => format!("{}d{}+{}", self.count, self.sides, bonus),
+            bonus => format!("{}d{}{}", self.count, self.sides, bonus),
+        }
+    }
+}
+
+///BUILTINS
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+//a line on what a builtin does, for editors
+pub fn builtin_help(name: &str) -> Option<&'static str> {
+    let help = match name {
+        "roll" => "An int from low to high, both included: roll(1, 20). Or rolls dice: roll(\"2d6+3\").",
+        "chance" => "1 with probability p (from 0.0 to 1.0), otherwise 0.",
+        "pick" => "One of its arguments, picked at random.",
+        "dice_min" => "The lowest some dice can roll, like dice_min(3d6) is 3. Doesn't roll them.",
+        "dice_max" => "The highest some dice can roll, like dice_max(3d6) is 18. Doesn't roll them.",
+        "dice_avg" => "What some dice roll on average, like dice_avg(3d6) is 10.5. Doesn't roll them.",
+        _ => return None,
+    };
+    Some(help)
+}
+
+//the dice of a dice literal that's an argument of dice_min, dice_max or dice_avg, which get them as
+//a rune instead of a roll. only for calls that don't go to a rite of the script, the backends check
+//that part.
+pub fn unrolled_dice<'a>(ident: &Ident, arg: &'a Expr) -> Option<&'a Dice> {
+    let inspects = matches!(ident.name.as_str(), "dice_min" | "dice_max" | "dice_avg");
+    match arg {
+        Expr::ATOM(Atom::LITERAL_DICE(dice)) if inspects && !ident.is_namespaced() => Some(dice),
+        _ => None,
+    }
+}
+
+//None if `name` isn't a builtin at all
+pub fn call_builtin(rng: &mut Rng, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let result = match name {
+        "roll" => roll(rng, args),
+        "chance" => chance(rng, args),
+        "pick" => match args.len() {
+            0 => Err("pick needs something to pick from!".to_string()),
+            len => Ok(args[rng.below(len as u64) as usize].clone()),
+        },
+        "dice_min" => inspect(name, args).map(|dice| Value::INT(dice.min())),
+        "dice_max" => inspect(name, args).map(|dice| Value::INT(dice.max())),
+        "dice_avg" => inspect(name, args).map(|dice| Value::FLOAT(dice.average())),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn roll(rng: &mut Rng, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::INT(low), Value::INT(high)] if low > high => Err(format!(
+            "roll({}, {}) can't roll anything, the low end is above the high end!", low, high
+        )),
+        [Value::INT(low), Value::INT(high)] => Ok(Value::INT(rng.int_between(*low, *high))),
+        [Value::STRING(dice)] => Ok(Value::INT(Dice::parse(dice)?.roll(rng))),
+        _ => Err("roll takes two ints, like roll(1, 20), or dice, like roll(\"2d6+3\")!".to_string()),
+    }
+}
+
+fn chance(rng: &mut Rng, args: &[Value]) -> Result<Value, String> {
+    let probability = match args {
+        [Value::FLOAT(probability)] => *probability,
+        [Value::INT(probability)] => *probability as f64,
+        _ => return Err("chance takes one probability, like chance(0.25)!".to_string()),
+    };
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(format!("chance takes a probability from 0.0 to 1.0, not {}!", probability));
+    }
+    Ok(Value::INT((rng.unit() < probability) as i64))
+}
+
+fn inspect(name: &str, args: &[Value]) -> Result<Dice, String> {
+    match args {
+        [Value::STRING(dice)] => Dice::parse(dice),
+        _ => Err(format!("{} takes dice written as a rune, like {}(\"3d6\")!", name, name)),
+    }
+}
@@ -0,0 +1,205 @@
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::events::Events;
+use crate::bytecode::TYPES;
+use crate::format::format_stmts;
+use crate::lexer::{tokenise, Span};
+use crate::module::{check_visibility, module_id, Module};
+use crate::optimise::optimise;
+use crate::parser::Parser;
+use crate::runtime::{rite_declarations, validate_module};
+use crate::value::{conform, type_name_of, Value};
+
+///RELOAD section
+//this here is HOT RELOADING. designers change a script while the game runs and want to see it
+//straight away, without playing back to where they were. ScriptRuntime::reload takes the new
+//source of a module that's loaded already and swaps it in:
+//  - rites get the code they have now. ones that are new can be called from then on, ones that are
+//    gone can't. other modules that call them pick the new code up too.
+//  - the top level code of the module runs again, so new globals get their values. then every
+//    global that was there before gets its old value back, as long as that still fits the type of
+//    what the new code set it to (an int still fits where a float is now). one that doesn't fit
+//    keeps the new value, and the report says so. globals that are gone are gone.
+//  - handlers (see events.rs) are subscribed again. ones that are gone are unsubscribed, and so is
+//    any rite the game subscribed that's gone.
+//  - a coroutine asleep in a rite that changed finishes on the code it started on (vm only).
+//    a save made before it finishes can't be restored, that code isn't any rite's any more.
+//
+//the new source can only summon modules that are loaded already, and it can't take away anything
+//the modules that summon it use. a reload that breaks either rule, or doesn't parse, or whose top
+//level code fails, changes nothing at all. what's wrong with the source is reported the way
+//diagnostics are, with the module's id for a file name since that's all a runtime knows it by.
+//
+//what changed comes back as a RELOAD. INCOMPATIBLE has what didn't carry over as it was: globals
+//that started over, rites that take or give back something else now (so callers written for the
+//old ones will fail), subscriptions that had to go.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reload {
+    pub module: String,
+    pub changed: Vec<String>, //rites whose code is different now
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub incompatible: Vec<String>,
+}
+
+impl Reload {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        let list = |rites: &[String]| match rites.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", rites.join(", ")),
+        };
+        let mut ret = format!(
+            "Reloaded {}: {} rite(s) changed{}, {} added{}, {} removed{}.",
+            self.module, self.changed.len(), list(&self.changed), self.added.len(), list(&self.added),
+            self.removed.len(), list(&self.removed)
+        );
+        for problem in &self.incompatible {
+            ret += "\n  ";
+            ret += problem;
+        }
+        ret
+    }
+}
+
+//parses and checks the new source of `old`, `others` being every other module that's loaded.
+//hands back the new module (optimised, if `old` was) and what's different about it, or why it
+//can't be swapped in.
+pub fn prepare_reload(
+    old: &Module, text: &str, others: &[&Module], optimising: bool,
+) -> Result<(Module, Reload), String> {
+    let file = &old.id;
+    let error = |span, err| Diagnostic::error(file, span, err).to_string();
+    let mut parser = Parser::new(tokenise(text));
+    let stmts = parser.parse_program().map_err(|err| error(parser.error_span(), err))?;
+    let mut module = Module::new(&old.id, old.file, stmts);
+    let find = |id: &str| others.iter().copied().find(|other| other.id == id);
+
+    for stmt in &module.stmts {
+        let Stmt::STATEMENT_IMPORT(import) = stmt else { continue };
+        let id = module_id(&import.module_path());
+        let span = import.path[0].span;
+        if find(&id).is_none() {
+            let err = format!("{} isn't loaded, so it can't be summoned by a reload!", id);
+            return Err(error(span, err));
+        }
+        if let Some(chain) = summons(others, &id, &old.id) {
+            return Err(error(span, format!("Import cycle! {} -> {}", old.id, chain.join(" -> "))));
+        }
+        module.imports.insert(import.local_name().to_owned(), id);
+    }
+    check_visibility(&module, file, find).map_err(|err| err.to_string())?;
+    validate_module(&module.stmts).map_err(|err| error(Span::default(), err))?;
+
+    //the modules that summon this one are checked against the new one
+    let lookup = |id: &str| if id == module.id { Some(&module) } else { find(id) };
+    let broken: Vec<String> = others.iter()
+        .filter(|other| other.imports.values().any(|id| *id == module.id))
+        .filter_map(|other| check_visibility(other, &other.id, lookup).err())
+        .map(|err| err.to_string())
+        .collect();
+    if !broken.is_empty() {
+        let err = format!("Reloading {} would break what summons it!", module.id);
+        return Err(error(Span::default(), err) + "\n" + &broken.join("\n"));
+    }
+
+    if optimising {
+        optimise(&mut module.stmts);
+    }
+    let reload = diff(old, &module);
+    Ok((module, reload))
+}
+
+//the top level code of the new source failed, reported like everything else that's wrong with it
+pub(crate) fn top_level_failed(module: &str, err: String) -> String {
+    Diagnostic::error(module, Span::default(), err).to_string()
+}
+
+//how `from` summons `id`, if it does at all: `from -> ... -> id`
+fn summons<'m>(modules: &[&'m Module], from: &'m str, id: &str) -> Option<Vec<&'m str>> {
+    if from == id {
+        return Some(vec![from]);
+    }
+    let module = modules.iter().find(|module| module.id == from)?;
+    module.imports.values().find_map(|next| {
+        let mut chain = summons(modules, next, id)?;
+        chain.insert(0, from);
+        Some(chain)
+    })
+}
+
+//every rite of a module with how it reads, export and all, to tell whether its code changed
+fn rites(stmts: &[Stmt]) -> Vec<(&FnDeclaration, String)> {
+    stmts.iter().filter_map(|stmt| {
+        let decl = rite_declarations(std::slice::from_ref(stmt)).pop()?;
+        Some((decl, format_stmts(std::slice::from_ref(stmt))))
+    }).collect()
+}
+
+//`(door: int, by: rune) -> void`
+fn signature(decl: &FnDeclaration) -> String {
+    let params: Vec<String> = decl.params.iter()
+        .map(|param| format!("{}: {}", param.ident.name, type_name_of(&param.type_t).unwrap_or("?")))
+        .collect();
+    format!("({}) -> {}", params.join(", "), type_name_of(&decl.type_t).unwrap_or("?"))
+}
+
+fn diff(old: &Module, new: &Module) -> Reload {
+    let mut reload = Reload { module: new.id.clone(), ..Reload::default() };
+    let old_rites = rites(&old.stmts);
+    let new_rites = rites(&new.stmts);
+    for (decl, text) in &new_rites {
+        let name = &decl.ident.name;
+        match old_rites.iter().find(|(old, _)| old.ident.name == *name) {
+            None => reload.added.push(name.clone()),
+            Some((_, old_text)) if old_text == text => {},
+            Some((old, _)) => {
+                reload.changed.push(name.clone());
+                if signature(old) != signature(decl) {
+                    reload.incompatible.push(format!(
+                        "Rite {} was {} and is {} now, calls written for the old one will fail!",
+                        name, signature(old), signature(decl)
+                    ));
+                }
+            },
+        }
+    }
+    for (decl, _) in &old_rites {
+        if !new_rites.iter().any(|(new, _)| new.ident.name == decl.ident.name) {
+            reload.removed.push(decl.ident.name.clone());
+        }
+    }
+    reload
+}
+
+//what a global that was there before the reload ends up as. `new` is what the new top level code
+//set it to, if anything.
+pub fn carry_over(name: &str, old: Value, new: Option<Value>, reload: &mut Reload) -> Value {
+    let Some(new) = new else { return old };
+    let type_t = TYPES.iter().find(|type_t| type_name_of(type_t) == Some(new.type_name()));
+    match type_t.and_then(|type_t| conform(old.clone(), type_t)) {
+        Some(kept) => kept,
+        None => {
+            reload.incompatible.push(format!(
+                "Global {} was {} and is {} now, so it starts over as {}!",
+                name, old.type_name(), new.type_name(), new.to_string()
+            ));
+            new
+        },
+    }
+}
+
+//subscribes the handlers of the new module and unsubscribes whatever of the old one is gone. a
+//handler going is what the script wants, a rite the game subscribed going is worth knowing about.
+pub fn resubscribe(events: &mut Events, old: &Module, new: &Module, reload: &mut Reload) {
+    let old_rites = rite_declarations(&old.stmts);
+    for subscription in events.resubscribe_handlers(&new.id, &new.stmts) {
+        let handler = old_rites.iter().any(|decl| decl.ident.name == subscription.rite && decl.event.is_some());
+        if !handler {
+            reload.incompatible.push(format!(
+                "Rite {} is gone, so it doesn't hear \"{}\" any more!", subscription.rite, subscription.event
+            ));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::{Expr, Stmt};
+use crate::interpreter::Interpreter;
+use crate::lexer::{tokenise, TokenType};
+use crate::parser::Parser;
+use crate::typeck::{StaticType, TypeChecker};
+use crate::value::Value;
+
+///REPL section
+//this here is the REPL behind `veil repl`. It keeps one interpreter alive between inputs, so
+//whatever was assigned or declared stays around for the next line. Every input runs as more top
+//level code of a single module, which is either an empty one or the script the repl was started
+//with, so that script's rites and globals are right there to poke at.
+//
+//an input is whatever has been typed until every `(` and `{` is closed again. It's tried as an
+//expression first, whose value gets printed, then as statements. Statements may leave off their
+//last `;`, nobody wants to type `gold = 10;` at a prompt.
+//
+//lines starting with `:` are for the repl itself, see HELP.
+//
+//the repl does no io of its own. it gets fed lines and hands back what to print, so the game can
+//put it in its debug console as easily as `veil` puts it in a terminal.
+
+pub const HELP: &str = ":tokens <code>   print the tokens of some code
+:ast <code>      print the syntax tree of some code
+:type <expr>     print the type of an expression without running it
+:reset           throw everything away and start over
+:quit            leave (so does end of input)
+:help            print this";
+
+//builds a fresh interpreter with the session's module already loaded in it
+pub type Setup = Box<dyn FnMut() -> Result<Interpreter, String>>;
+
+pub struct Repl {
+    interpreter: Interpreter,
+    setup: Setup,
+    module: String,
+    pending: String,      //lines typed so far for an input that isn't finished yet
+    blank_lines: usize,   //blank lines in a row while an input is pending
+}
+
+//what an input is, once it has been parsed
+#[allow(clippy::upper_case_acronyms)]
+enum Input {
+    EXPR(Expr),
+    STMTS(Vec<Stmt>),
+}
+
+impl Repl {
+    pub fn new(module: &str, mut setup: Setup) -> Result<Self, String> {
+        let interpreter = setup()?;
+        Ok(Repl { interpreter, setup, module: module.to_owned(), pending: String::new(), blank_lines: 0 })
+    }
+
+    //is a multi line input halfway done? (the prompt should say so)
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    //takes one line. gives back whatever should be printed, which is nothing while an input is
+    //still unfinished. errors are the session's problem, not the repl's, so it keeps going.
+    pub fn feed(&mut self, line: &str) -> Result<String, String> {
+        if !self.is_pending() && line.trim_start().starts_with(':') {
+            return self.command(line.trim());
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        //two blank lines in a row give up on waiting, so a stray `(` can't trap anyone
+        self.blank_lines = match line.trim().is_empty() {
+            true => self.blank_lines + 1,
+            false => 0,
+        };
+        if is_unfinished(&self.pending) && self.blank_lines < 2 {
+            return Ok(String::new());
+        }
+
+        let source = std::mem::take(&mut self.pending);
+        self.blank_lines = 0;
+        if source.trim().is_empty() {
+            return Ok(String::new());
+        }
+        match parse_input(&source)? {
+            Input::EXPR(expr) => self.interpreter.eval_in(&self.module, &expr).map(|value| match value {
+                Value::VOID => String::new(),
+                value => show_value(&value),
+            }),
+            Input::STMTS(stmts) => self.interpreter.extend(&self.module, stmts).map(|_| String::new()),
+        }
+    }
+
+    ///META COMMANDS
+
+    fn command(&mut self, line: &str) -> Result<String, String> {
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match name {
+            ":tokens" => {
+                let lines: Vec<String> = tokenise(rest).iter()
+                    .filter(|token| token.kind != TokenType::EOF)
+                    .map(|token| format!("{:?} -> {}", token.kind, token.lexeme))
+                    .collect();
+                Ok(lines.join("\n"))
+            },
+            ":ast" => match parse_input(rest)? {
+                Input::EXPR(expr) => Ok(expr.to_pretty_string()),
+                Input::STMTS(stmts) => {
+                    let lines: Vec<String> = stmts.iter().map(Stmt::to_pretty_string).collect();
+                    Ok(lines.join("\n"))
+                },
+            },
+            ":type" => self.type_of(rest),
+            ":reset" => {
+                self.interpreter = (self.setup)()?;
+                Ok("Started over.".to_string())
+            },
+            ":help" => Ok(HELP.to_string()),
+            other => Err(format!("Unknown command {}! Try :help.", other)),
+        }
+    }
+
+    //globals that already have a value have exactly that value's type, everything else is up to
+    //the checker
+    fn type_of(&mut self, source: &str) -> Result<String, String> {
+        let expr = parse_expr(source)?;
+        let module = self.interpreter.module(&self.module)
+            .ok_or_else(|| format!("No module named {}!", self.module))?;
+
+        let mut checker = TypeChecker::new("<repl>", &module.stmts);
+        if let Some(globals) = self.interpreter.globals(&self.module) {
+            for (name, value) in globals {
+                checker.globals.insert(name.clone(), StaticType::of_value(value));
+            }
+        }
+        let found = checker.expr(&expr);
+        match checker.diagnostics.first() {
+            Some(diagnostic) => Err(diagnostic.message.clone()),
+            None => Ok(found.to_string()),
+        }
+    }
+}
+
+//runes are shown with their quotes so "7" and 7 don't look the same
+fn show_value(value: &Value) -> String {
+    match value {
+        Value::STRING(text) => format!("{:?}", text),
+        other => other.to_string(),
+    }
+}
+
+//does this still have an open `(` or `{`, a string or a block comment that hasn't ended?
+pub fn is_unfinished(source: &str) -> bool {
+    let mut depth: i64 = 0;
+    for token in tokenise(source) {
+        match token.kind {
+            TokenType::LPAREN | TokenType::LBRACE => depth += 1,
+            TokenType::RPAREN | TokenType::RBRACE => depth -= 1,
+            TokenType::ERROR if token.lexeme.starts_with("/*") || token.lexeme == "\"" => return true,
+            _ => {},
+        }
+    }
+    depth > 0
+}
+
+fn parse_expr(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser::new(tokenise(source));
+    let expr = parser.parse_full_expr()?;
+    parser.check_advance(TokenType::EOF)?;
+    Ok(expr)
+}
+
+//an expression if it is one, otherwise statements, forgiving a missing `;` at the very end
+fn parse_input(source: &str) -> Result<Input, String> {
+    if let Ok(expr) = parse_expr(source) {
+        return Ok(Input::EXPR(expr));
+    }
+    let err = match Parser::new(tokenise(source)).parse_program() {
+        Ok(stmts) => return Ok(Input::STMTS(stmts)),
+        Err(err) => err,
+    };
+    let patched = format!("{};", source.trim_end());
+    Parser::new(tokenise(&patched)).parse_program().map(Input::STMTS).map_err(|_| err)
+}
@@ -0,0 +1,318 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::dialogue::{CHOOSE, SAY};
+use crate::lexer::{Span, TokenType};
+use crate::random::{is_builtin, BUILTINS};
+use crate::runtime::{global_names, no_such_rite, not_defined, rite_declarations};
+use crate::visit::{walk_dialogue, walk_fn_call, walk_scope, walk_stmts, Visitor};
+
+///RESOLVER section
+//this here is NAME RESOLUTION. It binds every name a module uses to whatever it refers to: a
+//rite, a global, a parameter, a local, or a native the game provides. Scoping follows the
+//interpreter exactly: a rite's parameters and its body share a scope, typed assignments declare
+//something new in the innermost scope, untyped ones go to the closest local, then a global, and
+//otherwise declare.
+//
+//along the way it reports names nothing defines (with a "did you mean" when something close
+//enough is around), and warns when a declaration in a nested scope shadows something outside it,
+//which is almost always a typo'd assignment that quietly made a new variable.
+//
+//`module::name` is the module loader's business, it already checks those.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    RITE,
+    GLOBAL,
+    PARAM,
+    LOCAL,
+}
+
+impl SymbolKind {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            SymbolKind::RITE => "rite".to_string(),
+            SymbolKind::GLOBAL => "global".to_string(),
+            SymbolKind::PARAM => "parameter".to_string(),
+            SymbolKind::LOCAL => "local".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,                //the name where it's defined
+    pub range: Span,               //the whole definition (the whole rite, for rites)
+    pub type_t: Option<TokenType>, //the declared type, the return type for rites
+    pub visible: (usize, usize),   //the bytes it can be used from
+}
+
+//what a name turned out to be
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    SYMBOL(usize), //an index into Resolution::symbols
+    NATIVE,
+    MODULE,        //`module::name`, something in another module
+    UNDEFINED,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub ident: Ident,
+    pub binding: Binding,
+    pub call: bool,
+}
+
+impl Reference {
+    pub fn symbol(&self) -> Option<usize> {
+        match self.binding {
+            Binding::SYMBOL(symbol) => Some(symbol),
+            _ => None,
+        }
+    }
+}
+
+pub struct Resolution {
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>, //in source order, definitions included
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+struct Resolver<'n> {
+    file: String,
+    natives: Option<&'n [String]>,
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+    diagnostics: Vec<Diagnostic>,
+    globals: HashMap<String, usize>,
+    rites: HashMap<String, usize>,
+    scopes: Vec<(HashMap<String, usize>, usize)>, //names, and the byte where the scope ends
+}
+
+impl Resolver<'_> {
+    fn define(&mut self, ident: &Ident, kind: SymbolKind, type_t: Option<TokenType>, visible: (usize, usize)) -> usize {
+        self.symbols.push(Symbol { name: ident.name.clone(), kind, span: ident.span, range: ident.span, type_t, visible });
+        self.symbols.len() - 1
+    }
+
+    fn define_local(&mut self, ident: &Ident, kind: SymbolKind, type_t: Option<TokenType>) -> usize {
+        //only declarations in a scope of their own can shadow, a parameter is where it all starts
+        if kind == SymbolKind::LOCAL && !self.scopes.last().is_some_and(|(names, _)| names.contains_key(&ident.name)) {
+            let outer = self.scopes.iter().rev().skip(1).find_map(|(names, _)| names.get(&ident.name))
+                .or_else(|| self.globals.get(&ident.name));
+            if let Some(outer) = outer {
+                let outer = &self.symbols[*outer];
+                let message = format!(
+                    "{} shadows the {} {} from line {}!", ident.name, outer.kind.to_string(), outer.name, outer.span.line
+                );
+                self.diagnostics.push(Diagnostic::warning(&self.file, ident.span, message));
+            }
+        }
+
+        let end = self.scopes.last().map(|(_, end)| *end).unwrap_or(usize::MAX);
+        let index = self.define(ident, kind, type_t, (ident.span.start, end));
+        if let Some((names, _)) = self.scopes.last_mut() {
+            names.insert(ident.name.clone(), index);
+        }
+        index
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|(names, _)| names.get(name).copied())
+    }
+
+    fn refer(&mut self, ident: &Ident, binding: Binding, call: bool) {
+        self.references.push(Reference { ident: ident.clone(), binding, call });
+    }
+
+    //rites and globals can be used from anywhere in the file, so they go in before anything else
+    fn resolve(&mut self, stmts: &[Stmt]) {
+        for decl in rite_declarations(stmts) {
+            let index = self.define(&decl.ident, SymbolKind::RITE, Some(decl.type_t.clone()), (0, usize::MAX));
+            self.symbols[index].range.end = decl.body.span.end;
+            self.rites.insert(decl.ident.name.clone(), index);
+        }
+        let names = global_names(stmts);
+        for stmt in stmts {
+            let stmt = match stmt {
+                Stmt::STATEMENT_EXPORT(inner) => inner.as_ref(),
+                other => other,
+            };
+            if let Stmt::STATEMENT_ASSIGNMENT(assignment) = stmt
+                && names.contains(&assignment.ident.name)
+                && !self.globals.contains_key(&assignment.ident.name) {
+                let index = self.define(&assignment.ident, SymbolKind::GLOBAL, assignment.type_t.clone(), (0, usize::MAX));
+                self.globals.insert(assignment.ident.name.clone(), index);
+            }
+        }
+        self.visit_stmts(stmts);
+    }
+
+    ///UNDEFINED NAMES
+
+    fn undefined(&mut self, ident: &Ident, call: bool) {
+        let (message, candidates) = match call {
+            true => {
+                let mut candidates: Vec<&str> = self.rites.keys().map(String::as_str).collect();
+                candidates.extend(self.natives.unwrap_or(&[]).iter().map(String::as_str));
+                candidates.extend(BUILTINS);
+                (no_such_rite(&ident.name), candidates)
+            },
+            false => {
+                let mut candidates: Vec<&str> = self.globals.keys().map(String::as_str).collect();
+                for (names, _) in &self.scopes {
+                    candidates.extend(names.keys().map(String::as_str));
+                }
+                (not_defined(&ident.name), candidates)
+            },
+        };
+        let message = match did_you_mean(&ident.name, candidates) {
+            Some(suggestion) => format!("{} Did you mean {}?", message, suggestion),
+            None => message,
+        };
+        self.diagnostics.push(Diagnostic::error(&self.file, ident.span, message));
+    }
+}
+
+impl<'ast> Visitor<'ast> for Resolver<'_> {
+    fn visit_scope(&mut self, scope: &'ast Scope) {
+        self.scopes.push((HashMap::new(), scope.span.end));
+        walk_scope(self, scope);
+        self.scopes.pop();
+    }
+
+    fn visit_fn_declaration(&mut self, decl: &'ast FnDeclaration) {
+        if let Some(index) = self.rites.get(&decl.ident.name).copied() {
+            self.refer(&decl.ident, Binding::SYMBOL(index), false);
+        }
+
+        let outer = std::mem::replace(&mut self.scopes, vec![(HashMap::new(), decl.body.span.end)]);
+        for param in &decl.params {
+            let index = self.define_local(&param.ident, SymbolKind::PARAM, Some(param.type_t.clone()));
+            self.symbols[index].visible.0 = decl.body.span.start;
+            self.refer(&param.ident, Binding::SYMBOL(index), false);
+        }
+        walk_stmts(self, &decl.body.stmts);
+        self.scopes = outer;
+    }
+
+    fn visit_assignment(&mut self, assignment: &'ast Assignment) {
+        self.visit_expr(&assignment.expr);
+        let ident = &assignment.ident;
+        let symbol = match (&assignment.type_t, self.local(&ident.name)) {
+            _ if self.scopes.is_empty() => self.globals[&ident.name],
+            (Some(type_t), _) => self.define_local(ident, SymbolKind::LOCAL, Some(type_t.clone())),
+            (None, Some(local)) => local,
+            (None, None) => match self.globals.get(&ident.name) {
+                Some(global) => *global,
+                None => self.define_local(ident, SymbolKind::LOCAL, None),
+            },
+        };
+        self.refer(ident, Binding::SYMBOL(symbol), false);
+    }
+
+    fn visit_atom(&mut self, atom: &'ast Atom) {
+        if let Atom::IDENTIFIER(ident) = atom {
+            let binding = match ident.is_namespaced() {
+                true => Binding::MODULE,
+                false => match self.local(&ident.name).or_else(|| self.globals.get(&ident.name).copied()) {
+                    Some(symbol) => Binding::SYMBOL(symbol),
+                    None => {
+                        self.undefined(ident, false);
+                        Binding::UNDEFINED
+                    },
+                },
+            };
+            self.refer(ident, binding, false);
+        }
+    }
+
+    //rites first, natives (and builtins) second, like the runtimes. with no list of natives every
+    //other call is taken to be one.
+    fn visit_fn_call(&mut self, fncall: &'ast FnCall) {
+        let ident = &fncall.ident;
+        let binding = match (ident.is_namespaced(), self.rites.get(&ident.name), self.natives) {
+            (true, _, _) => Binding::MODULE,
+            (false, Some(rite), _) => Binding::SYMBOL(*rite),
+            (false, None, None) => Binding::NATIVE,
+            (false, None, Some(natives)) if natives.contains(&ident.name) || is_builtin(&ident.name) => Binding::NATIVE,
+            (false, None, Some(_)) => {
+                self.undefined(ident, true);
+                Binding::UNDEFINED
+            },
+        };
+        self.refer(ident, binding, true);
+        walk_fn_call(self, fncall);
+    }
+
+    //a dialogue calls say and choose without ever naming them (see dialogue.rs)
+    fn visit_dialogue(&mut self, dialogue: &'ast Dialogue) {
+        let says = dialogue.nodes.iter().any(|node| !node.lines.is_empty());
+        let chooses = dialogue.nodes.iter().any(|node| matches!(node.exit, NodeExit::CHOICES(_)));
+        for (name, used) in [(SAY, says), (CHOOSE, chooses)] {
+            let known = self.rites.contains_key(name)
+                || self.natives.is_none_or(|natives| natives.iter().any(|native| native == name));
+            if used && !known {
+                let message = format!("This dialogue talks through a rite named {}, but there's no rite or native by that name!", name);
+                self.diagnostics.push(Diagnostic::error(&self.file, dialogue.span, message));
+            }
+        }
+        walk_dialogue(self, dialogue);
+    }
+}
+
+//resolves a whole module. `natives` are the names the game registers, if they're known.
+pub fn resolve(file: &str, stmts: &[Stmt], natives: Option<&[String]>) -> Resolution {
+    let mut resolver = Resolver {
+        file: file.to_owned(),
+        natives,
+        symbols: Vec::new(),
+        references: Vec::new(),
+        diagnostics: Vec::new(),
+        globals: HashMap::new(),
+        rites: HashMap::new(),
+        scopes: Vec::new(),
+    };
+    resolver.resolve(stmts);
+    let mut diagnostics = resolver.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.line);
+    Resolution { symbols: resolver.symbols, references: resolver.references, diagnostics }
+}
+
+///SUGGESTIONS
+
+//how many single character inserts, deletes and swaps it takes to turn one name into the other
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + (a_char != *b_char) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+//the closest candidate, if it's close enough to be a typo: a third of the name, at least one
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut candidates: Vec<&str> = candidates.into_iter().filter(|candidate| *candidate != name).collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
@@ -0,0 +1,501 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::dialogue::check_dialogue;
+use crate::events::{Events, Subscription};
+use crate::lexer::TokenType;
+use crate::locale::Catalogue;
+use crate::module::ModuleLoader;
+use crate::random::{call_builtin, Rng};
+use crate::save::{read_save, write_save, SaveState};
+use crate::reload::Reload;
+use crate::value::{conform, type_name_of, Value};
+
+///RUNTIME section
+//everything both ways of running scripts (the tree walking interpreter in interpreter.rs and the
+//bytecode vm in vm.rs) have to agree on lives here: how the host talks to them, how natives look,
+//and the exact wording of every runtime error. if the two ever disagree on what a script does,
+//that's a bug in one of them, and keeping the rules here is how we avoid it.
+
+//the deepest rites are allowed to nest calls before we call it infinite recursion, unless the
+//game picks another limit
+pub const MAX_CALL_DEPTH: usize = 200;
+
+//a rite written in rust by the game. gets its arguments already evaluated.
+pub type NativeFn = Box<dyn FnMut(&[Value]) -> Result<Value, String>>;
+
+//the natives of a runtime, and the builtins (see random.rs) behind them along with the rng they roll
+#[derive(Default)]
+pub struct Natives {
+    fns: HashMap<String, NativeFn>,
+    pub rng: Rng,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Natives::default()
+    }
+    pub fn register(&mut self, name: &str, native: NativeFn) {
+        self.fns.insert(name.to_owned(), native);
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.fns.contains_key(name)
+    }
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.fns.keys()
+    }
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        match self.fns.get_mut(name) {
+            Some(native) => native(args),
+            None => call_builtin(&mut self.rng, name, args).unwrap_or_else(|| Err(no_such_rite(name))),
+        }
+    }
+}
+
+//how the game drives a script runtime. pick either backend, the script can't tell the difference.
+pub trait ScriptRuntime {
+    fn register_native(&mut self, name: &str, native: NativeFn);
+    //takes every module the loader summoned and runs their top level code, dependencies first
+    fn load(&mut self, loader: ModuleLoader) -> Result<(), String>;
+    fn call(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<Value, String>;
+    fn global(&self, module: &str, name: &str) -> Option<Value>;
+    fn set_limits(&mut self, limits: Limits);
+    //what's left of the fuel of the last thing the host ran, None when there's no budget
+    fn fuel_left(&self) -> Option<u64>;
+    //the optimiser is on unless this turns it off. only matters for what gets loaded afterwards.
+    fn set_optimising(&mut self, optimising: bool);
+    //sets a rite up as a coroutine and hands back its id. none of it runs until the first resume.
+    fn spawn(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<usize, String>;
+    //runs a coroutine until it yields or finishes. `value` is what the yield it's sleeping in
+    //turns into; the very first resume has no yield to hand it to, so it's thrown away.
+    fn resume(&mut self, coroutine: usize, value: Value) -> Result<Resumed, String>;
+    //throws a sleeping coroutine away. false if there was none with that id.
+    fn cancel(&mut self, coroutine: usize) -> bool;
+    //everything the scripts need to pick up later where they are now, see save.rs. only call it
+    //between runs, it can't see into something that's running.
+    fn save_state(&self) -> SaveState;
+    //puts a save back, replacing the globals of its modules and every coroutine. it's all checked
+    //before anything changes, so a save that doesn't fit leaves the runtime as it was.
+    fn restore_state(&mut self, state: &SaveState) -> Result<(), String>;
+
+    //the rng every builtin rolls with, for seeding it (`*runtime.rng() = Rng::new(seed)`) or
+    //forking it off to someone else (`runtime.rng().fork()`)
+    fn rng(&mut self) -> &mut Rng;
+    //the translations dialogue is said in from now on (see locale.rs), None for the words as the
+    //scripts have them
+    fn set_locale(&mut self, locale: Option<Catalogue>);
+    //swaps `source` in as the new code of a module that's loaded already, while everything keeps
+    //running (see reload.rs). one that can't be swapped in leaves the runtime as it was, and
+    //what's wrong with it is a diagnostic against the module.
+    fn reload(&mut self, module: &str, source: &str) -> Result<Reload, String>;
+
+    //who is subscribed to what (see events.rs), for looking and for unsubscribing
+    fn events(&mut self) -> &mut Events;
+    fn has_rite(&self, module: &str, rite: &str) -> bool;
+    //lines a rite up to hear `event`, after everything already subscribed to it. hands back the id
+    //of the subscription.
+    fn subscribe(&mut self, event: &str, module: &str, rite: &str) -> Result<usize, String> {
+        if !self.has_rite(module, rite) {
+            return Err(no_such_rite(&format!("{}::{}", module, rite)));
+        }
+        Ok(self.events().subscribe(event, module, rite))
+    }
+    //calls everything subscribed to `event` with `payload`. whatever they give back is thrown away.
+    //one failing doesn't stop the rest, and the Err has what went wrong with each one that did.
+    fn emit(&mut self, event: &str, payload: &[Value]) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for subscription in self.events().subscriptions(event) {
+            if let Err(err) = self.call(&subscription.module, &subscription.rite, payload) {
+                errors.push(handler_failed(&subscription, &err));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        write_save(&self.save_state())
+    }
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.restore_state(&read_save(bytes)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    INTERPRETER,
+    VM,
+}
+
+pub fn new_runtime(backend: Backend) -> Box<dyn ScriptRuntime> {
+    match backend {
+        Backend::INTERPRETER => Box::new(crate::interpreter::Interpreter::new()),
+        Backend::VM => Box::new(crate::vm::Vm::new()),
+    }
+}
+
+///LIMITS section
+//mods are written by people we don't know, and a mod must never be able to freeze the game.
+//LIMITS are what a runtime enforces while a script runs:
+//  fuel             how many steps one go of the script may take. a step is applying an operator,
+//                   calling a rite or calling a native, counted the same way by both backends.
+//                   every time the host hands control over (a module's top level code, a call)
+//                   the tank is filled up again. running dry is an error like any other: whatever
+//                   was running is abandoned and the host gets "Out of fuel!" back. except in a
+//                   coroutine, which just stops where it is (Resumed::OUT_OF_FUEL), so a long mod
+//                   script can carry on next frame.
+//  max_call_depth   how deep rites may nest their calls
+//  max_rune_length  how long (in bytes) a rune may get, whether it's glued together by the script
+//                   or handed back by a native
+//nothing here looks at a clock, so the same script with the same limits always stops at exactly
+//the same place. that's what makes budgets testable.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub fuel: Option<u64>,
+    pub max_call_depth: usize,
+    pub max_rune_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { fuel: None, max_call_depth: MAX_CALL_DEPTH, max_rune_length: usize::MAX }
+    }
+}
+
+//the fuel tank of a runtime
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fuel {
+    left: Option<u64>,
+}
+
+impl Fuel {
+    pub fn fill(&mut self, limits: &Limits) {
+        self.left = limits.fuel;
+    }
+    pub fn left(&self) -> Option<u64> {
+        self.left
+    }
+    //takes one step's worth
+    pub fn burn(&mut self, limits: &Limits) -> Result<(), String> {
+        match &mut self.left {
+            None => Ok(()),
+            Some(0) => Err(out_of_fuel(limits.fuel.unwrap_or(0))),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            },
+        }
+    }
+}
+
+//runes built while running have to fit the limit
+pub fn check_rune(value: Value, limits: &Limits) -> Result<Value, String> {
+    match &value {
+        Value::STRING(text) if text.len() > limits.max_rune_length => Err(rune_too_long(limits.max_rune_length)),
+        _ => Ok(value),
+    }
+}
+
+///COROUTINES section
+//cutscenes and dialogue take many frames: `say("Hello"); wait(2.0); say("Bye");`. the game spawns
+//a rite as a COROUTINE and resumes it once per frame (or whenever it likes). every `yield` (or
+//`slumber`) puts the coroutine to sleep and hands a value to the game, with its whole call stack,
+//every rite in it and every local of every scope, kept exactly as it was. the next resume wakes it
+//up right there, and the yield turns into whatever value the game resumed it with:
+//
+//  rite cutscene() {
+//      say("Hello");
+//      yield wait(2.0);          //the game gets wait's value back and holds off for 2 seconds
+//      answer = yield ask("Stay?");
+//      say(answer);
+//  }
+//
+//each resume counts as the host handing control over, so it gets a full tank of fuel. a coroutine
+//that runs dry is put to sleep right before the step it had no fuel for, and the next resume
+//carries on from there (the value it's resumed with is thrown away, there's no yield to hand it
+//to). any other error while running kills the coroutine, same as finishing does; resuming a dead
+//one is an error.
+//yielding anywhere but inside a coroutine (a module's top level code, a plain call) is an error.
+//
+//dialogue blocks (see dialogue.rs) are built on exactly this.
+//
+//the vm puts a coroutine's stack aside as it is. the interpreter keeps its stack on rust's, which
+//can't be put anywhere, so it plays the coroutine back from the start instead (see interpreter.rs).
+
+//what a coroutine did with its turn
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resumed {
+    YIELDED(Value), //it's asleep and wants to be resumed later
+    FINISHED(Value), //its rite returned this, and the coroutine is gone
+    OUT_OF_FUEL,     //it's asleep mid-step and carries on with the next resume
+}
+
+///SHARED RULES section
+
+//the globals of a module are exactly the names assigned at its top level. everything else that
+//gets assigned somewhere is a local of whatever scope it first shows up in.
+pub fn global_names(stmts: &[Stmt]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for stmt in stmts {
+        let stmt = match stmt {
+            Stmt::STATEMENT_EXPORT(inner) => inner.as_ref(),
+            other => other,
+        };
+        if let Stmt::STATEMENT_ASSIGNMENT(assignment) = stmt
+            && !names.contains(&assignment.ident.name) {
+            names.push(assignment.ident.name.clone());
+        }
+    }
+    names
+}
+
+//every rite a module declares, in order
+pub fn rite_declarations(stmts: &[Stmt]) -> Vec<&FnDeclaration> {
+    stmts.iter().filter_map(|stmt| match stmt {
+        Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => Some(decl),
+        Stmt::STATEMENT_EXPORT(inner) => match inner.as_ref() {
+            Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => Some(decl),
+            _ => None,
+        },
+        _ => None,
+    }).collect()
+}
+
+//the static rules a module has to follow before either backend will run it: rites only live at
+//the top of a module, nothing gets declared twice, and every declared type is actually a type.
+pub fn validate_module(stmts: &[Stmt]) -> Result<(), String> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    for decl in rite_declarations(stmts) {
+        if !seen.insert(&decl.ident.name) {
+            return Err(format!("Rite {} is declared twice!", decl.ident.name));
+        }
+        if type_name_of(&decl.type_t).is_none() {
+            return Err(format!("Rite {} gives back {:?}, which is not a type!", decl.ident.name, decl.type_t));
+        }
+        for param in &decl.params {
+            if type_name_of(&param.type_t).is_none() {
+                return Err(format!(
+                    "Parameter {} of rite {} is a {:?}, which is not a type!",
+                    param.ident.name, decl.ident.name, param.type_t
+                ));
+            }
+        }
+        validate_scope(&decl.body.stmts)?;
+    }
+    for stmt in stmts {
+        match stmt {
+            Stmt::STATEMENT_FUNCTION_DECLARATION(_) | Stmt::STATEMENT_IMPORT(_) => {},
+            Stmt::STATEMENT_EXPORT(inner) => validate_stmt(inner)?,
+            other => validate_stmt(other)?,
+        }
+    }
+    Ok(())
+}
+
+fn validate_scope(stmts: &[Stmt]) -> Result<(), String> {
+    for stmt in stmts {
+        if let Stmt::STATEMENT_FUNCTION_DECLARATION(decl) = stmt {
+            return Err(format!(
+                "Rite {} is declared inside a scope! Rites can only live at the top of a module.",
+                decl.ident.name
+            ));
+        }
+        validate_stmt(stmt)?;
+    }
+    Ok(())
+}
+
+fn validate_stmt(stmt: &Stmt) -> Result<(), String> {
+    match stmt {
+        Stmt::STATEMENT_ASSIGNMENT(assignment) => {
+            if let Some(type_t) = &assignment.type_t
+                && type_name_of(type_t).is_none() {
+                return Err(format!(
+                    "{} is declared as {:?}, which is not a type!", assignment.ident.name, type_t
+                ));
+            }
+            validate_expr(&assignment.expr)
+        },
+        Stmt::STATEMENT_RETURN(ret) => validate_expr(&ret.expr),
+        Stmt::STATEMENT_FUNCTION_CALL(fncall) => validate_args(&fncall.args),
+        Stmt::SCOPE(scope) => validate_scope(&scope.stmts),
+        Stmt::STATEMENT_EXPORT(_) => Err("Only the top of a module can export things!".to_string()),
+        Stmt::STATEMENT_IMPORT(import) => Err(format!(
+            "{} is summoned inside a scope! Only the top of a module can summon.", import.module_path().join("::")
+        )),
+        Stmt::STATEMENT_YIELD(yield_stmt) => validate_yield(yield_stmt),
+        Stmt::STATEMENT_DIALOGUE(dialogue) => validate_dialogue(dialogue),
+        Stmt::STATEMENT_FUNCTION_DECLARATION(_) | Stmt::STATEMENT_ZERO_EFFECT(_) => Ok(()),
+    }
+}
+
+fn validate_args(args: &[Expr]) -> Result<(), String> {
+    args.iter().try_for_each(validate_expr)
+}
+
+fn validate_expr(expr: &Expr) -> Result<(), String> {
+    match expr {
+        Expr::ATOM(_) => Ok(()),
+        Expr::GROUPED_EXPR(inner) => validate_expr(inner),
+        Expr::BINARY_EXPR { left, right, .. } => {
+            validate_expr(left)?;
+            validate_expr(right)
+        },
+        Expr::UNARY_EXPR { expr, .. } => validate_expr(expr),
+        Expr::SCOPE(scope) => validate_scope(&scope.stmts),
+        Expr::FUNCTION_CALL(fncall) => validate_args(&fncall.args),
+        Expr::METHOD_CALL(call) => {
+            validate_expr(&call.base)?;
+            validate_args(&call.call.args)
+        },
+        Expr::FIELD_ACCESS(access) => validate_expr(&access.base),
+        Expr::YIELD(yield_expr) => validate_yield(yield_expr),
+    }
+}
+
+fn validate_yield(yield_expr: &Yield) -> Result<(), String> {
+    yield_expr.expr.as_deref().map_or(Ok(()), validate_expr)
+}
+
+fn validate_dialogue(dialogue: &Dialogue) -> Result<(), String> {
+    check_dialogue(dialogue)?;
+    for node in &dialogue.nodes {
+        for line in &node.lines {
+            validate_text(&line.text)?;
+        }
+        if let NodeExit::CHOICES(choices) = &node.exit {
+            for choice in choices {
+                validate_text(&choice.text)?;
+                choice.condition.as_ref().map_or(Ok(()), validate_expr)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_text(text: &Text) -> Result<(), String> {
+    text.parts.iter().try_for_each(|part| match part {
+        TextPart::EXPR(expr) => validate_expr(expr),
+        TextPart::TEXT(_) => Ok(()),
+    })
+}
+
+//binds the arguments of a call to the parameters of a rite, converting them where allowed
+pub fn bind_arguments(rite: &str, params: &[(String, TokenType)], args: Vec<Value>) -> Result<Vec<Value>, String> {
+    if params.len() != args.len() {
+        return Err(wrong_argument_count(rite, params.len(), args.len()));
+    }
+    params.iter().zip(args).map(|((name, type_t), arg)| {
+        let found = arg.type_name();
+        conform(arg, type_t).ok_or_else(|| wrong_argument_type(rite, type_t, name, found))
+    }).collect()
+}
+
+pub fn conform_return(rite: &str, type_t: &TokenType, value: Value) -> Result<Value, String> {
+    let found = value.type_name();
+    conform(value, type_t).ok_or_else(|| wrong_return_type(rite, type_t, found))
+}
+
+pub fn conform_assignment(name: &str, type_t: &TokenType, value: Value) -> Result<Value, String> {
+    let found = value.type_name();
+    conform(value, type_t).ok_or_else(|| wrong_assignment_type(name, type_t, found))
+}
+
+//the type checker (typeck.rs) reports the same mistakes before anything runs, in the same words
+pub fn wrong_argument_count(rite: &str, wanted: usize, got: usize) -> String {
+    format!("Rite {} takes {} argument(s), but got {}!", rite, wanted, got)
+}
+
+pub fn wrong_argument_type(rite: &str, type_t: &TokenType, param: &str, found: &str) -> String {
+    format!("Rite {} wants {} for {}, but got {}!", rite, type_name_of(type_t).unwrap_or("?"), param, found)
+}
+
+pub fn wrong_return_type(rite: &str, type_t: &TokenType, found: &str) -> String {
+    format!("Rite {} should give back {}, but gave back {}!", rite, type_name_of(type_t).unwrap_or("?"), found)
+}
+
+pub fn wrong_assignment_type(name: &str, type_t: &TokenType, found: &str) -> String {
+    format!("Can't put {} into {}: {}!", found, name, type_name_of(type_t).unwrap_or("?"))
+}
+
+pub fn not_defined(name: &str) -> String {
+    format!("{} is not defined!", name)
+}
+
+pub fn not_exported(name: &str, module: &str) -> String {
+    format!("{} is not exported by {}!", name, module)
+}
+
+pub fn no_such_rite(name: &str) -> String {
+    format!("No rite named {}!", name)
+}
+
+pub fn no_such_field(field: &str, value: &Value) -> String {
+    no_such_field_on(field, value.type_name())
+}
+
+pub fn no_such_field_on(field: &str, type_name: &str) -> String {
+    format!("Values don't have fields yet, so there's no .{} on {}!", field, type_name)
+}
+
+pub fn stack_overflow(limits: &Limits) -> String {
+    format!("Stack overflow! Rites nested deeper than {} calls.", limits.max_call_depth)
+}
+
+pub fn out_of_fuel(fuel: u64) -> String {
+    format!("Out of fuel! The script took more than {} steps.", fuel)
+}
+
+//for hosts that want to tell a script that ran too long from one that broke
+pub fn is_out_of_fuel(err: &str) -> bool {
+    err.starts_with("Out of fuel!")
+}
+
+pub fn rune_too_long(max: usize) -> String {
+    format!("Rune too long! Runes can be at most {} bytes.", max)
+}
+
+pub fn yield_outside_coroutine() -> String {
+    "Can't yield here! Only a rite running as a coroutine can yield.".to_string()
+}
+
+pub fn no_such_coroutine(coroutine: usize) -> String {
+    format!("No coroutine {}! It finished, failed, was cancelled or never existed.", coroutine)
+}
+
+pub fn not_a_global(module: &str, name: &str) -> String {
+    format!("The save has a global {} in module {}, which isn't one!", name, module)
+}
+
+pub fn no_such_choice(value: &Value, count: usize) -> String {
+    let found = match value {
+        Value::STRING(text) => format!("{:?}", text),
+        other => other.to_string(),
+    };
+    format!("The dialogue has choices 0 to {}, but was resumed with {}!", count.saturating_sub(1), found)
+}
+
+pub fn choice_not_offered(picked: &Value) -> String {
+    format!("Choice {} wasn't on offer, its condition didn't hold!", picked.to_string())
+}
+
+pub fn handler_failed(subscription: &Subscription, err: &str) -> String {
+    format!("Handler {} in module {} failed: {}", subscription.rite, subscription.module, err)
+}
+
+pub fn saved_by_the_other_backend(coroutine: usize, backend: Backend) -> String {
+    let backend = match backend {
+        Backend::INTERPRETER => "interpreter",
+        Backend::VM => "vm",
+    };
+    format!(
+        "Coroutine {} in the save was made by the {}, only the {} can pick it back up!",
+        coroutine, backend, backend
+    )
+}
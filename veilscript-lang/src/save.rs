@@ -0,0 +1,225 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::Stmt;
+use crate::bytecode_file::{crc32, Reader, Writer};
+use crate::format::format_stmts;
+use crate::random::Rng;
+use crate::value::Value;
+
+///SAVE GAME section
+//this here is how a game saves what its scripts are up to and picks it back up later, in the
+//middle of a quest: the rng (see random.rs), the globals of every module and every coroutine
+//that's asleep (see runtime.rs) with its whole stack. values are only ints, floats, runes and void
+//so far, so that's all the heap there is to save. a SAVE FILE is laid out like a bytecode file, everything little endian:
+//
+//  magic      4 bytes   "VLSV"
+//  version    u16       SAVE_VERSION, anything else is refused
+//  length     u32       size of the payload in bytes
+//  checksum   u32       crc32 of the payload
+//  payload    the rng, then modules, then coroutines (see write_save)
+//
+//a save only makes sense next to the scripts it was made with, a sleeping coroutine points at
+//exact instructions. so every module goes in with its VERSION, a checksum of its code as it was
+//loaded (printed canonically, so comments and layout don't count), and a save is refused unless
+//every module in it is loaded at that same version. modules the save doesn't know about keep
+//whatever state they have.
+//
+//in memory a save is a SaveState. both runtimes turn themselves into one and back, so the rng and
+//the globals of a save made with one backend load into the other just fine. coroutines don't, the
+//two keep them completely differently (see SavedCoroutine), and each refuses the other's.
+
+pub const SAVE_MAGIC: [u8; 4] = *b"VLSV";
+//bump this whenever the payload layout changes
+pub const SAVE_VERSION: u16 = 3;
+const HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+
+//the version of a module, from its statements as they're run (so after the optimiser)
+pub fn module_version(stmts: &[Stmt]) -> u32 {
+    crc32(format_stmts(stmts).as_bytes())
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SaveState {
+    pub rng: Rng,
+    pub modules: Vec<SavedModule>,
+    pub coroutines: Vec<SavedCoroutine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedModule {
+    pub id: String,
+    pub version: u32,
+    pub globals: Vec<(String, Value)>, //only the ones that have been assigned, by name
+}
+
+//the vm saves a coroutine's stack and frames as they are. the interpreter has neither (it plays a
+//coroutine back from the start on every resume, see interpreter.rs), so it saves the arguments as
+//the stack, the coroutine's rite as its only frame, and what it needs to play it back as `replay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedCoroutine {
+    pub id: usize,
+    pub asleep: bool,
+    pub stack: Vec<Value>,
+    pub frames: Vec<SavedFrame>, //outermost first
+    pub replay: Option<SavedReplay>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedReplay {
+    pub log: Vec<Value>, //what every effect so far came out as, in order
+    pub steps: u64, //the fuel it has burnt so far
+}
+
+//where one rite on a coroutine's call stack is at. rites go by name, so a save doesn't depend on
+//the order the game loaded its modules in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedFrame {
+    pub module: String,
+    pub rite: String,
+    pub ip: usize,
+    pub base: usize,
+}
+
+impl SaveState {
+    //`loaded` is the version of a module that's loaded right now, None if it isn't
+    pub fn check_versions(&self, loaded: impl Fn(&str) -> Option<u32>) -> Result<(), String> {
+        for module in &self.modules {
+            match loaded(&module.id) {
+                None => return Err(format!("The save needs module {}, which isn't loaded!", module.id)),
+                Some(version) if version != module.version => return Err(format!(
+                    "Module {} changed since the save was made! It was version {:08x}, now it's {:08x}.",
+                    module.id, module.version, version
+                )),
+                Some(_) => {},
+            }
+        }
+        for coroutine in &self.coroutines {
+            for frame in &coroutine.frames {
+                if !self.modules.iter().any(|module| module.id == frame.module) {
+                    return Err(format!(
+                        "Coroutine {} is asleep in module {}, which the save has no version of!", coroutine.id, frame.module
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+///WRITING
+
+pub fn write_save(state: &SaveState) -> Vec<u8> {
+    let mut payload = Writer { bytes: Vec::new() };
+
+    payload.u64(state.rng.state());
+
+    payload.count(state.modules.len());
+    for module in &state.modules {
+        payload.string(&module.id);
+        payload.u32(module.version);
+        payload.count(module.globals.len());
+        for (name, value) in &module.globals {
+            payload.string(name);
+            payload.value(value);
+        }
+    }
+
+    payload.count(state.coroutines.len());
+    for coroutine in &state.coroutines {
+        payload.count(coroutine.id);
+        payload.u8(coroutine.asleep as u8);
+        payload.count(coroutine.stack.len());
+        for value in &coroutine.stack {
+            payload.value(value);
+        }
+        payload.count(coroutine.frames.len());
+        for frame in &coroutine.frames {
+            payload.string(&frame.module);
+            payload.string(&frame.rite);
+            payload.count(frame.ip);
+            payload.count(frame.base);
+        }
+        payload.u8(coroutine.replay.is_some() as u8);
+        if let Some(replay) = &coroutine.replay {
+            payload.count(replay.log.len());
+            for value in &replay.log {
+                payload.value(value);
+            }
+            payload.u64(replay.steps);
+        }
+    }
+
+    let mut file = Vec::with_capacity(HEADER_SIZE + payload.bytes.len());
+    file.extend_from_slice(&SAVE_MAGIC);
+    file.extend_from_slice(&SAVE_VERSION.to_le_bytes());
+    file.extend_from_slice(&(payload.bytes.len() as u32).to_le_bytes());
+    file.extend_from_slice(&crc32(&payload.bytes).to_le_bytes());
+    file.extend_from_slice(&payload.bytes);
+    file
+}
+
+///READING
+
+//reads a save file back. whether it fits the scripts is up to the runtime restoring it.
+pub fn read_save(bytes: &[u8]) -> Result<SaveState, String> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != SAVE_MAGIC {
+        return Err("Not a Veilscript save file!".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != SAVE_VERSION {
+        return Err(format!(
+            "Save file is format version {}, but this build only reads version {}!", version, SAVE_VERSION
+        ));
+    }
+    let length = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+    let checksum = u32::from_le_bytes([bytes[10], bytes[11], bytes[12], bytes[13]]);
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != length {
+        return Err(format!("Save file should have {} bytes of state, but has {}!", length, payload.len()));
+    }
+    if crc32(payload) != checksum {
+        return Err("Save file is corrupt! The checksum doesn't match.".to_string());
+    }
+
+    let mut reader = Reader { bytes: payload, pos: 0, what: "Save file" };
+    let mut state = SaveState { rng: Rng::new(reader.u64()?), ..SaveState::default() };
+
+    for _ in 0..reader.count()? {
+        let id = reader.string()?;
+        let version = reader.u32()?;
+        let mut globals = Vec::new();
+        for _ in 0..reader.count()? {
+            let name = reader.string()?;
+            globals.push((name, reader.value()?));
+        }
+        state.modules.push(SavedModule { id, version, globals });
+    }
+
+    for _ in 0..reader.count()? {
+        let id = reader.index()?;
+        let asleep = reader.u8()? != 0;
+        let stack = (0..reader.count()?).map(|_| reader.value()).collect::<Result<Vec<_>, _>>()?;
+        let mut frames = Vec::new();
+        for _ in 0..reader.count()? {
+            let module = reader.string()?;
+            let rite = reader.string()?;
+            let ip = reader.index()?;
+            let base = reader.index()?;
+            frames.push(SavedFrame { module, rite, ip, base });
+        }
+        let replay = match reader.u8()? {
+            0 => None,
+            _ => {
+                let log = (0..reader.count()?).map(|_| reader.value());
+                let log = log.collect::<Result<Vec<_>, _>>()?;
+                Some(SavedReplay { log, steps: reader.u64()? })
+            },
+        };
+        state.coroutines.push(SavedCoroutine { id, asleep, stack, frames, replay });
+    }
+
+    if reader.pos != payload.len() {
+        return Err("Save file has junk after the last coroutine!".to_string());
+    }
+    Ok(state)
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+///FILE ID section
+//a FILE ID is a cheap handle to a loaded source file. Anything that needs to point back into a
+//script later (diagnostics, debug info) holds one of these instead of a name or the whole text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(pub u32);
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String, //whatever the loader calls it, like `quests/intro.veil`
+    pub text: String,
+}
+
+///SOURCE MAP section
+//the SOURCE MAP remembers every file that has been loaded, so a FileId can always be turned back
+//into a name (for error messages) and the original text.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn add(&mut self, name: &str, text: &str) -> FileId {
+        //the same file handed in twice gets the same id, so diagnostics don't show duplicates
+        if let Some(id) = self.find(name) {
+            self.files[id.0 as usize].text = text.to_owned();
+            return id;
+        }
+        self.files.push(SourceFile { name: name.to_owned(), text: text.to_owned() });
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    //runs the loader and remembers what came out of it
+    pub fn load(&mut self, loader: &dyn SourceLoader, name: &str) -> Result<FileId, String> {
+        let text = loader.load(name)?;
+        Ok(self.add(name, &text))
+    }
+
+    pub fn find(&self, name: &str) -> Option<FileId> {
+        self.files.iter().position(|file| file.name == name).map(|index| FileId(index as u32))
+    }
+
+    pub fn get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0 as usize)
+    }
+
+    pub fn name(&self, id: FileId) -> &str {
+        match self.get(id) {
+            Some(file) => &file.name,
+            None => "<unknown file>",
+        }
+    }
+
+    pub fn text(&self, id: FileId) -> &str {
+        match self.get(id) {
+            Some(file) => &file.text,
+            None => "",
+        }
+    }
+}
+
+///SOURCE LOADER section
+//a SOURCE LOADER fetches script text by name. The game implements this on top of its own asset
+//pipeline (zip/pak archives, bundles baked into the executable, a virtual filesystem...) so the
+//language never has to touch std::fs on its own. Names are always '/' separated.
+pub trait SourceLoader {
+    fn load(&self, name: &str) -> Result<String, String>;
+}
+
+//reads straight off the disk, relative to a root folder
+pub struct FsLoader {
+    pub root: PathBuf,
+}
+
+impl FsLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FsLoader { root: root.into() }
+    }
+}
+
+impl SourceLoader for FsLoader {
+    fn load(&self, name: &str) -> Result<String, String> {
+        let mut file = self.root.clone();
+        for segment in name.split('/') {
+            file.push(segment);
+        }
+        std::fs::read_to_string(&file)
+            .map_err(|err| format!("Couldn't read {}: {}", file.display(), err))
+    }
+}
+
+//every file lives in a hashmap. meant for tests, tools and games that ship scripts as one bundle.
+#[derive(Default)]
+pub struct MemoryLoader {
+    pub files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+    pub fn add(&mut self, name: &str, text: &str) {
+        self.files.insert(name.to_owned(), text.to_owned());
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<String, String> {
+        match self.files.get(name) {
+            Some(text) => Ok(text.clone()),
+            None => Err(format!("No file named {} in memory!", name)),
+        }
+    }
+}
@@ -0,0 +1,445 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::dialogue::{CHOOSE, SAY};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Span, TokenType};
+use crate::module::{Module, ModuleLoader};
+use crate::resolve::resolve;
+use crate::runtime::*;
+use crate::value::Value;
+use crate::visit::{walk_assignment, Visitor};
+
+///TYPE CHECKER section
+//this here is the TYPE CHECKER behind `veil check`. It reads a module without running it and
+//reports everything that would blow up at runtime no matter what: putting a rune into an int,
+//calling a rite with the wrong arguments, giving back the wrong type, maths on runes, fields.
+//
+//scripts are dynamically typed, so anything the checker can't pin down is ANY and never
+//complained about. what it can pin down is a lot though: there's no branching in the language,
+//so inside a block the type of a variable is simply whatever was last put into it. the one thing
+//it doesn't chase is globals inside rites, since any rite call could have changed them.
+//
+//the rules are the ones in value.rs and runtime.rs, and so are the messages.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaticType {
+    INT,
+    FLOAT,
+    RUNE,
+    VOID,
+    ANY,
+}
+
+impl StaticType {
+    pub fn of(type_t: &TokenType) -> StaticType {
+        match type_t {
+            TokenType::EXPERIMENTAL_TYPE_INT => StaticType::INT,
+            TokenType::TYPE_FLOAT => StaticType::FLOAT,
+            TokenType::TYPE_STRING => StaticType::RUNE,
+            TokenType::TYPE_VOID => StaticType::VOID,
+            _ => StaticType::ANY,
+        }
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            StaticType::INT => "int".to_string(),
+            StaticType::FLOAT => "float".to_string(),
+            StaticType::RUNE => "rune".to_string(),
+            StaticType::VOID => "void".to_string(),
+            StaticType::ANY => "anything".to_string(),
+        }
+    }
+
+    pub fn of_value(value: &Value) -> StaticType {
+        match value {
+            Value::INT(_) => StaticType::INT,
+            Value::FLOAT(_) => StaticType::FLOAT,
+            Value::STRING(_) => StaticType::RUNE,
+            Value::VOID => StaticType::VOID,
+        }
+    }
+
+    //could a value of this type be put somewhere declared as `type_t`? same rules as value::conform
+    pub fn fits(&self, type_t: &TokenType) -> bool {
+        let wanted = StaticType::of(type_t);
+        *self == StaticType::ANY || *self == wanted || (*self == StaticType::INT && wanted == StaticType::FLOAT)
+    }
+}
+
+pub struct TypeChecker<'a> {
+    file: String,
+    module: Option<&'a Module>,
+    modules: Option<&'a HashMap<String, Module>>,
+    rites: HashMap<&'a str, &'a FnDeclaration>,
+    global_names: HashSet<String>,
+    rite_writes: HashSet<&'a str>,            //globals some rite of this module might overwrite
+    pub globals: HashMap<String, StaticType>, //what the top level code has put in them so far
+    scopes: Vec<HashMap<String, StaticType>>,
+    rite: Option<&'a FnDeclaration>,          //the rite being checked, if any
+    blocks: Vec<Option<StaticType>>,          //per open scope expression: what its first `ret` gives
+    span: Span,                               //where the statement being checked is
+    pub diagnostics: Vec<Diagnostic>,
+    pub types: Vec<(Span, StaticType)>,       //what every name was found to be, where it was written
+}
+
+impl<'a> TypeChecker<'a> {
+    //a checker for loose statements that aren't part of a loaded module (the repl uses this)
+    pub fn new(file: &str, stmts: &'a [Stmt]) -> Self {
+        TypeChecker {
+            file: file.to_owned(),
+            module: None,
+            modules: None,
+            rites: rite_declarations(stmts).into_iter().map(|decl| (decl.ident.name.as_str(), decl)).collect(),
+            global_names: global_names(stmts).into_iter().collect(),
+            rite_writes: rite_writes(stmts),
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            rite: None,
+            blocks: Vec::new(),
+            span: Span::default(),
+            diagnostics: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    pub fn for_module(file: &str, module: &'a Module, modules: &'a HashMap<String, Module>) -> Self {
+        let mut checker = TypeChecker::new(file, &module.stmts);
+        checker.module = Some(module);
+        checker.modules = Some(modules);
+        checker
+    }
+
+    fn error(&mut self, message: String) {
+        self.diagnostics.push(Diagnostic::error(&self.file, self.span, message));
+    }
+
+    //the rites first, then the top level code in order
+    pub fn check_stmts(&mut self, stmts: &'a [Stmt]) {
+        for decl in rite_declarations(stmts) {
+            self.check_rite(decl);
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn check_rite(&mut self, decl: &'a FnDeclaration) {
+        let params = decl.params.iter()
+            .map(|param| (param.ident.name.clone(), StaticType::of(&param.type_t)))
+            .collect();
+        for param in &decl.params {
+            self.types.push((param.ident.span, StaticType::of(&param.type_t)));
+        }
+        let outer = std::mem::replace(&mut self.scopes, vec![params]);
+        self.rite = Some(decl);
+        for stmt in &decl.body.stmts {
+            self.stmt(stmt);
+        }
+        self.rite = None;
+        self.scopes = outer;
+    }
+
+    ///STATEMENTS
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        if let Some(span) = stmt.span() {
+            self.span = span;
+        }
+        match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(assignment) => self.assignment(assignment),
+            Stmt::STATEMENT_RETURN(ret) => {
+                let found = self.expr(&ret.expr);
+                self.span = ret.span;
+                match self.blocks.last_mut() {
+                    Some(block) => {
+                        block.get_or_insert(found);
+                    },
+                    None => {
+                        if let Some(decl) = self.rite && !found.fits(&decl.type_t) {
+                            self.error(wrong_return_type(&decl.ident.name, &decl.type_t, &found.to_string()));
+                        }
+                    },
+                }
+            },
+            Stmt::STATEMENT_FUNCTION_CALL(fncall) => {
+                let args = self.args(&fncall.args);
+                self.call(&fncall.ident, args);
+            },
+            Stmt::SCOPE(scope) => self.scope(scope),
+            Stmt::STATEMENT_EXPORT(inner) => self.stmt(inner),
+            Stmt::STATEMENT_YIELD(yield_stmt) => { self.yield_expr(yield_stmt); },
+            Stmt::STATEMENT_DIALOGUE(dialogue) => self.dialogue(dialogue),
+            Stmt::STATEMENT_FUNCTION_DECLARATION(_) | Stmt::STATEMENT_IMPORT(_)
+                | Stmt::STATEMENT_ZERO_EFFECT(_) => {},
+        }
+    }
+
+    fn scope(&mut self, scope: &'a Scope) {
+        self.scopes.push(HashMap::new());
+        for stmt in &scope.stmts {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    //every line is a call to say and every set of choices one to choose, so they're checked like
+    //those calls. a choice with a condition might be void instead of its text.
+    fn dialogue(&mut self, dialogue: &'a Dialogue) {
+        for node in &dialogue.nodes {
+            for line in &node.lines {
+                self.text(&line.text);
+                let speaker = match line.speaker {
+                    Some(_) => StaticType::RUNE,
+                    None => StaticType::VOID,
+                };
+                self.span = line.span();
+                self.call(&Ident::with_span(SAY, line.span()), vec![speaker, StaticType::RUNE]);
+            }
+            if let NodeExit::CHOICES(choices) = &node.exit {
+                let mut args = Vec::new();
+                for choice in choices {
+                    self.text(&choice.text);
+                    args.push(match &choice.condition {
+                        Some(condition) => {
+                            self.expr(condition);
+                            StaticType::ANY
+                        },
+                        None => StaticType::RUNE,
+                    });
+                }
+                self.span = choices[0].span;
+                self.call(&Ident::with_span(CHOOSE, choices[0].span), args);
+            }
+        }
+    }
+
+    fn text(&mut self, text: &'a Text) {
+        for part in &text.parts {
+            if let TextPart::EXPR(expr) = part {
+                self.expr(expr);
+            }
+        }
+    }
+
+    //same rules as Interpreter::exec_assignment, just with types instead of values
+    fn assignment(&mut self, assignment: &'a Assignment) {
+        let name = &assignment.ident.name;
+        let found = self.expr(&assignment.expr);
+        self.span = assignment.ident.span;
+
+        if let Some(type_t) = &assignment.type_t {
+            if !found.fits(type_t) {
+                self.error(wrong_assignment_type(name, type_t, &found.to_string()));
+            }
+            let declared = StaticType::of(type_t);
+            self.types.push((assignment.ident.span, declared));
+            match self.scopes.last_mut() {
+                Some(scope) => { scope.insert(name.clone(), declared); },
+                None => { self.globals.insert(name.clone(), declared); },
+            }
+            return;
+        }
+
+        self.types.push((assignment.ident.span, found));
+        if let Some(scope) = self.scopes.iter_mut().rev().find(|scope| scope.contains_key(name)) {
+            scope.insert(name.clone(), found);
+        } else if self.scopes.is_empty() || self.global_names.contains(name) {
+            if self.rite.is_none() {
+                self.globals.insert(name.clone(), found);
+            }
+        } else if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.clone(), found);
+        }
+    }
+
+    ///EXPRESSIONS
+
+    pub fn expr(&mut self, expr: &'a Expr) -> StaticType {
+        match expr {
+            Expr::ATOM(atom) => self.atom(atom),
+            Expr::GROUPED_EXPR(inner) => self.expr(inner),
+            Expr::BINARY_EXPR { left, opcode, right } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.binary(*opcode, left, right)
+            },
+            Expr::UNARY_EXPR { opcode, expr } => match self.expr(expr) {
+                found @ (StaticType::INT | StaticType::FLOAT | StaticType::ANY) => found,
+                found => {
+                    self.error(opcode.type_error(&found.to_string()));
+                    StaticType::ANY
+                },
+            },
+            Expr::SCOPE(scope) => {
+                self.blocks.push(None);
+                self.scope(scope);
+                self.blocks.pop().flatten().unwrap_or(StaticType::VOID)
+            },
+            Expr::FUNCTION_CALL(fncall) => {
+                let args = self.args(&fncall.args);
+                self.call(&fncall.ident, args)
+            },
+            Expr::METHOD_CALL(call) => {
+                let mut args = vec![self.expr(&call.base)];
+                args.extend(self.args(&call.call.args));
+                self.call(&call.call.ident, args)
+            },
+            Expr::FIELD_ACCESS(access) => {
+                let found = self.expr(&access.base);
+                self.error(no_such_field_on(&access.access.name, &found.to_string()));
+                StaticType::ANY
+            },
+            Expr::YIELD(yield_expr) => self.yield_expr(yield_expr),
+        }
+    }
+
+    //whatever the game resumes a coroutine with could be anything
+    fn yield_expr(&mut self, yield_expr: &'a Yield) -> StaticType {
+        if let Some(expr) = &yield_expr.expr {
+            self.expr(expr);
+        }
+        StaticType::ANY
+    }
+
+    fn atom(&mut self, atom: &Atom) -> StaticType {
+        let found = self.atom_type(atom);
+        if let Atom::IDENTIFIER(ident) = atom {
+            self.types.push((ident.span, found));
+        }
+        found
+    }
+
+    fn atom_type(&self, atom: &Atom) -> StaticType {
+        match atom {
+            Atom::LITERAL_INT(_) => StaticType::INT,
+            Atom::LITERAL_FLOAT(_) => StaticType::FLOAT,
+            Atom::LITERAL_DICE(_) => StaticType::INT,
+            Atom::LITERAL_STRING(_) => StaticType::RUNE,
+            Atom::IDENTIFIER(ident) if ident.is_namespaced() => StaticType::ANY,
+            Atom::IDENTIFIER(ident) => {
+                if let Some(scope) = self.scopes.iter().rev().find(|scope| scope.contains_key(&ident.name)) {
+                    return scope[&ident.name];
+                }
+                match self.rite {
+                    Some(_) => StaticType::ANY,
+                    None => self.globals.get(&ident.name).copied().unwrap_or(StaticType::ANY),
+                }
+            },
+        }
+    }
+
+    fn args(&mut self, args: &'a [Expr]) -> Vec<StaticType> {
+        args.iter().map(|arg| self.expr(arg)).collect()
+    }
+
+    //value.rs's BinOp::apply, one level up
+    fn binary(&mut self, opcode: BinOp, left: StaticType, right: StaticType) -> StaticType {
+        use StaticType::*;
+        let bitwise = matches!(opcode, BinOp::BIT_AND | BinOp::BIT_OR | BinOp::BIT_XOR | BinOp::SHL | BinOp::SHR);
+
+        if left == VOID || right == VOID || ((left == RUNE || right == RUNE) && opcode != BinOp::ADD) {
+            self.error(opcode.type_error_between(&left.to_string(), &right.to_string()));
+            return ANY;
+        }
+        match (left, right) {
+            (RUNE, RUNE | ANY) | (ANY, RUNE) => RUNE,
+            (RUNE, _) | (_, RUNE) => {
+                self.error(opcode.type_error_between(&left.to_string(), &right.to_string()));
+                ANY
+            },
+            (FLOAT, _) | (_, FLOAT) if bitwise => {
+                self.error(opcode.float_error());
+                INT
+            },
+            _ if bitwise => INT,
+            (FLOAT, _) | (_, FLOAT) => FLOAT,
+            (INT, INT) => INT,
+            _ => ANY,
+        }
+    }
+
+    //rites of the module first, natives second, exactly like the runtimes. natives are the game's
+    //business, so calls to them are taken on faith.
+    fn call(&mut self, ident: &Ident, args: Vec<StaticType>) -> StaticType {
+        let decl = match ident.is_namespaced() {
+            true => self.module
+                .and_then(|module| module.resolve_namespace(&ident.namespace))
+                .and_then(|id| self.modules?.get(id))
+                .and_then(|target| rite_declarations(&target.stmts).into_iter().find(|decl| decl.ident.name == ident.name)),
+            false => self.rites.get(ident.name.as_str()).copied(),
+        };
+        //whatever the rite did to our globals, we don't know it anymore
+        if !ident.is_namespaced() && decl.is_some() {
+            for name in &self.rite_writes {
+                self.globals.insert(name.to_string(), StaticType::ANY);
+            }
+        }
+        let decl = match decl {
+            Some(decl) => decl,
+            None => return StaticType::ANY,
+        };
+
+        let span = std::mem::replace(&mut self.span, ident.span);
+        if decl.params.len() != args.len() {
+            self.error(wrong_argument_count(&decl.ident.name, decl.params.len(), args.len()));
+        } else {
+            for (param, found) in decl.params.iter().zip(args) {
+                if !found.fits(&param.type_t) {
+                    self.error(wrong_argument_type(&decl.ident.name, &param.type_t, &param.ident.name, &found.to_string()));
+                }
+            }
+        }
+        self.span = span;
+        StaticType::of(&decl.type_t)
+    }
+}
+
+//every name a rite assigns to without declaring it. some of those are globals. (a few might be
+//locals that just happen to share a name, but forgetting a bit too much is harmless.)
+fn rite_writes(stmts: &[Stmt]) -> HashSet<&str> {
+    struct Writes<'a>(HashSet<&'a str>);
+    impl<'a> Visitor<'a> for Writes<'a> {
+        fn visit_assignment(&mut self, assignment: &'a Assignment) {
+            if assignment.type_t.is_none() {
+                self.0.insert(&assignment.ident.name);
+            }
+            walk_assignment(self, assignment);
+        }
+    }
+    let mut writes = Writes(HashSet::new());
+    for decl in rite_declarations(stmts) {
+        writes.visit_scope(&decl.body);
+    }
+    writes.0
+}
+
+//checks one module that came out of a loader: names first (see resolve.rs), then types.
+//`natives` are the ones the game registers, if they're known.
+pub fn check_module(file: &str, module: &Module, modules: &HashMap<String, Module>, natives: Option<&[String]>) -> Vec<Diagnostic> {
+    if let Err(err) = validate_module(&module.stmts) {
+        return vec![Diagnostic::error(file, Span::default(), err)];
+    }
+    let mut diagnostics = resolve(file, &module.stmts, natives).diagnostics;
+    let mut checker = TypeChecker::for_module(file, module, modules);
+    checker.check_stmts(&module.stmts);
+    diagnostics.extend(checker.diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.line);
+    diagnostics
+}
+
+//checks every module a loader has summoned, dependencies first
+pub fn check_loaded(loader: &ModuleLoader, natives: Option<&[String]>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for id in &loader.order {
+        if let Some(module) = loader.get(id) {
+            diagnostics.extend(check_module(loader.sources.name(module.file), module, &loader.modules, natives));
+        }
+    }
+    diagnostics
+}
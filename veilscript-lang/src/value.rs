@@ -0,0 +1,215 @@
+#![allow(non_camel_case_types)]
+
+use crate::ast::{BinOp, MonOp};
+use crate::lexer::TokenType;
+
+///VALUE section
+//this here is a VALUE. It's what an expression turns into once it's actually evaluated. Anything
+//that runs scripts (and anything that wants to pretend it runs scripts, like constant folding)
+//should go through the operator functions down below so everyone agrees on what 7 / 2 is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    INT(i64),
+    FLOAT(f64),
+    STRING(String),
+    VOID,
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::INT(_) => "int",
+            Value::FLOAT(_) => "float",
+            Value::STRING(_) => "rune",
+            Value::VOID => "void",
+        }
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            Value::INT(val) => val.to_string(),
+            Value::FLOAT(val) => val.to_string(),
+            Value::STRING(val) => val.clone(),
+            Value::VOID => "void".to_string(),
+        }
+    }
+
+    //whether a condition (the `if` of a dialogue choice) holds: a number that isn't zero, or a
+    //rune with something in it. void never does.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::INT(val) => *val != 0,
+            Value::FLOAT(val) => *val != 0.0,
+            Value::STRING(val) => !val.is_empty(),
+            Value::VOID => false,
+        }
+    }
+}
+
+//string atoms keep their quotes and escapes straight from the source. this turns one into the
+//text it actually stands for. unknown escapes are kept as they were written.
+pub fn unescape_string_literal(lexeme: &str) -> String {
+    let inner = lexeme.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).unwrap_or(lexeme);
+    let mut ret = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('0') => ret.push('\0'),
+            Some('"') => ret.push('"'),
+            Some('\\') => ret.push('\\'),
+            Some(other) => {
+                ret.push('\\');
+                ret.push(other);
+            },
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+///TYPE section
+//declared types are still plain TokenTypes in the AST (TYPE_FLOAT, EXPERIMENTAL_TYPE_INT...).
+//this is where a runtime value gets held up against one of them.
+pub fn type_name_of(type_t: &TokenType) -> Option<&'static str> {
+    match type_t {
+        TokenType::EXPERIMENTAL_TYPE_INT => Some("int"),
+        TokenType::TYPE_FLOAT => Some("float"),
+        TokenType::TYPE_STRING => Some("rune"),
+        TokenType::TYPE_VOID => Some("void"),
+        _ => None,
+    }
+}
+
+//hands the value back if it fits the type. ints quietly become floats where a float is wanted,
+//nothing else is converted. None means it doesn't fit (or the "type" isn't a type at all).
+pub fn conform(value: Value, type_t: &TokenType) -> Option<Value> {
+    match (type_t, value) {
+        (TokenType::EXPERIMENTAL_TYPE_INT, value @ Value::INT(_)) => Some(value),
+        (TokenType::TYPE_FLOAT, value @ Value::FLOAT(_)) => Some(value),
+        (TokenType::TYPE_FLOAT, Value::INT(val)) => Some(Value::FLOAT(val as f64)),
+        (TokenType::TYPE_STRING, value @ Value::STRING(_)) => Some(value),
+        (TokenType::TYPE_VOID, value @ Value::VOID) => Some(value),
+        _ => None,
+    }
+}
+
+///OPERATOR SEMANTICS section
+//the rules, written down once:
+//  int  (+,-,*) int   -> int, wrapping on overflow. scripts should never crash the game over maths.
+//  int  /  int        -> int, truncated towards zero. dividing by zero is an error.
+//  int  %  int        -> int, always non-negative for a positive rhs (-1 % 4 == 3) so it can wrap
+//                        indices and cooldown ticks. modulo by zero is an error.
+//  int  ** int        -> int, wrapping. a negative exponent is an error, the answer wouldn't be
+//                        an int (2 ** -1 is 0.5), so `2.0 ** -1` it is.
+//  int with float     -> the int is promoted, result is float. floats follow IEEE, so 1.0/0.0 is inf.
+//  &, |, ^, <<, >>    -> ints only. shifting by a negative amount or by 64+ is an error,
+//                        >> is arithmetic (keeps the sign).
+//  rune + rune        -> concatenation. nothing else is defined on runes.
+impl BinOp {
+    pub fn apply(&self, left: &Value, right: &Value) -> Result<Value, String> {
+        match (left, right) {
+            (Value::INT(l), Value::INT(r)) => self.apply_int(*l, *r),
+            (Value::INT(l), Value::FLOAT(r)) => self.apply_float(*l as f64, *r),
+            (Value::FLOAT(l), Value::INT(r)) => self.apply_float(*l, *r as f64),
+            (Value::FLOAT(l), Value::FLOAT(r)) => self.apply_float(*l, *r),
+            (Value::STRING(l), Value::STRING(r)) if matches!(self, BinOp::ADD) => {
+                Ok(Value::STRING(format!("{l}{r}")))
+            },
+            _ => Err(self.type_error(left, right)),
+        }
+    }
+
+    fn apply_int(&self, l: i64, r: i64) -> Result<Value, String> {
+        let result = match self {
+            BinOp::ADD => l.wrapping_add(r),
+            BinOp::SUB => l.wrapping_sub(r),
+            BinOp::MULT => l.wrapping_mul(r),
+            BinOp::DIV => {
+                if r == 0 { return Err("Division by zero!".to_string()); }
+                l.wrapping_div(r)
+            },
+            BinOp::MOD => {
+                if r == 0 { return Err("Modulo by zero!".to_string()); }
+                l.wrapping_rem_euclid(r)
+            },
+            BinOp::POW => {
+                if r < 0 {
+                    return Err(format!(
+                        "Can't raise an int to {r}! Negative powers need a float (2.0 ** {r})."
+                    ));
+                }
+                wrapping_pow(l, r as u64)
+            },
+            BinOp::BIT_AND => l & r,
+            BinOp::BIT_OR => l | r,
+            BinOp::BIT_XOR => l ^ r,
+            BinOp::SHL | BinOp::SHR => {
+                if !(0..64).contains(&r) {
+                    return Err(format!("Can't shift by {r}! Shift amounts must be within 0..64."));
+                }
+                if matches!(self, BinOp::SHL) { l << r } else { l >> r }
+            },
+        };
+        Ok(Value::INT(result))
+    }
+
+    fn apply_float(&self, l: f64, r: f64) -> Result<Value, String> {
+        let result = match self {
+            BinOp::ADD => l + r,
+            BinOp::SUB => l - r,
+            BinOp::MULT => l * r,
+            BinOp::DIV => l / r,
+            BinOp::MOD => l.rem_euclid(r),
+            BinOp::POW => l.powf(r),
+            _ => return Err(self.float_error()),
+        };
+        Ok(Value::FLOAT(result))
+    }
+
+    fn type_error(&self, left: &Value, right: &Value) -> String {
+        self.type_error_between(left.type_name(), right.type_name())
+    }
+
+    pub fn type_error_between(&self, left: &str, right: &str) -> String {
+        format!("Can't apply '{}' to {} and {}!", self.to_string(), left, right)
+    }
+    pub fn float_error(&self) -> String {
+        format!("Operator '{}' only works on ints, not floats!", self.to_string())
+    }
+}
+
+//i64::wrapping_pow only takes a u32 exponent, and scripts hand us i64s. square and multiply it is.
+fn wrapping_pow(mut base: i64, mut exponent: u64) -> i64 {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
+
+impl MonOp {
+    pub fn apply(&self, value: &Value) -> Result<Value, String> {
+        match (self, value) {
+            (MonOp::POS, Value::INT(_) | Value::FLOAT(_)) => Ok(value.clone()),
+            (MonOp::NEG, Value::INT(val)) => Ok(Value::INT(val.wrapping_neg())),
+            (MonOp::NEG, Value::FLOAT(val)) => Ok(Value::FLOAT(-val)),
+            _ => Err(self.type_error(value.type_name())),
+        }
+    }
+
+    pub fn type_error(&self, type_name: &str) -> String {
+        format!("Can't apply unary '{}' to {}!", self.to_string(), type_name)
+    }
+}
@@ -0,0 +1,370 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use crate::ast::*;
+
+///VISITOR section
+//this here is how passes over the ast get written without every one of them hand rolling the same
+//giant recursive match. implement VISITOR (or MUTVISITOR to change things in place), override the
+//visit_ methods for the nodes you care about, and the default walk_ functions take care of getting
+//everywhere else. an override that still wants to go deeper calls the matching walk_ itself.
+//
+//the order is always the order things appear in the source.
+
+pub trait Visitor<'ast> {
+    fn visit_stmts(&mut self, stmts: &'ast [Stmt]) {
+        walk_stmts(self, stmts)
+    }
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+    fn visit_scope(&mut self, scope: &'ast Scope) {
+        walk_scope(self, scope)
+    }
+    fn visit_assignment(&mut self, assignment: &'ast Assignment) {
+        walk_assignment(self, assignment)
+    }
+    fn visit_fn_declaration(&mut self, decl: &'ast FnDeclaration) {
+        walk_fn_declaration(self, decl)
+    }
+    fn visit_parameter(&mut self, param: &'ast Parameter) {
+        walk_parameter(self, param)
+    }
+    fn visit_return(&mut self, ret: &'ast ReturnStmt) {
+        walk_return(self, ret)
+    }
+    fn visit_import(&mut self, import: &'ast Import) {
+        walk_import(self, import)
+    }
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+    fn visit_atom(&mut self, atom: &'ast Atom) {
+        walk_atom(self, atom)
+    }
+    fn visit_fn_call(&mut self, fncall: &'ast FnCall) {
+        walk_fn_call(self, fncall)
+    }
+    fn visit_method_call(&mut self, call: &'ast MethodCall) {
+        walk_method_call(self, call)
+    }
+    fn visit_field_access(&mut self, access: &'ast FieldAccess) {
+        walk_field_access(self, access)
+    }
+    fn visit_yield(&mut self, yield_expr: &'ast Yield) {
+        walk_yield(self, yield_expr)
+    }
+    fn visit_dialogue(&mut self, dialogue: &'ast Dialogue) {
+        walk_dialogue(self, dialogue)
+    }
+    //every name in the tree ends up here: assigned ones, called ones, parameters, summoned paths.
+    //dialogue nodes and speakers aren't names of anything a script can use, so they don't.
+    fn visit_ident(&mut self, _ident: &'ast Ident) {}
+}
+
+pub fn walk_stmts<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmts: &'ast [Stmt]) {
+    for stmt in stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmt: &'ast Stmt) {
+    match stmt {
+        Stmt::STATEMENT_ASSIGNMENT(assignment) => visitor.visit_assignment(assignment),
+        Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => visitor.visit_fn_declaration(decl),
+        Stmt::STATEMENT_RETURN(ret) => visitor.visit_return(ret),
+        Stmt::STATEMENT_FUNCTION_CALL(fncall) => visitor.visit_fn_call(fncall),
+        Stmt::STATEMENT_IMPORT(import) => visitor.visit_import(import),
+        Stmt::STATEMENT_EXPORT(inner) => visitor.visit_stmt(inner),
+        Stmt::STATEMENT_YIELD(yield_stmt) => visitor.visit_yield(yield_stmt),
+        Stmt::STATEMENT_DIALOGUE(dialogue) => visitor.visit_dialogue(dialogue),
+        Stmt::SCOPE(scope) => visitor.visit_scope(scope),
+        Stmt::STATEMENT_ZERO_EFFECT(Some(ident)) => visitor.visit_ident(ident),
+        Stmt::STATEMENT_ZERO_EFFECT(None) => {},
+    }
+}
+
+pub fn walk_scope<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, scope: &'ast Scope) {
+    visitor.visit_stmts(&scope.stmts);
+}
+
+pub fn walk_assignment<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, assignment: &'ast Assignment) {
+    visitor.visit_ident(&assignment.ident);
+    visitor.visit_expr(&assignment.expr);
+}
+
+pub fn walk_fn_declaration<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, decl: &'ast FnDeclaration) {
+    visitor.visit_ident(&decl.ident);
+    for param in &decl.params {
+        visitor.visit_parameter(param);
+    }
+    visitor.visit_scope(&decl.body);
+}
+
+pub fn walk_parameter<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, param: &'ast Parameter) {
+    visitor.visit_ident(&param.ident);
+}
+
+pub fn walk_return<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, ret: &'ast ReturnStmt) {
+    visitor.visit_expr(&ret.expr);
+}
+
+pub fn walk_import<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, import: &'ast Import) {
+    for segment in &import.path {
+        visitor.visit_ident(segment);
+    }
+    if let Some(alias) = &import.alias {
+        visitor.visit_ident(alias);
+    }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expr) {
+    match expr {
+        Expr::ATOM(atom) => visitor.visit_atom(atom),
+        Expr::GROUPED_EXPR(inner) => visitor.visit_expr(inner),
+        Expr::BINARY_EXPR { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        },
+        Expr::UNARY_EXPR { expr, .. } => visitor.visit_expr(expr),
+        Expr::SCOPE(scope) => visitor.visit_scope(scope),
+        Expr::FUNCTION_CALL(fncall) => visitor.visit_fn_call(fncall),
+        Expr::METHOD_CALL(call) => visitor.visit_method_call(call),
+        Expr::FIELD_ACCESS(access) => visitor.visit_field_access(access),
+        Expr::YIELD(yield_expr) => visitor.visit_yield(yield_expr),
+    }
+}
+
+pub fn walk_atom<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, atom: &'ast Atom) {
+    match atom {
+        Atom::IDENTIFIER(ident) => visitor.visit_ident(ident),
+        Atom::LITERAL_FLOAT(_) | Atom::LITERAL_INT(_) | Atom::LITERAL_DICE(_) | Atom::LITERAL_STRING(_) => {},
+    }
+}
+
+pub fn walk_fn_call<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, fncall: &'ast FnCall) {
+    visitor.visit_ident(&fncall.ident);
+    for arg in fncall.args.iter() {
+        visitor.visit_expr(arg);
+    }
+}
+
+pub fn walk_method_call<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, call: &'ast MethodCall) {
+    visitor.visit_expr(&call.base);
+    visitor.visit_fn_call(&call.call);
+}
+
+pub fn walk_field_access<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, access: &'ast FieldAccess) {
+    visitor.visit_expr(&access.base);
+    visitor.visit_ident(&access.access);
+}
+
+pub fn walk_yield<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, yield_expr: &'ast Yield) {
+    if let Some(expr) = &yield_expr.expr {
+        visitor.visit_expr(expr);
+    }
+}
+
+//the `{expr}`s of every line, then of each choice its text and its condition
+pub fn walk_dialogue<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, dialogue: &'ast Dialogue) {
+    for node in &dialogue.nodes {
+        for line in &node.lines {
+            walk_text(visitor, &line.text);
+        }
+        if let NodeExit::CHOICES(choices) = &node.exit {
+            for choice in choices {
+                walk_text(visitor, &choice.text);
+                if let Some(condition) = &choice.condition {
+                    visitor.visit_expr(condition);
+                }
+            }
+        }
+    }
+}
+
+fn walk_text<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, text: &'ast Text) {
+    for part in &text.parts {
+        if let TextPart::EXPR(expr) = part {
+            visitor.visit_expr(expr);
+        }
+    }
+}
+
+///MUTVISITOR section
+//same walk, but everything is handed out as &mut so a pass can rewrite the tree in place. statement
+//lists come as the whole Vec, so a pass can also drop or add statements.
+
+pub trait MutVisitor {
+    fn visit_stmts_mut(&mut self, stmts: &mut Vec<Stmt>) {
+        walk_stmts_mut(self, stmts)
+    }
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+    fn visit_scope_mut(&mut self, scope: &mut Scope) {
+        walk_scope_mut(self, scope)
+    }
+    fn visit_assignment_mut(&mut self, assignment: &mut Assignment) {
+        walk_assignment_mut(self, assignment)
+    }
+    fn visit_fn_declaration_mut(&mut self, decl: &mut FnDeclaration) {
+        walk_fn_declaration_mut(self, decl)
+    }
+    fn visit_parameter_mut(&mut self, param: &mut Parameter) {
+        walk_parameter_mut(self, param)
+    }
+    fn visit_return_mut(&mut self, ret: &mut ReturnStmt) {
+        walk_return_mut(self, ret)
+    }
+    fn visit_import_mut(&mut self, import: &mut Import) {
+        walk_import_mut(self, import)
+    }
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+    fn visit_atom_mut(&mut self, atom: &mut Atom) {
+        walk_atom_mut(self, atom)
+    }
+    fn visit_fn_call_mut(&mut self, fncall: &mut FnCall) {
+        walk_fn_call_mut(self, fncall)
+    }
+    fn visit_method_call_mut(&mut self, call: &mut MethodCall) {
+        walk_method_call_mut(self, call)
+    }
+    fn visit_field_access_mut(&mut self, access: &mut FieldAccess) {
+        walk_field_access_mut(self, access)
+    }
+    fn visit_yield_mut(&mut self, yield_expr: &mut Yield) {
+        walk_yield_mut(self, yield_expr)
+    }
+    fn visit_dialogue_mut(&mut self, dialogue: &mut Dialogue) {
+        walk_dialogue_mut(self, dialogue)
+    }
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+}
+
+pub fn walk_stmts_mut<V: MutVisitor + ?Sized>(visitor: &mut V, stmts: &mut [Stmt]) {
+    for stmt in stmts.iter_mut() {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: MutVisitor + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::STATEMENT_ASSIGNMENT(assignment) => visitor.visit_assignment_mut(assignment),
+        Stmt::STATEMENT_FUNCTION_DECLARATION(decl) => visitor.visit_fn_declaration_mut(decl),
+        Stmt::STATEMENT_RETURN(ret) => visitor.visit_return_mut(ret),
+        Stmt::STATEMENT_FUNCTION_CALL(fncall) => visitor.visit_fn_call_mut(fncall),
+        Stmt::STATEMENT_IMPORT(import) => visitor.visit_import_mut(import),
+        Stmt::STATEMENT_EXPORT(inner) => visitor.visit_stmt_mut(inner),
+        Stmt::STATEMENT_YIELD(yield_stmt) => visitor.visit_yield_mut(yield_stmt),
+        Stmt::STATEMENT_DIALOGUE(dialogue) => visitor.visit_dialogue_mut(dialogue),
+        Stmt::SCOPE(scope) => visitor.visit_scope_mut(scope),
+        Stmt::STATEMENT_ZERO_EFFECT(Some(ident)) => visitor.visit_ident_mut(ident),
+        Stmt::STATEMENT_ZERO_EFFECT(None) => {},
+    }
+}
+
+pub fn walk_scope_mut<V: MutVisitor + ?Sized>(visitor: &mut V, scope: &mut Scope) {
+    visitor.visit_stmts_mut(&mut scope.stmts);
+}
+
+pub fn walk_assignment_mut<V: MutVisitor + ?Sized>(visitor: &mut V, assignment: &mut Assignment) {
+    visitor.visit_ident_mut(&mut assignment.ident);
+    visitor.visit_expr_mut(&mut assignment.expr);
+}
+
+pub fn walk_fn_declaration_mut<V: MutVisitor + ?Sized>(visitor: &mut V, decl: &mut FnDeclaration) {
+    visitor.visit_ident_mut(&mut decl.ident);
+    for param in decl.params.iter_mut() {
+        visitor.visit_parameter_mut(param);
+    }
+    visitor.visit_scope_mut(&mut decl.body);
+}
+
+pub fn walk_parameter_mut<V: MutVisitor + ?Sized>(visitor: &mut V, param: &mut Parameter) {
+    visitor.visit_ident_mut(&mut param.ident);
+}
+
+pub fn walk_return_mut<V: MutVisitor + ?Sized>(visitor: &mut V, ret: &mut ReturnStmt) {
+    visitor.visit_expr_mut(&mut ret.expr);
+}
+
+pub fn walk_import_mut<V: MutVisitor + ?Sized>(visitor: &mut V, import: &mut Import) {
+    for segment in import.path.iter_mut() {
+        visitor.visit_ident_mut(segment);
+    }
+    if let Some(alias) = &mut import.alias {
+        visitor.visit_ident_mut(alias);
+    }
+}
+
+pub fn walk_expr_mut<V: MutVisitor + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::ATOM(atom) => visitor.visit_atom_mut(atom),
+        Expr::GROUPED_EXPR(inner) => visitor.visit_expr_mut(inner),
+        Expr::BINARY_EXPR { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        },
+        Expr::UNARY_EXPR { expr, .. } => visitor.visit_expr_mut(expr),
+        Expr::SCOPE(scope) => visitor.visit_scope_mut(scope),
+        Expr::FUNCTION_CALL(fncall) => visitor.visit_fn_call_mut(fncall),
+        Expr::METHOD_CALL(call) => visitor.visit_method_call_mut(call),
+        Expr::FIELD_ACCESS(access) => visitor.visit_field_access_mut(access),
+        Expr::YIELD(yield_expr) => visitor.visit_yield_mut(yield_expr),
+    }
+}
+
+pub fn walk_atom_mut<V: MutVisitor + ?Sized>(visitor: &mut V, atom: &mut Atom) {
+    match atom {
+        Atom::IDENTIFIER(ident) => visitor.visit_ident_mut(ident),
+        Atom::LITERAL_FLOAT(_) | Atom::LITERAL_INT(_) | Atom::LITERAL_DICE(_) | Atom::LITERAL_STRING(_) => {},
+    }
+}
+
+pub fn walk_fn_call_mut<V: MutVisitor + ?Sized>(visitor: &mut V, fncall: &mut FnCall) {
+    visitor.visit_ident_mut(&mut fncall.ident);
+    for arg in fncall.args.iter_mut() {
+        visitor.visit_expr_mut(arg);
+    }
+}
+
+pub fn walk_method_call_mut<V: MutVisitor + ?Sized>(visitor: &mut V, call: &mut MethodCall) {
+    visitor.visit_expr_mut(&mut call.base);
+    visitor.visit_fn_call_mut(&mut call.call);
+}
+
+pub fn walk_field_access_mut<V: MutVisitor + ?Sized>(visitor: &mut V, access: &mut FieldAccess) {
+    visitor.visit_expr_mut(&mut access.base);
+    visitor.visit_ident_mut(&mut access.access);
+}
+
+pub fn walk_yield_mut<V: MutVisitor + ?Sized>(visitor: &mut V, yield_expr: &mut Yield) {
+    if let Some(expr) = &mut yield_expr.expr {
+        visitor.visit_expr_mut(expr);
+    }
+}
+
+pub fn walk_dialogue_mut<V: MutVisitor + ?Sized>(visitor: &mut V, dialogue: &mut Dialogue) {
+    for node in dialogue.nodes.iter_mut() {
+        for line in node.lines.iter_mut() {
+            walk_text_mut(visitor, &mut line.text);
+        }
+        if let NodeExit::CHOICES(choices) = &mut node.exit {
+            for choice in choices.iter_mut() {
+                walk_text_mut(visitor, &mut choice.text);
+                if let Some(condition) = &mut choice.condition {
+                    visitor.visit_expr_mut(condition);
+                }
+            }
+        }
+    }
+}
+
+fn walk_text_mut<V: MutVisitor + ?Sized>(visitor: &mut V, text: &mut Text) {
+    for part in text.parts.iter_mut() {
+        if let TextPart::EXPR(expr) = part {
+            visitor.visit_expr_mut(expr);
+        }
+    }
+}
@@ -0,0 +1,624 @@
+#![allow(non_camel_case_types)]
+#![allow(clippy::empty_line_after_doc_comments)]
+
+use std::collections::HashMap;
+
+use crate::bytecode::*;
+use crate::compiler::{compile, recompile};
+use crate::events::Events;
+use crate::locale::{line_text, placeholder_count, Catalogue};
+use crate::module::{Module, ModuleLoader};
+use crate::optimise::optimise_modules;
+use crate::random::{Dice, Rng};
+use crate::reload::{carry_over, prepare_reload, resubscribe, top_level_failed, Reload};
+use crate::runtime::*;
+use crate::save::{SaveState, SavedCoroutine, SavedFrame, SavedModule};
+use crate::value::Value;
+
+///VM section
+//this here is the BYTECODE VM. it runs what compiler.rs spits out on a single value stack. every
+//rite call gets a frame that remembers where its locals start on that stack. anything a script can
+//observe has to match the reference interpreter in interpreter.rs.
+//
+//coroutines (see runtime.rs) each get a stack and frames of their own. resuming one swaps them in
+//for the vm's, runs until a YIELD or the coroutine's rite returns, then swaps them back out.
+//
+//the vm doesn't know what a module exports, bytecode has no room for it. it only ever runs code
+//the loader or a reload checked (see check_visibility in module.rs), and a bytecode file is taken
+//on trust. the interpreter checks again at runtime because a session can feed it anything.
+
+struct CallFrame {
+    function: usize,
+    ip: usize,
+    base: usize, //where slot 0 of this frame lives on the stack
+}
+
+//a coroutine that isn't running right now, with everything it was running on put aside
+#[derive(Default)]
+struct Coroutine {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    call_depth: usize,
+    asleep: bool, //sleeping in a yield, rather than not started yet or out of fuel
+}
+
+//why run() stopped
+#[allow(clippy::upper_case_acronyms)]
+enum Exit {
+    RETURNED(Value),
+    YIELDED(Value),
+    OUT_OF_FUEL, //in a coroutine, with the frame still on the step it couldn't pay for
+}
+
+#[derive(Default)]
+pub struct Vm {
+    pub natives: Natives,
+    pub program: Program,
+    globals: Vec<Vec<Option<Value>>>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    call_depth: usize,
+    limits: Limits,
+    fuel: Fuel,
+    unoptimised: bool,
+    coroutines: HashMap<usize, Coroutine>, //the ones still alive, by id
+    next_coroutine: usize,
+    in_coroutine: bool,
+    locale: Option<Catalogue>,
+    events: Events,
+    sources: HashMap<String, Module>, //what was compiled, for the modules that were loaded from source
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::default()
+    }
+
+    //adds already compiled modules and runs their top level code
+    pub fn load_program(&mut self, program: Program) -> Result<(), String> {
+        let first = self.program.modules.len();
+        self.program = program;
+        self.sync_globals();
+        for module in first..self.program.modules.len() {
+            self.run_function(self.program.modules[module].init, Vec::new())?;
+        }
+        for function in self.program.functions.iter().filter(|function| function.module >= first) {
+            if let Some(event) = &function.event {
+                self.events.subscribe(event, &self.program.modules[function.module].id, &function.name);
+            }
+        }
+        Ok(())
+    }
+
+    //compiling can hand out new global slots to modules that are already running
+    fn sync_globals(&mut self) {
+        self.globals.resize_with(self.program.modules.len(), Vec::new);
+        for (slots, module) in self.globals.iter_mut().zip(&self.program.modules) {
+            slots.resize(module.globals.len(), None);
+        }
+    }
+
+    //calls a function from the outside. whatever happens, the vm is left the way it was found.
+    fn run_function(&mut self, function: usize, args: Vec<Value>) -> Result<Value, String> {
+        let exit_depth = self.frames.len();
+        let stack_height = self.stack.len();
+        let call_depth = self.call_depth;
+        self.fuel.fill(&self.limits);
+
+        let result = self.burn_for_call(function)
+            .and_then(|_| self.push_frame(function, args))
+            .and_then(|_| self.run(exit_depth))
+            .and_then(|exit| match exit {
+                Exit::RETURNED(value) => Ok(value),
+                Exit::YIELDED(_) => Err(yield_outside_coroutine()),
+                Exit::OUT_OF_FUEL => Err(out_of_fuel(self.limits.fuel.unwrap_or(0))),
+            });
+        if result.is_err() {
+            self.frames.truncate(exit_depth);
+            self.stack.truncate(stack_height);
+            self.call_depth = call_depth;
+        }
+        result
+    }
+
+    //puts the vm's own stack aside and runs on the coroutine's instead, or the other way round
+    fn swap_stacks(&mut self, coroutine: &mut Coroutine) {
+        std::mem::swap(&mut self.stack, &mut coroutine.stack);
+        std::mem::swap(&mut self.frames, &mut coroutine.frames);
+        std::mem::swap(&mut self.call_depth, &mut coroutine.call_depth);
+    }
+
+    fn find_function(&self, module: &str, rite: &str) -> Result<usize, String> {
+        let index = self.program.find_module(module).ok_or_else(|| format!("No module named {}!", module))?;
+        self.program.find_rite(index, rite).ok_or_else(|| no_such_rite(rite))
+    }
+
+    //a coroutine out of a save. the module versions match, but the save itself could still be
+    //made up, so everything it points at gets checked.
+    fn restore_coroutine(&self, saved: &SavedCoroutine) -> Result<Coroutine, String> {
+        let mut frames = Vec::new();
+        let mut base = 0;
+        for frame in &saved.frames {
+            let module = self.program.find_module(&frame.module).ok_or_else(|| format!("No module named {}!", frame.module))?;
+            let function = self.program.find_rite(module, &frame.rite).ok_or_else(|| no_such_rite(&frame.rite))?;
+            if !is_instruction_start(&self.program.functions[function].chunk.code, frame.ip) {
+                return Err(format!("Rite {} has no instruction at {}!", frame.rite, frame.ip));
+            }
+            if frame.base < base || frame.base > saved.stack.len() {
+                return Err(format!("Rite {} has its locals at {}, off its stack!", frame.rite, frame.base));
+            }
+            base = frame.base;
+            frames.push(CallFrame { function, ip: frame.ip, base: frame.base });
+        }
+        if frames.is_empty() {
+            return Err("It has no rites on its stack!".to_string());
+        }
+        Ok(Coroutine { stack: saved.stack.clone(), call_depth: frames.len(), frames, asleep: saved.asleep })
+    }
+
+    //calling a rite is a step, running top level code isn't
+    fn burn_for_call(&mut self, function: usize) -> Result<(), String> {
+        match self.program.functions[function].is_init {
+            true => Ok(()),
+            false => self.fuel.burn(&self.limits),
+        }
+    }
+
+    //the fuel for the call has been burnt already, see burn_for_call
+    fn push_frame(&mut self, function: usize, args: Vec<Value>) -> Result<(), String> {
+        let callee = &self.program.functions[function];
+        let args = if callee.is_init {
+            args
+        } else {
+            if self.call_depth >= self.limits.max_call_depth {
+                return Err(stack_overflow(&self.limits));
+            }
+            let args = bind_arguments(&callee.name, &callee.params, args)?;
+            self.call_depth += 1;
+            args
+        };
+        let base = self.stack.len();
+        self.stack.extend(args);
+        self.frames.push(CallFrame { function, ip: 0, base });
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or(Value::VOID)
+    }
+
+    fn pop_args(&mut self, argc: usize) -> Vec<Value> {
+        let at = self.stack.len().saturating_sub(argc);
+        self.stack.split_off(at)
+    }
+
+    fn constant_name(&self, function: usize, constant: u16) -> String {
+        match &self.program.functions[function].chunk.constants[constant as usize] {
+            Value::STRING(name) => name.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    //runs until the frame count drops back down to exit_depth, then hands back the last return
+    //value. inside a coroutine it stops at a yield too.
+    fn run(&mut self, exit_depth: usize) -> Result<Exit, String> {
+        loop {
+            let frame = match self.frames.last() {
+                Some(frame) => frame,
+                None => return Ok(Exit::RETURNED(Value::VOID)),
+            };
+            let (function, ip, base) = (frame.function, frame.ip, frame.base);
+            let (opcode, operands, next) = decode_at(&self.program.functions[function].chunk.code, ip)?;
+            let Operands { byte: first_u8, first: first_u16, second: second_u16 } = operands;
+
+            //steps are paid for before anything happens, so a coroutine out of fuel can stop on one
+            //and do the whole thing when it's resumed
+            let paid = match opcode {
+                OpCode::UNARY | OpCode::BINARY | OpCode::CALL_NATIVE | OpCode::ROLL => self.fuel.burn(&self.limits),
+                OpCode::CALL => self.burn_for_call(first_u16 as usize),
+                _ => Ok(()),
+            };
+            match paid {
+                Err(_) if self.in_coroutine => return Ok(Exit::OUT_OF_FUEL),
+                paid => paid?,
+            }
+
+            let mut jump_to = None;
+            match opcode {
+                OpCode::CONSTANT => {
+                    let value = self.program.functions[function].chunk.constants[first_u16 as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::VOID => self.stack.push(Value::VOID),
+                OpCode::POP => { self.pop(); },
+                OpCode::POP_N => {
+                    let count = first_u16 as usize;
+                    self.stack.truncate(self.stack.len().saturating_sub(count));
+                },
+                OpCode::SLIDE => {
+                    let count = first_u16 as usize;
+                    let top = self.pop();
+                    self.stack.truncate(self.stack.len().saturating_sub(count));
+                    self.stack.push(top);
+                },
+                OpCode::GET_LOCAL => {
+                    let value = self.stack.get(base + first_u16 as usize).cloned().ok_or_else(bad_slot)?;
+                    self.stack.push(value);
+                },
+                OpCode::SET_LOCAL => {
+                    let slot = base + first_u16 as usize;
+                    let value = self.pop();
+                    *self.stack.get_mut(slot).ok_or_else(bad_slot)? = value;
+                },
+                OpCode::GET_GLOBAL | OpCode::GET_MODULE_GLOBAL => {
+                    let (module, slot) = match opcode {
+                        OpCode::GET_GLOBAL => (self.program.functions[function].module, first_u16 as usize),
+                        _ => (first_u16 as usize, second_u16 as usize),
+                    };
+                    match &self.globals[module][slot] {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            let name = &self.program.modules[module].globals[slot];
+                            return Err(not_defined(&match opcode {
+                                OpCode::GET_GLOBAL => name.clone(),
+                                _ => format!("{}::{}", self.program.modules[module].id, name),
+                            }));
+                        },
+                    }
+                },
+                OpCode::SET_GLOBAL => {
+                    let module = self.program.functions[function].module;
+                    let slot = first_u16 as usize;
+                    let value = self.pop();
+                    self.globals[module][slot] = Some(value);
+                },
+                OpCode::CONFORM => {
+                    let type_t = TYPES[first_u8 as usize].clone();
+                    let name = self.constant_name(function, first_u16);
+                    let value = self.pop();
+                    self.stack.push(conform_assignment(&name, &type_t, value)?);
+                },
+                OpCode::UNARY => {
+                    let opcode = MONOPS[first_u8 as usize];
+                    let value = self.pop();
+                    self.stack.push(opcode.apply(&value)?);
+                },
+                OpCode::BINARY => {
+                    let opcode = BINOPS[first_u8 as usize];
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(check_rune(opcode.apply(&left, &right)?, &self.limits)?);
+                },
+                OpCode::CALL => {
+                    let callee = first_u16 as usize;
+                    let argc = first_u8 as usize;
+                    let args = self.pop_args(argc);
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.ip = next;
+                    }
+                    self.push_frame(callee, args)?;
+                    continue;
+                },
+                OpCode::CALL_NATIVE => {
+                    let name = self.constant_name(function, first_u16);
+                    let argc = first_u8 as usize;
+                    let args = self.pop_args(argc);
+                    let value = check_rune(self.natives.call(&name, &args)?, &self.limits)?;
+                    self.stack.push(value);
+                },
+                OpCode::ROLL => {
+                    let dice = Dice::parse(&self.constant_name(function, first_u16))?;
+                    self.stack.push(Value::INT(dice.roll(&mut self.natives.rng)));
+                },
+                OpCode::FIELD => {
+                    let name = self.constant_name(function, first_u16);
+                    let value = self.pop();
+                    return Err(no_such_field(&name, &value));
+                },
+                OpCode::JUMP => jump_to = Some(first_u16 as usize),
+                OpCode::JUMP_IF_NOT => {
+                    if !self.pop().is_truthy() {
+                        jump_to = Some(first_u16 as usize);
+                    }
+                },
+                OpCode::TEXT => {
+                    let (node, line) = (self.constant_name(function, first_u16), self.constant_name(function, second_u16));
+                    let values = self.pop_args(placeholder_count(&line)?);
+                    let text = Value::STRING(line_text(self.locale.as_ref(), &node, &line, &values)?);
+                    self.stack.push(if values.is_empty() { text } else { check_rune(text, &self.limits)? });
+                },
+                OpCode::BRANCH => {
+                    if self.stack.last() == Some(&Value::INT(first_u16 as i64)) {
+                        self.pop();
+                        jump_to = Some(second_u16 as usize);
+                    }
+                },
+                OpCode::PICK => {
+                    let picked = self.pop();
+                    let count = first_u16 as usize;
+                    let first = self.stack.len().checked_sub(count).ok_or_else(bad_slot)?;
+                    let offered = match picked {
+                        Value::INT(index) if index >= 0 && (index as usize) < count => {
+                            self.stack[first + index as usize].is_truthy()
+                        },
+                        _ => return Err(no_such_choice(&picked, count)),
+                    };
+                    if !offered {
+                        return Err(choice_not_offered(&picked));
+                    }
+                    self.stack.truncate(first);
+                    self.stack.push(picked);
+                },
+                OpCode::RETURN => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().unwrap_or(CallFrame { function, ip, base });
+                    let callee = &self.program.functions[frame.function];
+                    let value = match callee.is_init {
+                        true => value,
+                        false => conform_return(&callee.name, &callee.return_type, value)?,
+                    };
+                    if !callee.is_init {
+                        self.call_depth -= 1;
+                    }
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() <= exit_depth {
+                        return Ok(Exit::RETURNED(value));
+                    }
+                    self.stack.push(value);
+                    continue;
+                },
+                //the value resumed with gets pushed in place of the one yielded, see resume
+                OpCode::YIELD => {
+                    if !self.in_coroutine {
+                        return Err(yield_outside_coroutine());
+                    }
+                    let value = self.pop();
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.ip = next;
+                    }
+                    return Ok(Exit::YIELDED(value));
+                },
+            }
+
+            if let Some(frame) = self.frames.last_mut() {
+                frame.ip = jump_to.unwrap_or(next);
+            }
+        }
+    }
+}
+
+fn is_instruction_start(code: &[u8], ip: usize) -> bool {
+    let mut offset = 0;
+    while offset < ip {
+        match decode_at(code, offset) {
+            Ok((_, _, next)) => offset = next,
+            Err(_) => return false,
+        }
+    }
+    offset == ip && ip < code.len()
+}
+
+//bytecode straight from the compiler never does this, but a hand made file might
+fn bad_slot() -> String {
+    "Corrupt bytecode! A local slot points past the stack.".to_string()
+}
+
+impl ScriptRuntime for Vm {
+    fn register_native(&mut self, name: &str, native: NativeFn) {
+        self.natives.register(name, native);
+    }
+
+    fn load(&mut self, loader: ModuleLoader) -> Result<(), String> {
+        let mut modules = loader.into_modules();
+        if !self.unoptimised {
+            optimise_modules(&mut modules)?;
+        }
+        let mut program = self.program.clone();
+        compile(&mut program, &modules)?;
+        self.load_program(program)?;
+        self.sources.extend(modules.into_iter().map(|module| (module.id.clone(), module)));
+        Ok(())
+    }
+
+    fn call(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<Value, String> {
+        let function = self.find_function(module, rite)?;
+        self.run_function(function, args.to_vec())
+    }
+
+    fn global(&self, module: &str, name: &str) -> Option<Value> {
+        let index = self.program.find_module(module)?;
+        let slot = self.program.find_global(index, name)?;
+        self.globals[index][slot].clone()
+    }
+
+    fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    fn fuel_left(&self) -> Option<u64> {
+        self.fuel.left()
+    }
+
+    fn set_optimising(&mut self, optimising: bool) {
+        self.unoptimised = !optimising;
+    }
+
+    //the arguments are bound (and checked) right away, so a bad spawn fails here and not on the
+    //first resume
+    fn spawn(&mut self, module: &str, rite: &str, args: &[Value]) -> Result<usize, String> {
+        let function = self.find_function(module, rite)?;
+        let mut coroutine = Coroutine::default();
+        self.fuel.fill(&self.limits);
+        self.swap_stacks(&mut coroutine);
+        let pushed = self.burn_for_call(function).and_then(|_| self.push_frame(function, args.to_vec()));
+        self.swap_stacks(&mut coroutine);
+        pushed?;
+        let id = self.next_coroutine;
+        self.next_coroutine += 1;
+        self.coroutines.insert(id, coroutine);
+        Ok(id)
+    }
+
+    fn resume(&mut self, coroutine: usize, value: Value) -> Result<Resumed, String> {
+        let mut running = self.coroutines.remove(&coroutine).ok_or_else(|| no_such_coroutine(coroutine))?;
+        self.swap_stacks(&mut running);
+        if running.asleep {
+            self.stack.push(value);
+        }
+        self.fuel.fill(&self.limits);
+        self.in_coroutine = true;
+        let exit = self.run(0);
+        self.in_coroutine = false;
+        self.swap_stacks(&mut running);
+
+        //on an error it just never gets put back
+        match exit? {
+            Exit::YIELDED(value) => {
+                running.asleep = true;
+                self.coroutines.insert(coroutine, running);
+                Ok(Resumed::YIELDED(value))
+            },
+            Exit::OUT_OF_FUEL => {
+                running.asleep = false;
+                self.coroutines.insert(coroutine, running);
+                Ok(Resumed::OUT_OF_FUEL)
+            },
+            Exit::RETURNED(value) => Ok(Resumed::FINISHED(value)),
+        }
+    }
+
+    fn cancel(&mut self, coroutine: usize) -> bool {
+        self.coroutines.remove(&coroutine).is_some()
+    }
+
+    fn save_state(&self) -> SaveState {
+        let modules = self.program.modules.iter().zip(&self.globals).map(|(module, slots)| {
+            let globals = module.globals.iter().zip(slots)
+                .filter_map(|(name, value)| Some((name.clone(), value.clone()?)))
+                .collect();
+            SavedModule { id: module.id.clone(), version: module.version, globals }
+        }).collect();
+
+        let mut ids: Vec<usize> = self.coroutines.keys().copied().collect();
+        ids.sort();
+        let coroutines = ids.into_iter().map(|id| {
+            let coroutine = &self.coroutines[&id];
+            let frames = coroutine.frames.iter().map(|frame| {
+                let function = &self.program.functions[frame.function];
+                SavedFrame {
+                    module: self.program.modules[function.module].id.clone(),
+                    rite: function.name.clone(),
+                    ip: frame.ip,
+                    base: frame.base,
+                }
+            }).collect();
+            let stack = coroutine.stack.clone();
+            SavedCoroutine { id, asleep: coroutine.asleep, stack, frames, replay: None }
+        }).collect();
+
+        SaveState { rng: self.natives.rng, modules, coroutines }
+    }
+
+    fn restore_state(&mut self, state: &SaveState) -> Result<(), String> {
+        state.check_versions(|id| self.program.find_module(id).map(|index| self.program.modules[index].version))?;
+
+        let mut globals = self.globals.clone();
+        for module in &state.modules {
+            let index = self.program.find_module(&module.id).ok_or_else(|| format!("No module named {}!", module.id))?;
+            globals[index].iter_mut().for_each(|slot| *slot = None);
+            for (name, value) in &module.globals {
+                let slot = self.program.find_global(index, name).ok_or_else(|| not_a_global(&module.id, name))?;
+                globals[index][slot] = Some(value.clone());
+            }
+        }
+
+        let mut coroutines = HashMap::new();
+        for saved in &state.coroutines {
+            if saved.replay.is_some() {
+                return Err(saved_by_the_other_backend(saved.id, Backend::INTERPRETER));
+            }
+            let coroutine = self.restore_coroutine(saved)
+                .map_err(|err| format!("Coroutine {} in the save is broken! {}", saved.id, err))?;
+            if coroutines.insert(saved.id, coroutine).is_some() {
+                return Err(format!("The save has coroutine {} twice!", saved.id));
+            }
+        }
+
+        self.globals = globals;
+        self.next_coroutine = self.next_coroutine.max(coroutines.keys().max().map_or(0, |id| id + 1));
+        self.coroutines = coroutines;
+        self.natives.rng = state.rng;
+        Ok(())
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        &mut self.natives.rng
+    }
+
+    fn set_locale(&mut self, locale: Option<Catalogue>) {
+        self.locale = locale;
+    }
+
+    //the top level code runs on the new program with the module's globals emptied, so what it sets
+    //can be told apart from what was there before
+    fn reload(&mut self, module: &str, source: &str) -> Result<Reload, String> {
+        let index = self.program.find_module(module).ok_or_else(|| format!("No module named {}!", module))?;
+        let old = self.sources.get(module)
+            .ok_or_else(|| format!("Module {} was loaded as bytecode, there's no source to reload!", module))?;
+        let others: Vec<&Module> = self.sources.values().filter(|other| other.id != module).collect();
+        let (new, mut reload) = prepare_reload(old, source, &others, !self.unoptimised)?;
+        let mut program = self.program.clone();
+        recompile(&mut program, index, &new)?;
+
+        let old_program = std::mem::replace(&mut self.program, program);
+        let old_globals = std::mem::take(&mut self.globals[index]);
+        self.sync_globals();
+        if let Err(err) = self.run_function(self.program.modules[index].init, Vec::new()) {
+            self.program = old_program;
+            self.globals[index] = old_globals;
+            return Err(top_level_failed(module, err));
+        }
+
+        let mut fresh = std::mem::take(&mut self.globals[index]);
+        let mut slots = vec![None; fresh.len()];
+        for name in global_names(&new.stmts) {
+            let Some(slot) = self.program.find_global(index, &name) else { continue };
+            let value = fresh[slot].take();
+            slots[slot] = match old_globals.get(slot).cloned().flatten() {
+                Some(old) => Some(carry_over(&name, old, value, &mut reload)),
+                None => value,
+            };
+        }
+        self.globals[index] = slots;
+
+        //a coroutine asleep in a rite whose code changed wakes up in a copy of the code it was in
+        let mut copies: HashMap<usize, usize> = HashMap::new();
+        for frame in self.coroutines.values_mut().flat_map(|coroutine| coroutine.frames.iter_mut()) {
+            let before = &old_program.functions[frame.function];
+            let now = &self.program.functions[frame.function];
+            let same = before.chunk.code == now.chunk.code && before.chunk.constants == now.chunk.constants;
+            if before.module != index || same {
+                continue;
+            }
+            frame.function = *copies.entry(frame.function).or_insert_with(|| {
+                let mut copy = before.clone();
+                copy.name = format!("{} (before the reload)", before.name);
+                copy.event = None;
+                self.program.functions.push(copy);
+                self.program.functions.len() - 1
+            });
+        }
+
+        resubscribe(&mut self.events, &self.sources[module], &new, &mut reload);
+        self.sources.insert(module.to_string(), new);
+        Ok(reload)
+    }
+
+    fn events(&mut self) -> &mut Events {
+        &mut self.events
+    }
+
+    fn has_rite(&self, module: &str, rite: &str) -> bool {
+        self.find_function(module, rite).is_ok()
+    }
+}
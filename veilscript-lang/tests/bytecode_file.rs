@@ -0,0 +1,82 @@
+use std::process::Command;
+
+use veilscript_lang::bytecode::Program;
+use veilscript_lang::bytecode_file::{read_program, write_program};
+use veilscript_lang::compiler::compile;
+use veilscript_lang::module::ModuleLoader;
+use veilscript_lang::runtime::ScriptRuntime;
+use veilscript_lang::source::MemoryLoader;
+use veilscript_lang::value::Value;
+use veilscript_lang::vm::Vm;
+
+const LIB: &str = "
+pub rite twice(x: int) -> int {
+    ret x * 2;
+}
+pub base = 20;
+";
+
+const MAIN: &str = "
+summon lib;
+gold: int = lib::twice(lib::base);
+rite total() -> int {
+    ret gold + 3d1 + 2 ** 3;
+}
+rite greet(name: rune) -> rune {
+    ret \"hi \" + name;
+}
+";
+
+fn loader() -> ModuleLoader {
+    let mut files = MemoryLoader::new();
+    files.add("lib.veil", LIB);
+    let mut loader = ModuleLoader::from_loader(files);
+    loader.load_source("main", MAIN).unwrap();
+    loader
+}
+
+#[test]
+fn written_program_runs_like_the_script() {
+    let mut program = Program::default();
+    compile(&mut program, &loader().into_modules()).unwrap();
+    let bytes = write_program(&program).unwrap();
+
+    let mut from_file = Vm::new();
+    from_file.load_program(read_program(&bytes).unwrap()).unwrap();
+    let mut from_source = Vm::new();
+    from_source.load(loader()).unwrap();
+
+    for vm in [&mut from_file, &mut from_source] {
+        assert_eq!(vm.call("main", "total", &[]), Ok(Value::INT(51)));
+        let name = Value::STRING("you".to_owned());
+        assert_eq!(vm.call("main", "greet", &[name]), Ok(Value::STRING("hi you".to_owned())));
+        assert_eq!(vm.global("main", "gold"), Some(Value::INT(40)));
+    }
+    assert_eq!(write_program(&read_program(&bytes).unwrap()).unwrap(), bytes);
+}
+
+#[test]
+fn broken_file_is_refused() {
+    let mut program = Program::default();
+    compile(&mut program, &loader().into_modules()).unwrap();
+    let bytes = write_program(&program).unwrap();
+    assert!(read_program(&bytes[..bytes.len() - 1]).is_err());
+    assert!(read_program(b"VLBX").is_err());
+}
+
+#[test]
+fn veil_compile_then_run() {
+    let dir = std::env::temp_dir().join(format!("veil-compile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.veil"), LIB).unwrap();
+    std::fs::write(dir.join("main.veil"), MAIN).unwrap();
+    let veil = env!("CARGO_BIN_EXE_veil");
+
+    let compiled = Command::new(veil).arg("compile").arg(dir.join("main.veil")).output().unwrap();
+    assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+    let mut run = Command::new(veil);
+    let ran = run.arg("run").arg(dir.join("main.vlbc")).args(["--call", "total"]).output().unwrap();
+    assert!(ran.status.success(), "{}", String::from_utf8_lossy(&ran.stderr));
+    assert_eq!(String::from_utf8_lossy(&ran.stdout), "51\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
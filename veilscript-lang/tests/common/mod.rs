@@ -0,0 +1,40 @@
+//what most tests start from: modules out of memory, loaded into a runtime of either backend.
+//every test file only uses a bit of this.
+#![allow(dead_code)]
+
+use veilscript_lang::module::ModuleLoader;
+use veilscript_lang::runtime::{new_runtime, Backend, ScriptRuntime};
+use veilscript_lang::source::MemoryLoader;
+use veilscript_lang::value::Value;
+
+pub const BACKENDS: [Backend; 2] = [Backend::INTERPRETER, Backend::VM];
+
+//`source` as the module `id`, with `files` (name, text) around for it to summon
+pub fn loader(id: &str, source: &str, files: &[(&str, &str)]) -> ModuleLoader {
+    let mut memory = MemoryLoader::new();
+    for (name, text) in files {
+        memory.add(name, text);
+    }
+    let mut loader = ModuleLoader::from_loader(memory);
+    loader.load_source(id, source).unwrap();
+    loader
+}
+
+//a runtime with everything `loader` summoned loaded. `set_up` gets it first, for whatever has to be
+//in place before the top level code runs (limits, the optimiser).
+pub fn load_into(
+    backend: Backend, loader: ModuleLoader, set_up: impl FnOnce(&mut dyn ScriptRuntime),
+) -> Box<dyn ScriptRuntime> {
+    let mut runtime = new_runtime(backend);
+    set_up(runtime.as_mut());
+    runtime.load(loader).unwrap();
+    runtime
+}
+
+pub fn runtime(backend: Backend, id: &str, source: &str) -> Box<dyn ScriptRuntime> {
+    load_into(backend, loader(id, source, &[]), |_| {})
+}
+
+pub fn rune(text: &str) -> Value {
+    Value::STRING(text.to_string())
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::random::Rng;
+use veilscript_lang::runtime::{Backend, Resumed, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+//a talk that goes to sleep two rites deep, twice, with the game meddling with its globals in
+//between. every effect has to happen exactly once, however the backend wakes it back up.
+const TALK: &str = r#"
+said: rune = "";
+rounds: int = 0;
+rite note(line: rune) { said = said + line + ";"; }
+rite ask(question: rune) -> int {
+    note(question);
+    answer = yield question;
+    ret answer * 2;
+}
+rite talk(start: int) -> int {
+    rounds = rounds + 1;
+    first = ask("who");
+    heard(first);
+    second = ask("why");
+    ret start + first + second + rounds;
+}
+rite meddle() { rounds = rounds + 10; }
+rite rolls() -> int {
+    rolled = 1d1000;
+    got = yield rolled;
+    ret rolled + got + roll(1, 1000);
+}
+rite blurts() -> int { ret 1 + (yield 2); }
+rite breaks() -> int { yield 1; ret 1 / 0; }
+"#;
+
+fn talking(backend: Backend, heard: &Rc<Cell<i64>>) -> Box<dyn ScriptRuntime> {
+    let heard = Rc::clone(heard);
+    load_into(backend, loader("talk", TALK, &[]), move |runtime| {
+        runtime.register_native("heard", Box::new(move |_| {
+            heard.set(heard.get() + 1);
+            Ok(Value::INT(heard.get()))
+        }));
+    })
+}
+
+#[test]
+fn a_coroutine_yields_and_is_resumed_with_a_value_until_it_finishes() {
+    for backend in BACKENDS {
+        let heard = Rc::new(Cell::new(0));
+        let mut runtime = talking(backend, &heard);
+        let talk = runtime.spawn("talk", "talk", &[Value::INT(1)]).unwrap();
+        assert_eq!(runtime.global("talk", "rounds"), Some(Value::INT(0)), "nothing runs at spawn");
+
+        assert_eq!(runtime.resume(talk, Value::VOID), Ok(Resumed::YIELDED(rune("who"))));
+        runtime.call("talk", "meddle", &[]).unwrap();
+        assert_eq!(runtime.resume(talk, Value::INT(3)), Ok(Resumed::YIELDED(rune("why"))));
+        runtime.call("talk", "meddle", &[]).unwrap();
+        assert_eq!(runtime.resume(talk, Value::INT(5)), Ok(Resumed::FINISHED(Value::INT(38))));
+
+        assert_eq!(runtime.global("talk", "said"), Some(rune("who;why;")), "on {:?}", backend);
+        assert_eq!(runtime.global("talk", "rounds"), Some(Value::INT(21)));
+        assert_eq!(heard.get(), 1, "the native is only called once on {:?}", backend);
+        assert!(runtime.resume(talk, Value::VOID).is_err(), "a finished coroutine is gone");
+    }
+}
+
+#[test]
+fn dice_in_a_coroutine_roll_once() {
+    let [interpreter, vm] = BACKENDS.map(|backend| {
+        let mut runtime = talking(backend, &Rc::new(Cell::new(0)));
+        *runtime.rng() = Rng::new(7);
+        let rolls = runtime.spawn("talk", "rolls", &[]).unwrap();
+        let resumed = [runtime.resume(rolls, Value::VOID), runtime.resume(rolls, Value::INT(1))];
+        (resumed, *runtime.rng())
+    });
+    assert_eq!(interpreter, vm);
+
+    let mut rng = Rng::new(7);
+    let rolled = Value::INT(rng.int_between(1, 1000));
+    assert_eq!(interpreter.0[0], Ok(Resumed::YIELDED(rolled)));
+    rng.int_between(1, 1000);
+    assert_eq!(interpreter.1, rng, "two rolls and no more");
+}
+
+#[test]
+fn yielding_outside_a_coroutine_or_failing_in_one_is_an_error() {
+    for backend in BACKENDS {
+        let mut runtime = talking(backend, &Rc::new(Cell::new(0)));
+        let err = runtime.call("talk", "blurts", &[]).unwrap_err();
+        assert_eq!(err, "Can't yield here! Only a rite running as a coroutine can yield.");
+
+        let blurts = runtime.spawn("talk", "blurts", &[]).unwrap();
+        assert_eq!(runtime.resume(blurts, Value::VOID), Ok(Resumed::YIELDED(Value::INT(2))));
+        assert_eq!(runtime.resume(blurts, Value::INT(4)), Ok(Resumed::FINISHED(Value::INT(5))));
+
+        let breaks = runtime.spawn("talk", "breaks", &[]).unwrap();
+        runtime.resume(breaks, Value::VOID).unwrap();
+        assert_eq!(runtime.resume(breaks, Value::VOID), Err("Division by zero!".to_string()));
+        assert!(runtime.resume(breaks, Value::VOID).is_err(), "a failed coroutine is gone");
+        assert!(runtime.spawn("talk", "talk", &[]).is_err(), "the arguments are checked at spawn");
+        assert!(!runtime.cancel(breaks));
+    }
+}
+
+const STORY: &str = "
+rite step() -> int { ret 1; }
+rite story() -> int { a = step(); b = yield a; ret a + b + step(); }
+";
+
+//a coroutine finishes on the code it started on, but what it calls after waking up is new
+#[test]
+fn a_coroutine_finishes_on_the_code_it_started_on() {
+    let edited = "rite step() -> int { ret 100; }\nrite story() -> int { ret 0; }";
+    for backend in BACKENDS {
+        let mut runtime = common::runtime(backend, "story", STORY);
+        let asleep = runtime.spawn("story", "story", &[]).unwrap();
+        assert_eq!(runtime.resume(asleep, Value::VOID), Ok(Resumed::YIELDED(Value::INT(1))));
+        let unstarted = runtime.spawn("story", "story", &[]).unwrap();
+        runtime.reload("story", edited).unwrap();
+
+        let finished = runtime.resume(asleep, Value::INT(10));
+        assert_eq!(finished, Ok(Resumed::FINISHED(Value::INT(111))), "on {:?}", backend);
+        assert_eq!(runtime.resume(unstarted, Value::VOID), Ok(Resumed::YIELDED(Value::INT(100))));
+        let finished = runtime.resume(unstarted, Value::INT(10));
+        assert_eq!(finished, Ok(Resumed::FINISHED(Value::INT(210))));
+        let fresh = runtime.spawn("story", "story", &[]).unwrap();
+        assert_eq!(runtime.resume(fresh, Value::VOID), Ok(Resumed::FINISHED(Value::INT(0))));
+    }
+}
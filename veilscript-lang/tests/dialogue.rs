@@ -0,0 +1,196 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::ast::{Dialogue, NodeExit, Stmt, TextPart};
+use veilscript_lang::dialogue::{check_dialogue, CHOOSE, SAY};
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::runtime::{Backend, Resumed, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+const GUARD: &str = r#"
+rite meet_guard(name: rune, gold: int) {
+    dialogue {
+        node start {
+            Guard: "Halt! Who goes there?";
+            "The guard squints at {name}.";
+            choice "A friend." -> friend;
+            choice "Here, {gold} gold." if gold -> bribe;
+        }
+        node friend {
+            Guard: "Never heard of you.";
+            -> start;
+        }
+        node bribe {
+            Guard: "Move along then.";
+        }
+    }
+}
+rite noted() {
+    dialogue {
+        node only {
+            choice "{seen(2)}" if seen(1) -> only;
+            choice "{seen(5)}" if seen(4) -> only;
+            choice "{seen(7)}" if seen(3) -> only;
+        }
+    }
+}
+"#;
+
+//the first dialogue in the first rite of `source`
+fn dialogue(source: &str) -> Dialogue {
+    let stmts = Parser::new(tokenise(source)).parse_program().unwrap();
+    let Some(Stmt::STATEMENT_FUNCTION_DECLARATION(decl)) = stmts.into_iter().next() else {
+        panic!("no rite in {}", source)
+    };
+    match decl.body.stmts.into_iter().next() {
+        Some(Stmt::STATEMENT_DIALOGUE(dialogue)) => dialogue,
+        _ => panic!("no dialogue in {}", source),
+    }
+}
+
+#[test]
+fn a_dialogue_parses_into_nodes_lines_and_exits() {
+    let dialogue = dialogue(GUARD);
+    let names: Vec<&str> = dialogue.nodes.iter().map(|node| node.ident.name.as_str()).collect();
+    assert_eq!(names, ["start", "friend", "bribe"]);
+
+    let start = &dialogue.nodes[0];
+    let speakers: Vec<Option<&str>> = start.lines.iter()
+        .map(|line| line.speaker.as_ref().map(|speaker| speaker.name.as_str()))
+        .collect();
+    assert_eq!(speakers, [Some("Guard"), None]);
+    assert_eq!(start.lines[1].text.literal, "\"The guard squints at {name}.\"");
+    let parts = &start.lines[1].text.parts[..];
+    assert!(matches!(parts, [TextPart::TEXT(_), TextPart::EXPR(_), TextPart::TEXT(_)]));
+
+    let NodeExit::CHOICES(choices) = &start.exit else { panic!("{:?}", start.exit) };
+    let targets: Vec<&str> = choices.iter().map(|choice| choice.target.name.as_str()).collect();
+    assert_eq!(targets, ["friend", "bribe"]);
+    assert!(choices[0].condition.is_none() && choices[1].condition.is_some());
+    assert!(matches!(&dialogue.nodes[1].exit, NodeExit::JUMP(target) if target.name == "start"));
+    assert!(matches!(dialogue.nodes[2].exit, NodeExit::END));
+    assert_eq!(check_dialogue(&dialogue), Ok(()));
+}
+
+#[test]
+fn broken_dialogues_are_refused() {
+    let broken = |nodes: &str| {
+        let source = format!("rite talk() {{ dialogue {{ {} }} }}", nodes);
+        check_dialogue(&dialogue(&source)).unwrap_err()
+    };
+    assert_eq!(
+        broken(r#"node start { "Hi."; choice "Bye." -> frend; } node friend { "Yo."; }"#),
+        "Node start leads to frend, but there's no node named that! Did you mean friend?",
+    );
+    assert_eq!(
+        broken(r#"node start { "Hi."; -> a; } node a { -> b; } node b { -> a; }"#),
+        "Nodes a -> b -> a only jump in a circle and never say anything!",
+    );
+    assert_eq!(
+        broken(r#"node start { "Hi."; } node start { "Again."; }"#),
+        "Node start is declared twice in one dialogue!",
+    );
+
+    let source = r#"rite talk() { dialogue { node a { -> a; } } }"#;
+    for backend in BACKENDS {
+        let mut runtime = veilscript_lang::runtime::new_runtime(backend);
+        let err = runtime.load(loader("talk", source, &[])).unwrap_err();
+        let circle = "Nodes a -> a only jump in a circle and never say anything!";
+        assert!(err.ends_with(circle), "{} on {:?}", err, backend);
+    }
+}
+
+//say gives back the line (`~` for narration) and choose the choices joined up, with `-` for the
+//ones not on offer. seen keeps a note of every number it's handed.
+fn guard(backend: Backend, seen: &Rc<RefCell<Vec<i64>>>) -> Box<dyn ScriptRuntime> {
+    let seen = Rc::clone(seen);
+    load_into(backend, loader("guard", GUARD, &[]), move |runtime| {
+        runtime.register_native(SAY, Box::new(|args| Ok(match args {
+            [Value::VOID, text] => rune(&format!("~ {}", text.to_string())),
+            [speaker, text] => rune(&format!("{}: {}", speaker.to_string(), text.to_string())),
+            _ => panic!(),
+        })));
+        runtime.register_native(CHOOSE, Box::new(|args| {
+            let choices: Vec<String> = args.iter().map(|choice| match choice {
+                Value::VOID => "-".to_string(),
+                choice => choice.to_string(),
+            }).collect();
+            Ok(rune(&choices.join("|")))
+        }));
+        runtime.register_native("seen", Box::new(move |args| {
+            let Value::INT(n) = args[0] else { panic!() };
+            seen.borrow_mut().push(n);
+            Ok(Value::INT(n % 2))
+        }));
+    })
+}
+
+#[test]
+fn a_dialogue_plays_out_with_its_conditions_on_both_backends() {
+    let [interpreter, vm] = BACKENDS.map(|backend| {
+        let mut runtime = guard(backend, &Rc::new(RefCell::new(Vec::new())));
+        let mut played = Vec::new();
+        for (gold, picks) in [(0, [0, 1]), (5, [1, 0])] {
+            let args = [rune("Ann"), Value::INT(gold)];
+            let talk = runtime.spawn("guard", "meet_guard", &args).unwrap();
+            let mut picks = picks.iter();
+            let mut resumed = runtime.resume(talk, Value::VOID);
+            while let Ok(Resumed::YIELDED(Value::STRING(said))) = &resumed {
+                let value = match said.contains('|') {
+                    true => Value::INT(*picks.next().unwrap()),
+                    false => Value::VOID,
+                };
+                played.push(said.clone());
+                resumed = runtime.resume(talk, value);
+            }
+            played.push(format!("{:?}", resumed));
+        }
+        played
+    });
+    assert_eq!(interpreter, vm);
+    assert_eq!(interpreter, [
+        "Guard: Halt! Who goes there?", "~ The guard squints at Ann.", "A friend.|-",
+        "Guard: Never heard of you.",
+        "Guard: Halt! Who goes there?", "~ The guard squints at Ann.", "A friend.|-",
+        "Err(\"Choice 1 wasn't on offer, its condition didn't hold!\")",
+        "Guard: Halt! Who goes there?", "~ The guard squints at Ann.", "A friend.|Here, 5 gold.",
+        "Guard: Move along then.",
+        "Ok(FINISHED(VOID))",
+    ]);
+}
+
+#[test]
+fn a_pick_that_isnt_a_choice_is_an_error() {
+    for backend in BACKENDS {
+        for (pick, err) in [
+            (Value::INT(2), "The dialogue has choices 0 to 1, but was resumed with 2!"),
+            (Value::INT(-1), "The dialogue has choices 0 to 1, but was resumed with -1!"),
+            (Value::VOID, "The dialogue has choices 0 to 1, but was resumed with void!"),
+            (rune("0"), "The dialogue has choices 0 to 1, but was resumed with \"0\"!"),
+        ] {
+            let mut runtime = guard(backend, &Rc::new(RefCell::new(Vec::new())));
+            let talk = runtime.spawn("guard", "meet_guard", &[rune("Ann"), Value::INT(1)]).unwrap();
+            for _ in 0..3 {
+                runtime.resume(talk, Value::VOID).unwrap();
+            }
+            assert_eq!(runtime.resume(talk, pick), Err(err.to_string()), "on {:?}", backend);
+        }
+    }
+}
+
+//each choice has its condition worked out and then its text (if it's on offer), one choice after
+//the other
+#[test]
+fn choices_are_worked_out_in_order() {
+    for backend in BACKENDS {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut runtime = guard(backend, &seen);
+        let noted = runtime.spawn("guard", "noted", &[]).unwrap();
+        assert_eq!(runtime.resume(noted, Value::VOID), Ok(Resumed::YIELDED(rune("0|-|1"))));
+        assert_eq!(*seen.borrow(), [1, 2, 4, 3, 7], "on {:?}", backend);
+    }
+}
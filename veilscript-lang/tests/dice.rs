@@ -0,0 +1,105 @@
+mod common;
+
+use common::{loader, BACKENDS};
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::format::format_source;
+use veilscript_lang::lexer::{tokenise, TokenType};
+use veilscript_lang::random::{Dice, Rng};
+use veilscript_lang::runtime::{Backend, ScriptRuntime};
+use veilscript_lang::typeck::check_loaded;
+use veilscript_lang::value::Value;
+
+const DICE: &str = r#"
+rite three() -> int { ret 3d6; }
+rite bonus() -> int { ret 1d6+2*3; }
+rite both() -> int { ret 2d6+1d4; }
+rite lowest() -> int { ret dice_min(3d6); }
+rite highest() -> int { ret dice_max(2d8) + 3d6.dice_max(); }
+rite average() -> float { ret dice_avg(3d6); }
+rite written() -> int { ret dice_min("2d6+3"); }
+"#;
+
+fn runtime(backend: Backend) -> Box<dyn ScriptRuntime> {
+    let mut runtime = common::runtime(backend, "dice", DICE);
+    *runtime.rng() = Rng::new(3);
+    runtime
+}
+
+#[test]
+fn a_dice_literal_is_one_token_and_a_bonus_is_an_addition() {
+    use TokenType::*;
+    let tokens = tokenise("3d6 1d20+5");
+    let kinds: Vec<TokenType> = tokens.into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds[..4], [LITERAL_DICE, LITERAL_DICE, PLUS, LITERAL_INT]);
+    let formatted = format_source("x = 1d6+2*3;").unwrap();
+    assert_eq!(formatted.trim(), "x = 1d6 + 2 * 3;", "the bonus isn't part of the dice");
+}
+
+#[test]
+fn a_dice_literal_rolls_every_time_it_is_evaluated() {
+    for backend in BACKENDS {
+        let mut runtime = runtime(backend);
+        let mut rolled = Vec::new();
+        for _ in 0..200 {
+            let Ok(Value::INT(three)) = runtime.call("dice", "three", &[]) else { panic!() };
+            let Ok(Value::INT(bonus)) = runtime.call("dice", "bonus", &[]) else { panic!() };
+            let Ok(Value::INT(both)) = runtime.call("dice", "both", &[]) else { panic!() };
+            assert!((3..=18).contains(&three) && (7..=12).contains(&bonus));
+            assert!((3..=16).contains(&both));
+            rolled.push(three);
+        }
+        rolled.sort();
+        rolled.dedup();
+        assert!(rolled.len() > 10, "{:?} on {:?}", rolled, backend);
+    }
+
+    let [interpreter, vm] = BACKENDS.map(|backend| {
+        let mut runtime = runtime(backend);
+        (0..20).map(|_| runtime.call("dice", "three", &[])).collect::<Vec<_>>()
+    });
+    assert_eq!(interpreter, vm, "the same seed rolls the same");
+}
+
+//dice handed straight to dice_min and friends aren't rolled, they're looked at as written
+#[test]
+fn the_inspecting_builtins_get_the_dice_unrolled() {
+    for backend in BACKENDS {
+        let mut runtime = runtime(backend);
+        let before = *runtime.rng();
+        assert_eq!(runtime.call("dice", "lowest", &[]), Ok(Value::INT(3)), "on {:?}", backend);
+        assert_eq!(runtime.call("dice", "highest", &[]), Ok(Value::INT(34)));
+        assert_eq!(runtime.call("dice", "average", &[]), Ok(Value::FLOAT(10.5)));
+        assert_eq!(runtime.call("dice", "written", &[]), Ok(Value::INT(5)));
+        assert_eq!(*runtime.rng(), before, "nothing was rolled");
+    }
+    assert_eq!(Dice::parse("3d6").unwrap().to_string(), "3d6");
+}
+
+//a rite of the script that happens to be called dice_min is just a rite, it gets a roll
+#[test]
+fn a_rite_of_the_same_name_gets_a_roll() {
+    let source = "rite dice_min(n: int) -> int { ret n; }
+rite rolled() -> int { ret dice_min(1d1); }";
+    for backend in BACKENDS {
+        let mut runtime = common::runtime(backend, "own", source);
+        assert_eq!(runtime.call("own", "rolled", &[]), Ok(Value::INT(1)), "on {:?}", backend);
+    }
+}
+
+#[test]
+fn a_dice_literal_is_typed_int() {
+    let source = r#"
+a: int = 3d6 + 1;
+b: rune = 2d6;
+c: rune = "gold: " + 1d4;
+d: float = dice_avg(3d6);
+"#;
+    let diagnostics = check_loaded(&loader("typed", source, &[]), None);
+    let messages: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+    assert_eq!(messages, [
+        "typed:3: error: Can't put int into b: rune!",
+        "typed:4: error: Can't apply '+' to rune and int!",
+    ]);
+    let runtime = common::runtime(Backend::VM, "typed", "d: float = dice_avg(3d6);");
+    assert_eq!(runtime.global("typed", "d"), Some(Value::FLOAT(10.5)));
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::runtime::{Backend, Limits, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+//handlers that each fail a different way, between ones that don't. every one gets a tank of its
+//own, so the one that runs dry doesn't leave the rest without fuel.
+const DOOR: &str = r#"
+heard: rune = "";
+rite forever(n: int) -> int { ret forever(n + 1); }
+on "door_opened"(by: rune) { heard = heard + "first " + by + ";"; }
+on "door_opened"(by: rune) { oops = 1 / 0; heard = heard + "never;"; }
+on "door_opened"(by: rune) { heard = heard + "second;"; }
+on "door_opened"(by: rune) { forever(0); }
+on "door_opened"(by: int) { heard = heard + "wrong payload;"; }
+on "door_opened"(by: rune) { heard = heard + "third;"; }
+rite late(by: rune) { heard = heard + "late;"; }
+"#;
+
+fn runtime(backend: Backend) -> Box<dyn ScriptRuntime> {
+    load_into(backend, loader("door", DOOR, &[]), |runtime| {
+        runtime.set_limits(Limits { fuel: Some(100), ..Limits::default() });
+    })
+}
+
+fn heard(runtime: &dyn ScriptRuntime) -> Value {
+    runtime.global("door", "heard").unwrap()
+}
+
+#[test]
+fn a_failing_handler_doesnt_stop_the_others() {
+    for backend in BACKENDS {
+        let mut runtime = runtime(backend);
+        runtime.subscribe("door_opened", "door", "late").unwrap();
+        let errors = runtime.emit("door_opened", &[rune("bob")]).unwrap_err();
+        assert_eq!(heard(runtime.as_ref()), rune("first bob;second;third;late;"));
+
+        let failed = |n: usize, err: &str| {
+            format!("Handler on door_opened #{} in module door failed: {}", n, err)
+        };
+        assert_eq!(errors, [
+            failed(2, "Division by zero!"),
+            failed(4, "Out of fuel! The script took more than 100 steps."),
+            failed(5, "Rite on door_opened #5 wants int for by, but got rune!"),
+        ]);
+    }
+}
+
+#[test]
+fn unsubscribing_the_failing_handlers_makes_emit_succeed() {
+    for backend in BACKENDS {
+        let mut runtime = runtime(backend);
+        let failing: Vec<usize> = runtime.events().subscriptions("door_opened").iter()
+            .filter(|handler| ["#2", "#4", "#5"].iter().any(|n| handler.rite.ends_with(n)))
+            .map(|handler| handler.id)
+            .collect();
+        assert_eq!(failing.len(), 3);
+        for id in failing {
+            assert!(runtime.events().unsubscribe(id));
+        }
+        assert_eq!(runtime.emit("door_opened", &[rune("ann")]), Ok(()));
+        assert_eq!(heard(runtime.as_ref()), rune("first ann;second;third;"));
+        assert_eq!(runtime.emit("nobody_listens", &[]), Ok(()));
+    }
+}
@@ -0,0 +1,65 @@
+use veilscript_lang::format::{format_source, format_stmts};
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::parser::Parser;
+
+//every kind of statement, written about as untidily as the parser takes
+const MESSY: &str = r#"
+/// the guard's bits
+summon lib   as l ;
+export gold : int=3d6+  1;   // starting gold
+;
+rite  twice(x:num)->numeric{ret x*2;}
+
+/* a /* nested */ block comment */
+rite meet(name: string, gold: int) {
+    foo;
+    l::thing;
+    {  inner = -(2 ** 3) % 4 << 1; }
+    got = slumber gold * 10;
+    x = (yield 1) + 2;
+    dialogue {
+        //the opening
+        node start {
+            Guard: "Halt, {name}!"; // trailing
+
+            "The guard squints.";
+            choice "A friend." -> friend;
+            choice "Here, {gold} gold." if gold + 1 -> bribe;
+        }
+        node friend { Guard: "Never heard of you."; -> start; }
+        node bribe {
+        }
+    }
+    print(twice(got), name + "!");
+}
+
+
+on "tick"( ) { ; }
+"#;
+
+fn parsed(source: &str) -> String {
+    let stmts = Parser::new(tokenise(source)).parse_program().unwrap();
+    format_stmts(&stmts)
+}
+
+#[test]
+fn formatting_twice_changes_nothing() {
+    let once = format_source(MESSY).unwrap();
+    assert_eq!(format_source(&once).unwrap(), once);
+}
+
+#[test]
+fn formatting_keeps_the_code() {
+    let formatted = format_source(MESSY).unwrap();
+    assert_eq!(parsed(&formatted), parsed(MESSY));
+    assert!(formatted.contains("    foo;\n    l::thing;\n"), "{}", formatted);
+}
+
+#[test]
+fn formatting_keeps_the_comments() {
+    let formatted = format_source(MESSY).unwrap();
+    let comments = ["/// the guard's bits", "// starting gold", "//the opening", "// trailing"];
+    for comment in comments.into_iter().chain(["/* a /* nested */ block comment */"]) {
+        assert!(formatted.contains(comment), "{} went missing from\n{}", comment, formatted);
+    }
+}
@@ -0,0 +1,32 @@
+use veilscript_lang::highlight::{textmate_grammar, verify_spellings};
+use veilscript_lang::lexer::*;
+
+#[test]
+fn every_spelling_lexes_as_listed() {
+    assert_eq!(verify_spellings(), Ok(()));
+}
+
+#[test]
+fn aliases_are_listed() {
+    let aliases = ["rite", "ret", "summon", "pub", "slumber", "num", "rune", "nothing", "null"];
+    for alias in aliases {
+        assert!(SPELLINGS.iter().any(|(spelling, _)| *spelling == alias), "{} isn't listed", alias);
+    }
+}
+
+#[test]
+fn grammar_has_every_keyword() {
+    let grammar = textmate_grammar().unwrap();
+    let keywords = grammar.path(&["repository", "keywords"]).unwrap().to_string();
+    let spellings = SPELLINGS.iter().map(|(spelling, _)| *spelling);
+    for spelling in spellings.filter(|spelling| spelling.chars().all(char::is_alphanumeric)) {
+        assert!(keywords.contains(spelling), "{} isn't highlighted", spelling);
+    }
+}
+
+#[test]
+fn dice_keep_their_bonus_apart() {
+    let kinds: Vec<TokenType> = tokenise("3d6+1").into_iter().map(|token| token.kind).collect();
+    let wanted = [TokenType::LITERAL_DICE, TokenType::PLUS, TokenType::LITERAL_INT, TokenType::EOF];
+    assert_eq!(kinds, wanted);
+}
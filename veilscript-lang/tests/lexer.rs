@@ -0,0 +1,42 @@
+use veilscript_lang::lexer::{lossless_to_string, tokenise_lossless, TokenType, TriviaKind};
+
+//nested block comments, doc comments, windows line endings and whatever is left after the last
+//token
+const SOURCES: [&str; 5] = [
+    "/* outer /* inner */ still outer */ x = 1;",
+    "/// what gold is for\ngold: int = 10; // trailing\n",
+    "rite f() {\r\n\tret 1;\r\n}\r\n\n\n   // comment at the end, no newline",
+    "x = 1;   \t  ",
+    "",
+];
+
+#[test]
+fn lossless_tokens_glue_back_into_the_source() {
+    for source in SOURCES {
+        assert_eq!(lossless_to_string(&tokenise_lossless(source)), source);
+    }
+}
+
+#[test]
+fn trivia_is_kept_where_it_was() {
+    let tokens = tokenise_lossless(SOURCES[0]);
+    let comment = &tokens[0].leading[0];
+    assert_eq!(comment.kind, TriviaKind::BLOCK_COMMENT);
+    assert_eq!(comment.text, "/* outer /* inner */ still outer */", "comments nest");
+
+    let tokens = tokenise_lossless(SOURCES[1]);
+    assert_eq!(tokens[0].leading[0].kind, TriviaKind::DOC_COMMENT);
+    let trailing: Vec<TriviaKind> = tokens.iter()
+        .flat_map(|token| &token.trailing)
+        .map(|trivia| trivia.kind)
+        .collect();
+    assert!(trailing.contains(&TriviaKind::LINE_COMMENT), "{:?}", trailing);
+
+    //whatever comes after the last token hangs off the end of input
+    let tokens = tokenise_lossless(SOURCES[2]);
+    let last = tokens.last().unwrap();
+    assert_eq!(last.token.kind, TokenType::EOF);
+    let kinds: Vec<TriviaKind> = last.leading.iter().map(|trivia| trivia.kind).collect();
+    use TriviaKind::*;
+    assert_eq!(kinds, [NEWLINE, NEWLINE, NEWLINE, WHITESPACE, LINE_COMMENT]);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::runtime::{Backend, Limits, Resumed, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+const SCRIPT: &str = r#"
+ticks: int = 0;
+rite sum(x: int) -> int { ret x + x + x + x + x; }
+rite twice(x: int) -> int { ret x * 2; }
+rite mixed(x: int) -> int { ret roll(1, 6) + 2d6 + twice(x) * x; }
+rite tick() -> int { ticks = ticks + 1; ret ticks * 1; }
+rite grind() -> int {
+    a = tick() + tick();
+    b = yield a;
+    ret a + b + tick() + tick();
+}
+rite deep(n: int) -> int { ret deep(n + 1); }
+rite one() -> int { ret two(); }
+rite two() -> int { ret three(); }
+rite three() -> int { ret 3; }
+rite glue(a: rune, b: rune) -> rune { ret a + b; }
+rite written() -> rune { ret "longer than the limit"; }
+rite loud() -> rune { ret shout(); }
+"#;
+
+fn runtime(backend: Backend, limits: Limits) -> Box<dyn ScriptRuntime> {
+    load_into(backend, loader("limits", SCRIPT, &[]), |runtime| {
+        runtime.register_native("shout", Box::new(|_| Ok(rune("AAAAAAAA"))));
+        runtime.set_limits(limits);
+    })
+}
+
+fn fuel(steps: u64) -> Limits {
+    Limits { fuel: Some(steps), ..Limits::default() }
+}
+
+//a step is an operator, a rite call, a native call or a roll, and both backends count them alike
+#[test]
+fn the_same_fuel_runs_out_at_the_same_step_on_both_backends() {
+    for (rite, steps) in [("sum", 5), ("mixed", 8)] {
+        let mut enough = Vec::new();
+        for backend in BACKENDS {
+            let cut_offs: Vec<(Result<Value, String>, Option<u64>)> = (0..12).map(|steps| {
+                let mut runtime = runtime(backend, fuel(steps));
+                let result = runtime.call("limits", rite, &[Value::INT(3)]);
+                (result.map(|_| Value::VOID), runtime.fuel_left())
+            }).collect();
+            enough.push(cut_offs.iter().position(|(result, _)| result.is_ok()));
+            let (last_failure, _) = &cut_offs[steps as usize - 1];
+            let err = format!("Out of fuel! The script took more than {} steps.", steps - 1);
+            assert_eq!(last_failure, &Err(err), "{} on {:?}", rite, backend);
+            assert_eq!(cut_offs[steps as usize], (Ok(Value::VOID), Some(0)));
+        }
+        assert_eq!(enough, [Some(steps as usize); 2], "{}", rite);
+    }
+}
+
+//it stops right before the step it can't pay for and carries on from there, without doing
+//anything twice
+#[test]
+fn a_coroutine_that_runs_dry_carries_on_with_the_next_resume() {
+    let turns = BACKENDS.map(|backend| {
+        let mut runtime = runtime(backend, fuel(4));
+        let grind = runtime.spawn("limits", "grind", &[]).unwrap();
+        let mut turns = Vec::new();
+        let mut value = Value::VOID;
+        while turns.len() < 50 {
+            let resumed = runtime.resume(grind, value).unwrap();
+            value = match resumed {
+                Resumed::YIELDED(_) => Value::INT(10),
+                _ => Value::VOID,
+            };
+            turns.push(resumed.clone());
+            if let Resumed::FINISHED(_) = resumed {
+                break;
+            }
+        }
+        assert_eq!(runtime.global("limits", "ticks"), Some(Value::INT(4)), "on {:?}", backend);
+        turns
+    });
+    assert_eq!(turns[0], turns[1]);
+    let dry = turns[0].iter().filter(|turn| **turn == Resumed::OUT_OF_FUEL).count();
+    assert!(dry >= 3, "{:?}", turns[0]);
+    assert!(turns[0].contains(&Resumed::YIELDED(Value::INT(3))));
+    assert_eq!(turns[0].last(), Some(&Resumed::FINISHED(Value::INT(20))));
+
+    for backend in BACKENDS {
+        let err = runtime(backend, fuel(0)).spawn("limits", "grind", &[]).unwrap_err();
+        assert_eq!(err, "Out of fuel! The script took more than 0 steps.", "spawning is a step");
+    }
+}
+
+#[test]
+fn runaway_recursion_hits_the_call_depth_limit() {
+    for backend in BACKENDS {
+        let limits = |max_call_depth| Limits { max_call_depth, ..Limits::default() };
+        let mut runtime = runtime(backend, Limits::default());
+        let err = runtime.call("limits", "deep", &[Value::INT(0)]).unwrap_err();
+        assert_eq!(err, "Stack overflow! Rites nested deeper than 200 calls.", "on {:?}", backend);
+
+        runtime.set_limits(limits(3));
+        assert_eq!(runtime.call("limits", "one", &[]), Ok(Value::INT(3)));
+        runtime.set_limits(limits(2));
+        let err = runtime.call("limits", "one", &[]).unwrap_err();
+        assert_eq!(err, "Stack overflow! Rites nested deeper than 2 calls.");
+    }
+}
+
+//only runes that are made while running count, the ones written in the script are as long as
+//they are
+#[test]
+fn runes_made_while_running_have_to_fit_the_limit() {
+    for backend in BACKENDS {
+        let mut runtime = runtime(backend, Limits { max_rune_length: 4, ..Limits::default() });
+        let glue = |runtime: &mut dyn ScriptRuntime, a, b| {
+            runtime.call("limits", "glue", &[rune(a), rune(b)])
+        };
+        assert_eq!(glue(runtime.as_mut(), "ab", "cd"), Ok(rune("abcd")), "on {:?}", backend);
+        let err = "Rune too long! Runes can be at most 4 bytes.".to_string();
+        assert_eq!(glue(runtime.as_mut(), "ab", "cde"), Err(err.clone()));
+        assert_eq!(runtime.call("limits", "loud", &[]), Err(err), "natives count too");
+        assert_eq!(runtime.call("limits", "written", &[]), Ok(rune("longer than the limit")));
+    }
+}
@@ -0,0 +1,86 @@
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::json::parse_json;
+use veilscript_lang::lint::{lint, LintConfig};
+
+const SCRIPT: &str = r#"
+rite unused_variable() -> int { x = 1; ret 2; }
+rite unused_parameter(x: int, _y: int) -> int { ret 2; }
+rite unreachable_code() -> int { ret 1; ret 2; }
+rite empty_scope() { {} }
+rite zero_effect() { empty_scope; }
+rite division_by_zero() -> int { ret 1 / 0 + 1 % 0; }
+rite missing_return() -> int { print(1); }
+"#;
+
+//every diagnostic, in order
+fn messages(source: &str, config: &LintConfig) -> Vec<String> {
+    lint("lint", source, config).iter().map(Diagnostic::to_string).collect()
+}
+
+#[test]
+fn every_rule_has_something_to_say() {
+    assert_eq!(messages(SCRIPT, &LintConfig::default()), [
+        "lint:2: warning: x is never used! [unused-variable]",
+        "lint:3: warning: The parameter x is never used! Call it _x if that's on purpose. \
+            [unused-parameter]",
+        "lint:4: warning: This can never run, it comes after a ret! [unreachable-code]",
+        "lint:5: warning: This scope is empty! [empty-scope]",
+        "lint:6: warning: empty_scope; does nothing! [zero-effect]",
+        "lint:7: warning: This divides by zero! [division-by-zero]",
+        "lint:7: warning: This takes a modulo by zero! [division-by-zero]",
+        "lint:8: warning: Rite missing_return should give back int, but can reach its end without \
+            a ret! [missing-return]",
+    ]);
+}
+
+#[test]
+fn comments_turn_rules_off_and_on() {
+    let source = r#"
+rite quiet() -> int {
+    //lint: allow unused-variable
+    a = 1;
+    b = 2; //lint: allow unused-variable
+    c = 3;
+    //lint: off unused-variable, division-by-zero
+    d = 4;
+    e = 1 / 0;
+    //lint: on all
+    f = 5;
+    //lint: allow unused-varable
+    //lint: silence all
+    ret 6 / 0;
+}
+"#;
+    assert_eq!(messages(source, &LintConfig::default()), [
+        "lint:6: warning: c is never used! [unused-variable]",
+        "lint:11: warning: f is never used! [unused-variable]",
+        "lint:12: warning: No lint rule named unused-varable! Did you mean unused-variable?",
+        "lint:13: warning: Don't know what //lint: silence means! It's allow, off or on.",
+        "lint:14: warning: This divides by zero! [division-by-zero]",
+    ]);
+}
+
+#[test]
+fn a_config_makes_rules_errors_or_turns_them_off() {
+    let json = r#"{"rules": {"division-by-zero": "error", "unused-variable": "off"}}"#;
+    let config = LintConfig::from_json(&parse_json(json).unwrap()).unwrap();
+    let source = "rite split() -> int {\n    x = 1;\n    ret 1 / 0;\n}\n";
+    let divides = "lint:3: error: This divides by zero! [division-by-zero]";
+    assert_eq!(messages(source, &config), [divides]);
+    //and a comment can still turn it back on, as a warning
+    let source = format!("//lint: on unused-variable\n{}", source);
+    assert_eq!(messages(&source, &config), [
+        "lint:3: warning: x is never used! [unused-variable]",
+        "lint:4: error: This divides by zero! [division-by-zero]",
+    ]);
+
+    for (json, err) in [
+        (r#"{"rules": {"division-by-nil": "error"}}"#,
+            "No lint rule named division-by-nil! Did you mean division-by-zero?"),
+        (r#"{"rules": {"empty-scope": "loud"}}"#,
+            "loud is not a lint level! It's one of off, warning or error."),
+        (r#"{"rules": ["empty-scope"]}"#, "\"rules\" in a lint config has to be an object!"),
+    ] {
+        assert_eq!(LintConfig::from_json(&parse_json(json).unwrap()).unwrap_err(), err);
+    }
+}
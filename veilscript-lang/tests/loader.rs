@@ -0,0 +1,132 @@
+use std::process::Command;
+
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::interpreter::Interpreter;
+use veilscript_lang::module::ModuleLoader;
+use veilscript_lang::repl::Repl;
+use veilscript_lang::runtime::{new_runtime, Backend, ScriptRuntime};
+use veilscript_lang::source::MemoryLoader;
+use veilscript_lang::value::Value;
+
+fn load(files: &[(&str, &str)], main: &str) -> Result<ModuleLoader, Diagnostic> {
+    let mut memory = MemoryLoader::new();
+    for (name, text) in files {
+        memory.add(name, text);
+    }
+    let mut loader = ModuleLoader::from_loader(memory);
+    loader.load_source("main", main)?;
+    Ok(loader)
+}
+
+#[test]
+fn parse_errors_point_into_the_broken_file() {
+    let err = load(&[("lib.veil", "x = 1;\ny = (;\n")], "summon lib;").err().unwrap();
+    assert_eq!((err.file.as_str(), err.span.line), ("lib.veil", 2));
+}
+
+#[test]
+fn missing_and_circular_summons_point_at_the_summon() {
+    let err = load(&[], "x = 1;\nsummon nowhere;").err().unwrap();
+    assert_eq!((err.file.as_str(), err.span.line), ("main", 2));
+    assert!(err.message.contains("nowhere"), "{}", err.message);
+
+    let err = load(&[("a.veil", "\n\nsummon a;")], "summon a;").err().unwrap();
+    assert_eq!((err.file.as_str(), err.span.line), ("a.veil", 3));
+    assert!(err.message.starts_with("Import cycle!"), "{}", err.message);
+}
+
+const SWORD: (&str, &str) = ("items/sword.veil", "
+pub damage: int = 7;
+export rite swing(times: int) -> int { ret damage * times + edge(); }
+rite edge() -> int { ret 1; }
+");
+
+#[test]
+fn summoned_modules_are_reached_with_their_name_or_an_alias() {
+    let main = "
+summon items::sword;
+summon items::sword as blade;
+rite both() -> int { ret sword::swing(2) + blade::damage + items::sword::damage; }
+";
+    for backend in [Backend::INTERPRETER, Backend::VM] {
+        let mut runtime = new_runtime(backend);
+        runtime.load(load(&[SWORD], main).unwrap()).unwrap();
+        assert_eq!(runtime.call("main", "both", &[]), Ok(Value::INT(29)), "on {:?}", backend);
+        assert_eq!(runtime.global("items::sword", "damage"), Some(Value::INT(7)));
+    }
+}
+
+//a module summoned along two paths is loaded once, only going round in a circle is refused
+#[test]
+fn a_diamond_is_fine_but_a_cycle_is_not() {
+    let top = ("top.veil", "summon left;\nsummon right;");
+    let left = ("left.veil", "summon bottom;\npub x: int = bottom::loaded + 1;");
+    let right = ("right.veil", "summon bottom;\npub y: int = bottom::loaded + 2;");
+    let bottom = ("bottom.veil", "pub loaded: int = 10;");
+    let loader = load(&[top, left, right, bottom], "summon top;").unwrap();
+    let mut runtime = new_runtime(Backend::INTERPRETER);
+    runtime.load(loader).unwrap();
+    assert_eq!(runtime.global("left", "x"), Some(Value::INT(11)));
+    assert_eq!(runtime.global("right", "y"), Some(Value::INT(12)));
+
+    let bottom = ("bottom.veil", "summon top;");
+    let err = load(&[top, left, right, bottom], "summon top;").err().unwrap();
+    let cycle = "Import cycle! top -> left -> bottom -> top";
+    assert_eq!(err.to_string(), format!("bottom.veil:1: error: {}", cycle));
+}
+
+#[test]
+fn unexported_names_point_at_the_name() {
+    let lib = ("lib.veil", "secret = 1;");
+    let err = load(&[lib], "summon lib;\n\nx = lib::secret;").err().unwrap();
+    assert_eq!(err.to_string(), "main:3: error: secret is not exported by lib!");
+    let err = load(&[SWORD], "summon items::sword;\nx = sword::edge();").err().unwrap();
+    assert_eq!(err.to_string(), "main:2: error: edge is not exported by items::sword!");
+    let err = load(&[SWORD], "summon items::sword;\nx = sword::blunt();").err().unwrap();
+    assert_eq!(err.to_string(), "main:2: error: items::sword has no rite or global named blunt!");
+}
+
+//a session types its code in long after the loader looked, so the interpreter checks again
+#[test]
+fn a_session_cant_reach_unexported_names_either() {
+    let setup = || {
+        let mut interpreter = Interpreter::new();
+        interpreter.load(load(&[SWORD], "summon items::sword;").unwrap())?;
+        Ok(interpreter)
+    };
+    let mut repl = Repl::new("main", Box::new(setup)).unwrap();
+    assert_eq!(repl.feed("sword::swing(1)"), Ok("8".to_string()));
+    let err = "edge is not exported by items::sword!".to_string();
+    assert_eq!(repl.feed("sword::edge()"), Err(err.clone()));
+    assert_eq!(repl.feed("x = sword::edge();"), Err(err));
+    assert_eq!(repl.feed("pub rite mine() -> int { ret 1; }"), Ok(String::new()));
+}
+
+#[test]
+fn reload_errors_are_diagnostics_against_the_module() {
+    for backend in [Backend::INTERPRETER, Backend::VM] {
+        let mut runtime = new_runtime(backend);
+        runtime.load(load(&[], "x = 1;").unwrap()).unwrap();
+        let err = runtime.reload("main", "x = 1;\nx = (;").unwrap_err();
+        assert!(err.starts_with("main:2: error: "), "{}", err);
+        let err = runtime.reload("main", "x = 1 / 0;").unwrap_err();
+        assert_eq!(err, "main: error: Division by zero!");
+    }
+}
+
+#[test]
+fn every_command_reports_the_same_way() {
+    let dir = std::env::temp_dir().join(format!("veil-loader-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("broken.veil");
+    std::fs::write(&file, "x = 1;\ny = (;\n").unwrap();
+    let wanted = format!("{}:2: error: ", file.display());
+    let veil = env!("CARGO_BIN_EXE_veil");
+    for command in ["run", "check", "compile", "disasm"] {
+        let output = Command::new(veil).arg(command).arg(&file).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with(&wanted), "veil {} printed {}", command, stderr);
+        assert_eq!(output.status.code(), Some(1));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
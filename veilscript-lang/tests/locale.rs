@@ -0,0 +1,158 @@
+mod common;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::ast::Stmt;
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::dialogue::SAY;
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::locale::{extract, text_id, Catalogue, Entry};
+use veilscript_lang::parser::Parser;
+use veilscript_lang::runtime::Resumed;
+use veilscript_lang::value::Value;
+
+const GATE: &str = r#"
+rite gate(name: rune, gold: int) {
+    dialogue {
+        node start {
+            Guard: "Halt!";
+            "The guard squints at {name}.";
+            Guard: "{name} owes me {gold} gold.";
+            choice "Pay {gold}." -> start;
+        }
+    }
+}
+"#;
+
+const CELLAR: &str = r#"
+dialogue {
+    node start {
+        Guard: "Halt!";
+        "It's dark.";
+    }
+}
+"#;
+
+fn parse(source: &str) -> Vec<Stmt> {
+    Parser::new(tokenise(source)).parse_program().unwrap()
+}
+
+fn entries() -> Vec<Entry> {
+    let mut entries = Vec::new();
+    extract("gate.veil", &parse(GATE), &mut entries);
+    extract("cellar.veil", &parse(CELLAR), &mut entries);
+    entries
+}
+
+#[test]
+fn every_line_and_choice_is_extracted_once_with_where_and_who() {
+    let entries = entries();
+    let found: Vec<(&str, String, Vec<String>)> = entries.iter()
+        .map(|entry| (entry.text.as_str(), entry.context(), entry.locations()))
+        .collect();
+    assert_eq!(found, [
+        ("Halt!", "said by Guard, in node start of gate".to_string(),
+            vec!["gate.veil:5".to_string(), "cellar.veil:4".to_string()]),
+        ("The guard squints at {name}.", "narration, in node start of gate".to_string(),
+            vec!["gate.veil:6".to_string()]),
+        ("{name} owes me {gold} gold.", "said by Guard, in node start of gate".to_string(),
+            vec!["gate.veil:7".to_string()]),
+        ("Pay {gold}.", "a choice, in node start of gate".to_string(),
+            vec!["gate.veil:8".to_string()]),
+        ("It's dark.", "narration, in node start".to_string(), vec!["cellar.veil:5".to_string()]),
+    ]);
+    assert_eq!(entries[1].literal, "\"The guard squints at {name}.\"");
+}
+
+//the ID only hangs on the node and the words, so it's the same on every machine and survives
+//lines moving about
+#[test]
+fn ids_are_stable() {
+    assert_eq!(text_id("start", "\"Halt!\""), "352bfbe2878df2bd");
+    assert_eq!(entries()[0].id, text_id("start", "\"Halt!\""));
+
+    let moved = GATE.replace("rite gate(", "\nrite renamed(").replace("node start", "node start ");
+    let mut again = Vec::new();
+    extract("moved.veil", &parse(&moved), &mut again);
+    let ids = |entries: &[Entry]| entries.iter().map(|entry| entry.id.clone()).collect::<Vec<_>>();
+    assert_eq!(ids(&again), ids(&entries()[..4]));
+
+    assert_ne!(text_id("start", "\"Halt!\""), text_id("start", "\"Halt.\""));
+    assert_ne!(text_id("start", "\"Halt!\""), text_id("gate", "\"Halt!\""));
+}
+
+fn german() -> Catalogue {
+    let mut catalogue = Catalogue::new();
+    for (line, translation) in [
+        ("\"Halt!\"", "Stehen bleiben!"),
+        ("\"The guard squints at {name}.\"", "{ name }, der Wächter schielt."),
+        ("\"{name} owes me {gold} gold.\"", "{gold} Gold schuldet mir {name}."),
+        ("\"Pay {gold}.\"", "Bezahle {golden}."),
+    ] {
+        catalogue.insert(&text_id("start", line), translation);
+    }
+    catalogue
+}
+
+//placeholders go wherever the translation puts them. a broken translation is said as written.
+#[test]
+fn lines_come_out_translated_with_their_placeholders_filled_in() {
+    for backend in BACKENDS {
+        let mut runtime = load_into(backend, loader("gate", GATE, &[]), |runtime| {
+            runtime.register_native(SAY, Box::new(|args| Ok(args[1].clone())));
+            runtime.register_native("choose", Box::new(|args| Ok(args[0].clone())));
+            runtime.set_locale(Some(german()));
+        });
+        let gate = runtime.spawn("gate", "gate", &[rune("Ann"), Value::INT(3)]).unwrap();
+        let said: Vec<_> = (0..4).map(|_| runtime.resume(gate, Value::VOID)).collect();
+        let said_as = |text| Ok(Resumed::YIELDED(rune(text)));
+        assert_eq!(said, [
+            said_as("Stehen bleiben!"),
+            said_as("Ann, der Wächter schielt."),
+            said_as("3 Gold schuldet mir Ann."),
+            said_as("Pay 3."),
+        ], "on {:?}", backend);
+
+        runtime.set_locale(None);
+        assert_eq!(runtime.resume(gate, Value::INT(0)), said_as("Halt!"));
+    }
+}
+
+#[test]
+fn broken_translations_are_warned_about() {
+    let mut catalogue = german();
+    catalogue.insert(&text_id("start", "\"It's dark.\""), "Es ist {dunkel.");
+    let warnings = catalogue.check(&entries());
+    let warnings: Vec<String> = warnings.iter().map(Diagnostic::to_string).collect();
+    assert_eq!(warnings, [
+        "gate.veil:8: warning: The translation of \"Pay {gold}.\" has a {golden} in it, but the \
+            line doesn't! It's said as written instead.",
+        "cellar.veil:5: warning: The translation of \"It's dark.\" is broken! A { in this line is \
+            never closed! Write {{ for a brace of its own. It's said as written instead.",
+    ]);
+}
+
+#[test]
+fn tables_are_read_from_po_and_csv() {
+    let po = Catalogue::from_po(r#"
+msgctxt "a"
+msgid "Halt!"
+msgstr "Stehen "
+"bleiben!"
+
+#, fuzzy
+msgctxt "b"
+msgid "It's dark."
+msgstr "Dunkel."
+
+msgctxt "c"
+msgid "Untranslated."
+msgstr ""
+"#).unwrap();
+    assert_eq!((po.get("a"), po.get("b"), po.get("c")), (Some("Stehen bleiben!"), None, None));
+    assert_eq!(po.len(), 1, "fuzzy and empty entries aren't translations");
+
+    let csv = "id,text,translation\na,Halt!,\"Stehen \"\"bleiben\"\"!\"\nc,x,\n";
+    let csv = Catalogue::from_csv(csv).unwrap();
+    assert_eq!((csv.get("a"), csv.len()), (Some("Stehen \"bleiben\"!"), 1));
+    assert!(Catalogue::from_csv("id,text\n").is_err(), "no translation column");
+}
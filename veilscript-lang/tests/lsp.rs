@@ -0,0 +1,137 @@
+use std::io::{BufReader, Write};
+use std::process::{Command, Stdio};
+
+use veilscript_lang::json::{parse_json, Json};
+use veilscript_lang::lsp::{path_to_uri, read_message};
+
+const LIB: &str = "/// doubles it
+pub rite twice(x: int) -> int {
+    ret x * 2;
+}
+pub base = 20;
+";
+
+const MAIN: &str = "summon lib;
+/// how much gold there is
+gold: int = lib::twice(lib::base);
+rite spend(cost: int) -> int {
+    ret gold - cost;
+}
+oops: rune = gold;
+";
+
+//one editor session against the real binary, message by message, the way editors frame them
+fn session(messages: &[String]) -> (Vec<Json>, bool) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_veil-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    for message in messages {
+        let body = parse_json(message).unwrap().to_string();
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+    drop(stdin);
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut replies = Vec::new();
+    while let Some(reply) = read_message(&mut stdout).unwrap() {
+        replies.push(reply);
+    }
+    (replies, server.wait().unwrap().success())
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "{}", "params": {}}}"#, id, method, params)
+}
+
+fn notification(method: &str, params: &str) -> String {
+    format!(r#"{{"jsonrpc": "2.0", "method": "{}", "params": {}}}"#, method, params)
+}
+
+fn at(uri: &str, line: usize, character: usize) -> String {
+    let position = format!(r#"{{"line": {}, "character": {}}}"#, line, character);
+    format!(r#"{{"textDocument": {{"uri": "{}"}}, "position": {}}}"#, uri, position)
+}
+
+fn range(from: (usize, usize), to: (usize, usize)) -> String {
+    format!(
+        r#"{{"start": {{"line": {}, "character": {}}}, "end": {{"line": {}, "character": {}}}}}"#,
+        from.0, from.1, to.0, to.1
+    )
+}
+
+fn reply_to(replies: &[Json], id: usize) -> &Json {
+    let id = Json::from(id);
+    replies.iter().find(|reply| reply.get("id") == Some(&id)).unwrap()
+}
+
+fn expect(reply: &Json, result: &str) {
+    assert_eq!(reply.get("result"), Some(&parse_json(result).unwrap()), "{}", reply.to_string());
+}
+
+#[test]
+fn editor_session() {
+    let dir = std::env::temp_dir().join(format!("veil-lsp-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.veil"), LIB).unwrap();
+    let main = path_to_uri(&dir.join("main.veil"));
+    let lib = path_to_uri(&dir.join("lib.veil"));
+    let text = Json::from(MAIN).to_string();
+
+    let document = format!(r#"{{"uri": "{}", "languageId": "veil", "text": {}}}"#, main, text);
+
+    let (replies, clean) = session(&[
+        request(1, "initialize", r#"{"capabilities": {}}"#),
+        notification("initialized", "{}"),
+        notification("textDocument/didOpen", &format!(r#"{{"textDocument": {}}}"#, document)),
+        request(2, "textDocument/hover", &at(&main, 4, 11)),
+        request(3, "textDocument/hover", &at(&main, 2, 18)),
+        request(4, "textDocument/definition", &at(&main, 2, 18)),
+        request(5, "textDocument/definition", &at(&main, 4, 16)),
+        request(6, "textDocument/completion", &at(&main, 2, 17)),
+        request(7, "shutdown", "null"),
+        notification("exit", "null"),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(clean, "exit after shutdown should be a clean exit");
+
+    let capabilities = reply_to(&replies, 1).path(&["result", "capabilities"]).unwrap();
+    for capability in ["hoverProvider", "definitionProvider", "completionProvider"] {
+        assert!(capabilities.get(capability).is_some(), "no {}", capability);
+    }
+
+    let published = replies.iter().find(|reply| reply.get("method").is_some()).unwrap();
+    assert_eq!(published, &parse_json(&format!(r#"{{
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {{"uri": "{}", "diagnostics": [{{
+            "range": {},
+            "severity": 1,
+            "source": "veil",
+            "message": "Can't put int into oops: rune!"
+        }}]}}
+    }}"#, main, range((6, 0), (6, 4)))).unwrap());
+
+    expect(reply_to(&replies, 2), r#"{"contents": {"kind": "markdown",
+        "value": "```veil\n(global) gold: int\n```\n\nhow much gold there is"}}"#);
+    expect(reply_to(&replies, 3), r#"{"contents": {"kind": "markdown",
+        "value": "```veil\nrite twice(x: int) -> int\n```\n\ndoubles it"}}"#);
+    let definition = |uri: &str, from, to| {
+        format!(r#"{{"uri": "{}", "range": {}}}"#, uri, range(from, to))
+    };
+    expect(reply_to(&replies, 4), &definition(&lib, (1, 9), (1, 14)));
+    expect(reply_to(&replies, 5), &definition(&main, (3, 11), (3, 15)));
+    expect(reply_to(&replies, 6), r#"[
+        {"label": "base", "kind": 6, "detail": "global"},
+        {"label": "twice", "kind": 3, "detail": "rite twice(x: int) -> int"}
+    ]"#);
+    expect(reply_to(&replies, 7), "null");
+}
+
+#[test]
+fn exit_without_shutdown_is_not_clean() {
+    let (replies, clean) = session(&[notification("exit", "null")]);
+    assert!(replies.is_empty());
+    assert!(!clean);
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{loader, BACKENDS};
+use veilscript_lang::ast::BinOp;
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::runtime::new_runtime;
+use veilscript_lang::typeck::check_loaded;
+use veilscript_lang::value::Value;
+
+#[test]
+fn int_power_is_an_int() {
+    assert_eq!(BinOp::POW.apply(&Value::INT(3), &Value::INT(4)), Ok(Value::INT(81)));
+    assert_eq!(BinOp::POW.apply(&Value::INT(2), &Value::INT(64)), Ok(Value::INT(0)));
+    assert_eq!(BinOp::POW.apply(&Value::FLOAT(2.0), &Value::INT(-1)), Ok(Value::FLOAT(0.5)));
+    assert!(BinOp::POW.apply(&Value::INT(2), &Value::INT(-1)).is_err());
+}
+
+#[test]
+fn int_power_is_typed_int() {
+    let source = "a: rune = 2 ** 3;\nb: int = 2 ** 3;";
+    let diagnostics = check_loaded(&loader("ops", source, &[]), None);
+    let messages: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    assert!(messages[0].starts_with("ops:1: error:"), "{:?}", messages);
+}
+
+#[test]
+fn negative_int_power_fails_on_both_backends() {
+    let source = "rite folded() -> int { ret 2 ** -1; }\nrite late(n: int) -> int { ret 2 ** n; }";
+    for backend in BACKENDS {
+        for optimising in [true, false] {
+            let mut runtime = new_runtime(backend);
+            runtime.set_optimising(optimising);
+            runtime.load(loader("ops", source, &[])).unwrap();
+            assert!(runtime.call("ops", "folded", &[]).is_err());
+            assert!(runtime.call("ops", "late", &[Value::INT(-2)]).is_err());
+            assert_eq!(runtime.call("ops", "late", &[Value::INT(5)]), Ok(Value::INT(32)));
+        }
+    }
+}
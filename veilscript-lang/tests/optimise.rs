@@ -0,0 +1,104 @@
+mod common;
+
+use common::{load_into, loader, BACKENDS};
+use veilscript_lang::format::format_stmts;
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::optimise::optimise;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::runtime::{Backend, Limits, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+//every rite here is called with and without the optimiser, and has to come out the same
+const SCRIPT: &str = r#"
+rite day() -> int { ret 60 * 60 * 24; }
+rite wraps() -> int { ret 9223372036854775807 + 1; }
+rite promotes() -> float { ret 1 + 2.5 * 2; }
+rite truncates() -> int { ret -7 / 2; }
+rite wraps_round() -> int { ret -7 % 3; }
+rite powers() -> int { ret 2 ** 63 + 3 ** 2; }
+rite shifts() -> int { ret 1 << 3 | 6 & 3 ^ 1; }
+rite divides_by_zero() -> int { ret 1 / 0; }
+rite shifts_too_far() -> int { ret 1 << 70; }
+rite float_by_zero() -> float { ret 1.0 / 0.0; }
+rite negates(x: int) -> int { ret -(-x) + +x; }
+rite negates_a_rune() -> int { name = "bob"; ret -(-name); }
+rite concatenates() -> rune { ret ("a" + "b") + "c"; }
+rite stops_at_ret() -> int {
+    x = 1;
+    ret x;
+    x = 1 / 0;
+    ret x + 1;
+}
+rite rolls() -> int { ret 3d6 + 1d20 * 2; }
+total: int = day() / (60 * 60);
+"#;
+
+const RITES: [&str; 15] = [
+    "day", "wraps", "promotes", "truncates", "wraps_round", "powers", "shifts", "divides_by_zero",
+    "shifts_too_far", "float_by_zero", "negates", "negates_a_rune", "concatenates", "stops_at_ret",
+    "rolls",
+];
+
+fn runtime(backend: Backend, optimising: bool) -> Box<dyn ScriptRuntime> {
+    load_into(backend, loader("script", SCRIPT, &[]), |runtime| runtime.set_optimising(optimising))
+}
+
+fn results(runtime: &mut dyn ScriptRuntime) -> Vec<Result<Value, String>> {
+    let mut results: Vec<Result<Value, String>> = RITES.iter().map(|rite| {
+        let args = if *rite == "negates" { vec![Value::INT(-5)] } else { Vec::new() };
+        runtime.call("script", rite, &args)
+    }).collect();
+    results.push(runtime.global("script", "total").ok_or_else(|| "no total".to_string()));
+    results
+}
+
+#[test]
+fn optimised_or_not_gives_the_same() {
+    for backend in BACKENDS {
+        let plain = results(runtime(backend, false).as_mut());
+        let optimised = results(runtime(backend, true).as_mut());
+        for (at, (plain, optimised)) in plain.iter().zip(&optimised).enumerate() {
+            let rite = RITES.get(at).unwrap_or(&"total");
+            assert_eq!(plain, optimised, "{} on {:?}", rite, backend);
+        }
+        assert_eq!(plain[0], Ok(Value::INT(86400)));
+        assert!(plain[7].is_err() && plain[8].is_err() && plain[11].is_err());
+    }
+}
+
+#[test]
+fn folds_what_it_can_and_nothing_else() {
+    let mut stmts = Parser::new(tokenise(SCRIPT)).parse_program().unwrap();
+    optimise(&mut stmts);
+    let optimised = format_stmts(&stmts);
+    for folded in ["ret 86400;", "ret -9223372036854775808;", "ret 6.0;", "ret -3;", "ret 2;"] {
+        assert!(optimised.contains(folded), "no {} in\n{}", folded, optimised);
+    }
+    //runes aren't folded, and neither are dice, they're different every time
+    let kept = ["ret 1 / 0;", "ret 1 << 70;", "ret x;\n}", "ret --name;", "\"a\" + \"b\""];
+    for kept in kept.into_iter().chain(["3d6 + 1d20"]) {
+        assert!(optimised.contains(kept), "no {} in\n{}", kept, optimised);
+    }
+}
+
+//the one thing a script can tell: folded operators are steps it doesn't have to pay fuel for
+#[test]
+fn folded_operators_cost_no_fuel() {
+    for backend in BACKENDS {
+        let fuel = |optimising| (0..10).find(|steps| {
+            let mut runtime = runtime(backend, optimising);
+            runtime.set_limits(Limits { fuel: Some(*steps), ..Limits::default() });
+            runtime.call("script", "day", &[]).is_ok()
+        });
+        assert_eq!((fuel(false), fuel(true)), (Some(3), Some(1)), "on {:?}", backend);
+
+        //what isn't folded still costs
+        let divide = |steps| {
+            let mut runtime = runtime(backend, true);
+            runtime.set_limits(Limits { fuel: Some(steps), ..Limits::default() });
+            runtime.call("script", "divides_by_zero", &[]).unwrap_err()
+        };
+        assert_eq!(divide(1), "Out of fuel! The script took more than 1 steps.");
+        assert_eq!(divide(2), "Division by zero!");
+    }
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{load_into, loader, BACKENDS};
+use veilscript_lang::ast::Expr;
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::value::Value;
+
+//how an expression groups, every binary and unary expression in brackets
+fn grouped(source: &str) -> String {
+    Parser::new(tokenise(source)).parse_expr(0).unwrap().to_pretty_string()
+}
+
+#[test]
+fn operators_group_by_the_precedence_table() {
+    assert_eq!(grouped("2 ** 3 ** 2"), "(2 ** (3 ** 2))", "** is right associative");
+    assert_eq!(grouped("10 - 4 - 3"), "((10 - 4) - 3)", "the rest are left associative");
+    assert_eq!(grouped("-2 ** 2"), "(-(2 ** 2))", "** binds tighter than a sign");
+    assert_eq!(grouped("1 | 2 ^ 3 & 4 << 1"), "(1 | (2 ^ (3 & (4 << 1))))");
+    assert_eq!(grouped("1 + 2 * 3 % 4"), "(1 + ((2 * 3) % 4))");
+}
+
+#[test]
+fn calls_and_dots_bind_tightest() {
+    assert_eq!(grouped("-f(2) ** 2"), "(-(f([2]) ** 2))");
+    assert_eq!(grouped("a.b.c(1) * 2"), "(a.b.c([1]) * 2)");
+    assert_eq!(grouped("1 + x.len() * 2"), "(1 + (x.len() * 2))");
+
+    //dots chain from the left: (a.b).c(1)
+    let Expr::METHOD_CALL(call) = Parser::new(tokenise("a.b.c(1)")).parse_expr(0).unwrap() else {
+        panic!("not a method call")
+    };
+    assert_eq!(call.call.ident.name, "c");
+    assert!(matches!(*call.base, Expr::FIELD_ACCESS(ref access) if access.access.name == "b"));
+}
+
+//and the backends work them out that way, folded or not
+#[test]
+fn both_backends_agree_on_the_grouping() {
+    let source = "
+rite two() -> int { ret 2; }
+power: int = 2 ** 3 ** 2;
+minus: int = 10 - 4 - 3;
+sign: int = -2 ** 2;
+bits: int = 1 | 2 ^ 3 & 4 << 1;
+called: int = -two() ** 2;
+";
+    for backend in BACKENDS {
+        for optimising in [true, false] {
+            let loader = loader("ops", source, &[]);
+            let runtime = load_into(backend, loader, |runtime| runtime.set_optimising(optimising));
+            let names = ["power", "minus", "sign", "bits", "called"];
+            let globals = names.map(|name| runtime.global("ops", name));
+            let wanted = [512, 3, -4, 3, -4].map(|value| Some(Value::INT(value)));
+            assert_eq!(globals, wanted, "on {:?} (optimising: {})", backend, optimising);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use veilscript_lang::random::Rng;
+use veilscript_lang::runtime::{Backend, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+//every way a script has of rolling something
+const ROLLS: &str = r#"
+rite die() -> int { ret roll(1, 1000000); }
+rite written() -> int { ret roll("2d6+3"); }
+rite literal() -> int { ret 3d6 - 1d4; }
+rite coin() -> int { ret chance(0.5); }
+rite pick_one() -> rune { ret pick("sword", "shield", "potion", "map"); }
+"#;
+
+const RITES: [&str; 5] = ["die", "written", "literal", "coin", "pick_one"];
+
+fn runtime(backend: Backend, seed: u64) -> Box<dyn ScriptRuntime> {
+    let mut runtime = common::runtime(backend, "rolls", ROLLS);
+    *runtime.rng() = Rng::new(seed);
+    runtime
+}
+
+fn rolls(runtime: &mut dyn ScriptRuntime, turns: usize) -> Vec<Value> {
+    let mut rolled = Vec::new();
+    for _ in 0..turns {
+        for rite in RITES {
+            rolled.push(runtime.call("rolls", rite, &[]).unwrap());
+        }
+    }
+    rolled
+}
+
+#[test]
+fn the_same_seed_rolls_the_same_on_both_backends() {
+    let interpreter = rolls(runtime(Backend::INTERPRETER, 42).as_mut(), 50);
+    let vm = rolls(runtime(Backend::VM, 42).as_mut(), 50);
+    assert_eq!(interpreter, vm);
+    assert_ne!(interpreter, rolls(runtime(Backend::VM, 43).as_mut(), 50));
+}
+
+#[test]
+fn a_restored_game_rolls_what_it_would_have_rolled() {
+    for (saved_on, loaded_on) in [
+        (Backend::INTERPRETER, Backend::VM),
+        (Backend::VM, Backend::INTERPRETER),
+        (Backend::VM, Backend::VM),
+    ] {
+        let mut playing = runtime(saved_on, 7);
+        rolls(playing.as_mut(), 3);
+        let save = playing.save();
+        let mut loaded = runtime(loaded_on, 0);
+        loaded.restore(&save).unwrap();
+        assert_eq!(rolls(loaded.as_mut(), 20), rolls(playing.as_mut(), 20));
+    }
+}
+
+#[test]
+fn a_fork_rolls_its_own_numbers() {
+    let mut parent = runtime(Backend::VM, 42);
+    let fork = parent.rng().fork();
+    let mut forked = runtime(Backend::INTERPRETER, 0);
+    *forked.rng() = fork;
+    let mut again = runtime(Backend::VM, 42);
+    *again.rng() = again.rng().fork();
+    let forked = rolls(forked.as_mut(), 10);
+    assert_ne!(rolls(parent.as_mut(), 10), forked);
+    assert_eq!(rolls(again.as_mut(), 10), forked);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{load_into, loader, rune, BACKENDS};
+use veilscript_lang::runtime::{Backend, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+const BANK: &str = r#"
+gold: int = 10;
+title: rune = "squire";
+rate: float = 1.0;
+count: int = 0;
+gone: int = 5;
+pub rite earn(n: int) -> int { gold = gold + n; count = count + 1; ret gold; }
+rite promote() { title = "knight"; }
+"#;
+
+//what the designer saved while the game was running
+const EDITED: &str = r#"
+gold: int = 10;
+title: int = 0;
+rate: float = 2.0;
+count: float = 0.0;
+fresh: int = gold + 1;
+pub rite earn(n: int) -> int { gold = gold + n * 2; count = count + 1.0; ret gold; }
+rite bonus() -> int { ret gold + fresh; }
+"#;
+
+const MAIN: &str = "summon bank;\nrite pay() -> int { ret bank::earn(1); }";
+
+fn playing(backend: Backend) -> Box<dyn ScriptRuntime> {
+    let mut runtime = load_into(backend, loader("main", MAIN, &[("bank.veil", BANK)]), |_| {});
+    runtime.call("bank", "earn", &[Value::INT(5)]).unwrap();
+    runtime.call("bank", "promote", &[]).unwrap();
+    runtime
+}
+
+#[test]
+fn reloading_keeps_the_globals_that_still_fit() {
+    for backend in BACKENDS {
+        let mut runtime = playing(backend);
+        let report = runtime.reload("bank", EDITED).unwrap();
+        let global = |name| runtime.global("bank", name);
+        assert_eq!(global("gold"), Some(Value::INT(15)), "kept on {:?}", backend);
+        assert_eq!(global("rate"), Some(Value::FLOAT(1.0)), "kept over the new initial value");
+        assert_eq!(global("count"), Some(Value::FLOAT(1.0)), "an int still fits a float");
+        assert_eq!(global("title"), Some(Value::INT(0)), "a rune doesn't fit an int");
+        assert_eq!(global("fresh"), Some(Value::INT(11)), "new globals get their values");
+        assert_eq!(global("gone"), None);
+
+        assert_eq!(report.changed, ["earn"]);
+        assert_eq!(report.added, ["bonus"]);
+        assert_eq!(report.removed, ["promote"]);
+        assert_eq!(report.incompatible.len(), 1, "{:?}", report.incompatible);
+        assert!(report.incompatible[0].contains("title"), "{:?}", report.incompatible);
+
+        assert_eq!(runtime.call("main", "pay", &[]), Ok(Value::INT(17)), "callers get new code");
+        assert_eq!(runtime.call("bank", "bonus", &[]), Ok(Value::INT(28)));
+        assert!(runtime.call("bank", "promote", &[]).is_err());
+    }
+}
+
+#[test]
+fn a_failed_reload_changes_nothing() {
+    for backend in BACKENDS {
+        let mut runtime = playing(backend);
+        let broken = [
+            EDITED.replace("fresh: int = gold + 1;", "fresh: int = gold / 0;"),
+            EDITED.replace("pub rite earn", "rite earn"),
+            EDITED.replace("ret gold; }", "ret gold;"),
+        ];
+        for source in &broken {
+            assert!(runtime.reload("bank", source).is_err(), "{}", source);
+            assert_eq!(runtime.global("bank", "gold"), Some(Value::INT(15)));
+            assert_eq!(runtime.global("bank", "title"), Some(rune("knight")));
+            assert_eq!(runtime.global("bank", "fresh"), None);
+        }
+        assert_eq!(runtime.call("main", "pay", &[]), Ok(Value::INT(16)), "still the old code");
+        assert!(runtime.call("bank", "promote", &[]).is_ok());
+    }
+}
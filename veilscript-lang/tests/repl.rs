@@ -0,0 +1,70 @@
+use veilscript_lang::interpreter::Interpreter;
+use veilscript_lang::module::ModuleLoader;
+use veilscript_lang::repl::Repl;
+use veilscript_lang::runtime::ScriptRuntime;
+use veilscript_lang::source::MemoryLoader;
+
+//a repl on `script`, the way `veil repl <file>` starts one
+fn repl(script: &'static str) -> Repl {
+    let setup = move || {
+        let mut loader = ModuleLoader::from_loader(MemoryLoader::new());
+        loader.load_source("script", script).map_err(|err| err.to_string())?;
+        let mut interpreter = Interpreter::new();
+        interpreter.load(loader)?;
+        Ok(interpreter)
+    };
+    Repl::new("script", Box::new(setup)).unwrap()
+}
+
+//what each line prints
+fn feed(repl: &mut Repl, lines: &[&str]) -> Vec<Result<String, String>> {
+    lines.iter().map(|line| repl.feed(line)).collect()
+}
+
+const SHOP: &str = "gold: int = 10;\nrite twice(x: int) -> int { ret x * 2; }";
+
+fn ok(printed: &str) -> Result<String, String> {
+    Ok(printed.to_string())
+}
+
+#[test]
+fn what_one_input_does_the_next_one_sees() {
+    let mut repl = repl(SHOP);
+    assert_eq!(feed(&mut repl, &["gold + 1", "gold = gold + 5", "gold", "twice(gold)", "\"7\""]), [
+        ok("11"), ok(""), ok("15"), ok("30"), ok("\"7\""),
+    ]);
+    assert_eq!(feed(&mut repl, &["x = 1", "x + gold", "nowhere"]), [
+        ok(""), ok("16"), Err("nowhere is not defined!".to_string()),
+    ]);
+}
+
+#[test]
+fn an_input_goes_on_until_its_brackets_close() {
+    let mut repl = repl(SHOP);
+    for line in ["rite twice(x: int) -> int {", "  ret x * 3;"] {
+        assert_eq!(repl.feed(line), ok(""));
+        assert!(repl.is_pending());
+    }
+    assert_eq!(feed(&mut repl, &["}", "twice(2)"]), [ok(""), ok("6")], "declared again, replaced");
+    assert!(!repl.is_pending());
+
+    //two blank lines give up on it
+    assert_eq!(feed(&mut repl, &["(1 +", ""]), [ok(""), ok("")]);
+    assert_eq!(repl.feed(""), Err("Can't start a statement with LPAREN!".to_string()));
+    assert_eq!(repl.feed("1 + 1"), ok("2"));
+}
+
+#[test]
+fn commands_type_and_reset() {
+    let mut repl = repl(SHOP);
+    assert_eq!(feed(&mut repl, &[":type gold", ":type 1.5 + gold", ":type twice(1)"]), [
+        ok("int"), ok("float"), ok("int"),
+    ]);
+    assert_eq!(repl.feed(":type \"a\" - 1"), Err("Can't apply '-' to rune and int!".to_string()));
+    assert_eq!(repl.feed("gold"), ok("10"), ":type doesn't run anything");
+
+    assert_eq!(feed(&mut repl, &["gold = 99", "x = 1", ":reset", "gold", "x"]), [
+        ok(""), ok(""), ok("Started over."), ok("10"), Err("x is not defined!".to_string()),
+    ]);
+    assert_eq!(repl.feed(":nope"), Err("Unknown command :nope! Try :help.".to_string()));
+}
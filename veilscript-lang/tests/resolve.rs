@@ -0,0 +1,69 @@
+use veilscript_lang::diagnostic::Diagnostic;
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::resolve::{did_you_mean, edit_distance, resolve, Binding, SymbolKind};
+
+const SCRIPT: &str = r#"
+gold: int = 10;
+rite spend(amount: int) -> int {
+    left = gold - amount;
+    {
+        gold: int = 0;
+        amount = 1;
+        {
+            left: int = 2;
+            fresh = gols + left;
+        }
+        fresh = 3;
+    }
+    ret lefft + shout("no");
+}
+rite payday() { spnd(1); }
+"#;
+
+//every diagnostic, in order
+fn diagnostics(natives: Option<&[String]>) -> Vec<String> {
+    let stmts = Parser::new(tokenise(SCRIPT)).parse_program().unwrap();
+    resolve("shop", &stmts, natives).diagnostics.iter().map(Diagnostic::to_string).collect()
+}
+
+//the same typos, with and without the natives the game provides being known
+#[test]
+fn undefined_names_get_a_suggestion_when_something_is_close() {
+    let natives = ["print".to_string()];
+    let known = diagnostics(Some(&natives));
+    assert_eq!(known[2..], [
+        "shop:10: error: gols is not defined! Did you mean gold?",
+        "shop:14: error: lefft is not defined! Did you mean left?",
+        "shop:14: error: No rite named shout!",
+        "shop:16: error: No rite named spnd! Did you mean spend?",
+    ]);
+    let unknown = diagnostics(None);
+    assert_eq!(unknown[2..], known[2..4], "without the natives, calls are taken on trust");
+
+    assert_eq!(edit_distance("glod", "gold"), 2, "a swap is two edits");
+    assert_eq!(did_you_mean("glod", ["gold", "spend"]), None);
+    assert_eq!(did_you_mean("spennd", ["gold", "spend"]), Some("spend"));
+}
+
+//only a declaration in a nested scope shadows. assigning a parameter or a name whose scope is
+//over doesn't.
+#[test]
+fn declarations_in_nested_scopes_warn_about_shadowing() {
+    assert_eq!(diagnostics(None)[..2], [
+        "shop:6: warning: gold shadows the global gold from line 2!",
+        "shop:9: warning: left shadows the local left from line 4!",
+    ]);
+
+    let stmts = Parser::new(tokenise(SCRIPT)).parse_program().unwrap();
+    let resolution = resolve("shop", &stmts, None);
+    let kinds = |name: &str| -> Vec<Option<SymbolKind>> {
+        resolution.references.iter().filter(|reference| reference.ident.name == name)
+            .map(|reference| reference.symbol().map(|symbol| resolution.symbols[symbol].kind))
+            .collect()
+    };
+    assert_eq!(kinds("amount"), [Some(SymbolKind::PARAM); 3]);
+    assert_eq!(kinds("fresh"), [Some(SymbolKind::LOCAL); 2]);
+    let spnd = resolution.references.iter().find(|reference| reference.ident.name == "spnd");
+    assert_eq!(spnd.map(|reference| reference.binding), Some(Binding::NATIVE), "taken on trust");
+}
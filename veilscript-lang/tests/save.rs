@@ -0,0 +1,110 @@
+mod common;
+
+use common::BACKENDS;
+use veilscript_lang::runtime::{Backend, Resumed, ScriptRuntime};
+use veilscript_lang::value::Value;
+
+//a quest that goes to sleep two rites deep, with locals in both and a global it keeps counting
+const QUEST: &str = "
+counter: int = 0;
+rite bump() { counter = counter + 3; }
+rite ask(n: int) -> int {
+    asked = n * 10;
+    got = yield asked;
+    ret got + n;
+}
+rite quest(start: int) -> int {
+    counter = counter + 1;
+    total = start + ask(1);
+    counter = counter + 1;
+    total = total + (yield total);
+    ret total + counter;
+}
+";
+
+fn runtime(backend: Backend, source: &str) -> Box<dyn ScriptRuntime> {
+    common::runtime(backend, "quest", source)
+}
+
+//the rest of the quest from where the save was made, the way the game would resume it
+fn finish(runtime: &mut dyn ScriptRuntime, quest: usize) -> Vec<Resumed> {
+    vec![
+        runtime.resume(quest, Value::INT(7)).unwrap(),
+        runtime.resume(quest, Value::INT(100)).unwrap(),
+    ]
+}
+
+#[test]
+fn a_sleeping_coroutine_wakes_up_in_a_fresh_runtime() {
+    for backend in BACKENDS {
+        let mut playing = runtime(backend, QUEST);
+        let quest = playing.spawn("quest", "quest", &[Value::INT(5)]).unwrap();
+        assert_eq!(playing.resume(quest, Value::VOID), Ok(Resumed::YIELDED(Value::INT(10))));
+        let save = playing.save();
+
+        let mut loaded = runtime(backend, QUEST);
+        loaded.restore(&save).unwrap();
+        assert_eq!(loaded.global("quest", "counter"), Some(Value::INT(1)));
+        let expected = vec![Resumed::YIELDED(Value::INT(13)), Resumed::FINISHED(Value::INT(115))];
+        assert_eq!(finish(loaded.as_mut(), quest), expected, "on {:?}", backend);
+        assert_eq!(finish(playing.as_mut(), quest), expected);
+        assert_eq!(loaded.global("quest", "counter"), playing.global("quest", "counter"));
+        assert!(loaded.resume(quest, Value::VOID).is_err(), "a finished quest should be gone");
+    }
+}
+
+#[test]
+fn a_restored_coroutine_keeps_its_id_to_itself() {
+    for backend in BACKENDS {
+        let mut playing = runtime(backend, QUEST);
+        let quest = playing.spawn("quest", "quest", &[Value::INT(5)]).unwrap();
+        playing.resume(quest, Value::VOID).unwrap();
+
+        let mut loaded = runtime(backend, QUEST);
+        loaded.restore(&playing.save()).unwrap();
+        let another = loaded.spawn("quest", "quest", &[Value::INT(0)]).unwrap();
+        assert_ne!(another, quest);
+        assert_eq!(loaded.resume(another, Value::VOID), Ok(Resumed::YIELDED(Value::INT(10))));
+        assert_eq!(loaded.resume(quest, Value::INT(7)), Ok(Resumed::YIELDED(Value::INT(13))));
+    }
+}
+
+#[test]
+fn a_save_from_other_code_is_refused_and_changes_nothing() {
+    for backend in BACKENDS {
+        let mut playing = runtime(backend, QUEST);
+        let quest = playing.spawn("quest", "quest", &[Value::INT(5)]).unwrap();
+        playing.resume(quest, Value::VOID).unwrap();
+        let save = playing.save();
+
+        let mut changed = runtime(backend, &QUEST.replace("n * 10", "n * 20"));
+        let err = changed.restore(&save).unwrap_err();
+        assert!(err.starts_with("Module quest changed since the save was made!"), "{}", err);
+        assert_eq!(changed.global("quest", "counter"), Some(Value::INT(0)));
+        assert!(changed.resume(quest, Value::VOID).is_err());
+
+        let mut truncated = runtime(backend, QUEST);
+        assert!(truncated.restore(&save[..save.len() - 1]).is_err());
+    }
+}
+
+//the globals go across, a sleeping coroutine only wakes up on the backend it fell asleep on
+#[test]
+fn a_save_from_the_other_backend_has_the_same_globals() {
+    for [from, to] in [BACKENDS, [Backend::VM, Backend::INTERPRETER]] {
+        let mut saving = runtime(from, QUEST);
+        saving.call("quest", "bump", &[]).unwrap();
+        let mut loading = runtime(to, QUEST);
+        let quest = loading.spawn("quest", "quest", &[Value::INT(5)]).unwrap();
+        loading.restore(&saving.save()).unwrap();
+        assert!(loading.resume(quest, Value::VOID).is_err(), "the save had no coroutines");
+        assert_eq!(loading.global("quest", "counter"), Some(Value::INT(3)));
+
+        let quest = saving.spawn("quest", "quest", &[Value::INT(5)]).unwrap();
+        saving.resume(quest, Value::VOID).unwrap();
+        let err = loading.restore(&saving.save()).unwrap_err();
+        let refused = format!("Coroutine {} in the save was made by the", quest);
+        assert!(err.starts_with(&refused), "{}", err);
+        assert_eq!(loading.global("quest", "counter"), Some(Value::INT(3)), "nothing changed");
+    }
+}
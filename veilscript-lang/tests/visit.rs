@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use veilscript_lang::ast::{Expr, Ident, Stmt};
+use veilscript_lang::lexer::tokenise;
+use veilscript_lang::parser::Parser;
+use veilscript_lang::visit::{walk_expr, walk_stmt, MutVisitor, Visitor};
+
+//every kind of statement and expression there is, dialogue included
+const SCRIPT: &str = r#"
+summon lib;
+pub gold: int = 10;
+rite talk(name: rune) -> int {
+    {
+        gold;
+        ;
+        greet(name);
+        x = -gold + (2 * 3);
+        y = name.len() + name.size;
+        z = { ret 1; };
+        yield x;
+        w = yield;
+    }
+    dialogue {
+        node start {
+            Guard: "Hi {name}, you have {gold} gold.";
+            choice "Pay {gold - 1}." if gold -> start;
+            choice "Leave." -> done;
+        }
+        node done {
+            -> start;
+        }
+    }
+    ret lib::base;
+}
+"#;
+
+#[derive(Default)]
+struct Counter {
+    kinds: BTreeMap<&'static str, usize>,
+    idents: Vec<String>,
+}
+
+impl<'ast> Visitor<'ast> for Counter {
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        let kind = match stmt {
+            Stmt::STATEMENT_ASSIGNMENT(_) => "assignment",
+            Stmt::STATEMENT_FUNCTION_DECLARATION(_) => "rite",
+            Stmt::STATEMENT_ZERO_EFFECT(_) => "zero effect",
+            Stmt::STATEMENT_RETURN(_) => "ret",
+            Stmt::STATEMENT_FUNCTION_CALL(_) => "call statement",
+            Stmt::STATEMENT_IMPORT(_) => "summon",
+            Stmt::STATEMENT_EXPORT(_) => "pub",
+            Stmt::STATEMENT_YIELD(_) => "yield statement",
+            Stmt::STATEMENT_DIALOGUE(_) => "dialogue",
+            Stmt::SCOPE(_) => "scope statement",
+        };
+        *self.kinds.entry(kind).or_default() += 1;
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        let kind = match expr {
+            Expr::ATOM(_) => "atom",
+            Expr::GROUPED_EXPR(_) => "grouping",
+            Expr::BINARY_EXPR { .. } => "binary",
+            Expr::UNARY_EXPR { .. } => "unary",
+            Expr::SCOPE(_) => "scope",
+            Expr::FUNCTION_CALL(_) => "call",
+            Expr::METHOD_CALL(_) => "method call",
+            Expr::FIELD_ACCESS(_) => "field",
+            Expr::YIELD(_) => "yield",
+        };
+        *self.kinds.entry(kind).or_default() += 1;
+        walk_expr(self, expr);
+    }
+
+    fn visit_ident(&mut self, ident: &'ast Ident) {
+        self.idents.push(ident.full_name());
+    }
+}
+
+fn parse() -> Vec<Stmt> {
+    Parser::new(tokenise(SCRIPT)).parse_program().unwrap()
+}
+
+#[test]
+fn a_visitor_gets_everywhere_in_source_order() {
+    let mut counter = Counter::default();
+    counter.visit_stmts(&parse());
+    let kinds: Vec<(&str, usize)> = counter.kinds.into_iter().collect();
+    assert_eq!(kinds, [
+        ("assignment", 5), ("atom", 15), ("binary", 4), ("call statement", 1), ("dialogue", 1),
+        ("field", 1), ("grouping", 1), ("method call", 1), ("pub", 1), ("ret", 2), ("rite", 1),
+        ("scope", 1), ("scope statement", 1), ("summon", 1), ("unary", 1), ("yield", 1),
+        ("yield statement", 1), ("zero effect", 2),
+    ]);
+    assert_eq!(counter.idents, [
+        "lib", "gold", "talk", "name", "gold", "greet", "name", "x", "gold", "y", "name", "len",
+        "name", "size", "z", "x", "w", "name", "gold", "gold", "gold", "lib::base",
+    ], "dialogue: the line, the choice's text, then its condition");
+}
+
+struct Renamer;
+
+impl MutVisitor for Renamer {
+    fn visit_ident_mut(&mut self, ident: &mut Ident) {
+        if ident.name == "gold" {
+            ident.name = "coins".to_string();
+        }
+    }
+}
+
+#[test]
+fn a_mut_visitor_gets_to_the_same_places() {
+    let mut stmts = parse();
+    Renamer.visit_stmts_mut(&mut stmts);
+    let mut counter = Counter::default();
+    counter.visit_stmts(&stmts);
+    let count = |name| counter.idents.iter().filter(|ident| *ident == name).count();
+    assert_eq!((count("gold"), count("coins")), (0, 6));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{load_into, loader, BACKENDS};
+use veilscript_lang::runtime::ScriptRuntime;
+use veilscript_lang::value::Value;
+
+const LIB: &str = "
+pub base: int = 20;
+pub rite twice(x: int) -> int { ret x * 2; }
+pub rite bump() -> int { base = base + 1; ret base; }
+";
+
+//every rite is called on both backends, in this order, and has to come out the same: values and
+//error messages alike
+const MAIN: &str = r#"
+summon lib;
+count: int = 0;
+rite shadows() -> int {
+    x: int = 1;
+    y = {
+        x: int = 10;
+        x = x + 1;
+        ret x;
+    };
+    ret x * 100 + y;
+}
+rite reaches_out() -> int {
+    x = 1;
+    { x = 2; z = 5; }
+    ret x;
+}
+rite inner_local_is_gone() -> int { { z = 5; } ret z; }
+rite counts() -> int { count = count + 1; ret count; }
+rite shadows_a_global() -> int { count: int = 99; ret count; }
+rite counts_again() -> int { ret counts(); }
+rite calls_across() -> int { ret lib::twice(lib::base) + lib::bump(); }
+rite reads_across() -> int { ret lib::base; }
+rite wraps() -> int { ret 9223372036854775807 * 2; }
+rite wraps_below() -> int { x = 2; ret -9223372036854775807 - x; }
+rite shifts_round() -> int { ret 1 << 65; }
+rite promotes() -> float { ret 3 / 2 + 0.5; }
+rite promotes_a_return() -> float { ret 2; }
+rite promotes_an_assignment() -> float { f: float = 7; ret f; }
+rite conform_fails() -> int { x: int = "rune"; ret x; }
+rite bad_return() -> int { ret "no"; }
+rite takes_int(n: int) -> int { ret n; }
+rite bad_argument() -> int { ret takes_int(1.5); }
+rite too_many() -> int { ret takes_int(1, 2); }
+rite deep(n: int) -> int { ret deep(n + 1); }
+rite runs_away() -> int { ret deep(0); }
+rite undefined() -> int { ret nowhere; }
+rite rune_maths() -> rune { ret "a" - 1; }
+rite glues() -> rune { ret "gold: " + count; }
+"#;
+
+const RITES: [&str; 24] = [
+    "shadows", "reaches_out", "inner_local_is_gone", "counts", "shadows_a_global", "counts_again",
+    "calls_across", "reads_across", "wraps", "wraps_below", "shifts_round", "promotes",
+    "promotes_a_return", "promotes_an_assignment", "conform_fails", "bad_return", "bad_argument",
+    "too_many", "runs_away", "undefined", "rune_maths", "glues", "counts", "calls_across",
+];
+
+fn play(runtime: &mut dyn ScriptRuntime) -> Vec<Result<Value, String>> {
+    let mut results: Vec<Result<Value, String>> = RITES.iter()
+        .map(|rite| runtime.call("main", rite, &[]))
+        .collect();
+    results.push(runtime.global("main", "count").ok_or_else(|| "no count".to_string()));
+    results.push(runtime.global("lib", "base").ok_or_else(|| "no base".to_string()));
+    results
+}
+
+fn results(optimising: bool) -> [Vec<Result<Value, String>>; 2] {
+    BACKENDS.map(|backend| {
+        let loader = loader("main", MAIN, &[("lib.veil", LIB)]);
+        play(load_into(backend, loader, |runtime| runtime.set_optimising(optimising)).as_mut())
+    })
+}
+
+#[test]
+fn both_backends_agree_on_everything() {
+    for optimising in [true, false] {
+        let [interpreter, vm] = results(optimising);
+        for (at, (interpreter, vm)) in interpreter.iter().zip(&vm).enumerate() {
+            let what = RITES.get(at).unwrap_or(&"the globals");
+            assert_eq!(interpreter, vm, "{} (optimising: {})", what, optimising);
+        }
+    }
+}
+
+//agreeing isn't worth much if both are wrong, so here's what the reference interpreter says
+#[test]
+fn and_they_agree_on_the_right_thing() {
+    let [results, _] = results(true);
+    let ok = |at: usize| results[at].clone().unwrap();
+    let err = |at: usize| results[at].clone().unwrap_err();
+
+    assert_eq!(ok(0), Value::INT(111), "a typed assignment declares in the innermost scope");
+    assert_eq!(ok(1), Value::INT(2), "an untyped one goes to the closest local");
+    assert_eq!(err(2), "z is not defined!");
+    assert_eq!((ok(3), ok(4), ok(5)), (Value::INT(1), Value::INT(99), Value::INT(2)));
+    assert_eq!((ok(6), ok(7)), (Value::INT(61), Value::INT(21)));
+    assert_eq!((ok(8), ok(9)), (Value::INT(-2), Value::INT(i64::MAX)), "ints wrap");
+    assert_eq!(err(10), "Can't shift by 65! Shift amounts must be within 0..64.");
+    assert_eq!((ok(11), ok(12), ok(13)), (Value::FLOAT(1.5), Value::FLOAT(2.0), Value::FLOAT(7.0)));
+    assert_eq!(err(14), "Can't put rune into x: int!");
+    assert_eq!(err(15), "Rite bad_return should give back int, but gave back rune!");
+    assert_eq!(err(16), "Rite takes_int wants int for n, but got float!");
+    assert_eq!(err(17), "Rite takes_int takes 1 argument(s), but got 2!");
+    assert_eq!(err(18), "Stack overflow! Rites nested deeper than 200 calls.");
+    assert_eq!(err(19), "nowhere is not defined!");
+    assert_eq!(err(20), "Can't apply '-' to rune and int!");
+    assert_eq!(err(21), "Can't apply '+' to rune and int!");
+    assert_eq!((ok(22), ok(23)), (Value::INT(3), Value::INT(64)));
+    assert_eq!((ok(24), ok(25)), (Value::INT(3), Value::INT(22)), "the globals at the end");
+}